@@ -2,11 +2,12 @@ import { parseISO } from "date-fns";
 
 const BASE_URL = process.env.NEXT_PUBLIC_API_URI;
 
-type ProjectResponse = {
+export type ProjectResponse = {
   id: string;
   title: string;
   description?: string;
   created_at: string;
+  workspace_id: string;
 };
 
 type ProjectStatsResponse = {
@@ -17,7 +18,7 @@ type ProjectStatsResponse = {
   complete: number;
 };
 
-type TaskResponse = {
+export type TaskResponse = {
   id: string;
   title: string;
   status: string;
@@ -26,6 +27,11 @@ type TaskResponse = {
   created_at: string;
 };
 
+type PageResponse<T> = {
+  items: T[];
+  next_cursor?: string;
+};
+
 export const TASK_STATUS_SERVER_TO_USER = {
   todo: "Todo",
   in_progress: "In Progress",
@@ -55,6 +61,7 @@ export type Project = {
   title: string;
   description?: string;
   createdAt: Date;
+  workspaceId: string;
 };
 
 export type Task = {
@@ -73,16 +80,17 @@ export type ProjectWithStats = Project & {
   inProgress: number;
 };
 
-function deserializeProject(p: ProjectResponse): Project {
+export function deserializeProject(p: ProjectResponse): Project {
   return {
     id: p.id,
     title: p.title,
     description: p.description,
     createdAt: parseISO(p.created_at),
+    workspaceId: p.workspace_id,
   };
 }
 
-function deserializeTask(t: TaskResponse): Task {
+export function deserializeTask(t: TaskResponse): Task {
   return {
     id: t.id,
     title: t.title,
@@ -139,14 +147,29 @@ export async function fetchTaskProjects(id: string): Promise<Project[]> {
   return ((await res.json()) as ProjectResponse[]).map(deserializeProject);
 }
 
+// Follow next_cursor until every page of a task listing is fetched
+async function fetchAllTasks(url: string): Promise<Task[]> {
+  const tasks: TaskResponse[] = [];
+  const separator = url.includes("?") ? "&" : "?";
+  let cursor: string | undefined;
+  do {
+    const pageUrl = cursor ? `${url}${separator}cursor=${cursor}` : url;
+    const res = await fetch(pageUrl);
+    const page = (await res.json()) as PageResponse<TaskResponse>;
+    tasks.push(...page.items);
+    cursor = page.next_cursor;
+  } while (cursor);
+  return tasks.map(deserializeTask);
+}
+
 export async function fetchProjectTasks(id: string): Promise<Task[]> {
-  const res = await fetch(`${BASE_URL}/projects/${id}/tasks`);
-  return ((await res.json()) as TaskResponse[]).map(deserializeTask);
+  return fetchAllTasks(`${BASE_URL}/projects/${id}/tasks`);
 }
 
 export async function searchTasks(search: string): Promise<Task[]> {
   const res = await fetch(`${BASE_URL}/tasks?search=${search}`);
-  return ((await res.json()) as TaskResponse[]).map(deserializeTask);
+  const page = (await res.json()) as PageResponse<TaskResponse>;
+  return page.items.map(deserializeTask);
 }
 
 export async function createTaskInProject({
@@ -1,60 +1,230 @@
-import { createContext, useEffect, useState } from "react";
+import { createContext, useContext, useEffect, useMemo, useRef } from "react";
 import { QueryClient } from "@tanstack/react-query";
+import {
+  deserializeProject,
+  deserializeTask,
+  Project,
+  ProjectResponse,
+  Task,
+  TaskResponse,
+} from "../api";
 
 const WS_BASE_URI = process.env.NEXT_PUBLIC_WS_URI;
+const RECONNECT_DELAY_MS = 1000;
 
-type SubscriptionPayload = {
-  entityType: "tasks" | "projects";
-  entityId?: string;
+// What the server pushes updates for. A workspace covers everything in it,
+// a project the tasks in it.
+export type Topic = {
+  type: "workspace" | "project" | "task" | "view";
+  id: string;
 };
 
 export const CacheContext = createContext({
-  subscribe: (_: SubscriptionPayload) => {},
-  unsubscribe: (_: SubscriptionPayload) => {},
+  subscribe: (_: Topic) => {},
+  unsubscribe: (_: Topic) => {},
 });
 
+// Hear about changes to the topics while the component is mounted
+export function useSubscriptions(topics: Topic[]) {
+  const { subscribe, unsubscribe } = useContext(CacheContext);
+  const key = topics.map(topicKey).join(",");
+
+  useEffect(() => {
+    topics.forEach(subscribe);
+    return () => topics.forEach(unsubscribe);
+  }, [key, subscribe, unsubscribe]);
+}
+
 type ServerUpdateEvent = {
+  // Position in the server's feed, to resume from after reconnecting
+  seq: number;
   kind: "Create" | "Update" | "Destroy";
-  entity_type: "Project" | "Task";
+  entity_type: "Project" | "Task" | string;
   entity_id: string;
+  // The entity after the change, and which of its fields changed
+  entity?: TaskResponse | ProjectResponse;
+  changed_fields?: string[];
+  // Projects a task joined or left
+  projects?: { added?: string[]; removed?: string[] };
 };
 
 type ClientUpdateEvent = {
   kind: "create" | "update" | "destroy";
   entityType: "projects" | "tasks";
   entityId: string;
+  entity?: Task | Project;
+  changedFields?: string[];
+  projectIds: string[];
 };
 
 function toClient(e: ServerUpdateEvent): ClientUpdateEvent {
+  const entityType = e.entity_type === "Project" ? "projects" : "tasks";
+  let entity: Task | Project | undefined;
+  if (e.entity) {
+    entity =
+      entityType === "tasks"
+        ? deserializeTask(e.entity as TaskResponse)
+        : deserializeProject(e.entity as ProjectResponse);
+  }
   return {
     kind: e.kind.toLowerCase() as ClientUpdateEvent["kind"],
-    entityType: e.entity_type === "Project" ? "projects" : "tasks",
+    entityType,
     entityId: e.entity_id,
+    entity,
+    changedFields: e.changed_fields,
+    projectIds: [...(e.projects?.added ?? []), ...(e.projects?.removed ?? [])],
   };
 }
 
+// Replies to messages sent over the socket
+type ServerReply =
+  | { type: "subscribed" | "unsubscribed"; topic: Topic }
+  | { type: "error"; topic?: Topic; code: string; message: string }
+  // Updates were missed that the server no longer has
+  | { type: "resync_required"; seq: number };
+
+function topicKey(topic: Topic): string {
+  return `${topic.type}:${topic.id}`;
+}
+
+// Task changes that don't affect the project list page's counts
+function onlyTaskDetails(message: ClientUpdateEvent): boolean {
+  return (
+    message.entityType === "tasks" &&
+    message.kind === "update" &&
+    message.projectIds.length === 0 &&
+    !!message.changedFields &&
+    !message.changedFields.includes("status")
+  );
+}
+
 export function useCacheInvalidator(queryClient: QueryClient) {
-  const [webSocket] = useState(new WebSocket(`${WS_BASE_URI}/subscribe`));
+  const socket = useRef<WebSocket | null>(null);
+  // Components subscribed to each topic, so it's kept until the last leaves
+  const subscribers = useRef(new Map<string, { topic: Topic; count: number }>());
+  const lastSeq = useRef<number | null>(null);
+
+  const cache = useMemo(() => {
+    // Topics subscribed to before connecting go in the URL instead
+    function send(message: object) {
+      const webSocket = socket.current;
+      if (webSocket?.readyState === WebSocket.OPEN) {
+        webSocket.send(JSON.stringify(message));
+      } else if (webSocket?.readyState === WebSocket.CONNECTING) {
+        webSocket.addEventListener("open", () => send(message), {
+          once: true,
+        });
+      }
+    }
+
+    return {
+      subscribe: (topic: Topic) => {
+        const key = topicKey(topic);
+        const count = subscribers.current.get(key)?.count ?? 0;
+        subscribers.current.set(key, { topic, count: count + 1 });
+        if (count === 0) send({ action: "subscribe", topic });
+      },
+      unsubscribe: (topic: Topic) => {
+        const key = topicKey(topic);
+        const count = subscribers.current.get(key)?.count ?? 0;
+        if (count > 1) {
+          subscribers.current.set(key, { topic, count: count - 1 });
+        } else {
+          subscribers.current.delete(key);
+          send({ action: "unsubscribe", topic });
+        }
+      },
+    };
+  }, []);
 
   useEffect(() => {
-    webSocket.addEventListener("message", (event) => {
-      const message = toClient(JSON.parse(event.data) as ServerUpdateEvent);
-      const { entityId, entityType } = message;
+    function handleUpdate(data: ServerUpdateEvent) {
+      const message = toClient(data);
+      const { entity, entityId, entityType, kind, projectIds } = message;
 
-      // Sadly, all changes affect project list page.
-      queryClient.invalidateQueries({ queryKey: ["projects"] });
+      if (!onlyTaskDetails(message)) {
+        queryClient.invalidateQueries({ queryKey: ["projects"], exact: true });
+      }
 
-      if (entityType === "tasks") {
+      // Patch the entity in place when the event carries it
+      if (entity && kind !== "destroy") {
+        queryClient.setQueryData([entityType, entityId], entity);
+        if (entityType === "tasks") {
+          queryClient.setQueriesData<Task[]>({ queryKey: ["tasks"] }, (old) =>
+            Array.isArray(old)
+              ? old.map((t) => (t.id === entityId ? (entity as Task) : t))
+              : old,
+          );
+        }
+      } else if (entityType === "tasks") {
         queryClient.invalidateQueries({ queryKey: ["tasks"] });
+      } else {
+        queryClient.invalidateQueries({ queryKey: [entityType, entityId] });
       }
 
-      if (!!entityId) {
-        queryClient.invalidateQueries({ queryKey: [entityType, entityId] });
+      // Task lists of projects the task joined or left, or is new to
+      if (entityType === "tasks") {
+        for (const projectId of projectIds) {
+          queryClient.invalidateQueries({ queryKey: ["tasks", projectId] });
+        }
+        if (projectIds.length > 0) {
+          queryClient.invalidateQueries({
+            queryKey: ["projects", "task", entityId],
+          });
+        }
+      }
+      if (entityType === "projects") {
+        queryClient.invalidateQueries({ queryKey: ["projects", "task"] });
+        if (!entity) {
+          queryClient.invalidateQueries({ queryKey: ["tasks", entityId] });
+        }
       }
-    });
+    }
+
+    function handleMessage(data: ServerUpdateEvent | ServerReply) {
+      if ("type" in data) {
+        if (data.type === "resync_required") {
+          lastSeq.current = data.seq;
+          queryClient.invalidateQueries();
+        }
+        return;
+      }
+      lastSeq.current = data.seq;
+      // Comments, views and the like aren't cached here
+      if (data.entity_type === "Project" || data.entity_type === "Task") {
+        handleUpdate(data);
+      }
+    }
+
+    // Reconnect with the topics subscribed to and the last update received,
+    // so the server sends whatever was missed in between
+    let closed = false;
+    let retry: ReturnType<typeof setTimeout> | undefined;
+    function connect() {
+      const params = new URLSearchParams();
+      if (lastSeq.current !== null) {
+        params.set("since", String(lastSeq.current));
+      }
+      for (const { topic } of subscribers.current.values()) {
+        params.append("topic", topicKey(topic));
+      }
+      const webSocket = new WebSocket(`${WS_BASE_URI}/subscribe?${params}`);
+      webSocket.addEventListener("message", (event) => {
+        handleMessage(JSON.parse(event.data));
+      });
+      webSocket.addEventListener("close", () => {
+        if (!closed) retry = setTimeout(connect, RECONNECT_DELAY_MS);
+      });
+      socket.current = webSocket;
+    }
+    connect();
 
     return () => {
-      webSocket.close();
+      closed = true;
+      clearTimeout(retry);
+      socket.current?.close();
     };
-  }, [webSocket]);
+  }, [queryClient]);
+
+  return cache;
 }
@@ -14,6 +14,7 @@ import Button from "@/app/components/button";
 import ConfirmationModal from "@/app/components/confirmation-modal";
 import { Title } from "@/app/components/field";
 import { usePageTitle } from "./hooks";
+import { useSubscriptions } from "./components/cache-invalidator";
 
 export default function Home() {
   usePageTitle("All Projects");
@@ -22,6 +23,8 @@ export default function Home() {
     queryKey: ["projects"],
     queryFn: fetchProjects,
   });
+  const workspaceIds = [...new Set(projects?.map((p) => p.workspaceId))];
+  useSubscriptions(workspaceIds.map((id) => ({ type: "workspace", id })));
 
   const submitProject = useMutation({
     mutationFn: createProject,
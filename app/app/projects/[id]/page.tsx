@@ -11,6 +11,7 @@ import {
 } from "@/app/api";
 import { Title, Description } from "@/app/components/field";
 import { usePageTitle } from "@/app/hooks";
+import { useSubscriptions } from "@/app/components/cache-invalidator";
 import Button from "@/app/components/button";
 import TaskDetailModal from "./task-detail-modal";
 import Task from "./task";
@@ -20,6 +21,7 @@ export default function Project() {
   const searchParams = useSearchParams();
   const selectedTaskId = searchParams.get("task_id");
   const router = useRouter();
+  useSubscriptions([{ type: "project", id }]);
 
   const { data: project } = useQuery({
     queryKey: ["projects", id],
@@ -16,6 +16,7 @@ import {
   TaskStatusUser,
 } from "@/app/api";
 import Status from "@/app/components/status";
+import { useSubscriptions } from "@/app/components/cache-invalidator";
 import Button from "@/app/components/button";
 import TaskDeleteHandler from "./delete-task";
 import { useState } from "react";
@@ -36,6 +37,7 @@ export default function TaskDetailModal({
   projectId: string;
 }) {
   const router = useRouter();
+  useSubscriptions([{ type: "task", id: taskId }]);
   const { data: task } = useQuery({
     queryKey: ["tasks", taskId],
     queryFn: () => fetchTask(taskId),
@@ -3,13 +3,20 @@
 import React from "react";
 
 import { QueryClient, QueryClientProvider } from "@tanstack/react-query";
-import { useCacheInvalidator } from "@/app/components/cache-invalidator";
+import {
+  CacheContext,
+  useCacheInvalidator,
+} from "@/app/components/cache-invalidator";
 
 export default function ReactQueryProvider({
   children,
 }: React.PropsWithChildren) {
   const [client] = React.useState(() => new QueryClient());
-  useCacheInvalidator(client);
+  const cache = useCacheInvalidator(client);
 
-  return <QueryClientProvider client={client}>{children}</QueryClientProvider>;
+  return (
+    <QueryClientProvider client={client}>
+      <CacheContext.Provider value={cache}>{children}</CacheContext.Provider>
+    </QueryClientProvider>
+  );
 }
@@ -2,6 +2,26 @@
 
 pub mod prelude;
 
+pub mod auth_token;
+pub mod change_log;
+pub mod comment;
+pub mod description_doc;
+pub mod event_log;
+pub mod job_checkpoint;
+pub mod notification;
 pub mod project;
+pub mod project_member;
+pub mod saved_view;
+pub mod sync_change;
+pub mod sync_mutation;
 pub mod task;
+pub mod task_dependency;
 pub mod task_project;
+pub mod user;
+pub mod webhook;
+pub mod webhook_attempt;
+pub mod webhook_delivery;
+pub mod workflow_status;
+pub mod workflow_transition;
+pub mod workspace;
+pub mod workspace_member;
@@ -1,5 +1,25 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 2.0.0-rc.10
 
+pub use super::auth_token::Entity as AuthToken;
+pub use super::change_log::Entity as ChangeLog;
+pub use super::comment::Entity as Comment;
+pub use super::description_doc::Entity as DescriptionDoc;
+pub use super::event_log::Entity as EventLog;
+pub use super::job_checkpoint::Entity as JobCheckpoint;
+pub use super::notification::Entity as Notification;
 pub use super::project::Entity as Project;
+pub use super::project_member::Entity as ProjectMember;
+pub use super::saved_view::Entity as SavedView;
+pub use super::sync_change::Entity as SyncChange;
+pub use super::sync_mutation::Entity as SyncMutation;
 pub use super::task::Entity as Task;
+pub use super::task_dependency::Entity as TaskDependency;
 pub use super::task_project::Entity as TaskProject;
+pub use super::user::Entity as User;
+pub use super::webhook::Entity as Webhook;
+pub use super::webhook_attempt::Entity as WebhookAttempt;
+pub use super::webhook_delivery::Entity as WebhookDelivery;
+pub use super::workflow_status::Entity as WorkflowStatus;
+pub use super::workflow_transition::Entity as WorkflowTransition;
+pub use super::workspace::Entity as Workspace;
+pub use super::workspace_member::Entity as WorkspaceMember;
@@ -11,12 +11,37 @@ pub struct Model {
     pub title: String,
     pub description: Option<String>,
     pub created_at: DateTime,
+    pub workspace_id: Uuid,
+    pub block_completion: bool,
+    pub version: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::project_member::Entity")]
+    ProjectMember,
     #[sea_orm(has_many = "super::task_project::Entity")]
     TaskProject,
+    #[sea_orm(has_many = "super::webhook::Entity")]
+    Webhook,
+    #[sea_orm(has_many = "super::workflow_status::Entity")]
+    WorkflowStatus,
+    #[sea_orm(has_many = "super::workflow_transition::Entity")]
+    WorkflowTransition,
+    #[sea_orm(
+        belongs_to = "super::workspace::Entity",
+        from = "Column::WorkspaceId",
+        to = "super::workspace::Column::Id",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    Workspace,
+}
+
+impl Related<super::project_member::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ProjectMember.def()
+    }
 }
 
 impl Related<super::task_project::Entity> for Entity {
@@ -25,6 +50,30 @@ impl Related<super::task_project::Entity> for Entity {
     }
 }
 
+impl Related<super::webhook::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Webhook.def()
+    }
+}
+
+impl Related<super::workflow_status::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::WorkflowStatus.def()
+    }
+}
+
+impl Related<super::workflow_transition::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::WorkflowTransition.def()
+    }
+}
+
+impl Related<super::workspace::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Workspace.def()
+    }
+}
+
 impl Related<super::task::Entity> for Entity {
     fn to() -> RelationDef {
         super::task_project::Relation::Task.def()
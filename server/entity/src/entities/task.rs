@@ -10,17 +10,49 @@ pub struct Model {
     pub id: Uuid,
     pub title: String,
     pub status: String,
+    pub status_category: String,
     pub description: Option<String>,
     pub created_at: DateTime,
     pub due_date: Option<Date>,
+    pub due_time: Option<Time>,
+    pub time_zone: Option<String>,
+    pub due_at: Option<DateTime>,
+    pub parent_id: Option<Uuid>,
+    pub recurrence: Option<Json>,
+    pub previous_occurrence_id: Option<Uuid>,
+    pub version: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::comment::Entity")]
+    Comment,
+    #[sea_orm(has_one = "super::description_doc::Entity")]
+    DescriptionDoc,
+    #[sea_orm(has_many = "super::notification::Entity")]
+    Notification,
     #[sea_orm(has_many = "super::task_project::Entity")]
     TaskProject,
 }
 
+impl Related<super::comment::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Comment.def()
+    }
+}
+
+impl Related<super::description_doc::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DescriptionDoc.def()
+    }
+}
+
+impl Related<super::notification::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Notification.def()
+    }
+}
+
 impl Related<super::task_project::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::TaskProject.def()
@@ -11,6 +11,7 @@ pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub task_id: Uuid,
     pub created_at: DateTime,
+    pub position: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
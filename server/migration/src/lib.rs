@@ -3,6 +3,25 @@ pub use sea_orm_migration::prelude::*;
 mod m20220101_000001_create_table;
 mod m20251018_143924_create_tasks;
 mod m20251021_171134_task_due_date_date;
+mod m20251103_120000_create_users;
+mod m20251105_090000_create_workspaces;
+mod m20251106_100000_create_project_members;
+mod m20251108_140000_create_change_log;
+mod m20251109_100000_create_comments;
+mod m20251110_090000_create_task_dependencies;
+mod m20251111_090000_add_task_parent;
+mod m20251112_090000_add_task_project_position;
+mod m20251113_090000_create_search_index;
+mod m20251114_090000_create_saved_views;
+mod m20251115_090000_create_workflows;
+mod m20251116_090000_add_task_recurrence;
+mod m20251117_090000_add_task_due_time;
+mod m20251118_090000_create_notifications;
+mod m20251119_090000_create_webhooks;
+mod m20251120_090000_create_event_log;
+mod m20251121_090000_create_sync_changes;
+mod m20251122_090000_create_description_docs;
+mod m20251123_090000_add_versions;
 
 pub struct Migrator;
 
@@ -13,6 +32,25 @@ impl MigratorTrait for Migrator {
             Box::new(m20220101_000001_create_table::Migration),
             Box::new(m20251018_143924_create_tasks::Migration),
             Box::new(m20251021_171134_task_due_date_date::Migration),
+            Box::new(m20251103_120000_create_users::Migration),
+            Box::new(m20251105_090000_create_workspaces::Migration),
+            Box::new(m20251106_100000_create_project_members::Migration),
+            Box::new(m20251108_140000_create_change_log::Migration),
+            Box::new(m20251109_100000_create_comments::Migration),
+            Box::new(m20251110_090000_create_task_dependencies::Migration),
+            Box::new(m20251111_090000_add_task_parent::Migration),
+            Box::new(m20251112_090000_add_task_project_position::Migration),
+            Box::new(m20251113_090000_create_search_index::Migration),
+            Box::new(m20251114_090000_create_saved_views::Migration),
+            Box::new(m20251115_090000_create_workflows::Migration),
+            Box::new(m20251116_090000_add_task_recurrence::Migration),
+            Box::new(m20251117_090000_add_task_due_time::Migration),
+            Box::new(m20251118_090000_create_notifications::Migration),
+            Box::new(m20251119_090000_create_webhooks::Migration),
+            Box::new(m20251120_090000_create_event_log::Migration),
+            Box::new(m20251121_090000_create_sync_changes::Migration),
+            Box::new(m20251122_090000_create_description_docs::Migration),
+            Box::new(m20251123_090000_add_versions::Migration),
         ]
     }
 }
@@ -37,4 +37,5 @@ pub enum Project {
     Title,
     Description,
     CreatedAt,
+    WorkspaceId,
 }
@@ -0,0 +1,149 @@
+use crate::m20220101_000001_create_table::Project;
+use crate::m20251103_120000_create_users::User;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Workspace::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Workspace::Id))
+                    .col(string(Workspace::Name))
+                    .col(
+                        date_time(Workspace::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkspaceMember::Table)
+                    .if_not_exists()
+                    .primary_key(
+                        Index::create()
+                            .col(WorkspaceMember::WorkspaceId)
+                            .col(WorkspaceMember::UserId),
+                    )
+                    .col(uuid(WorkspaceMember::WorkspaceId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_workspace_member_workspace")
+                            .from(WorkspaceMember::Table, WorkspaceMember::WorkspaceId)
+                            .to(Workspace::Table, Workspace::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(uuid(WorkspaceMember::UserId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_workspace_member_user")
+                            .from(WorkspaceMember::Table, WorkspaceMember::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        date_time(WorkspaceMember::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    // owner or member. Owners may remove other members.
+                    .col(string(WorkspaceMember::Role).default("member"))
+                    .to_owned(),
+            )
+            .await?;
+
+        // SQLite can't add a NOT NULL foreign key column to an existing table,
+        // so this is a plain column backfilled below.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Project::Table)
+                    .add_column(
+                        uuid(Project::WorkspaceId)
+                            .default(Expr::cust("X'00000000000000000000000000000000'")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Existing projects and users all move into one shared workspace, which
+        // they all own as nobody created it
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"INSERT INTO workspace (id, name)
+               SELECT randomblob(16), 'Default'
+               WHERE EXISTS (SELECT 1 FROM project) OR EXISTS (SELECT 1 FROM user)"#,
+        )
+        .await?;
+        db.execute_unprepared(r#"UPDATE project SET workspace_id = (SELECT id FROM workspace)"#)
+            .await?;
+        db.execute_unprepared(
+            r#"INSERT INTO workspace_member (workspace_id, user_id, role)
+               SELECT w.id, u.id, 'owner' FROM workspace w, user u"#,
+        )
+        .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_project_workspace_id")
+                    .table(Project::Table)
+                    .col(Project::WorkspaceId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_project_workspace_id")
+                    .table(Project::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Project::Table)
+                    .drop_column(Project::WorkspaceId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(WorkspaceMember::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Workspace::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Workspace {
+    Table,
+    Id,
+    Name,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum WorkspaceMember {
+    Table,
+    WorkspaceId,
+    UserId,
+    CreatedAt,
+    Role,
+}
@@ -0,0 +1,77 @@
+use crate::m20220101_000001_create_table::Project;
+use crate::m20251103_120000_create_users::User;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ProjectMember::Table)
+                    .if_not_exists()
+                    .primary_key(
+                        Index::create()
+                            .col(ProjectMember::ProjectId)
+                            .col(ProjectMember::UserId),
+                    )
+                    .col(uuid(ProjectMember::ProjectId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_project_member_project")
+                            .from(ProjectMember::Table, ProjectMember::ProjectId)
+                            .to(Project::Table, Project::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(uuid(ProjectMember::UserId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_project_member_user")
+                            .from(ProjectMember::Table, ProjectMember::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(string(ProjectMember::Role))
+                    .col(
+                        date_time(ProjectMember::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Everyone could do anything before roles existed, so keep it that way
+        // for existing projects
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"INSERT INTO project_member (project_id, user_id, role)
+                   SELECT p.id, wm.user_id, 'owner'
+                   FROM project p
+                   JOIN workspace_member wm ON wm.workspace_id = p.workspace_id"#,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ProjectMember::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ProjectMember {
+    Table,
+    ProjectId,
+    UserId,
+    Role,
+    CreatedAt,
+}
@@ -0,0 +1,75 @@
+use crate::m20251103_120000_create_users::User;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ChangeLog::Table)
+                    .if_not_exists()
+                    .col(pk_auto(ChangeLog::Id))
+                    // Kept when the user is deleted, the log is append-only
+                    .col(uuid_null(ChangeLog::ActorId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_change_log_actor")
+                            .from(ChangeLog::Table, ChangeLog::ActorId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(string(ChangeLog::EntityType))
+                    .col(uuid(ChangeLog::EntityId))
+                    // Workspace the entity was in, so its history stays
+                    // readable to the workspace's members once it's deleted
+                    .col(uuid_null(ChangeLog::WorkspaceId))
+                    .col(string(ChangeLog::Action))
+                    .col(string_null(ChangeLog::Field))
+                    .col(json_null(ChangeLog::OldValue))
+                    .col(json_null(ChangeLog::NewValue))
+                    .col(
+                        date_time(ChangeLog::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_change_log_entity")
+                    .table(ChangeLog::Table)
+                    .col(ChangeLog::EntityType)
+                    .col(ChangeLog::EntityId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ChangeLog::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ChangeLog {
+    Table,
+    Id,
+    ActorId,
+    EntityType,
+    EntityId,
+    WorkspaceId,
+    Action,
+    Field,
+    OldValue,
+    NewValue,
+    CreatedAt,
+}
@@ -0,0 +1,83 @@
+use crate::m20251018_143924_create_tasks::Task;
+use crate::m20251103_120000_create_users::User;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Comment::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Comment::Id))
+                    .col(uuid(Comment::TaskId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_comment_task")
+                            .from(Comment::Table, Comment::TaskId)
+                            .to(Task::Table, Task::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(uuid(Comment::AuthorId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_comment_author")
+                            .from(Comment::Table, Comment::AuthorId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    // Replies are removed along with the comment they reply to
+                    .col(uuid_null(Comment::ParentId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_comment_parent")
+                            .from(Comment::Table, Comment::ParentId)
+                            .to(Comment::Table, Comment::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(text(Comment::Body))
+                    .col(
+                        date_time(Comment::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .col(date_time_null(Comment::EditedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_comment_task")
+                    .table(Comment::Table)
+                    .col(Comment::TaskId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Comment::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Comment {
+    Table,
+    Id,
+    TaskId,
+    AuthorId,
+    ParentId,
+    Body,
+    CreatedAt,
+    EditedAt,
+}
@@ -0,0 +1,94 @@
+use crate::m20251018_143924_create_tasks::Task;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TaskDependency::Table)
+                    .if_not_exists()
+                    .primary_key(
+                        Index::create()
+                            .col(TaskDependency::TaskId)
+                            .col(TaskDependency::BlockerId),
+                    )
+                    .col(uuid(TaskDependency::TaskId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_task_dependency_task")
+                            .from(TaskDependency::Table, TaskDependency::TaskId)
+                            .to(Task::Table, Task::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(uuid(TaskDependency::BlockerId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_task_dependency_blocker")
+                            .from(TaskDependency::Table, TaskDependency::BlockerId)
+                            .to(Task::Table, Task::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        date_time(TaskDependency::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_task_dependency_blocker_id")
+                    .table(TaskDependency::Table)
+                    .col(TaskDependency::BlockerId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Project::Table)
+                    .add_column(boolean(Project::BlockCompletion).default(false))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Project::Table)
+                    .drop_column(Project::BlockCompletion)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(TaskDependency::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TaskDependency {
+    Table,
+    TaskId,
+    BlockerId,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Project {
+    Table,
+    // Refuse to complete tasks in the project while they have open blockers
+    BlockCompletion,
+}
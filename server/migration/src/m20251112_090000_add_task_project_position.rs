@@ -0,0 +1,72 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TaskProject::Table)
+                    .add_column(string(TaskProject::Position).default("V"))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Keep the existing order, by task creation time. Keys are "V", the
+        // task's rank, then "1" so they never end in a zero digit.
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"UPDATE task_project SET position = (
+                 SELECT 'V' || printf('%06d', COUNT(*)) || '1'
+                 FROM task_project tp
+                 JOIN task t ON t.id = tp.task_id
+                 JOIN task this ON this.id = task_project.task_id
+                 WHERE tp.project_id = task_project.project_id
+                   AND (t.created_at < this.created_at
+                        OR (t.created_at = this.created_at AND t.id <= this.id))
+               )"#,
+        )
+        .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_task_project_position")
+                    .table(TaskProject::Table)
+                    .col(TaskProject::ProjectId)
+                    .col(TaskProject::Position)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_task_project_position")
+                    .table(TaskProject::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TaskProject::Table)
+                    .drop_column(TaskProject::Position)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TaskProject {
+    Table,
+    ProjectId,
+    // Fractional index ordering the task within the project
+    Position,
+}
@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Full text index over task and project titles and descriptions, kept up to
+// date by triggers. Rows are keyed by entity type and id rather than rowid, as
+// VACUUM may renumber the rowids of tables without an integer primary key.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"CREATE VIRTUAL TABLE search_index USING fts5(
+                 entity_type UNINDEXED,
+                 entity_id UNINDEXED,
+                 title,
+                 description,
+                 tokenize = 'unicode61 remove_diacritics 2'
+               )"#,
+        )
+        .await?;
+
+        for table in ["task", "project"] {
+            db.execute_unprepared(&format!(
+                r#"CREATE TRIGGER {table}_search_insert AFTER INSERT ON {table} BEGIN
+                     INSERT INTO search_index (entity_type, entity_id, title, description)
+                     VALUES ('{table}', new.id, new.title, new.description);
+                   END;
+
+                   CREATE TRIGGER {table}_search_update AFTER UPDATE OF title, description ON {table} BEGIN
+                     UPDATE search_index SET title = new.title, description = new.description
+                     WHERE entity_type = '{table}' AND entity_id = old.id;
+                   END;
+
+                   CREATE TRIGGER {table}_search_delete AFTER DELETE ON {table} BEGIN
+                     DELETE FROM search_index WHERE entity_type = '{table}' AND entity_id = old.id;
+                   END;
+
+                   INSERT INTO search_index (entity_type, entity_id, title, description)
+                   SELECT '{table}', id, title, description FROM {table};"#
+            ))
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for table in ["task", "project"] {
+            db.execute_unprepared(&format!(
+                r#"DROP TRIGGER IF EXISTS {table}_search_insert;
+                   DROP TRIGGER IF EXISTS {table}_search_update;
+                   DROP TRIGGER IF EXISTS {table}_search_delete;"#
+            ))
+            .await?;
+        }
+        db.execute_unprepared("DROP TABLE IF EXISTS search_index")
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use crate::m20251103_120000_create_users::User;
+use crate::m20251105_090000_create_workspaces::Workspace;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SavedView::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(SavedView::Id))
+                    .col(uuid(SavedView::WorkspaceId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_saved_view_workspace")
+                            .from(SavedView::Table, SavedView::WorkspaceId)
+                            .to(Workspace::Table, Workspace::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    // Views are shared with the workspace, so outlive their creator
+                    .col(uuid_null(SavedView::CreatorId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_saved_view_creator")
+                            .from(SavedView::Table, SavedView::CreatorId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(string(SavedView::Name))
+                    .col(json(SavedView::Definition))
+                    .col(
+                        date_time(SavedView::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_saved_view_workspace")
+                    .table(SavedView::Table)
+                    .col(SavedView::WorkspaceId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SavedView::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SavedView {
+    Table,
+    Id,
+    WorkspaceId,
+    CreatorId,
+    Name,
+    Definition,
+    CreatedAt,
+}
@@ -0,0 +1,151 @@
+use crate::m20220101_000001_create_table::Project;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkflowStatus::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(WorkflowStatus::Id))
+                    .col(uuid(WorkflowStatus::ProjectId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_workflow_status_project")
+                            .from(WorkflowStatus::Table, WorkflowStatus::ProjectId)
+                            .to(Project::Table, Project::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    // Stored in task.status, so stays fixed once created
+                    .col(string(WorkflowStatus::Key))
+                    .col(string(WorkflowStatus::Name))
+                    .col(integer(WorkflowStatus::Position))
+                    .col(string(WorkflowStatus::Category))
+                    .col(string(WorkflowStatus::Color))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_workflow_status_project_key")
+                    .table(WorkflowStatus::Table)
+                    .col(WorkflowStatus::ProjectId)
+                    .col(WorkflowStatus::Key)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(WorkflowTransition::Table)
+                    .if_not_exists()
+                    .col(uuid(WorkflowTransition::ProjectId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_workflow_transition_project")
+                            .from(WorkflowTransition::Table, WorkflowTransition::ProjectId)
+                            .to(Project::Table, Project::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(string(WorkflowTransition::FromStatus))
+                    .col(string(WorkflowTransition::ToStatus))
+                    .primary_key(
+                        Index::create()
+                            .col(WorkflowTransition::ProjectId)
+                            .col(WorkflowTransition::FromStatus)
+                            .col(WorkflowTransition::ToStatus),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .add_column(string(Task::StatusCategory).default("not_started"))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Every existing project gets the statuses tasks could have until now
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            r#"INSERT INTO workflow_status (id, project_id, key, name, position, category, color)
+               SELECT randomblob(16), p.id, s.key, s.name, s.position, s.category, s.color
+               FROM project p
+               CROSS JOIN (
+                 SELECT 'todo' AS key, 'Todo' AS name, 0 AS position,
+                        'not_started' AS category, '#9ca3af' AS color
+                 UNION ALL SELECT 'in_progress', 'In Progress', 1, 'active', '#3b82f6'
+                 UNION ALL SELECT 'complete', 'Complete', 2, 'done', '#22c55e'
+               ) s"#,
+        )
+        .await?;
+        db.execute_unprepared(
+            r#"UPDATE task SET status_category = CASE status
+                 WHEN 'in_progress' THEN 'active'
+                 WHEN 'complete' THEN 'done'
+                 ELSE 'not_started'
+               END"#,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Task::Table)
+                    .drop_column(Task::StatusCategory)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(WorkflowTransition::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(WorkflowStatus::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WorkflowStatus {
+    Table,
+    Id,
+    ProjectId,
+    Key,
+    Name,
+    Position,
+    Category,
+    Color,
+}
+
+#[derive(DeriveIden)]
+enum WorkflowTransition {
+    Table,
+    ProjectId,
+    FromStatus,
+    ToStatus,
+}
+
+#[derive(DeriveIden)]
+enum Task {
+    Table,
+    // Category of the task's status, kept alongside it for filtering
+    StatusCategory,
+}
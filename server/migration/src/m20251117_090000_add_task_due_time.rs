@@ -0,0 +1,95 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite only adds one column per statement
+        for col in [
+            time_null(Task::DueTime),
+            string_null(Task::TimeZone),
+            date_time_null(Task::DueAt),
+        ] {
+            manager
+                .alter_table(Table::alter().table(Task::Table).add_column(col).to_owned())
+                .await?;
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(string_null(User::TimeZone))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_task_due_at")
+                    .table(Task::Table)
+                    .col(Task::DueAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Existing due dates are in UTC, due by the end of the day
+        let db = manager.get_connection();
+        db.execute_unprepared(
+            "UPDATE task SET due_at = datetime(due_date, '+1 day') WHERE due_date IS NOT NULL",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_task_due_at")
+                    .table(Task::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::TimeZone)
+                    .to_owned(),
+            )
+            .await?;
+        for col in [Task::DueAt, Task::TimeZone, Task::DueTime] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Task::Table)
+                        .drop_column(col)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Task {
+    Table,
+    // Local time of day the task is due, on its due date
+    DueTime,
+    // IANA name of the time zone the due date and time are in. UTC if unset.
+    TimeZone,
+    // The instant the task is due, in UTC
+    DueAt,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    // Time zone given to the tasks the user sets deadlines on
+    TimeZone,
+}
@@ -0,0 +1,135 @@
+use crate::m20251018_143924_create_tasks::Task;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Notification::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Notification::Id))
+                    .col(uuid(Notification::UserId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_notification_user")
+                            .from(Notification::Table, Notification::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(uuid(Notification::TaskId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_notification_task")
+                            .from(Notification::Table, Notification::TaskId)
+                            .to(Task::Table, Task::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(string(Notification::Reminder))
+                    .col(date_time(Notification::DueAt))
+                    .col(date_time(Notification::RemindAt))
+                    .col(
+                        date_time(Notification::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .col(date_time_null(Notification::ReadAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        // A reminder is sent once per deadline, however often the job runs
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_notification_reminder")
+                    .table(Notification::Table)
+                    .col(Notification::UserId)
+                    .col(Notification::TaskId)
+                    .col(Notification::Reminder)
+                    .col(Notification::DueAt)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(JobCheckpoint::Table)
+                    .if_not_exists()
+                    .col(string(JobCheckpoint::Job).primary_key())
+                    .col(date_time(JobCheckpoint::Checkpoint))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(json_null(User::ReminderOffsets))
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::ReminderOffsets)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(JobCheckpoint::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Notification::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Notification {
+    Table,
+    Id,
+    // Recipient
+    UserId,
+    TaskId,
+    // Which of the user's reminder offsets this was sent for
+    Reminder,
+    // Deadline the reminder is for. Moving the deadline brings new reminders.
+    DueAt,
+    // When the reminder was due to go out
+    RemindAt,
+    CreatedAt,
+    ReadAt,
+}
+
+// Progress of background jobs, so they resume where they left off on restart
+#[derive(DeriveIden)]
+enum JobCheckpoint {
+    Table,
+    Job,
+    // Everything up to this instant has been processed
+    Checkpoint,
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Id,
+    // How long before their deadlines the user is reminded of tasks.
+    // Defaults apply if unset.
+    ReminderOffsets,
+}
@@ -0,0 +1,203 @@
+use crate::m20220101_000001_create_table::Project;
+use crate::m20251103_120000_create_users::User;
+use crate::m20251105_090000_create_workspaces::Workspace;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Webhook::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Webhook::Id))
+                    .col(uuid(Webhook::WorkspaceId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_webhook_workspace")
+                            .from(Webhook::Table, Webhook::WorkspaceId)
+                            .to(Workspace::Table, Workspace::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(uuid_null(Webhook::ProjectId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_webhook_project")
+                            .from(Webhook::Table, Webhook::ProjectId)
+                            .to(Project::Table, Project::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(uuid_null(Webhook::CreatorId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_webhook_creator")
+                            .from(Webhook::Table, Webhook::CreatorId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(string(Webhook::Url))
+                    .col(string(Webhook::Secret))
+                    .col(json(Webhook::EntityTypes))
+                    .col(json(Webhook::Kinds))
+                    .col(
+                        date_time(Webhook::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webhook_workspace")
+                    .table(Webhook::Table)
+                    .col(Webhook::WorkspaceId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(WebhookDelivery::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(WebhookDelivery::Id))
+                    .col(uuid(WebhookDelivery::WebhookId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_webhook_delivery_webhook")
+                            .from(WebhookDelivery::Table, WebhookDelivery::WebhookId)
+                            .to(Webhook::Table, Webhook::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(string(WebhookDelivery::Event))
+                    .col(json(WebhookDelivery::Payload))
+                    .col(string(WebhookDelivery::State))
+                    .col(integer(WebhookDelivery::AttemptCount).default(0))
+                    .col(date_time_null(WebhookDelivery::NextAttemptAt))
+                    .col(
+                        date_time(WebhookDelivery::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // For finding deliveries to (re)try
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webhook_delivery_next_attempt")
+                    .table(WebhookDelivery::Table)
+                    .col(WebhookDelivery::NextAttemptAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webhook_delivery_webhook")
+                    .table(WebhookDelivery::Table)
+                    .col(WebhookDelivery::WebhookId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(WebhookAttempt::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(WebhookAttempt::Id))
+                    .col(uuid(WebhookAttempt::DeliveryId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_webhook_attempt_delivery")
+                            .from(WebhookAttempt::Table, WebhookAttempt::DeliveryId)
+                            .to(WebhookDelivery::Table, WebhookDelivery::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(date_time(WebhookAttempt::AttemptedAt))
+                    .col(integer_null(WebhookAttempt::StatusCode))
+                    .col(string_null(WebhookAttempt::Error))
+                    .col(integer(WebhookAttempt::DurationMs))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webhook_attempt_delivery")
+                    .table(WebhookAttempt::Table)
+                    .col(WebhookAttempt::DeliveryId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WebhookAttempt::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(WebhookDelivery::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Webhook::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Webhook {
+    Table,
+    Id,
+    WorkspaceId,
+    // Limits the webhook to events about the project and its tasks
+    ProjectId,
+    CreatorId,
+    Url,
+    // Key for signing payloads
+    Secret,
+    // Events the webhook is sent, empty for all
+    EntityTypes,
+    Kinds,
+    CreatedAt,
+}
+
+// An event to be sent to a webhook
+#[derive(DeriveIden)]
+enum WebhookDelivery {
+    Table,
+    Id,
+    WebhookId,
+    // Like task.update
+    Event,
+    Payload,
+    // pending, succeeded or failed
+    State,
+    AttemptCount,
+    // Unset once the delivery succeeds or is given up on
+    NextAttemptAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum WebhookAttempt {
+    Table,
+    Id,
+    DeliveryId,
+    AttemptedAt,
+    // Response status, unset if there was no response
+    StatusCode,
+    Error,
+    DurationMs,
+}
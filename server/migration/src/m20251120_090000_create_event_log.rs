@@ -0,0 +1,44 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EventLog::Table)
+                    .if_not_exists()
+                    .col(big_integer(EventLog::Seq).primary_key())
+                    .col(uuid(EventLog::WorkspaceId))
+                    .col(json(EventLog::Event))
+                    .col(
+                        date_time(EventLog::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EventLog::Table).to_owned())
+            .await
+    }
+}
+
+// The most recent update events, so clients can catch up on what they missed.
+// Rows outlive the workspaces they're about, and are pruned by age instead.
+#[derive(DeriveIden)]
+enum EventLog {
+    Table,
+    // Position in the update feed, increasing by one with each event
+    Seq,
+    WorkspaceId,
+    // The event as sent to clients
+    Event,
+    CreatedAt,
+}
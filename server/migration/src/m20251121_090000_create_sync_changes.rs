@@ -0,0 +1,199 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Journal of changes to tasks, projects and their memberships, kept by triggers
+// so no write can be missed. Offline clients pull the changes after the last id
+// they saw.
+//
+// Rows are written for every workspace the changed row is in at the time:
+// - tasks through their projects, so a task only starts being journaled once
+//   it joins one, which is journaled as a membership
+// - a task's deletion before it happens, while its memberships still exist
+// - a project's memberships before the project is deleted, since the cascade
+//   runs once the project is gone
+//
+// Pulls tell deletions from what no longer exists when they run, since a
+// deleted membership may be back by then.
+const TRIGGERS: &str = r#"
+CREATE TRIGGER project_sync_insert AFTER INSERT ON project BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id)
+  VALUES (new.workspace_id, 'project', new.id);
+END;
+
+CREATE TRIGGER project_sync_update AFTER UPDATE ON project BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id)
+  VALUES (new.workspace_id, 'project', new.id);
+END;
+
+CREATE TRIGGER project_sync_delete BEFORE DELETE ON project BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id, project_id)
+  SELECT old.workspace_id, 'task_project', tp.task_id, old.id
+  FROM task_project tp WHERE tp.project_id = old.id;
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id)
+  VALUES (old.workspace_id, 'project', old.id);
+END;
+
+CREATE TRIGGER task_sync_update AFTER UPDATE ON task BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id)
+  SELECT DISTINCT p.workspace_id, 'task', new.id
+  FROM task_project tp JOIN project p ON p.id = tp.project_id
+  WHERE tp.task_id = new.id;
+END;
+
+CREATE TRIGGER task_sync_delete BEFORE DELETE ON task BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id)
+  SELECT DISTINCT p.workspace_id, 'task', old.id
+  FROM task_project tp JOIN project p ON p.id = tp.project_id
+  WHERE tp.task_id = old.id;
+END;
+
+CREATE TRIGGER task_project_sync_insert AFTER INSERT ON task_project BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id, project_id)
+  SELECT workspace_id, 'task_project', new.task_id, new.project_id
+  FROM project WHERE id = new.project_id;
+END;
+
+CREATE TRIGGER task_project_sync_update AFTER UPDATE ON task_project BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id, project_id)
+  SELECT workspace_id, 'task_project', new.task_id, new.project_id
+  FROM project WHERE id = new.project_id;
+END;
+
+CREATE TRIGGER task_project_sync_delete AFTER DELETE ON task_project BEGIN
+  INSERT INTO sync_change (workspace_id, entity_type, entity_id, project_id)
+  SELECT workspace_id, 'task_project', old.task_id, old.project_id
+  FROM project WHERE id = old.project_id;
+END;
+"#;
+
+const TRIGGER_NAMES: [&str; 8] = [
+    "project_sync_insert",
+    "project_sync_update",
+    "project_sync_delete",
+    "task_sync_update",
+    "task_sync_delete",
+    "task_project_sync_insert",
+    "task_project_sync_update",
+    "task_project_sync_delete",
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SyncChange::Table)
+                    .if_not_exists()
+                    .col(pk_auto(SyncChange::Id))
+                    .col(uuid(SyncChange::WorkspaceId))
+                    .col(string(SyncChange::EntityType))
+                    .col(uuid(SyncChange::EntityId))
+                    .col(uuid_null(SyncChange::ProjectId))
+                    .col(uuid_null(SyncChange::ClientId))
+                    .col(
+                        date_time(SyncChange::CreatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_sync_change_workspace")
+                    .table(SyncChange::Table)
+                    .col(SyncChange::WorkspaceId)
+                    .col(SyncChange::Id)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_sync_change_entity")
+                    .table(SyncChange::Table)
+                    .col(SyncChange::EntityId)
+                    .to_owned(),
+            )
+            .await?;
+        // Pruned by age, oldest first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_sync_change_created_at")
+                    .table(SyncChange::Table)
+                    .col(SyncChange::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(SyncMutation::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(SyncMutation::Id))
+                    .col(uuid(SyncMutation::ClientId))
+                    .col(uuid(SyncMutation::WorkspaceId))
+                    .col(date_time(SyncMutation::MadeAt))
+                    .col(
+                        date_time(SyncMutation::AppliedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        db.execute_unprepared(TRIGGERS).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for name in TRIGGER_NAMES {
+            db.execute_unprepared(&format!("DROP TRIGGER IF EXISTS {name}"))
+                .await?;
+        }
+        manager
+            .drop_table(Table::drop().table(SyncMutation::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(SyncChange::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SyncChange {
+    Table,
+    // Cursor clients pull from, only ever increasing
+    Id,
+    WorkspaceId,
+    // task, project or task_project
+    EntityType,
+    // The task or project, or the task of a membership
+    EntityId,
+    // The project of a membership
+    ProjectId,
+    // Sync client that pushed the change, if any
+    ClientId,
+    CreatedAt,
+}
+
+// Mutations pushed by sync clients, so ones sent again after a lost response
+// aren't applied twice
+#[derive(DeriveIden)]
+enum SyncMutation {
+    Table,
+    // Picked by the client
+    Id,
+    ClientId,
+    WorkspaceId,
+    // When the client made the mutation
+    MadeAt,
+    AppliedAt,
+}
@@ -0,0 +1,50 @@
+use crate::m20251018_143924_create_tasks::Task;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DescriptionDoc::Table)
+                    .if_not_exists()
+                    .col(uuid(DescriptionDoc::TaskId).primary_key())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_description_doc_task")
+                            .from(DescriptionDoc::Table, DescriptionDoc::TaskId)
+                            .to(Task::Table, Task::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(blob(DescriptionDoc::State))
+                    .col(
+                        date_time(DescriptionDoc::UpdatedAt)
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(DescriptionDoc::Table).to_owned())
+            .await
+    }
+}
+
+// Task descriptions as collaboratively edited text documents. Only tasks whose
+// description was edited that way have one.
+#[derive(DeriveIden)]
+enum DescriptionDoc {
+    Table,
+    TaskId,
+    // The document as a single update, merged from all the ones applied
+    State,
+    UpdatedAt,
+}
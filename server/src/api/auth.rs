@@ -0,0 +1,64 @@
+use rocket::State;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sea_orm::DatabaseConnection;
+
+use crate::models::user::{self, UserModel};
+use crate::result::Error;
+
+// Request guard for the user making the request.
+// Expects an `Authorization: Bearer <token>` header. Browsers can't set headers
+// on websocket upgrades, so those may pass the token as a `token` query param.
+pub struct AuthUser {
+    pub user: UserModel,
+    pub token: String,
+}
+
+fn bearer_token<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    if let Some(header) = req.headers().get_one("Authorization") {
+        return header.strip_prefix("Bearer ").map(str::trim);
+    }
+
+    let is_websocket = req
+        .headers()
+        .get_one("Upgrade")
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    if is_websocket {
+        return req.query_value::<&str>("token").and_then(|t| t.ok());
+    }
+
+    None
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthUser {
+    type Error = Error;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(token) = bearer_token(req) else {
+            return Outcome::Error((
+                Status::Unauthorized,
+                Error::Unauthorized("Missing bearer token".to_string()),
+            ));
+        };
+        let db = match req.guard::<&State<DatabaseConnection>>().await {
+            Outcome::Success(db) => db,
+            Outcome::Error((status, _)) => {
+                return Outcome::Error((status, Error::Internal(anyhow::Error::msg("No db"))));
+            }
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        match user::user_for_token(db.inner(), token).await {
+            Ok(Some(user)) => Outcome::Success(AuthUser {
+                user,
+                token: token.to_string(),
+            }),
+            Ok(None) => Outcome::Error((
+                Status::Unauthorized,
+                Error::Unauthorized("Invalid or expired token".to_string()),
+            )),
+            Err(e) => Outcome::Error((e.status(), e)),
+        }
+    }
+}
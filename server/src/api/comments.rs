@@ -0,0 +1,348 @@
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::DatabaseConnection;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::auth::AuthUser;
+use super::helpers::parse_uuid;
+use super::subscription::{FeedWriter, UpdateEvent, UpdateKind};
+use crate::models::access::{self, TaskAccess, TaskAction};
+use crate::models::comment::{self, CommentModel};
+use crate::models::task;
+use crate::result::{Error, Result};
+
+// Get comments on the task with the given id, oldest first
+#[get("/tasks/<id>/comments")]
+async fn task_comments(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<CommentModel>>> {
+    let id = parse_uuid(id)?;
+    access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
+    let comments = comment::comments_for_task(db.inner(), &id).await?;
+    Ok(Json(comments))
+}
+
+// Get a single comment on the task
+#[get("/tasks/<id>/comments/<comment_id>")]
+async fn get_comment(
+    id: &str,
+    comment_id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<CommentModel>> {
+    let id = parse_uuid(id)?;
+    let comment_id = parse_uuid(comment_id)?;
+    access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
+    let comment = comment::find_comment(db.inner(), &id, &comment_id).await?;
+    Ok(Json(comment))
+}
+
+// Comment on a task, or reply to one of its comments
+#[derive(Deserialize)]
+struct CreateCommentPayload {
+    body: String,
+    parent_id: Option<Uuid>,
+}
+
+#[post("/tasks/<id>/comments", format = "json", data = "<comment>")]
+async fn create_comment(
+    id: &str,
+    comment: Json<CreateCommentPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<CommentModel>> {
+    let id = parse_uuid(id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Edit).await?;
+    let comment = comment.into_inner();
+    let comment = comment::create_comment(
+        db.inner(),
+        &id,
+        &auth.user.id,
+        comment.body,
+        comment.parent_id,
+    )
+    .await?;
+    let project_ids = task::project_ids(db.inner(), &id).await?;
+    UpdateEvent::comment(access.workspace_id, UpdateKind::Create, comment.id, id)
+        .in_projects(project_ids)
+        .with_entity(&comment)?
+        .send(feed.inner());
+    Ok(Json(comment))
+}
+
+// Comments can only be changed by their author, as long as they can still see
+// the task
+async fn require_author(
+    db: &DatabaseConnection,
+    auth: &AuthUser,
+    task_id: &Uuid,
+    comment_id: &Uuid,
+) -> Result<TaskAccess> {
+    let access = access::require_task(db, &auth.user.id, task_id, TaskAction::View).await?;
+    let comment = comment::find_comment(db, task_id, comment_id).await?;
+    if comment.author_id != auth.user.id {
+        return Err(Error::forbidden("Only the author can change a comment"));
+    }
+    Ok(access)
+}
+
+// Edit the body of a comment
+#[derive(Deserialize)]
+struct EditCommentPayload {
+    body: String,
+}
+
+#[patch(
+    "/tasks/<id>/comments/<comment_id>",
+    format = "json",
+    data = "<comment>"
+)]
+async fn edit_comment(
+    id: &str,
+    comment_id: &str,
+    comment: Json<EditCommentPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<CommentModel>> {
+    let id = parse_uuid(id)?;
+    let comment_id = parse_uuid(comment_id)?;
+    let access = require_author(db.inner(), &auth, &id, &comment_id).await?;
+    let comment = comment::edit_comment(db.inner(), &comment_id, comment.into_inner().body).await?;
+    let project_ids = task::project_ids(db.inner(), &id).await?;
+    UpdateEvent::comment(access.workspace_id, UpdateKind::Update, comment_id, id)
+        .in_projects(project_ids)
+        .with_entity(&comment)?
+        .with_changed_fields(["body", "edited_at"])
+        .send(feed.inner());
+    Ok(Json(comment))
+}
+
+// Delete a comment along with any replies to it
+#[delete("/tasks/<id>/comments/<comment_id>")]
+async fn delete_comment(
+    id: &str,
+    comment_id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let comment_id = parse_uuid(comment_id)?;
+    let access = require_author(db.inner(), &auth, &id, &comment_id).await?;
+    let reply_ids = comment::delete_comment(db.inner(), &comment_id).await?;
+    let project_ids = task::project_ids(db.inner(), &id).await?;
+    for comment_id in std::iter::once(comment_id).chain(reply_ids) {
+        UpdateEvent::comment(access.workspace_id, UpdateKind::Destroy, comment_id, id)
+            .in_projects(project_ids.clone())
+            .send(feed.inner());
+    }
+    Ok(())
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        task_comments,
+        get_comment,
+        create_comment,
+        edit_comment,
+        delete_comment
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::comment::{self, Comment, CommentModel};
+    use crate::models::{project, task, user};
+    use crate::result::ErrorBody;
+    use crate::test_helpers;
+    use rocket::http::{ContentType, Status};
+    use sea_orm::{EntityTrait, PaginatorTrait};
+    use serde_json;
+
+    #[rocket::async_test]
+    async fn test_comment_thread() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(&db, "Task".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .post(uri!(super::create_comment(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "body": "Who moved this back to todo?" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let question: CommentModel = serde_json::from_str(&response_str).expect("A comment");
+        assert_eq!(question.author_id, client.user.id);
+        assert_eq!(question.parent_id, None);
+
+        let response = client
+            .post(uri!(super::create_comment(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"{{ "body": "Me, sorry", "parent_id": "{}" }}"#,
+                question.id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let reply: CommentModel = serde_json::from_str(&response_str).expect("A comment");
+        assert_eq!(reply.parent_id, Some(question.id));
+
+        let response = client
+            .get(uri!(super::task_comments(task.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let comments: Vec<CommentModel> = serde_json::from_str(&response_str).expect("Comments");
+        assert_eq!(comments, vec![question.clone(), reply]);
+
+        let response = client
+            .patch(uri!(super::edit_comment(
+                task.id.to_string(),
+                question.id.to_string()
+            )))
+            .header(ContentType::JSON)
+            .body(r#"{ "body": "Who moved this?" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let edited: CommentModel = serde_json::from_str(&response_str).expect("A comment");
+        assert_eq!(edited.body, "Who moved this?");
+        assert!(edited.edited_at.is_some());
+
+        // Replies go along with the comment
+        let response = client
+            .delete(uri!(super::delete_comment(
+                task.id.to_string(),
+                question.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(uri!(super::task_comments(task.id.to_string())))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let comments: Vec<CommentModel> = serde_json::from_str(&response_str).expect("Comments");
+        assert!(comments.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn test_invalid_comments() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(&db, "Task".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let other_task =
+            task::create_task_in_project(&db, "Other".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let elsewhere =
+            comment::create_comment(&db, &other_task.id, &other.id, "Hi".to_string(), None)
+                .await
+                .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .post(uri!(super::create_comment(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "body": "   " }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.field, Some("body".to_string()));
+
+        // Can't reply across tasks
+        let response = client
+            .post(uri!(super::create_comment(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"{{ "body": "Reply", "parent_id": "{}" }}"#,
+                elsewhere.id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.field, Some("parent_id".to_string()));
+
+        // Nor look comments up through the wrong task
+        let response = client
+            .get(uri!(super::get_comment(
+                task.id.to_string(),
+                elsewhere.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Only the author may change a comment
+        let response = client
+            .patch(uri!(super::edit_comment(
+                other_task.id.to_string(),
+                elsewhere.id.to_string()
+            )))
+            .header(ContentType::JSON)
+            .body(r#"{ "body": "Edited" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn test_deleting_task_deletes_comments() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(&db, "Task".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let client = test_helpers::init_server(Some(db.clone())).await.unwrap();
+        let response = client
+            .post(uri!(super::create_comment(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "body": "Soon gone" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .delete(format!("/tasks/{}", task.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(Comment::find().count(&db).await.unwrap(), 0);
+    }
+}
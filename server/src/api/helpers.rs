@@ -1,9 +1,369 @@
-use crate::result::{Result, error_response};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use sea_orm::prelude::{Date, DateTime};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, Order, QueryFilter};
+use serde::Serialize;
+
+use super::subscription::{EntityType, FeedWriter, UpdateEvent, UpdateKind};
+use crate::models::access::TaskAccess;
+use crate::models::change_log;
+use crate::models::dependency;
+use crate::models::listing::{self, Sort, TaskFilter, TaskListParams};
+use crate::models::project::ProjectModel;
+use crate::models::task::{self, DeletedTask, Task, TaskModel, TaskResponse};
+use crate::models::workflow::Category;
+use crate::result::{self, Error, Result};
 use uuid::Uuid;
 
 pub fn parse_uuid(s: &str) -> Result<Uuid> {
     match Uuid::parse_str(s) {
         Ok(s) => Ok(s),
-        Err(_) => Err(error_response(format!("Invalid uuid {s}"))),
+        Err(_) => Err(Error::Validation {
+            message: format!("Invalid uuid {s}"),
+            field: None,
+        }),
+    }
+}
+
+// Version of the entity given in an If-Match header, which edits should only
+// apply to. Edits without one, or with `*`, apply to any version.
+pub struct IfMatch(pub Option<i32>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = Error;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(value) = req.headers().get_one("If-Match").map(str::trim) else {
+            return Outcome::Success(IfMatch(None));
+        };
+        if value == "*" {
+            return Outcome::Success(IfMatch(None));
+        }
+        let version = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .and_then(|v| v.parse().ok());
+        match version {
+            Some(version) => Outcome::Success(IfMatch(Some(version))),
+            None => Outcome::Error((
+                Status::BadRequest,
+                Error::validation("If-Match", format!("Invalid ETag {value}")),
+            )),
+        }
+    }
+}
+
+// JSON response tagged with the version of the entity it holds
+pub struct Tagged<T>(pub Json<T>, pub i32);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Tagged<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.0.respond_to(request)?;
+        response.set_header(result::etag(self.1));
+        Ok(response)
+    }
+}
+
+// Broadcast updates to tasks whose derived fields changed along with another
+// task, like a parent's subtask counts, carrying their new state
+pub async fn broadcast_task_updates(
+    db: &DatabaseConnection,
+    feed: &FeedWriter,
+    workspace_id: Uuid,
+    ids: impl IntoIterator<Item = Uuid>,
+    changed_fields: &[&str],
+) -> Result<()> {
+    let tasks = Task::find()
+        .filter(task::Column::Id.is_in(ids))
+        .all(db)
+        .await?;
+    let mut projects = task::projects_by_task(db, tasks.iter().map(|t| t.id)).await?;
+    for response in task::responses(db, tasks).await? {
+        let project_ids = projects.remove(&response.task.id).unwrap_or_default();
+        UpdateEvent::new(
+            workspace_id,
+            UpdateKind::Update,
+            EntityType::Task,
+            response.task.id,
+        )
+        .with_entity(&response)?
+        .with_changed_fields(changed_fields.iter().copied())
+        .in_projects(project_ids)
+        .send(feed);
+    }
+    Ok(())
+}
+
+// Updates for each kind of change, sent alike whether it was requested or
+// pushed through sync
+pub fn broadcast_project_created(feed: &FeedWriter, project: &ProjectModel) -> Result<()> {
+    UpdateEvent::new(
+        project.workspace_id,
+        UpdateKind::Create,
+        EntityType::Project,
+        project.id,
+    )
+    .with_entity(project)?
+    .send(feed);
+    Ok(())
+}
+
+pub fn broadcast_project_edit(
+    feed: &FeedWriter,
+    old: &ProjectModel,
+    project: &ProjectModel,
+) -> Result<()> {
+    let changed_fields = change_log::field_changes(old, project)?
+        .into_iter()
+        .filter_map(|c| c.field);
+    UpdateEvent::new(
+        project.workspace_id,
+        UpdateKind::Update,
+        EntityType::Project,
+        project.id,
+    )
+    .with_entity(project)?
+    .with_changed_fields(changed_fields)
+    .send(feed);
+    Ok(())
+}
+
+// The project, the tasks deleted with it, and their subtasks that stayed in
+// other projects and moved to the top level
+pub async fn broadcast_project_deleted(
+    db: &DatabaseConnection,
+    feed: &FeedWriter,
+    project: &ProjectModel,
+    task_ids: Vec<Uuid>,
+    detached_ids: Vec<Uuid>,
+) -> Result<()> {
+    let workspace_id = project.workspace_id;
+    UpdateEvent::broadcast(
+        feed,
+        workspace_id,
+        UpdateKind::Destroy,
+        EntityType::Project,
+        project.id,
+    );
+    for id in task_ids {
+        UpdateEvent::new(workspace_id, UpdateKind::Destroy, EntityType::Task, id)
+            .with_projects(vec![], vec![project.id])
+            .send(feed);
+    }
+    broadcast_task_updates(db, feed, workspace_id, detached_ids, &["parent_id"]).await
+}
+
+// A new task, its projects and its parent's subtask counts
+pub async fn broadcast_task_created(
+    db: &DatabaseConnection,
+    feed: &FeedWriter,
+    workspace_id: Uuid,
+    task: TaskModel,
+) -> Result<TaskResponse> {
+    let project_ids = task::project_ids(db, &task.id).await?;
+    let parent_id = task.parent_id;
+    let response = task::response(db, task).await?;
+    UpdateEvent::new(
+        workspace_id,
+        UpdateKind::Create,
+        EntityType::Task,
+        response.task.id,
+    )
+    .with_entity(&response)?
+    .with_projects(project_ids.clone(), vec![])
+    .send(feed);
+    for project_id in project_ids {
+        UpdateEvent::broadcast(
+            feed,
+            workspace_id,
+            UpdateKind::Update,
+            EntityType::Project,
+            project_id,
+        );
+    }
+    broadcast_task_updates(db, feed, workspace_id, parent_id, &["subtasks"]).await?;
+    Ok(response)
+}
+
+// The edited task with the fields that changed, and the next occurrence if
+// it was a completed recurring task. Tasks blocked by it may have become
+// (un)blocked by a status change, and its parent's subtask counts changed.
+pub async fn broadcast_edit(
+    db: &DatabaseConnection,
+    feed: &FeedWriter,
+    access: TaskAccess,
+    task: TaskModel,
+    next: Option<TaskModel>,
+) -> Result<TaskResponse> {
+    let changed_fields = task::changed_fields(&access.task, &task)?;
+    let project_ids = task::project_ids(db, &task.id).await?;
+    let response = task::response(db, task).await?;
+    UpdateEvent::new(
+        access.workspace_id,
+        UpdateKind::Update,
+        EntityType::Task,
+        response.task.id,
+    )
+    .with_entity(&response)?
+    .with_changed_fields(changed_fields)
+    .in_projects(project_ids)
+    .send(feed);
+
+    if response.task.status != access.task.status {
+        let dependents = dependency::dependents(&response.task.id).all(db).await?;
+        let ids = dependents.into_iter().map(|t| t.id);
+        broadcast_task_updates(db, feed, access.workspace_id, ids, &["blocked"]).await?;
+        let parent_id = response.task.parent_id;
+        broadcast_task_updates(db, feed, access.workspace_id, parent_id, &["subtasks"]).await?;
     }
+    if let Some(next) = next {
+        broadcast_task_created(db, feed, access.workspace_id, next).await?;
+    }
+    Ok(response)
+}
+
+// The deleted task and subtasks, along with the tasks it was blocking and its
+// parent, whose subtask counts changed
+pub async fn broadcast_task_deleted(
+    db: &DatabaseConnection,
+    feed: &FeedWriter,
+    access: &TaskAccess,
+    project_ids: Vec<Uuid>,
+    subtasks: Vec<DeletedTask>,
+    dependent_ids: Vec<Uuid>,
+) -> Result<()> {
+    let workspace_id = access.workspace_id;
+    UpdateEvent::new(
+        workspace_id,
+        UpdateKind::Destroy,
+        EntityType::Task,
+        access.task.id,
+    )
+    .with_projects(vec![], project_ids)
+    .send(feed);
+    for subtask in subtasks {
+        UpdateEvent::new(
+            workspace_id,
+            UpdateKind::Destroy,
+            EntityType::Task,
+            subtask.id,
+        )
+        .with_projects(vec![], subtask.project_ids)
+        .send(feed);
+    }
+    broadcast_task_updates(db, feed, workspace_id, dependent_ids, &["blocked"]).await?;
+    let parent_id = access.task.parent_id;
+    broadcast_task_updates(db, feed, workspace_id, parent_id, &["subtasks"]).await
+}
+
+// A task joined or left the project
+pub async fn broadcast_membership(
+    db: &DatabaseConnection,
+    feed: &FeedWriter,
+    access: TaskAccess,
+    project_id: Uuid,
+    (added, removed): (Vec<Uuid>, Vec<Uuid>),
+) -> Result<()> {
+    UpdateEvent::broadcast(
+        feed,
+        access.workspace_id,
+        UpdateKind::Update,
+        EntityType::Project,
+        project_id,
+    );
+    let project_ids = task::project_ids(db, &access.task.id).await?;
+    let response = task::response(db, access.task).await?;
+    UpdateEvent::new(
+        access.workspace_id,
+        UpdateKind::Update,
+        EntityType::Task,
+        response.task.id,
+    )
+    .with_entity(&response)?
+    .with_projects(added, removed)
+    .in_projects(project_ids)
+    .send(feed);
+    Ok(())
+}
+
+// Query parameters accepted by every task listing
+#[derive(Debug, FromForm)]
+pub struct TaskListQuery {
+    // Repeat to match any of several status keys or categories
+    status: Vec<String>,
+    category: Vec<String>,
+    // Dates as YYYY-MM-DD, exclusive
+    due_before: Option<String>,
+    due_after: Option<String>,
+    overdue: Option<bool>,
+    has_description: Option<bool>,
+    // A date or a datetime like 2025-01-31T12:00:00
+    created_after: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u64>,
+}
+
+impl TaskListQuery {
+    // `project_id` is set when listing a project's tasks, allowing sorting by position
+    pub fn params(
+        self,
+        default_sort: Sort,
+        default_order: Order,
+        project_id: Option<Uuid>,
+    ) -> Result<TaskListParams> {
+        let categories = self
+            .category
+            .iter()
+            .map(|c| Category::parse(c))
+            .collect::<Result<_>>()?;
+        let filter = TaskFilter {
+            statuses: self.status,
+            categories,
+            due_before: self
+                .due_before
+                .map(|d| parse_date("due_before", &d))
+                .transpose()?,
+            due_after: self
+                .due_after
+                .map(|d| parse_date("due_after", &d))
+                .transpose()?,
+            overdue: self.overdue,
+            has_description: self.has_description,
+            created_after: self
+                .created_after
+                .map(|d| parse_datetime("created_after", &d))
+                .transpose()?,
+        };
+        let sort = match self.sort {
+            Some(sort) => Sort::parse(&sort, project_id)?,
+            None => default_sort,
+        };
+        let order = match self.order {
+            Some(order) => listing::parse_order(&order)?,
+            None => default_order,
+        };
+        Ok(TaskListParams {
+            filter,
+            sort,
+            order,
+            cursor: self.cursor,
+            limit: listing::page_limit(self.limit)?,
+        })
+    }
+}
+
+fn parse_date(field: &str, s: &str) -> Result<Date> {
+    Date::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| Error::validation(field, format!("Invalid date {s}")))
+}
+
+fn parse_datetime(field: &str, s: &str) -> Result<DateTime> {
+    DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| parse_date(field, s).map(|d| d.and_time(Default::default())))
+        .map_err(|_| Error::validation(field, format!("Invalid datetime {s}")))
 }
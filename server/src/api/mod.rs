@@ -1,4 +1,15 @@
+pub mod auth;
+pub mod comments;
 mod helpers;
+pub mod notifications;
+pub mod presence;
 pub mod projects;
+pub mod search;
 pub mod subscription;
+pub mod sync;
 pub mod tasks;
+pub mod users;
+pub mod views;
+pub mod webhooks;
+pub mod workflows;
+pub mod workspaces;
@@ -0,0 +1,276 @@
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::DatabaseConnection;
+use sea_orm::prelude::DateTime;
+use serde::Deserialize;
+use std::time::Duration;
+
+use super::auth::AuthUser;
+use super::helpers::parse_uuid;
+use super::subscription::{FeedWriter, UpdateEvent};
+use crate::models::notification::{self, NotificationModel};
+use crate::models::reminder::{self, ReminderOffset};
+use crate::result::Result;
+
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60);
+
+// Get the current user's notifications, newest first
+#[get("/notifications?<unread>")]
+async fn list_notifications(
+    unread: Option<bool>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<NotificationModel>>> {
+    let notifications =
+        notification::notifications_for_user(db.inner(), &auth.user.id, unread.unwrap_or(false))
+            .await?;
+    Ok(Json(notifications))
+}
+
+#[post("/notifications/<id>/read")]
+async fn read_notification(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<NotificationModel>> {
+    let id = parse_uuid(id)?;
+    let notification = notification::mark_read(db.inner(), &auth.user.id, &id).await?;
+    Ok(Json(notification))
+}
+
+// When the current user is reminded of their tasks' deadlines
+#[get("/me/reminders")]
+async fn get_reminders(auth: AuthUser) -> Json<Vec<ReminderOffset>> {
+    Json(reminder::offsets(&auth.user))
+}
+
+#[derive(Deserialize)]
+struct RemindersPayload {
+    // Null to go back to the defaults, empty for no reminders
+    offsets: Option<Vec<ReminderOffset>>,
+}
+
+#[put("/me/reminders", format = "json", data = "<reminders>")]
+async fn set_reminders(
+    reminders: Json<RemindersPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<ReminderOffset>>> {
+    let reminders = reminders.into_inner();
+    let user = reminder::set_offsets(db.inner(), &auth.user.id, reminders.offsets).await?;
+    Ok(Json(reminder::offsets(&user)))
+}
+
+// Create the reminders due by `now` and push them to their recipients
+async fn send_reminders(db: &DatabaseConnection, feed: &FeedWriter, now: DateTime) -> Result<()> {
+    for (notification, workspace_id) in reminder::send_due_reminders(db, now).await? {
+        UpdateEvent::broadcast_notification(feed, workspace_id, &notification)?;
+    }
+    Ok(())
+}
+
+// Send reminders in the background for as long as the server runs
+pub fn start_reminders(db: DatabaseConnection, feed: &FeedWriter) {
+    let feed = feed.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REMINDER_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = send_reminders(&db, &feed, Utc::now().naive_utc()).await {
+                eprintln!("Failed to send reminders: {e:?}");
+            }
+        }
+    });
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        list_notifications,
+        read_notification,
+        get_reminders,
+        set_reminders
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::api::subscription::EntityType;
+    use crate::models::notification::NotificationModel;
+    use crate::models::reminder::{self, JobCheckpointActiveModel};
+    use crate::models::task::{self, TaskActiveModel, TaskModel};
+    use crate::models::workspace::{self, WorkspaceRole};
+    use crate::models::{deadline, project, user};
+    use crate::test_helpers;
+    use chrono::{Duration, Timelike, Utc};
+    use rocket::http::{ContentType, Status};
+    use sea_orm::prelude::{DateTime, Time};
+    use sea_orm::{ActiveModelTrait, ActiveValue, DatabaseConnection};
+    use serde_json::{self, json};
+
+    fn at(s: &str) -> DateTime {
+        DateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    async fn task_due(
+        db: &DatabaseConnection,
+        due_date: &str,
+        due_time: Option<Time>,
+        time_zone: Option<&str>,
+    ) -> TaskModel {
+        let workspace_id = test_helpers::workspace_id(db).await.unwrap();
+        let project = project::create_project(db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(db, "A task".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let mut task: TaskActiveModel = task.into();
+        let due_date = Some(due_date.parse().unwrap());
+        task.due_date = ActiveValue::Set(due_date);
+        task.due_time = ActiveValue::Set(due_time);
+        task.time_zone = ActiveValue::Set(time_zone.map(str::to_string));
+        task.due_at = ActiveValue::Set(deadline::due_at(due_date, due_time, time_zone));
+        task.update(db).await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn test_reminders() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap()
+            .id;
+        workspace::add_member(&db, &workspace_id, &other, WorkspaceRole::Member)
+            .await
+            .unwrap();
+        // Due at 13:00 UTC
+        let task = task_due(
+            &db,
+            "2025-10-21",
+            Time::from_hms_opt(15, 0, 0),
+            Some("Europe/Berlin"),
+        )
+        .await;
+
+        // The first run starts the clock
+        let sent = reminder::send_due_reminders(&db, at("2025-10-19 00:00"))
+            .await
+            .unwrap();
+        assert!(sent.is_empty());
+        let sent = reminder::send_due_reminders(&db, at("2025-10-20 12:00"))
+            .await
+            .unwrap();
+        assert!(sent.is_empty());
+
+        // Both the day before and the morning of were missed while down
+        let sent = reminder::send_due_reminders(&db, at("2025-10-21 12:00"))
+            .await
+            .unwrap();
+        let mut reminders: Vec<_> = sent
+            .iter()
+            .map(|(n, _)| (n.user_id, n.reminder.clone(), n.remind_at))
+            .collect();
+        reminders.sort();
+        let mut expected = vec![];
+        for user_id in [user.id, other] {
+            expected.push((user_id, "before:1440".to_string(), at("2025-10-20 13:00")));
+            expected.push((user_id, "on_day:09:00".to_string(), at("2025-10-21 07:00")));
+        }
+        expected.sort();
+        assert_eq!(reminders, expected);
+        assert!(
+            sent.iter()
+                .all(|(n, w)| n.task_id == task.id && *w == workspace_id)
+        );
+
+        // Running over the same period again, say after a crash, sends nothing
+        let checkpoint = JobCheckpointActiveModel {
+            job: ActiveValue::Set("reminders".to_string()),
+            checkpoint: ActiveValue::Set(at("2025-10-20 00:00")),
+        };
+        checkpoint.update(&db).await.unwrap();
+        let resent = reminder::send_due_reminders(&db, at("2025-10-21 12:30"))
+            .await
+            .unwrap();
+        assert!(resent.is_empty());
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let response = client.get("/notifications").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let notifications: Vec<NotificationModel> =
+            serde_json::from_str(&response_str).expect("Notifications");
+        assert_eq!(notifications.len(), 2);
+        assert!(notifications.iter().all(|n| n.user_id == user.id));
+        assert_eq!(notifications[0].reminder, "on_day:09:00");
+
+        let response = client
+            .post(format!("/notifications/{}/read", notifications[0].id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get("/notifications?unread=true").dispatch().await;
+        let response_str = response.into_string().await.unwrap();
+        let unread: Vec<NotificationModel> =
+            serde_json::from_str(&response_str).expect("Notifications");
+        assert_eq!(unread.len(), 1);
+        assert_eq!(unread[0].id, notifications[1].id);
+
+        // Other users' notifications can't be touched
+        let theirs = sent.iter().find(|(n, _)| n.user_id == other).unwrap().0.id;
+        let response = client
+            .post(format!("/notifications/{theirs}/read"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_reminder_pushed() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let now = Utc::now().naive_utc();
+        reminder::send_due_reminders(&db, now).await.unwrap();
+        // A week and a bit from now, so only a reminder a week before falls due
+        let due = (now + Duration::days(7) + Duration::seconds(30))
+            .with_nanosecond(0)
+            .unwrap();
+        let task = task_due(&db, &due.date().to_string(), Some(due.time()), None).await;
+        let client = test_helpers::init_server(Some(db.clone())).await.unwrap();
+        let mut updates = client.updates();
+
+        let response = client
+            .put("/me/reminders")
+            .header(ContentType::JSON)
+            .body(json!({ "offsets": [{ "kind": "before", "minutes": 10080 }] }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .put("/me/reminders")
+            .header(ContentType::JSON)
+            .body(json!({ "offsets": [{ "kind": "before", "minutes": 0 }] }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        super::send_reminders(&db, client.feed(), now + Duration::minutes(1))
+            .await
+            .unwrap();
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let event = updates.recv().await.unwrap();
+                if event.entity_type == EntityType::Notification {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("A notification");
+        assert_eq!(event.task_id, Some(task.id));
+        assert_eq!(event.user_id, Some(client.user.id));
+    }
+}
@@ -0,0 +1,244 @@
+// Who is viewing or editing which project or task, announced by websocket
+// clients and kept in memory only. Clients send heartbeats while they stay;
+// entries not heard from in a while are dropped as if the client left.
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use uuid::Uuid;
+
+use super::subscription::Topic;
+
+// How long a client stays present without a heartbeat
+pub const PRESENCE_TIMEOUT: Duration = Duration::from_secs(30);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceKind {
+    // Also sent when the field being edited changes
+    Join,
+    Leave,
+}
+
+// Someone on a project or task, as sent to others there
+#[derive(Serialize, Debug, Clone)]
+pub struct Present {
+    // Distinguishes the same user in several tabs
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    // Field being edited, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PresenceEvent {
+    pub kind: PresenceKind,
+    pub workspace_id: Uuid,
+    pub topic: Topic,
+    #[serde(flatten)]
+    pub present: Present,
+    // Projects of the task, for routing to their subscribers
+    #[serde(skip)]
+    pub project_ids: Vec<Uuid>,
+}
+
+struct Entry {
+    user_id: Uuid,
+    workspace_id: Uuid,
+    project_ids: Vec<Uuid>,
+    field: Option<String>,
+    seen: Instant,
+}
+
+impl Entry {
+    fn present(&self, connection_id: Uuid) -> Present {
+        Present {
+            connection_id,
+            user_id: self.user_id,
+            field: self.field.clone(),
+        }
+    }
+}
+
+// Presence of every connection, by connection and project or task
+#[derive(Clone)]
+pub struct PresenceTable {
+    entries: Arc<Mutex<HashMap<(Uuid, Topic), Entry>>>,
+    sender: Sender<PresenceEvent>,
+}
+
+impl PresenceTable {
+    pub fn new(capacity: usize) -> Self {
+        PresenceTable {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    // Create the table and drop entries that time out
+    pub fn start() -> Self {
+        let table = PresenceTable::new(64);
+        let sweeping = table.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                sweeping.expire(Instant::now());
+            }
+        });
+        table
+    }
+
+    pub fn subscribe(&self) -> Receiver<PresenceEvent> {
+        self.sender.subscribe()
+    }
+
+    fn send(&self, kind: PresenceKind, connection_id: Uuid, topic: Topic, entry: &Entry) {
+        let event = PresenceEvent {
+            kind,
+            workspace_id: entry.workspace_id,
+            topic,
+            present: entry.present(connection_id),
+            project_ids: entry.project_ids.clone(),
+        };
+        // Only fails when nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    // Mark the connection as on the project or task, returning who else is
+    pub fn join(
+        &self,
+        connection_id: Uuid,
+        user_id: Uuid,
+        workspace_id: Uuid,
+        // Of the task, for tasks
+        project_ids: Vec<Uuid>,
+        topic: Topic,
+        field: Option<String>,
+    ) -> Vec<Present> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = Entry {
+            user_id,
+            workspace_id,
+            project_ids,
+            field,
+            seen: Instant::now(),
+        };
+        let changed = entries
+            .get(&(connection_id, topic))
+            .is_none_or(|e| e.field != entry.field);
+        if changed {
+            self.send(PresenceKind::Join, connection_id, topic, &entry);
+        }
+        entries.insert((connection_id, topic), entry);
+        entries
+            .iter()
+            .filter(|((id, t), _)| *t == topic && *id != connection_id)
+            .map(|((id, _), e)| e.present(*id))
+            .collect()
+    }
+
+    pub fn leave(&self, connection_id: Uuid, topic: Topic) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.remove(&(connection_id, topic)) {
+            self.send(PresenceKind::Leave, connection_id, topic, &entry);
+        }
+    }
+
+    // Keep the connection present wherever it is
+    pub fn heartbeat(&self, connection_id: Uuid) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        for ((id, _), entry) in entries.iter_mut() {
+            if *id == connection_id {
+                entry.seen = now;
+            }
+        }
+    }
+
+    // Leave everywhere, when the connection closes
+    pub fn disconnect(&self, connection_id: Uuid) {
+        self.remove_where(|(id, _), _| *id == connection_id);
+    }
+
+    fn expire(&self, now: Instant) {
+        self.remove_where(|_, entry| now.duration_since(entry.seen) > PRESENCE_TIMEOUT);
+    }
+
+    fn remove_where(&self, remove: impl Fn(&(Uuid, Topic), &Entry) -> bool) {
+        let mut entries = self.entries.lock().unwrap();
+        let removed: Vec<_> = entries
+            .iter()
+            .filter(|(key, entry)| remove(key, entry))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in removed {
+            if let Some(entry) = entries.remove(&key) {
+                self.send(PresenceKind::Leave, key.0, key.1, &entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PRESENCE_TIMEOUT, PresenceKind, PresenceTable};
+    use crate::api::subscription::Topic;
+    use std::time::{Duration, Instant};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_presence() {
+        let table = PresenceTable::new(16);
+        let mut events = table.subscribe();
+        let (workspace_id, task_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let topic = Topic::Task(task_id);
+        // Connections, and their users
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice_user, bob_user) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let others = table.join(alice, alice_user, workspace_id, vec![], topic, None);
+        assert!(others.is_empty());
+        let others = table.join(bob, bob_user, workspace_id, vec![], topic, None);
+        assert_eq!(others.len(), 1);
+        assert_eq!(others[0].user_id, alice_user);
+        // Starting to edit a field
+        table.join(
+            bob,
+            bob_user,
+            workspace_id,
+            vec![],
+            topic,
+            Some("title".to_string()),
+        );
+        // Nothing changed
+        table.join(alice, alice_user, workspace_id, vec![], topic, None);
+
+        let sent: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[2].present.field.as_deref(), Some("title"));
+
+        // Alice stops sending heartbeats and times out, Bob disconnects
+        table.heartbeat(bob);
+        table.expire(Instant::now() + PRESENCE_TIMEOUT - Duration::from_millis(100));
+        assert!(events.try_recv().is_err());
+        // Bob's next heartbeat
+        table
+            .entries
+            .lock()
+            .unwrap()
+            .get_mut(&(bob, topic))
+            .unwrap()
+            .seen += PRESENCE_TIMEOUT;
+        table.expire(Instant::now() + PRESENCE_TIMEOUT + Duration::from_secs(1));
+        let left = events.try_recv().unwrap();
+        assert_eq!(left.kind, PresenceKind::Leave);
+        assert_eq!(left.present.connection_id, alice);
+        table.disconnect(bob);
+        assert_eq!(events.try_recv().unwrap().present.connection_id, bob);
+        assert!(table.entries.lock().unwrap().is_empty());
+    }
+}
@@ -1,58 +1,122 @@
+use chrono::Utc;
 use rocket::serde::json::Json;
 use rocket::{Route, State};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, FromQueryResult, QueryOrder, raw_sql};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, FromQueryResult, Order, QueryFilter, QueryOrder,
+    raw_sql,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::models::project::{self, EditProjectPayload, Project, ProjectModel};
-use crate::models::task::{self, Task, TaskModel};
-use crate::result::{Result, error_response};
-
-use super::helpers::parse_uuid;
+use crate::models::access::{self, ProjectAction, Role, TaskAction};
+use crate::models::change_log::{self, HistoryEntry, LoggedEntity};
+use crate::models::listing::{self, Page, Sort};
+use crate::models::project::{self, EditProjectPayload, Project, ProjectMember, ProjectModel};
+use crate::models::task::{self, Task, TaskProject, TaskProjectModel, TaskResponse};
+use crate::models::user::{self, User, UserModel};
+use crate::models::workflow;
+use crate::models::workspace::{self, Workspace};
+use crate::result::{Error, Result};
+
+use super::auth::AuthUser;
+use super::helpers::{
+    IfMatch, Tagged, TaskListQuery, broadcast_membership, broadcast_project_created,
+    broadcast_project_deleted, broadcast_project_edit, broadcast_task_created, parse_uuid,
+};
 use super::subscription::{EntityType, FeedWriter, UpdateEvent, UpdateKind};
 
-// Get task counts for projects with ids specified in query
-#[get("/projects")]
-async fn projects(db: &State<DatabaseConnection>) -> Result<Json<Vec<ProjectModel>>> {
+// Get projects in the caller's workspaces, or only the one specified in query
+#[get("/projects?<workspace_id>")]
+async fn projects(
+    workspace_id: Option<&str>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<ProjectModel>>> {
+    let workspace_ids = match workspace_id {
+        Some(id) => {
+            let id = parse_uuid(id)?;
+            access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+            vec![id]
+        }
+        None => workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?,
+    };
     let projects = Project::find()
+        .filter(project::Column::WorkspaceId.is_in(workspace_ids))
         .order_by_desc(project::Column::CreatedAt)
         .all(db.inner())
         .await?;
     Ok(Json(projects))
 }
 
-// Get task counts for projects with ids specified in query
-#[derive(Debug, PartialEq, Deserialize, Serialize, FromQueryResult)]
+// Get task counts for projects with ids specified in query: per status
+// category, and per status of each project's workflow
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 struct ProjectStats {
     id: Uuid,
+    // Counts by category: done, active and not started
     complete: u32,
     in_progress: u32,
     todo: u32,
     total: u32,
+    // Past the instant they're due and not done
+    overdue: u32,
+    statuses: Vec<StatusCount>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+struct StatusCount {
+    key: String,
+    name: String,
+    category: String,
+    color: String,
+    count: u32,
+}
+
+#[derive(FromQueryResult)]
+struct CategoryCounts {
+    id: Uuid,
+    complete: u32,
+    in_progress: u32,
+    todo: u32,
+    total: u32,
+    overdue: u32,
+}
+
+#[derive(FromQueryResult)]
+struct StatusCountRow {
+    project_id: Uuid,
+    status: String,
+    count: u32,
 }
 
 #[get("/projects/stats?<ids>")]
 async fn project_stats(
     ids: Vec<&str>,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
 ) -> Result<Json<Vec<ProjectStats>>> {
     let ids: Vec<Uuid> = ids
         .into_iter()
         .map(|id| Uuid::parse_str(id).unwrap_or(Uuid::nil()))
         .collect();
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
+    let now = Utc::now().naive_utc();
 
-    let stats: Vec<ProjectStats> = ProjectStats::find_by_statement(raw_sql!(
+    let counts = CategoryCounts::find_by_statement(raw_sql!(
         Sqlite,
         r#"select
 	           p.id as id,
-	           COUNT(CASE WHEN t.status = 'todo' THEN 1 ELSE NULL END) AS todo,
-	           COUNT(CASE WHEN t.status = 'in_progress' THEN 1 ELSE NULL END) AS in_progress,
-	           COUNT(CASE WHEN t.status = 'complete' THEN 1 ELSE NULL END) AS complete,
-	           count(*) as total
+	           COUNT(CASE WHEN t.status_category = 'not_started' THEN 1 ELSE NULL END) AS todo,
+	           COUNT(CASE WHEN t.status_category = 'active' THEN 1 ELSE NULL END) AS in_progress,
+	           COUNT(CASE WHEN t.status_category = 'done' THEN 1 ELSE NULL END) AS complete,
+	           count(t.id) as total,
+	           COUNT(CASE WHEN t.due_at < {now} AND t.status_category != 'done' THEN 1 ELSE NULL END) AS overdue
 	         from project p
 	         left join task_project tp on tp.project_id = p.id
 	         left join task t on tp.task_id = t.id
            WHERE "p"."id" IN ({..ids})
+             AND "p"."workspace_id" IN ({..workspace_ids})
 	         group by p.id, p.created_at
            ORDER BY p.created_at
         "#
@@ -60,41 +124,95 @@ async fn project_stats(
     .all(db.inner())
     .await?;
 
+    let project_ids: Vec<Uuid> = counts.iter().map(|c| c.id).collect();
+    let mut status_counts: HashMap<(Uuid, String), u32> =
+        StatusCountRow::find_by_statement(raw_sql!(
+            Sqlite,
+            r#"SELECT tp.project_id AS project_id, t.status AS status, COUNT(*) AS count
+               FROM task_project tp
+               JOIN task t ON tp.task_id = t.id
+               WHERE tp.project_id IN ({..project_ids})
+               GROUP BY tp.project_id, t.status
+            "#
+        ))
+        .all(db.inner())
+        .await?
+        .into_iter()
+        .map(|row| ((row.project_id, row.status), row.count))
+        .collect();
+
+    let mut stats = Vec::with_capacity(counts.len());
+    for c in counts {
+        let statuses = workflow::statuses(db.inner(), &c.id)
+            .await?
+            .into_iter()
+            .map(|s| StatusCount {
+                count: status_counts.remove(&(c.id, s.key.clone())).unwrap_or(0),
+                key: s.key,
+                name: s.name,
+                category: s.category,
+                color: s.color,
+            })
+            .collect();
+        stats.push(ProjectStats {
+            id: c.id,
+            complete: c.complete,
+            in_progress: c.in_progress,
+            todo: c.todo,
+            total: c.total,
+            overdue: c.overdue,
+            statuses,
+        });
+    }
+
     Ok(Json(stats))
 }
 
-// Create a new project with the given title
+// Create a new project with the given title.
+// Goes in the caller's oldest workspace unless one is specified.
 #[derive(Deserialize)]
 struct CreateProjectPayload {
     title: String,
+    workspace_id: Option<Uuid>,
 }
 
 #[post("/projects", format = "json", data = "<project>")]
 async fn create_project(
     project: Json<CreateProjectPayload>,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
 ) -> Result<Json<ProjectModel>> {
-    let project = project::create_project(db.inner(), project.title.clone()).await?;
-    UpdateEvent::broadcast(
-        feed.inner(),
-        UpdateKind::Create,
-        EntityType::Project,
-        project.id.clone(),
-    )?;
+    let workspace_id = match project.workspace_id {
+        Some(id) => {
+            access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+            id
+        }
+        None => workspace::workspace_ids_for_user(db.inner(), &auth.user.id)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::validation("workspace_id", "User has no workspace"))?,
+    };
+    let title = project.title.clone();
+    let project =
+        project::create_owned_project(db.inner(), &workspace_id, title, &auth.user.id).await?;
+    broadcast_project_created(feed.inner(), &project)?;
     Ok(Json(project))
 }
 
 // Get project with the given ID
 #[get("/projects/<id>")]
-async fn get_project(id: &str, db: &State<DatabaseConnection>) -> Result<Json<ProjectModel>> {
+async fn get_project(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Tagged<ProjectModel>> {
     let id = parse_uuid(id)?;
-    let project = Project::find_by_id(id).one(db.inner()).await?;
-    match project {
-        Some(p) => Ok(Json(p)),
-        // TODO: this should 404
-        None => Err(error_response(format!("Project with id {id:?} not found!"))),
-    }
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::View).await?;
+    let version = project.version;
+    Ok(Tagged(Json(project), version))
 }
 
 // Edit field<>value pair(s) on project
@@ -102,52 +220,71 @@ async fn get_project(id: &str, db: &State<DatabaseConnection>) -> Result<Json<Pr
 async fn edit_project(
     id: &str,
     project: Json<EditProjectPayload>,
+    if_match: IfMatch,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
-) -> Result<Json<ProjectModel>> {
+) -> Result<Tagged<ProjectModel>> {
     let id = parse_uuid(id)?;
-    let project = project::edit_project(db.inner(), &id, project).await?;
-    UpdateEvent::broadcast(
-        feed.inner(),
-        UpdateKind::Update,
-        EntityType::Project,
-        id.clone(),
-    )?;
-    Ok(Json(project))
+    let old = access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::Edit).await?;
+    let project =
+        project::edit_project(db.inner(), &auth.user.id, &id, project, if_match.0).await?;
+    broadcast_project_edit(feed.inner(), &old, &project)?;
+    let version = project.version;
+    Ok(Tagged(Json(project), version))
 }
 
 // Delete project with the given ID
 #[delete("/projects/<id>")]
 async fn delete_project(
     id: &str,
+    if_match: IfMatch,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
 ) -> Result<()> {
     let id = parse_uuid(id)?;
-    Project::delete_by_id(id).exec(db.inner()).await?;
-    UpdateEvent::broadcast(
-        feed.inner(),
-        UpdateKind::Destroy,
-        EntityType::Project,
-        id.clone(),
-    )?;
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::Delete).await?;
+    let (task_ids, detached_ids) =
+        project::delete_project(db.inner(), &auth.user.id, &id, if_match.0).await?;
+    broadcast_project_deleted(db.inner(), feed.inner(), &project, task_ids, detached_ids).await?;
     Ok(())
 }
 
-// Get tasks belonging to project with the given id
-#[get("/projects/<id>/tasks")]
+// Get the change history of project with the given id, oldest first.
+// Includes tasks being added to or removed from the project, and stays
+// readable once the project is deleted.
+#[get("/projects/<id>/history")]
+async fn get_project_history(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<HistoryEntry>>> {
+    let id = parse_uuid(id)?;
+    access::require_history(db.inner(), &auth.user.id, LoggedEntity::Project, &id).await?;
+    let history = change_log::history(db.inner(), LoggedEntity::Project, &id).await?;
+    Ok(Json(history))
+}
+
+// Get tasks belonging to project with the given id, in the project's order
+// unless sorted otherwise
+#[get("/projects/<id>/tasks?<query..>")]
 async fn get_project_tasks(
     id: &str,
+    query: TaskListQuery,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
-) -> Result<Json<Vec<TaskModel>>> {
+) -> Result<Json<Page<TaskResponse>>> {
     let id = parse_uuid(id)?;
+    let params = query.params(Sort::Position(id), Order::Asc, Some(id))?;
+    access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::View).await?;
     let tasks = Task::find()
-        .has_related(Project, project::Column::Id.eq(id))
-        .order_by_asc(task::Column::CreatedAt)
-        .all(db.inner())
-        .await?;
+        .inner_join(TaskProject)
+        .filter(task::TaskProjectColumn::ProjectId.eq(id));
+    let page = listing::list_tasks(db.inner(), tasks, &params).await?;
 
-    Ok(Json(tasks))
+    Ok(Json(task::response_page(db.inner(), page).await?))
 }
 
 // Create a new task and add to project with the given id
@@ -160,81 +297,221 @@ struct CreateTaskPayload {
 async fn create_task_in_project(
     id: &str,
     task: Json<CreateTaskPayload>,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
-) -> Result<Json<TaskModel>> {
+) -> Result<Json<TaskResponse>> {
     let id = parse_uuid(id)?;
-    let task =
-        task::create_task_in_project(db.inner(), task.title.clone(), task::Status::Todo, &id)
-            .await?;
-    UpdateEvent::broadcast(
-        feed.inner(),
-        UpdateKind::Create,
-        EntityType::Task,
-        task.id.clone(),
-    )?;
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::Edit).await?;
+    let status = workflow::initial_status(db.inner(), &id).await?;
+    let task = task::create_task_in_project(db.inner(), task.title.clone(), status, &id).await?;
+    let response =
+        broadcast_task_created(db.inner(), feed.inner(), project.workspace_id, task).await?;
+    Ok(Json(response))
+}
+
+// Move a task within the project's ordering, placing it after the `after`
+// task and/or before the `before` one
+#[derive(Deserialize)]
+struct MoveTaskPayload {
+    before: Option<Uuid>,
+    after: Option<Uuid>,
+}
+
+#[post(
+    "/projects/<id>/tasks/<task_id>/move",
+    format = "json",
+    data = "<placement>"
+)]
+async fn move_task_in_project(
+    id: &str,
+    task_id: &str,
+    placement: Json<MoveTaskPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<TaskProjectModel>> {
+    let id = parse_uuid(id)?;
+    let task_id = parse_uuid(task_id)?;
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::Edit).await?;
+    let moved = task::move_in_project(
+        db.inner(),
+        &auth.user.id,
+        &id,
+        &task_id,
+        placement.before,
+        placement.after,
+    )
+    .await?;
     UpdateEvent::broadcast(
         feed.inner(),
+        project.workspace_id,
         UpdateKind::Update,
         EntityType::Project,
-        id.clone(),
-    )?;
-    Ok(Json(task))
+        id,
+    );
+    Ok(Json(moved))
 }
 
 // Associate a task to a project.
 // Preserves any pre-existing project associations for the task.
+// Tasks can't be shared across workspaces.
 #[post("/projects/<project_id>/add_task?<task_id>")]
 async fn add_task_to_project(
     project_id: &str,
     task_id: &str,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
 ) -> Result<()> {
     let project_id = parse_uuid(project_id)?;
     let task_id = parse_uuid(task_id)?;
-    task::add_to_project(db.inner(), &task_id, &project_id).await?;
-
-    UpdateEvent::broadcast(
-        feed.inner(),
-        UpdateKind::Update,
-        EntityType::Project,
-        project_id.clone(),
-    )?;
-    UpdateEvent::broadcast(
-        feed.inner(),
-        UpdateKind::Update,
-        EntityType::Task,
-        task_id.clone(),
-    )?;
-
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &project_id, ProjectAction::Edit)
+            .await?;
+    let task = access::require_task(db.inner(), &auth.user.id, &task_id, TaskAction::Edit).await?;
+    if task.workspace_id != project.workspace_id {
+        return Err(Error::validation(
+            "task_id",
+            "Task belongs to a different workspace",
+        ));
+    }
+    task::add_to_project(db.inner(), &auth.user.id, &task_id, &project_id).await?;
+    let projects = (vec![project_id], vec![]);
+    broadcast_membership(db.inner(), feed.inner(), task, project_id, projects).await?;
     Ok(())
 }
 
 // Dissociate a task from a project.
-// Preserves any other project associations.
+// Preserves any other project associations; a task's last project can't be
+// removed, so it stays reachable.
 #[post("/projects/<project_id>/remove_task?<task_id>")]
 async fn remove_task_from_project(
     project_id: &str,
     task_id: &str,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
 ) -> Result<()> {
     let project_id = parse_uuid(project_id)?;
     let task_id = parse_uuid(task_id)?;
-    task::remove_from_project(db.inner(), &task_id, &project_id).await?;
+    access::require_project(db.inner(), &auth.user.id, &project_id, ProjectAction::Edit).await?;
+    let task = access::require_task(db.inner(), &auth.user.id, &task_id, TaskAction::View).await?;
+    task::remove_from_project(db.inner(), &auth.user.id, &task_id, &project_id).await?;
+    let projects = (vec![], vec![project_id]);
+    broadcast_membership(db.inner(), feed.inner(), task, project_id, projects).await?;
+    Ok(())
+}
+
+// Get every workspace member's role on the project
+#[derive(Debug, Deserialize, Serialize)]
+struct ProjectMemberResponse {
+    user: UserModel,
+    role: Role,
+    // Whether the role was set on the project, rather than the workspace default
+    explicit: bool,
+}
+
+#[get("/projects/<id>/members")]
+async fn project_members(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<ProjectMemberResponse>>> {
+    let id = parse_uuid(id)?;
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::View).await?;
+    let users = User::find()
+        .has_related(Workspace, workspace::Column::Id.eq(project.workspace_id))
+        .order_by_asc(user::Column::Username)
+        .all(db.inner())
+        .await?;
+    let explicit = ProjectMember::find()
+        .filter(project::ProjectMemberColumn::ProjectId.eq(id))
+        .all(db.inner())
+        .await?;
+
+    let mut members = Vec::with_capacity(users.len());
+    for user in users {
+        let role = explicit.iter().find(|m| m.user_id == user.id);
+        members.push(ProjectMemberResponse {
+            role: match role {
+                Some(m) => Role::parse(&m.role)?,
+                None => access::DEFAULT_ROLE,
+            },
+            explicit: role.is_some(),
+            user,
+        });
+    }
+
+    Ok(Json(members))
+}
+
+// Set a workspace member's role on the project
+#[derive(Deserialize)]
+struct SetRolePayload {
+    role: Role,
+}
+
+#[put(
+    "/projects/<id>/members/<user_id>",
+    format = "json",
+    data = "<payload>"
+)]
+async fn set_project_member(
+    id: &str,
+    user_id: &str,
+    payload: Json<SetRolePayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let user_id = parse_uuid(user_id)?;
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::ManageMembers)
+            .await?;
+    if !workspace::is_member(db.inner(), &project.workspace_id, &user_id).await? {
+        return Err(Error::validation(
+            "user_id",
+            "User is not a member of the project's workspace",
+        ));
+    }
+    project::set_member_role(db.inner(), &id, &user_id, payload.role).await?;
     UpdateEvent::broadcast(
         feed.inner(),
+        project.workspace_id,
         UpdateKind::Update,
         EntityType::Project,
-        project_id.clone(),
-    )?;
+        id,
+    );
+    Ok(())
+}
+
+// Reset a workspace member's role on the project to the default
+#[delete("/projects/<id>/members/<user_id>")]
+async fn remove_project_member(
+    id: &str,
+    user_id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let user_id = parse_uuid(user_id)?;
+    let project =
+        access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::ManageMembers)
+            .await?;
+    project::remove_member(db.inner(), &id, &user_id).await?;
     UpdateEvent::broadcast(
         feed.inner(),
+        project.workspace_id,
         UpdateKind::Update,
-        EntityType::Task,
-        task_id.clone(),
-    )?;
+        EntityType::Project,
+        id,
+    );
     Ok(())
 }
 
@@ -246,27 +523,39 @@ pub fn routes() -> Vec<Route> {
         delete_project,
         project_stats,
         get_project,
+        get_project_history,
         get_project_tasks,
         create_task_in_project,
+        move_task_in_project,
         add_task_to_project,
-        remove_task_from_project
+        remove_task_from_project,
+        project_members,
+        set_project_member,
+        remove_project_member
     ]
 }
 
 #[cfg(test)]
 mod test {
+    use crate::models::access::Role;
+    use crate::models::change_log::HistoryEntry;
+    use crate::models::listing::Page;
     use crate::models::project::{self, ProjectModel};
-    use crate::models::task::{self, TaskModel};
+    use crate::models::task::{self, TaskActiveModel, TaskModel, TaskResponse};
+    use crate::models::workspace::{self, WorkspaceRole};
+    use crate::models::{deadline, user};
+    use crate::result::ErrorBody;
     use crate::test_helpers;
-    use rocket::http::{ContentType, Status};
-    use serde_json;
+    use rocket::http::{ContentType, Header, Status};
+    use sea_orm::{ActiveModelTrait, ActiveValue};
+    use serde_json::{self, json};
 
-    use super::ProjectStats;
+    use super::{ProjectMemberResponse, ProjectStats};
 
     #[rocket::async_test]
     async fn test_get_projects() {
         let client = test_helpers::init_server(None).await.unwrap();
-        let response = client.get(uri!(super::projects)).dispatch().await;
+        let response = client.get(uri!(super::projects(_))).dispatch().await;
         assert_eq!(response.status(), Status::Ok);
         let response_str = response.into_string().await.unwrap();
         let projects: Vec<ProjectModel> =
@@ -274,6 +563,43 @@ mod test {
         assert_eq!(projects.len(), 0);
     }
 
+    #[rocket::async_test]
+    async fn test_projects_scoped_to_workspace() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let mine = project::create_project(&db, &workspace_id, "Mine".to_string())
+            .await
+            .unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let other_workspace = workspace::workspace_ids_for_user(&db, &other.id)
+            .await
+            .unwrap()[0];
+        let theirs = project::create_project(&db, &other_workspace, "Theirs".to_string())
+            .await
+            .unwrap();
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let response = client.get(uri!(super::projects(_))).dispatch().await;
+        let response_str = response.into_string().await.unwrap();
+        let projects: Vec<ProjectModel> =
+            serde_json::from_str(&response_str).expect("List of projects");
+        assert_eq!(projects, vec![mine]);
+
+        let response = client
+            .get(uri!(super::get_project(theirs.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .get(uri!(super::projects(Some(other_workspace.to_string()))))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
     #[rocket::async_test]
     async fn test_create_project() {
         let client = test_helpers::init_server(None).await.unwrap();
@@ -292,12 +618,14 @@ mod test {
         let response_str = response.into_string().await.unwrap();
         let project: ProjectModel = serde_json::from_str(&response_str).expect("The Project");
         assert_eq!(project.title, "A new project!");
+        assert_eq!(project.workspace_id, client.workspace_id);
     }
 
     #[rocket::async_test]
     async fn test_edit_project() {
         let db = test_helpers::db_conn().await.unwrap();
-        let project = project::create_project(&db, "A project".to_string())
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
@@ -320,10 +648,50 @@ mod test {
         assert_eq!(project.description, Some("A description!".to_string()));
     }
 
+    #[rocket::async_test]
+    async fn test_edit_project_if_match() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        project::set_member_role(&db, &project.id, &user.id, Role::Owner)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let edit = async |etag: &'static str| {
+            client
+                .patch(uri!(super::edit_project(project.id.to_string())))
+                .header(ContentType::JSON)
+                .header(Header::new("If-Match", etag))
+                .body(r#"{"title": "New Name!!"}"#)
+                .dispatch()
+                .await
+        };
+        let response = edit("\"1\"").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+
+        let response = edit("\"1\"").await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let err: ErrorBody = response.into_json().await.unwrap();
+        let current: ProjectModel = serde_json::from_value(err.current.unwrap()).unwrap();
+        assert_eq!(current.version, 2);
+
+        let response = client
+            .delete(uri!(super::delete_project(project.id.to_string())))
+            .header(Header::new("If-Match", "\"1\""))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+    }
+
     #[rocket::async_test]
     async fn test_edit_project_single_field() {
         let db = test_helpers::db_conn().await.unwrap();
-        let project = project::create_project(&db, "A project".to_string())
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
@@ -348,7 +716,8 @@ mod test {
     #[rocket::async_test]
     async fn test_edit_project_multiline_text() {
         let db = test_helpers::db_conn().await.unwrap();
-        let project = project::create_project(&db, "A project".to_string())
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
@@ -382,7 +751,8 @@ with many lines"#
     #[rocket::async_test]
     async fn test_edit_project_special_chars() {
         let db = test_helpers::db_conn().await.unwrap();
-        let project = project::create_project(&db, "A project".to_string())
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
@@ -412,7 +782,8 @@ with many lines"#
     #[rocket::async_test]
     async fn test_create_task_in_project() {
         let db = test_helpers::db_conn().await.unwrap();
-        let project = project::create_project(&db, "A project".to_string())
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
@@ -437,12 +808,22 @@ with many lines"#
     #[rocket::async_test]
     async fn test_add_task_to_project() {
         let db = test_helpers::db_conn().await.unwrap();
-        let project = project::create_project(&db, "A project".to_string())
-            .await
-            .unwrap();
-        let task = task::create_task(&db, "Task".to_string(), task::Status::InProgress)
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
+        let other_project =
+            project::create_project(&db, &workspace_id, "Other project".to_string())
+                .await
+                .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "Task".to_string(),
+            task::Status::InProgress,
+            &other_project.id,
+        )
+        .await
+        .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
 
         let response = client
@@ -455,12 +836,28 @@ with many lines"#
 
         // FIXME: add ability to access db after API call to verify side effects
         assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .post(uri!(super::add_task_to_project(
+                project.id.to_string(),
+                task.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.code, "conflict");
     }
 
     #[rocket::async_test]
     async fn test_remove_task_from_project() {
         let db = test_helpers::db_conn().await.unwrap();
-        let project = project::create_project(&db, "A project".to_string())
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let other = project::create_project(&db, &workspace_id, "Other".to_string())
             .await
             .unwrap();
         let task = task::create_task_in_project(
@@ -471,6 +868,10 @@ with many lines"#
         )
         .await
         .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        task::add_to_project(&db, &user.id, &task.id, &other.id)
+            .await
+            .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
 
         let response = client
@@ -483,13 +884,29 @@ with many lines"#
 
         // FIXME: add ability to access db after API call to verify side effects
         assert_eq!(response.status(), Status::Ok);
+
+        // Its last project stays
+        let response = client
+            .post(uri!(super::remove_task_from_project(
+                other.id.to_string(),
+                task.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.field, Some("task_id".to_string()));
+        let response = client.get(format!("/tasks/{}", task.id)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
     }
 
     #[rocket::async_test]
     async fn test_get_project() {
         let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
 
-        let project = project::create_project(&db, "A project".to_string())
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
@@ -507,8 +924,9 @@ with many lines"#
     #[rocket::async_test]
     async fn test_get_project_tasks() {
         let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
 
-        let project = project::create_project(&db, "A project".to_string())
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let task = task::create_task_in_project(
@@ -527,24 +945,124 @@ with many lines"#
 
         let client = test_helpers::init_server(Some(db)).await.unwrap();
         let response = client
-            .get(uri!(super::get_project_tasks(project.id.to_string())))
+            .get(format!("/projects/{}/tasks", project.id))
             .dispatch()
             .await;
 
         assert_eq!(response.status(), Status::Ok);
         let response_str = response.into_string().await.unwrap();
-        let res: Vec<TaskModel> = serde_json::from_str(&response_str).expect("Task list");
+        let res = serde_json::from_str::<Page<TaskModel>>(&response_str)
+            .expect("Task list")
+            .items;
         assert_eq!(res.len(), 1);
         assert_eq!(res[0].id, task.id);
     }
 
     #[rocket::async_test]
-    async fn test_project_stats() {
+    async fn test_filter_and_page_project_tasks() {
         let db = test_helpers::db_conn().await.unwrap();
-
-        let proj_two_complete = project::create_project(&db, "Project 0/0/2".to_string())
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
+        // Title, status, due date, description
+        let specs = [
+            ("a", task::Status::Todo, Some("2000-01-01"), None),
+            ("b", task::Status::Complete, Some("2000-01-02"), None),
+            ("c", task::Status::InProgress, None, Some("Details")),
+            ("d", task::Status::Todo, Some("2999-01-01"), Some("")),
+            ("e", task::Status::InProgress, Some("2999-01-02"), None),
+        ];
+        for (title, status, due_date, description) in specs {
+            let task = task::create_task_in_project(&db, title.to_string(), status, &project.id)
+                .await
+                .unwrap();
+            let mut task: TaskActiveModel = task.into();
+            let due_date = due_date.map(|d| d.parse().unwrap());
+            task.due_date = ActiveValue::Set(due_date);
+            task.due_at = ActiveValue::Set(deadline::due_at(due_date, None, None));
+            task.description = ActiveValue::Set(description.map(str::to_string));
+            task.update(&db).await.unwrap();
+        }
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        // Titles of the listed tasks, with the cursor to the next page
+        let titles = async |query: &str| {
+            let response = client
+                .get(format!("/projects/{}/tasks?{query}", project.id))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            let response_str = response.into_string().await.unwrap();
+            let page: Page<TaskModel> = serde_json::from_str(&response_str).expect("Tasks");
+            let titles: String = page.items.into_iter().map(|t| t.title).collect();
+            (titles, page.next_cursor)
+        };
+
+        assert_eq!(titles("").await.0, "abcde");
+        assert_eq!(titles("status=todo&status=in_progress").await.0, "acde");
+        assert_eq!(titles("category=done").await.0, "b");
+        assert_eq!(titles("overdue=true").await.0, "a");
+        assert_eq!(titles("overdue=false").await.0, "bcde");
+        assert_eq!(titles("has_description=true").await.0, "c");
+        assert_eq!(
+            titles("due_after=2000-01-01&due_before=2999-01-02").await.0,
+            "bd"
+        );
+        // Ties are broken by id
+        let by_status = titles("sort=status").await.0;
+        let mut groups: Vec<Vec<char>> = [&by_status[..2], &by_status[2..4], &by_status[4..]]
+            .iter()
+            .map(|g| g.chars().collect())
+            .collect();
+        groups.iter_mut().for_each(|g| g.sort());
+        assert_eq!(groups, vec![vec!['a', 'd'], vec!['c', 'e'], vec!['b']]);
+        assert_eq!(titles("sort=title&order=desc").await.0, "edcba");
+        // Undated tasks come last either way
+        assert_eq!(titles("sort=due_date").await.0, "abdec");
+        assert_eq!(titles("sort=due_date&order=desc").await.0, "edbac");
+
+        // Pages pick up where the last one ended, ties and all
+        for sort in ["position", "due_date", "status", "title", "created_at"] {
+            let (mut all, mut query) = (String::new(), format!("sort={sort}&limit=2"));
+            loop {
+                let (page, next) = titles(&query).await;
+                assert!(page.len() <= 2);
+                all.push_str(&page);
+                let Some(cursor) = next else { break };
+                query = format!("sort={sort}&limit=2&cursor={cursor}");
+            }
+            assert_eq!(all, titles(&format!("sort={sort}")).await.0);
+        }
+
+        let response = client
+            .get(format!("/projects/{}/tasks?category=finished", project.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        // Cursors only make sense with the sort they came from
+        let (_, cursor) = titles("sort=title&limit=1").await;
+        let response = client
+            .get(format!(
+                "/projects/{}/tasks?sort=status&cursor={}",
+                project.id,
+                cursor.unwrap()
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    #[rocket::async_test]
+    async fn test_project_stats() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+
+        let proj_two_complete =
+            project::create_project(&db, &workspace_id, "Project 0/0/2".to_string())
+                .await
+                .unwrap();
         task::create_task_in_project(
             &db,
             "Task 1".to_string(),
@@ -562,9 +1080,10 @@ with many lines"#
         .await
         .unwrap();
 
-        let proj_one_complete = project::create_project(&db, "Project 1/0/1".to_string())
-            .await
-            .unwrap();
+        let proj_one_complete =
+            project::create_project(&db, &workspace_id, "Project 1/0/1".to_string())
+                .await
+                .unwrap();
         task::create_task_in_project(
             &db,
             "Task 1".to_string(),
@@ -582,13 +1101,15 @@ with many lines"#
         .await
         .unwrap();
 
-        let empty_project = project::create_project(&db, "Project 0/0/0".to_string())
-            .await
-            .unwrap();
+        let empty_project =
+            project::create_project(&db, &workspace_id, "Project 0/0/0".to_string())
+                .await
+                .unwrap();
 
-        let in_progress_proj = project::create_project(&db, "Project 0/1/0".to_string())
-            .await
-            .unwrap();
+        let in_progress_proj =
+            project::create_project(&db, &workspace_id, "Project 0/1/0".to_string())
+                .await
+                .unwrap();
         task::create_task_in_project(
             &db,
             "Task 4".to_string(),
@@ -613,23 +1134,450 @@ with many lines"#
         let response_str = response.into_string().await.unwrap();
         let res: Vec<ProjectStats> = serde_json::from_str(&response_str).expect("List of stats");
         assert_eq!(res.len(), 4);
+        // Projects created within the same second can come in any order
+        let stats = |id| res.iter().find(|s| s.id == id).unwrap();
+        let two_complete = stats(proj_two_complete.id);
+        assert_eq!((two_complete.complete, two_complete.total), (2, 2));
+        let one_complete = stats(proj_one_complete.id);
+        assert_eq!((one_complete.todo, one_complete.complete), (1, 1));
+        assert_eq!(stats(empty_project.id).total, 0);
+        let counts: Vec<(&str, u32)> = stats(in_progress_proj.id)
+            .statuses
+            .iter()
+            .map(|s| (s.key.as_str(), s.count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("todo", 0), ("in_progress", 1), ("complete", 0)]
+        );
     }
 
     #[rocket::async_test]
     async fn test_delete_project() {
         let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
 
-        let p = project::create_project(&db, "A project".to_string())
+        let p = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        project::set_member_role(&db, &p.id, &user.id, Role::Owner)
+            .await
+            .unwrap();
+        let other = project::create_project(&db, &workspace_id, "Other".to_string())
+            .await
+            .unwrap();
+        let only_here =
+            task::create_task_in_project(&db, "Only here".to_string(), task::Status::Todo, &p.id)
+                .await
+                .unwrap();
+        let also_other =
+            task::create_task_in_project(&db, "Also other".to_string(), task::Status::Todo, &p.id)
+                .await
+                .unwrap();
+        task::add_to_project(&db, &user.id, &also_other.id, &other.id)
+            .await
+            .unwrap();
+        // A subtask of a deleted task that's also in the other project
+        let subtask = task::create_subtask(&db, &only_here.id, "Subtask".to_string())
+            .await
+            .unwrap();
+        task::add_to_project(&db, &user.id, &subtask.id, &other.id)
             .await
             .unwrap();
 
         let client = test_helpers::init_server(Some(db)).await.unwrap();
         let response = client
-            .get(uri!(super::delete_project(p.id.to_string())))
+            .delete(uri!(super::delete_project(p.id.to_string())))
             .dispatch()
             .await;
 
-        // FIXME: add ability to access db after API call to verify side effects
         assert_eq!(response.status(), Status::Ok);
+        // Tasks in no other project are deleted with it
+        let response = client
+            .get(format!("/tasks/{}", only_here.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client
+            .get(format!("/tasks/{}", also_other.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        // Subtasks staying in another project move to the top level instead
+        let response = client
+            .get(format!("/tasks/{}", subtask.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let kept: TaskResponse =
+            serde_json::from_str(&response.into_string().await.unwrap()).expect("A task");
+        assert_eq!(kept.task.parent_id, None);
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_project_requires_owner() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let p = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let response = client
+            .delete(uri!(super::delete_project(p.id.to_string())))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Forbidden);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.code, "forbidden");
+    }
+
+    #[rocket::async_test]
+    async fn test_viewer_permissions() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let p = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        workspace::add_member(&db, &workspace_id, &other.id, WorkspaceRole::Member)
+            .await
+            .unwrap();
+        project::set_member_role(&db, &p.id, &other.id, Role::Owner)
+            .await
+            .unwrap();
+        project::set_member_role(&db, &p.id, &user.id, Role::Viewer)
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(&db, "Task".to_string(), task::Status::Todo, &p.id)
+            .await
+            .unwrap();
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let response = client
+            .get(format!("/projects/{}/tasks", p.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .patch(format!("/tasks/{}", task.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Renamed" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .post(uri!(super::create_task_in_project(p.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "A new task!" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn test_shared_task_permissions() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let viewing = project::create_project(&db, &workspace_id, "Viewing".to_string())
+            .await
+            .unwrap();
+        let editing = project::create_project(&db, &workspace_id, "Editing".to_string())
+            .await
+            .unwrap();
+        project::set_member_role(&db, &viewing.id, &user.id, Role::Viewer)
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(&db, "Task".to_string(), task::Status::Todo, &viewing.id)
+                .await
+                .unwrap();
+        task::add_to_project(&db, &user.id, &task.id, &editing.id)
+            .await
+            .unwrap();
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        // Editor through one of the task's projects is enough to edit it...
+        let response = client
+            .patch(format!("/tasks/{}", task.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Renamed" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // ...but deleting it would take it out of the viewer-only project too
+        let response = client
+            .delete(format!("/tasks/{}", task.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn test_project_members() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        workspace::add_member(&db, &workspace_id, &other.id, WorkspaceRole::Member)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .post(uri!(super::create_project))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Mine" }"#)
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let p: ProjectModel = serde_json::from_str(&response_str).expect("The Project");
+
+        let response = client
+            .put(uri!(super::set_project_member(
+                p.id.to_string(),
+                other.id.to_string()
+            )))
+            .header(ContentType::JSON)
+            .body(r#"{ "role": "viewer" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get(uri!(super::project_members(p.id.to_string())))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let members: Vec<ProjectMemberResponse> =
+            serde_json::from_str(&response_str).expect("Member list");
+        let roles: Vec<(String, Role)> = members
+            .into_iter()
+            .map(|m| (m.user.username, m.role))
+            .collect();
+        assert!(roles.contains(&("other".to_string(), Role::Viewer)));
+        assert!(roles.contains(&("test-user".to_string(), Role::Owner)));
+
+        // The only owner can't step down
+        let response = client
+            .put(uri!(super::set_project_member(
+                p.id.to_string(),
+                client.user.id.to_string()
+            )))
+            .header(ContentType::JSON)
+            .body(r#"{ "role": "editor" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+    }
+
+    #[rocket::async_test]
+    async fn test_get_project_history() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let other_project =
+            project::create_project(&db, &workspace_id, "Other project".to_string())
+                .await
+                .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "Task".to_string(),
+            task::Status::Todo,
+            &other_project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .patch(uri!(super::edit_project(project.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Renamed" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        for uri in [
+            uri!(super::add_task_to_project(
+                project.id.to_string(),
+                task.id.to_string()
+            )),
+            uri!(super::remove_task_from_project(
+                project.id.to_string(),
+                task.id.to_string()
+            )),
+        ] {
+            let response = client.post(uri).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+
+        let response = client
+            .get(uri!(super::get_project_history(project.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let history: Vec<HistoryEntry> = serde_json::from_str(&response_str).expect("History");
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].action, "update");
+        assert_eq!(history[0].field, Some("title".to_string()));
+        assert_eq!(history[0].old_value, Some(json!("A project")));
+        assert_eq!(history[0].new_value, Some(json!("Renamed")));
+        assert_eq!(history[1].action, "add");
+        assert_eq!(history[1].new_value, Some(json!(task.id.to_string())));
+        assert_eq!(history[2].action, "remove");
+        assert_eq!(history[2].old_value, Some(json!(task.id.to_string())));
+        assert!(
+            history
+                .iter()
+                .all(|e| e.actor_username == Some(client.user.username.clone()))
+        );
+
+        // Membership changes show up on the task too
+        let response = client
+            .get(format!("/tasks/{}/history", task.id))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let history: Vec<HistoryEntry> = serde_json::from_str(&response_str).expect("History");
+        let actions: Vec<&str> = history.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, vec!["add", "remove"]);
+    }
+
+    #[rocket::async_test]
+    async fn test_move_task_in_project() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let mut tasks = vec![];
+        for title in ["a", "b", "c", "d"] {
+            let task = task::create_task_in_project(
+                &db,
+                title.to_string(),
+                task::Status::Todo,
+                &project.id,
+            )
+            .await
+            .unwrap();
+            tasks.push(task);
+        }
+        let outside = task::create_task(&db, "outside".to_string(), task::Status::Todo)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let move_task = |task: &TaskModel, body: String| {
+            client
+                .post(uri!(super::move_task_in_project(
+                    project.id.to_string(),
+                    task.id.to_string()
+                )))
+                .header(ContentType::JSON)
+                .body(body)
+        };
+        let titles = || async {
+            let response = client
+                .get(format!("/projects/{}/tasks", project.id))
+                .dispatch()
+                .await;
+            let response_str = response.into_string().await.unwrap();
+            let tasks = serde_json::from_str::<Page<TaskModel>>(&response_str)
+                .expect("Tasks")
+                .items;
+            tasks
+                .into_iter()
+                .map(|t| t.title)
+                .collect::<Vec<_>>()
+                .join("")
+        };
+
+        let (a, b, c, d) = (&tasks[0], &tasks[1], &tasks[2], &tasks[3]);
+        let response = move_task(d, format!(r#"{{ "before": "{}" }}"#, a.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(titles().await, "dabc");
+
+        let response = move_task(a, format!(r#"{{ "after": "{}" }}"#, c.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(titles().await, "dbca");
+
+        let response = move_task(
+            c,
+            format!(r#"{{ "after": "{}", "before": "{}" }}"#, d.id, b.id),
+        )
+        .dispatch()
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(titles().await, "dcba");
+
+        // Tasks added later still go last
+        let response = client
+            .post(uri!(super::create_task_in_project(project.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "e" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(titles().await, "dcbae");
+
+        let response = move_task(a, "{}".to_string()).dispatch().await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = move_task(a, format!(r#"{{ "after": "{}" }}"#, outside.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.field, Some("after".to_string()));
+        let response = move_task(&outside, format!(r#"{{ "after": "{}" }}"#, a.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+        // Neighbours in the wrong order
+        let response = move_task(
+            c,
+            format!(r#"{{ "after": "{}", "before": "{}" }}"#, a.id, d.id),
+        )
+        .dispatch()
+        .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let err: ErrorBody = response.into_json().await.expect("An error");
+        assert_eq!(err.field, Some("before".to_string()));
+
+        // Moves are in the task's history
+        let response = client
+            .get(format!("/tasks/{}/history", c.id))
+            .dispatch()
+            .await;
+        let history: Vec<HistoryEntry> = response.into_json().await.expect("History");
+        let moves: Vec<_> = history
+            .iter()
+            .filter(|e| e.field.as_deref() == Some("position"))
+            .collect();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].action, "update");
+        assert_eq!(
+            moves[0].new_value.as_ref().unwrap()["project_id"],
+            json!(project.id)
+        );
     }
 }
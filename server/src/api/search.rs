@@ -0,0 +1,352 @@
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::DatabaseConnection;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+use super::auth::AuthUser;
+use super::subscription::{EntityType, FeedWriter, UpdateEvent};
+use crate::models::fuzzy::{FuzzyHit, FuzzyIndex};
+use crate::models::search::{self, SearchHit};
+use crate::models::workspace;
+use crate::result::Result;
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 100;
+
+struct IndexState {
+    updates: Receiver<UpdateEvent>,
+    // Built on first use
+    index: Option<FuzzyIndex>,
+}
+
+impl IndexState {
+    // Apply updates broadcast since last time, rebuilding if any were missed
+    async fn catch_up(&mut self, db: &DatabaseConnection) -> Result<&FuzzyIndex> {
+        let mut events = vec![];
+        loop {
+            match self.updates.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Lagged(_)) => self.index = None,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+
+        // Taken out while updating, so it's rebuilt next time if that fails
+        let mut index = match self.index.take() {
+            Some(index) => index,
+            None => FuzzyIndex::load(db).await?,
+        };
+        for event in events {
+            match event.entity_type {
+                EntityType::Task => index.refresh_task(db, &event.entity_id).await?,
+                EntityType::Project => index.refresh_project(db, &event.entity_id).await?,
+                EntityType::Comment => {
+                    if let Some(task_id) = event.task_id {
+                        index.refresh_task_comments(db, &task_id).await?;
+                    }
+                }
+                EntityType::Workspace | EntityType::View | EntityType::Notification => {}
+            }
+        }
+        Ok(self.index.insert(index))
+    }
+}
+
+// Fuzzy search index, kept up to date from the update feed. Mutations
+// broadcast before responding, and searches catch up with the feed before
+// running, so results always reflect earlier requests.
+pub struct FuzzySearch {
+    state: Arc<Mutex<IndexState>>,
+}
+
+impl FuzzySearch {
+    pub fn start(db: DatabaseConnection, feed: &FeedWriter) -> Self {
+        let state = Arc::new(Mutex::new(IndexState {
+            updates: feed.subscribe(),
+            index: None,
+        }));
+
+        // Also catch up in the background, so the feed doesn't overflow
+        // between searches
+        let mut doorbell = feed.subscribe();
+        let background = state.clone();
+        tokio::spawn(async move {
+            while !matches!(doorbell.recv().await, Err(RecvError::Closed)) {
+                if let Err(e) = background.lock().await.catch_up(&db).await {
+                    eprintln!("Failed to update fuzzy search index: {e:?}");
+                }
+            }
+        });
+
+        FuzzySearch { state }
+    }
+
+    async fn search(
+        &self,
+        db: &DatabaseConnection,
+        workspace_ids: &[uuid::Uuid],
+        q: &str,
+        limit: usize,
+    ) -> Result<Vec<FuzzyHit>> {
+        let mut state = self.state.lock().await;
+        let index = state.catch_up(db).await?;
+        index.search(db, workspace_ids, q, limit).await
+    }
+}
+
+// Search tasks and projects in the caller's workspaces, best matches first.
+// All words must match; use "quotes" for phrases and a trailing * for prefixes.
+#[get("/search?<q>&<limit>")]
+async fn search_all(
+    q: &str,
+    limit: Option<u32>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<SearchHit>>> {
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let hits = search::search(db.inner(), workspace_ids, q, limit).await?;
+    Ok(Json(hits))
+}
+
+// Typo tolerant search over tasks, projects and comments in the caller's
+// workspaces, best matches first. Words can match in any order.
+#[get("/search/fuzzy?<q>&<limit>")]
+async fn fuzzy_search(
+    q: &str,
+    limit: Option<u32>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    index: &State<FuzzySearch>,
+) -> Result<Json<Vec<FuzzyHit>>> {
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let hits = index
+        .search(db.inner(), &workspace_ids, q, limit as usize)
+        .await?;
+    Ok(Json(hits))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![search_all, fuzzy_search]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::access::Role;
+    use crate::models::fuzzy::{DocKind, FuzzyHit};
+    use crate::models::search::SearchHit;
+    use crate::models::{project, task, user, workspace};
+    use crate::test_helpers;
+    use rocket::http::{ContentType, Status};
+    use serde_json;
+
+    async fn search(client: &test_helpers::TestClient, q: &str) -> Vec<SearchHit> {
+        let response = client.get(uri!(super::search_all(q, _))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        serde_json::from_str(&response_str).expect("Search hits")
+    }
+
+    fn titles(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|h| h.title.as_str()).collect()
+    }
+
+    #[rocket::async_test]
+    async fn test_search() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let garden = project::create_project(&db, &workspace_id, "Garden".to_string())
+            .await
+            .unwrap();
+        let tasks = [
+            ("Plant tomatoes", None),
+            ("Water plants", Some("Mostly the tomato seedlings")),
+            (
+                "Build a fence",
+                Some("Keep the rabbits away from the plants"),
+            ),
+        ];
+        for (title, description) in tasks {
+            let task = task::create_task_in_project(
+                &db,
+                title.to_string(),
+                task::Status::Todo,
+                &garden.id,
+            )
+            .await
+            .unwrap();
+            if let Some(description) = description {
+                let mut task: task::TaskActiveModel = task.into();
+                task.description = sea_orm::ActiveValue::Set(Some(description.to_string()));
+                sea_orm::ActiveModelTrait::update(task, &db).await.unwrap();
+            }
+        }
+
+        // Not visible to the test user
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let other_workspace = workspace::workspace_ids_for_user(&db, &other.id)
+            .await
+            .unwrap()[0];
+        let hidden = project::create_project(&db, &other_workspace, "Tomato farm".to_string())
+            .await
+            .unwrap();
+        task::create_task_in_project(
+            &db,
+            "Plant tomatoes".to_string(),
+            task::Status::Todo,
+            &hidden.id,
+        )
+        .await
+        .unwrap();
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        // Title matches rank above description matches
+        let hits = search(&client, "plant*").await;
+        assert_eq!(hits[0].title, "Plant tomatoes");
+        assert_eq!(hits.len(), 3);
+        assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+
+        // Every word has to match
+        let hits = search(&client, "tomato* plant*").await;
+        assert_eq!(titles(&hits), vec!["Plant tomatoes", "Water plants"]);
+        assert_eq!(hits[0].snippet, "<mark>Plant</mark> <mark>tomatoes</mark>");
+
+        // Phrases match in order
+        let hits = search(&client, r#""rabbits away""#).await;
+        assert_eq!(titles(&hits), vec!["Build a fence"]);
+        assert_eq!(hits[0].entity_type, "task");
+        let hits = search(&client, r#""away rabbits""#).await;
+        assert!(hits.is_empty());
+
+        // Projects too
+        let hits = search(&client, "garden").await;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].entity_type, "project");
+        assert_eq!(hits[0].id, garden.id);
+
+        // Kept up to date as titles change
+        let response = client
+            .patch(format!("/projects/{}", garden.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Allotment" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(search(&client, "garden").await.is_empty());
+        assert_eq!(search(&client, "allotment").await.len(), 1);
+
+        // Query syntax can't be broken by user input
+        assert!(search(&client, "fence OR) NEAR(").await.is_empty());
+        let response = client
+            .get(uri!(super::search_all("  \"* ", _)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    async fn fuzzy_search(client: &test_helpers::TestClient, q: &str) -> Vec<FuzzyHit> {
+        let response = client.get(uri!(super::fuzzy_search(q, _))).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        serde_json::from_str(&response_str).expect("Search hits")
+    }
+
+    #[rocket::async_test]
+    async fn test_fuzzy_search() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "Philosophy".to_string())
+            .await
+            .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        project::set_member_role(&db, &project.id, &user.id, Role::Owner)
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "Comprehend nothingness".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        task::create_task_in_project(
+            &db,
+            "Go on a walk".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let other_workspace = workspace::workspace_ids_for_user(&db, &other.id)
+            .await
+            .unwrap()[0];
+        let hidden = project::create_project(&db, &other_workspace, "Hidden".to_string())
+            .await
+            .unwrap();
+        task::create_task_in_project(
+            &db,
+            "Comprehend everything".to_string(),
+            task::Status::Todo,
+            &hidden.id,
+        )
+        .await
+        .unwrap();
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        // Typos and word order don't matter
+        for q in ["comprehnd nothng", "nothingness comprehend", "COMPREHEND"] {
+            let hits = fuzzy_search(&client, q).await;
+            assert_eq!(hits.len(), 1, "{q}");
+            assert_eq!(hits[0].id, task.id);
+            assert_eq!(hits[0].entity_type, DocKind::Task);
+        }
+        let hits = fuzzy_search(&client, "philosphy").await;
+        assert_eq!(hits[0].id, project.id);
+        assert!(fuzzy_search(&client, "comprehend walk").await.is_empty());
+
+        // Edits show up straight away
+        let response = client
+            .patch(format!("/tasks/{}", task.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Ponder the void" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(fuzzy_search(&client, "nothingness").await.is_empty());
+        assert_eq!(fuzzy_search(&client, "pondr void").await[0].id, task.id);
+
+        let response = client
+            .post(format!("/tasks/{}/comments", task.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "body": "Maybe read some Heidegger first" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let hits = fuzzy_search(&client, "heideger").await;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].entity_type, DocKind::Comment);
+        assert_eq!(hits[0].task_id, Some(task.id));
+
+        // Tasks left without a project are no longer visible
+        let response = client
+            .delete(format!("/projects/{}", project.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(fuzzy_search(&client, "void").await.is_empty());
+        assert!(fuzzy_search(&client, "heidegger").await.is_empty());
+    }
+}
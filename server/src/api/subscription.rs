@@ -1,85 +1,953 @@
 use anyhow::Result;
-use rocket::futures::SinkExt;
+use base64::prelude::*;
+use chrono::Utc;
 use rocket::futures::stream::FusedStream;
+use rocket::futures::{SinkExt, StreamExt};
 use rocket::tokio::select;
 use rocket::{Route, Shutdown, State};
-use serde::Serialize;
-use tokio::sync::broadcast::Sender;
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{self, Receiver, Sender};
 use uuid::Uuid;
 use ws::WebSocket;
+use ws::stream::DuplexStream;
 
-pub type FeedWriter = Sender<UpdateEvent>;
+use super::auth::AuthUser;
+use super::presence::{PresenceEvent, PresenceKind, PresenceTable, Present};
+use crate::models::access::{self, ProjectAction, TaskAction};
+use crate::models::description;
+use crate::models::event_log::{self, EventLogModel};
+use crate::models::notification::NotificationModel;
+use crate::models::task;
+use crate::models::view;
+use crate::models::workspace;
+use crate::result::{Error, ErrorBody};
 
-#[derive(Serialize, Debug, Clone)]
+// How many of the latest events are kept for clients catching up
+const EVENT_LOG_SIZE: usize = 1000;
+
+// Broadcasts update events, numbering them in the order they're sent and
+// keeping the latest so clients can be sent the ones they missed
+#[derive(Clone)]
+pub struct FeedWriter {
+    sender: Sender<UpdateEvent>,
+    log: Arc<Mutex<EventLog>>,
+}
+
+struct EventLog {
+    seq: u64,
+    events: VecDeque<UpdateEvent>,
+}
+
+impl EventLog {
+    // Kept events after the given one
+    fn after(&self, seq: u64) -> Vec<UpdateEvent> {
+        let first = self.events.front().map_or(self.seq + 1, |e| e.seq);
+        let skip = (seq + 1).saturating_sub(first) as usize;
+        self.events.iter().skip(skip).cloned().collect()
+    }
+
+    // Events after the given one, or None if some are no longer kept
+    fn since(&self, seq: u64) -> Option<Vec<UpdateEvent>> {
+        let first = self.events.front().map_or(self.seq + 1, |e| e.seq);
+        if seq > self.seq || seq + 1 < first {
+            return None;
+        }
+        Some(self.after(seq))
+    }
+}
+
+// A subscription to the feed picking up where a client left off
+pub struct Resumed {
+    pub receiver: Receiver<UpdateEvent>,
+    // Events since the client's last one, if they're all still kept
+    pub missed: Option<Vec<UpdateEvent>>,
+    pub seq: u64,
+}
+
+impl FeedWriter {
+    pub fn new(capacity: usize) -> Self {
+        FeedWriter {
+            sender: broadcast::channel(capacity).0,
+            log: Arc::new(Mutex::new(EventLog {
+                seq: 0,
+                events: VecDeque::new(),
+            })),
+        }
+    }
+
+    // Continue numbering from the events logged before a restart
+    pub async fn load(db: &DatabaseConnection, capacity: usize) -> Result<Self> {
+        let feed = FeedWriter::new(capacity);
+        let logged = event_log::recent(db, EVENT_LOG_SIZE as u64)
+            .await
+            .map_err(|e| anyhow::Error::msg(format!("{e:?}")))?;
+        {
+            let mut log = feed.log.lock().unwrap();
+            for logged in logged {
+                let event: UpdateEvent = serde_json::from_value(logged.event)?;
+                log.seq = event.seq;
+                log.events.push_back(event);
+            }
+        }
+        Ok(feed)
+    }
+
+    pub fn subscribe(&self) -> Receiver<UpdateEvent> {
+        self.sender.subscribe()
+    }
+
+    // Sequence number of the latest event
+    pub fn seq(&self) -> u64 {
+        self.log.lock().unwrap().seq
+    }
+
+    pub fn since(&self, seq: u64) -> Option<Vec<UpdateEvent>> {
+        self.log.lock().unwrap().since(seq)
+    }
+
+    // Subscribe along with the events after `seq`, without gaps or repeats
+    pub fn resume(&self, seq: Option<u64>) -> Resumed {
+        let log = self.log.lock().unwrap();
+        Resumed {
+            receiver: self.sender.subscribe(),
+            missed: match seq {
+                Some(seq) => log.since(seq),
+                None => Some(vec![]),
+            },
+            seq: log.seq,
+        }
+    }
+
+    fn after(&self, seq: u64) -> Vec<UpdateEvent> {
+        self.log.lock().unwrap().after(seq)
+    }
+
+    fn send(&self, mut event: UpdateEvent) {
+        let mut log = self.log.lock().unwrap();
+        log.seq += 1;
+        event.seq = log.seq;
+        log.events.push_back(event.clone());
+        if log.events.len() > EVENT_LOG_SIZE {
+            log.events.pop_front();
+        }
+        // Only fails when nobody is listening
+        let _ = self.sender.send(event);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum UpdateKind {
     Create,
     Update,
     Destroy,
+    // A task entering or leaving a saved view
+    Add,
+    Remove,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum EntityType {
     Project,
     Task,
+    Workspace,
+    Comment,
+    View,
+    Notification,
+}
+
+// Projects a task joined or left
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ProjectChanges {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<Uuid>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<Uuid>,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UpdateEvent {
-    kind: UpdateKind,
-    entity_id: Uuid,
-    entity_type: EntityType,
+    // Position in the feed, assigned when sent
+    #[serde(default)]
+    pub seq: u64,
+    pub kind: UpdateKind,
+    pub entity_id: Uuid,
+    pub entity_type: EntityType,
+    pub workspace_id: Uuid,
+    // Task the entity hangs off for comments and notifications, or the task
+    // that entered or left a view
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<Uuid>,
+    // Only sent to this user, for notifications
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<Uuid>,
+    // The entity after the change, as the API returns it, so clients can
+    // update their caches without refetching it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<Value>,
+    // Top level fields of the entity that changed, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed_fields: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projects: Option<ProjectChanges>,
+    // Projects the task, or the comment's task, is in, so subscribers to them
+    // are found without looking the task up for each
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub project_ids: Vec<Uuid>,
+    // User who joined or left the workspace, for membership changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member_id: Option<Uuid>,
+    // Base64 update to the task's description document, for clients editing
+    // it collaboratively to merge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_update: Option<String>,
 }
 
 impl UpdateEvent {
-    pub fn broadcast(
-        sender: &FeedWriter,
+    pub fn new(
+        workspace_id: Uuid,
         kind: UpdateKind,
         entity_type: EntityType,
         entity_id: Uuid,
-    ) -> Result<()> {
-        println!("Sending to feed");
-        match sender.send(UpdateEvent {
+    ) -> Self {
+        UpdateEvent {
+            seq: 0,
             kind,
             entity_id,
             entity_type,
-        }) {
-            Ok(_) => {}
-            Err(e) => eprintln!("Failed to write to feed: {e:?}"),
+            workspace_id,
+            task_id: None,
+            user_id: None,
+            entity: None,
+            changed_fields: None,
+            projects: None,
+            project_ids: vec![],
+            member_id: None,
+            description_update: None,
+        }
+    }
+
+    pub fn with_entity<T: Serialize>(mut self, entity: &T) -> Result<Self> {
+        self.entity = Some(serde_json::to_value(entity)?);
+        Ok(self)
+    }
+
+    pub fn with_changed_fields<S: Into<String>>(
+        mut self,
+        fields: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.changed_fields = Some(fields.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn with_projects(mut self, added: Vec<Uuid>, removed: Vec<Uuid>) -> Self {
+        self.projects = Some(ProjectChanges { added, removed });
+        self
+    }
+
+    pub fn in_projects(mut self, project_ids: Vec<Uuid>) -> Self {
+        self.project_ids = project_ids;
+        self
+    }
+
+    pub fn with_member(mut self, user_id: Uuid) -> Self {
+        self.member_id = Some(user_id);
+        self
+    }
+
+    pub fn with_description_update(mut self, update: &[u8]) -> Self {
+        self.description_update = Some(BASE64_STANDARD.encode(update));
+        self
+    }
+
+    pub fn broadcast(
+        sender: &FeedWriter,
+        workspace_id: Uuid,
+        kind: UpdateKind,
+        entity_type: EntityType,
+        entity_id: Uuid,
+    ) {
+        UpdateEvent::new(workspace_id, kind, entity_type, entity_id).send(sender)
+    }
+
+    pub fn broadcast_view_change(
+        sender: &FeedWriter,
+        workspace_id: Uuid,
+        kind: UpdateKind,
+        view_id: Uuid,
+        task_id: Uuid,
+    ) {
+        UpdateEvent {
+            task_id: Some(task_id),
+            ..UpdateEvent::new(workspace_id, kind, EntityType::View, view_id)
+        }
+        .send(sender)
+    }
+
+    // Event about a comment on the task
+    pub fn comment(workspace_id: Uuid, kind: UpdateKind, comment_id: Uuid, task_id: Uuid) -> Self {
+        UpdateEvent {
+            task_id: Some(task_id),
+            ..UpdateEvent::new(workspace_id, kind, EntityType::Comment, comment_id)
+        }
+    }
+
+    pub fn broadcast_notification(
+        sender: &FeedWriter,
+        workspace_id: Uuid,
+        notification: &NotificationModel,
+    ) -> Result<()> {
+        UpdateEvent {
+            task_id: Some(notification.task_id),
+            user_id: Some(notification.user_id),
+            ..UpdateEvent::new(
+                workspace_id,
+                UpdateKind::Create,
+                EntityType::Notification,
+                notification.id,
+            )
+        }
+        .with_entity(notification)?
+        .send(sender);
+        Ok(())
+    }
+
+    pub fn send(self, sender: &FeedWriter) {
+        sender.send(self);
+    }
+}
+
+async fn workspace_ids(db: &DatabaseConnection, user_id: &Uuid) -> HashSet<Uuid> {
+    match workspace::workspace_ids_for_user(db, user_id).await {
+        Ok(ids) => ids.into_iter().collect(),
+        Err(e) => {
+            eprintln!("Failed to load workspaces for subscriber {user_id}: {e:?}");
+            HashSet::new()
+        }
+    }
+}
+
+// Something a websocket client can ask to hear about. A workspace covers
+// everything in it, a project the tasks in it, and a view the tasks it holds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum Topic {
+    Workspace(Uuid),
+    Project(Uuid),
+    Task(Uuid),
+    View(Uuid),
+}
+
+// Messages clients send over the websocket, like
+// {"action": "subscribe", "topic": {"type": "project", "id": "..."}}
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe {
+        topic: Topic,
+    },
+    Unsubscribe {
+        topic: Topic,
+    },
+    // Ask for what the client's copy of the task's description document is
+    // missing, given its base64 state vector, or for all of it
+    DescriptionSync {
+        task_id: Uuid,
+        state_vector: Option<String>,
+    },
+    // Merge a base64 update into the task's description document
+    DescriptionUpdate {
+        task_id: Uuid,
+        update: String,
+    },
+    // Announce being on a project or task, and the field being edited if any
+    Join {
+        topic: Topic,
+        field: Option<String>,
+    },
+    Leave {
+        topic: Topic,
+    },
+    // Stay present, sent every so often. Any other message does too.
+    Heartbeat,
+}
+
+// Replies to client messages, sent alongside update events
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Subscribed {
+        topic: Topic,
+    },
+    Unsubscribed {
+        topic: Topic,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        topic: Option<Topic>,
+        #[serde(flatten)]
+        error: ErrorBody,
+    },
+    // Events were missed that can no longer be sent. Clients should refetch
+    // what they show, which is up to date as of `seq`.
+    ResyncRequired {
+        seq: u64,
+    },
+    // Answers description_sync with a base64 update
+    DescriptionState {
+        task_id: Uuid,
+        update: String,
+    },
+    // The client's update was merged, and is sent on to others editing the
+    // description along with the task's new state
+    DescriptionMerged {
+        task_id: Uuid,
+    },
+    // Answers join with who else is there
+    Joined {
+        topic: Topic,
+        present: Vec<Present>,
+    },
+    Left {
+        topic: Topic,
+    },
+    Heartbeat,
+    // Someone else joined or left a project or task the client is on or
+    // subscribed to
+    Presence(PresenceEvent),
+}
+
+impl ServerMessage {
+    fn error(topic: Option<Topic>, error: Error) -> Self {
+        if let Error::Internal(e) = &error {
+            eprintln!("Internal error handling subscription to {topic:?}: {e:?}");
+        }
+        ServerMessage::Error {
+            topic,
+            error: error.body(),
+        }
+    }
+}
+
+// The topics a websocket client subscribed to
+struct Subscriptions {
+    user_id: Uuid,
+    workspaces: HashSet<Uuid>,
+    topics: HashSet<Topic>,
+    // Tasks in each subscribed view, kept up to date by its add and remove
+    // events
+    views: HashMap<Uuid, HashSet<Uuid>>,
+    presence: PresenceTable,
+    connection_id: Uuid,
+    // Projects and tasks the client joined
+    joined: HashSet<Topic>,
+}
+
+impl Subscriptions {
+    async fn new(db: &DatabaseConnection, user_id: Uuid, presence: PresenceTable) -> Self {
+        Subscriptions {
+            user_id,
+            workspaces: workspace_ids(db, &user_id).await,
+            topics: HashSet::new(),
+            views: HashMap::new(),
+            presence,
+            connection_id: Uuid::new_v4(),
+            joined: HashSet::new(),
+        }
+    }
+
+    async fn handle(
+        &mut self,
+        db: &DatabaseConnection,
+        feed: &FeedWriter,
+        message: &str,
+    ) -> ServerMessage {
+        let message = match serde_json::from_str(message) {
+            Ok(message) => message,
+            Err(e) => {
+                let error = Error::validation("action", format!("Invalid message: {e}"));
+                return ServerMessage::error(None, error);
+            }
+        };
+        self.presence.heartbeat(self.connection_id);
+        match message {
+            ClientMessage::Subscribe { topic } => match self.subscribe(db, topic).await {
+                Ok(()) => ServerMessage::Subscribed { topic },
+                Err(e) => ServerMessage::error(Some(topic), e),
+            },
+            ClientMessage::Unsubscribe { topic } => {
+                self.unsubscribe(topic);
+                ServerMessage::Unsubscribed { topic }
+            }
+            ClientMessage::DescriptionSync {
+                task_id,
+                state_vector,
+            } => match self.sync_description(db, &task_id, state_vector).await {
+                Ok(update) => ServerMessage::DescriptionState { task_id, update },
+                Err(e) => ServerMessage::error(Some(Topic::Task(task_id)), e),
+            },
+            ClientMessage::DescriptionUpdate { task_id, update } => {
+                match self.update_description(db, feed, &task_id, &update).await {
+                    Ok(()) => ServerMessage::DescriptionMerged { task_id },
+                    Err(e) => ServerMessage::error(Some(Topic::Task(task_id)), e),
+                }
+            }
+            ClientMessage::Join { topic, field } => match self.join(db, topic, field).await {
+                Ok(present) => ServerMessage::Joined { topic, present },
+                Err(e) => ServerMessage::error(Some(topic), e),
+            },
+            ClientMessage::Leave { topic } => {
+                self.joined.remove(&topic);
+                self.presence.leave(self.connection_id, topic);
+                ServerMessage::Left { topic }
+            }
+            ClientMessage::Heartbeat => ServerMessage::Heartbeat,
+        }
+    }
+
+    async fn join(
+        &mut self,
+        db: &DatabaseConnection,
+        topic: Topic,
+        field: Option<String>,
+    ) -> crate::result::Result<Vec<Present>> {
+        if field
+            .as_ref()
+            .is_some_and(|f| f.is_empty() || f.len() > 100)
+        {
+            return Err(Error::validation(
+                "field",
+                "Field must be between 1 and 100 characters",
+            ));
+        }
+        let (workspace_id, project_ids) = match topic {
+            Topic::Project(id) => {
+                let project =
+                    access::require_project(db, &self.user_id, &id, ProjectAction::View).await?;
+                (project.workspace_id, vec![])
+            }
+            Topic::Task(id) => {
+                let access = access::require_task(db, &self.user_id, &id, TaskAction::View).await?;
+                (access.workspace_id, task::project_ids(db, &id).await?)
+            }
+            Topic::Workspace(_) | Topic::View(_) => {
+                return Err(Error::validation(
+                    "topic",
+                    "Only projects and tasks can be joined",
+                ));
+            }
+        };
+        self.joined.insert(topic);
+        let present = self.presence.join(
+            self.connection_id,
+            self.user_id,
+            workspace_id,
+            project_ids,
+            topic,
+            field,
+        );
+        Ok(present)
+    }
+
+    async fn sync_description(
+        &self,
+        db: &DatabaseConnection,
+        task_id: &Uuid,
+        state_vector: Option<String>,
+    ) -> crate::result::Result<String> {
+        let access = access::require_task(db, &self.user_id, task_id, TaskAction::View).await?;
+        let state_vector = state_vector
+            .map(|sv| decode_base64("state_vector", &sv))
+            .transpose()?;
+        let update = description::missing_update(db, &access.task, state_vector.as_deref()).await?;
+        Ok(BASE64_STANDARD.encode(update))
+    }
+
+    async fn update_description(
+        &self,
+        db: &DatabaseConnection,
+        feed: &FeedWriter,
+        task_id: &Uuid,
+        update: &str,
+    ) -> crate::result::Result<()> {
+        let access = access::require_task(db, &self.user_id, task_id, TaskAction::Edit).await?;
+        let update = decode_base64("update", update)?;
+        let task = description::apply_update(db, &self.user_id, task_id, &update).await?;
+        let changed_fields = match task.description != access.task.description {
+            true => vec!["description"],
+            false => vec![],
+        };
+        let project_ids = task::project_ids(db, task_id).await?;
+        let response = task::response(db, task).await?;
+        UpdateEvent::new(
+            access.workspace_id,
+            UpdateKind::Update,
+            EntityType::Task,
+            *task_id,
+        )
+        .with_entity(&response)?
+        .with_changed_fields(changed_fields)
+        .with_description_update(&update)
+        .in_projects(project_ids)
+        .send(feed);
+        Ok(())
+    }
+
+    async fn subscribe(
+        &mut self,
+        db: &DatabaseConnection,
+        topic: Topic,
+    ) -> crate::result::Result<()> {
+        match topic {
+            Topic::Workspace(id) => access::require_workspace(db, &self.user_id, &id).await?,
+            Topic::Project(id) => {
+                access::require_project(db, &self.user_id, &id, ProjectAction::View).await?;
+            }
+            Topic::Task(id) => {
+                access::require_task(db, &self.user_id, &id, TaskAction::View).await?;
+            }
+            Topic::View(id) => self.load_view(db, &id).await?,
         }
+        self.topics.insert(topic);
+        Ok(())
+    }
+
+    async fn load_view(&mut self, db: &DatabaseConnection, id: &Uuid) -> crate::result::Result<()> {
+        let workspace_ids = self.workspaces.iter().copied().collect();
+        let view = view::find_view(db, workspace_ids, id).await?;
+        let task_ids = view::task_ids(db, &view).await?;
+        self.views.insert(*id, task_ids);
         Ok(())
     }
+
+    fn unsubscribe(&mut self, topic: Topic) {
+        self.topics.remove(&topic);
+        if let Topic::View(id) = topic {
+            self.views.remove(&id);
+        }
+    }
+
+    // Whether the client should be sent the event. Decided from what the event
+    // and the subscriptions hold, so the database is only read for changes to
+    // this client's own workspaces or views.
+    async fn wants(
+        &mut self,
+        db: &DatabaseConnection,
+        event: &UpdateEvent,
+    ) -> crate::result::Result<bool> {
+        // The user joined or left the workspace
+        if event.entity_type == EntityType::Workspace && event.member_id == Some(self.user_id) {
+            self.workspaces = workspace_ids(db, &self.user_id).await;
+        }
+        if !self.workspaces.contains(&event.workspace_id) {
+            return Ok(false);
+        }
+        // Personal events need no subscription
+        if let Some(user_id) = event.user_id {
+            return Ok(user_id == self.user_id);
+        }
+        if self.topics.contains(&Topic::Workspace(event.workspace_id)) {
+            return Ok(true);
+        }
+        match event.entity_type {
+            EntityType::Project => Ok(self.topics.contains(&Topic::Project(event.entity_id))),
+            EntityType::Task => Ok(self.wants_task(
+                &event.entity_id,
+                &event.project_ids,
+                event.projects.as_ref(),
+            )),
+            EntityType::Comment => Ok(event
+                .task_id
+                .is_some_and(|task_id| self.wants_task(&task_id, &event.project_ids, None))),
+            EntityType::View => {
+                let id = event.entity_id;
+                let Some(task_ids) = self.views.get_mut(&id) else {
+                    return Ok(false);
+                };
+                match (&event.kind, event.task_id) {
+                    (UpdateKind::Add, Some(task_id)) => {
+                        task_ids.insert(task_id);
+                    }
+                    (UpdateKind::Remove, Some(task_id)) => {
+                        task_ids.remove(&task_id);
+                    }
+                    // Its definition may have changed
+                    (UpdateKind::Update, _) => self.load_view(db, &id).await?,
+                    (UpdateKind::Destroy, _) => self.unsubscribe(Topic::View(id)),
+                    _ => {}
+                }
+                Ok(true)
+            }
+            EntityType::Workspace | EntityType::Notification => Ok(false),
+        }
+    }
+
+    // Whether the event is the connection itself being dropped for missing
+    // heartbeats, forgetting it joined there
+    fn expired(&mut self, event: &PresenceEvent) -> bool {
+        event.kind == PresenceKind::Leave
+            && event.present.connection_id == self.connection_id
+            && self.joined.remove(&event.topic)
+    }
+
+    // Whether the client should hear of someone else joining or leaving, when
+    // on or subscribed to the same project or task
+    fn wants_presence(&self, event: &PresenceEvent) -> bool {
+        if event.present.connection_id == self.connection_id
+            || !self.workspaces.contains(&event.workspace_id)
+        {
+            return false;
+        }
+        if self.joined.contains(&event.topic)
+            || self.topics.contains(&event.topic)
+            || self.topics.contains(&Topic::Workspace(event.workspace_id))
+        {
+            return true;
+        }
+        match event.topic {
+            Topic::Task(id) => self.wants_task(&id, &event.project_ids, None),
+            _ => false,
+        }
+    }
+
+    // The task itself, any of its projects including ones it just joined or
+    // left, or a view holding it
+    fn wants_task(
+        &self,
+        task_id: &Uuid,
+        project_ids: &[Uuid],
+        projects: Option<&ProjectChanges>,
+    ) -> bool {
+        let changed = projects
+            .into_iter()
+            .flat_map(|p| p.added.iter().chain(&p.removed));
+        self.topics.contains(&Topic::Task(*task_id))
+            || project_ids
+                .iter()
+                .chain(changed)
+                .any(|id| self.topics.contains(&Topic::Project(*id)))
+            || self.views.values().any(|ids| ids.contains(task_id))
+    }
 }
 
-#[get("/subscribe")]
-fn subscribe(
+// Leave everywhere the client was when its connection closes
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        self.presence.disconnect(self.connection_id);
+    }
+}
+
+fn decode_base64(field: &str, s: &str) -> crate::result::Result<Vec<u8>> {
+    BASE64_STANDARD
+        .decode(s)
+        .map_err(|e| Error::validation(field, format!("Invalid base64: {e}")))
+}
+
+// Write events to the database as they're sent, so clients can catch up on
+// them after a restart
+pub fn start_event_log(db: DatabaseConnection, feed: &FeedWriter) {
+    let mut updates = feed.subscribe();
+    let feed = feed.clone();
+    let mut logged = feed.seq();
+    tokio::spawn(async move {
+        loop {
+            // The feed holds on to what was missed when lagging
+            if let Err(RecvError::Closed) = updates.recv().await {
+                return;
+            }
+            let events = feed.after(logged);
+            let Some(last) = events.last().map(|e| e.seq) else {
+                continue;
+            };
+            match log_events(&db, events).await {
+                Ok(()) => logged = last,
+                Err(e) => eprintln!("Failed to log update events: {e:?}"),
+            }
+        }
+    });
+}
+
+async fn log_events(
+    db: &DatabaseConnection,
+    events: Vec<UpdateEvent>,
+) -> crate::result::Result<()> {
+    let last = events.last().map_or(0, |e| e.seq);
+    let now = Utc::now().naive_utc();
+    let events = events
+        .into_iter()
+        .map(|e| {
+            Ok(EventLogModel {
+                seq: e.seq as i64,
+                workspace_id: e.workspace_id,
+                event: serde_json::to_value(&e)?,
+                created_at: now,
+            })
+        })
+        .collect::<crate::result::Result<Vec<_>>>()?;
+    event_log::append(db, events).await?;
+    event_log::prune(db, last as i64 - EVENT_LOG_SIZE as i64).await
+}
+
+async fn reply(stream: &mut DuplexStream, message: &ServerMessage) -> ws::result::Result<()> {
+    match serde_json::to_string(message) {
+        Ok(message) => stream.send(message.into()).await,
+        Err(e) => {
+            eprintln!("Failed to process reply. Reply: {message:?} Error: {e:?}");
+            Ok(())
+        }
+    }
+}
+
+// Send the event if the client subscribed to it
+async fn forward(
+    stream: &mut DuplexStream,
+    subscriptions: &mut Subscriptions,
+    db: &DatabaseConnection,
+    update: &UpdateEvent,
+) -> ws::result::Result<()> {
+    match subscriptions.wants(db, update).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(()),
+        Err(e) => {
+            eprintln!("Failed to route update event. Event: {update:?} Error: {e:?}");
+            return Ok(());
+        }
+    }
+    let message = match serde_json::to_string(update) {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!("Failed to process update event. Event: {update:?} Error: {e:?}");
+            return Ok(());
+        }
+    };
+    stream.send(message.into()).await
+}
+
+// Parse topics given like project:<uuid>
+fn parse_topic(s: &str) -> Option<Topic> {
+    let (kind, id) = s.split_once(':')?;
+    let id = Uuid::parse_str(id).ok()?;
+    match kind {
+        "workspace" => Some(Topic::Workspace(id)),
+        "project" => Some(Topic::Project(id)),
+        "task" => Some(Topic::Task(id)),
+        "view" => Some(Topic::View(id)),
+        _ => None,
+    }
+}
+
+// Push update events for the topics the client subscribes to over the socket.
+// Reconnecting clients pass the topics they had and the seq of the last event
+// they got, and are sent what they missed, or told to resync if it's too old.
+#[get("/subscribe?<since>&<topic>")]
+#[allow(clippy::too_many_arguments)] // Rocket fills them in
+async fn subscribe(
+    since: Option<u64>,
+    topic: Vec<&str>,
     ws: WebSocket,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
     update_feed: &State<FeedWriter>,
+    presence: &State<PresenceTable>,
     mut shutdown: Shutdown,
 ) -> ws::Channel<'static> {
-    let mut feed = update_feed.subscribe();
+    let db = db.inner().clone();
+    let mut presence_events = presence.subscribe();
+    let mut subscriptions = Subscriptions::new(&db, auth.user.id, presence.inner().clone()).await;
+    let mut replies = vec![];
+    for topic in topic {
+        let reply = match parse_topic(topic) {
+            Some(topic) => match subscriptions.subscribe(&db, topic).await {
+                Ok(()) => continue,
+                Err(e) => ServerMessage::error(Some(topic), e),
+            },
+            None => ServerMessage::error(
+                None,
+                Error::validation("topic", format!("Invalid topic {topic}")),
+            ),
+        };
+        replies.push(reply);
+    }
+    let Resumed {
+        receiver: mut feed,
+        missed,
+        seq: mut last_seq,
+    } = update_feed.resume(since);
+    let update_feed = update_feed.inner().clone();
 
     ws.channel(move |mut stream| {
         Box::pin(async move {
+            for message in &replies {
+                reply(&mut stream, message).await?;
+            }
+            match missed {
+                Some(missed) => {
+                    for update in &missed {
+                        forward(&mut stream, &mut subscriptions, &db, update).await?;
+                    }
+                }
+                None => reply(&mut stream, &ServerMessage::ResyncRequired { seq: last_seq }).await?,
+            }
+
             // Process messages
             // On shutdown signal, terminate websocket stream and close request
             loop {
                 select! {
+                    message = stream.next() => {
+                        let message = match message {
+                            None | Some(Ok(ws::Message::Close(_))) => return Ok(()),
+                            Some(Err(e)) => return Err(e),
+                            Some(Ok(ws::Message::Text(text))) => {
+                                subscriptions.handle(&db, &update_feed, &text).await
+                            }
+                            Some(Ok(_)) => continue,
+                        };
+                        reply(&mut stream, &message).await?;
+                    },
                     update = feed.recv() => {
+                        if stream.is_terminated() {
+                            return Ok(());
+                        }
                         match update {
-                            Err(_) => continue,
-                            Ok(update) => {
-                                if stream.is_terminated() {
-                                    return Ok(());
-                                }
-                                let message = match serde_json::to_string(&update) {
-                                    Ok(message) => message,
-                                    Err(e) => {
-                                        eprintln!("Failed to process update event. Event: {update:?} Error: {e:?}");
-                                        continue;
+                            Err(RecvError::Closed) => return Ok(()),
+                            // Catch up from the feed's log rather than drop events
+                            Err(RecvError::Lagged(_)) => match update_feed.since(last_seq) {
+                                Some(missed) => {
+                                    for update in &missed {
+                                        forward(&mut stream, &mut subscriptions, &db, update).await?;
+                                        last_seq = update.seq;
                                     }
-                                };
-                                stream.send(message.into()).await?;
+                                }
+                                None => {
+                                    last_seq = update_feed.seq();
+                                    reply(&mut stream, &ServerMessage::ResyncRequired { seq: last_seq }).await?;
+                                }
                             },
+                            // Already sent while catching up
+                            Ok(update) if update.seq <= last_seq => continue,
+                            Ok(update) => {
+                                last_seq = update.seq;
+                                forward(&mut stream, &mut subscriptions, &db, &update).await?;
+                            },
+                        }
+                    },
+                    event = presence_events.recv() => {
+                        match event {
+                            Err(RecvError::Closed) => return Ok(()),
+                            // Presence is only ever current, so missed events can go
+                            Err(RecvError::Lagged(_)) => continue,
+                            // Tell the client it timed out, so it can join again
+                            Ok(event) if subscriptions.expired(&event) => {
+                                reply(&mut stream, &ServerMessage::Left { topic: event.topic }).await?
+                            }
+                            Ok(event) if subscriptions.wants_presence(&event) => {
+                                reply(&mut stream, &ServerMessage::Presence(event)).await?
+                            }
+                            Ok(_) => {}
                         }
                     },
                     _ = &mut shutdown => {
@@ -97,3 +965,389 @@ fn subscribe(
 pub fn routes() -> Vec<Route> {
     routes![subscribe]
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        EVENT_LOG_SIZE, EntityType, FeedWriter, PresenceKind, PresenceTable, ServerMessage,
+        Subscriptions, Topic, UpdateEvent, UpdateKind,
+    };
+    use crate::models::event_log;
+    use crate::models::{project, task, user, workspace};
+    use crate::test_helpers;
+    use uuid::Uuid;
+
+    #[rocket::async_test]
+    async fn test_topic_routing() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let mut projects = vec![];
+        for title in ["A project", "Another"] {
+            let project = project::create_project(&db, &workspace_id, title.to_string())
+                .await
+                .unwrap();
+            projects.push(project.id);
+        }
+        let mut tasks = vec![];
+        for project_id in &projects {
+            let task = task::create_task_in_project(
+                &db,
+                "A task".to_string(),
+                task::Status::Todo,
+                project_id,
+            )
+            .await
+            .unwrap();
+            tasks.push(task.id);
+        }
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let theirs = workspace::create_workspace(&db, "Theirs".to_string(), &other.id)
+            .await
+            .unwrap();
+
+        let event =
+            |entity_type, id| UpdateEvent::new(workspace_id, UpdateKind::Update, entity_type, id);
+        // Task events carry the projects the task is in
+        let task_event =
+            |i: usize| event(EntityType::Task, tasks[i]).in_projects(vec![projects[i]]);
+        let feed = FeedWriter::new(16);
+        let mut subscriptions = Subscriptions::new(&db, user.id, PresenceTable::new(16)).await;
+        assert!(!subscriptions.wants(&db, &task_event(0)).await.unwrap());
+
+        let reply = subscriptions
+            .handle(
+                &db,
+                &feed,
+                &format!(
+                    r#"{{"action": "subscribe", "topic": {{"type": "project", "id": "{}"}}}}"#,
+                    projects[0]
+                ),
+            )
+            .await;
+        assert!(matches!(
+            reply,
+            ServerMessage::Subscribed {
+                topic: Topic::Project(_)
+            }
+        ));
+        let wants = async |subscriptions: &mut Subscriptions, event| {
+            subscriptions.wants(&db, &event).await.unwrap()
+        };
+        assert!(wants(&mut subscriptions, task_event(0)).await);
+        assert!(wants(&mut subscriptions, event(EntityType::Project, projects[0])).await);
+        assert!(!wants(&mut subscriptions, task_event(1)).await);
+        assert!(!wants(&mut subscriptions, event(EntityType::Project, projects[1])).await);
+        // Leaving the subscribed project
+        let left = event(EntityType::Task, tasks[1]).with_projects(vec![], vec![projects[0]]);
+        assert!(wants(&mut subscriptions, left).await);
+        // Someone else's notification
+        let notification = UpdateEvent {
+            user_id: Some(other.id),
+            ..event(EntityType::Notification, Uuid::new_v4())
+        };
+        assert!(!wants(&mut subscriptions, notification).await);
+
+        subscriptions.unsubscribe(Topic::Project(projects[0]));
+        assert!(!wants(&mut subscriptions, task_event(0)).await);
+        subscriptions
+            .subscribe(&db, Topic::Workspace(workspace_id))
+            .await
+            .unwrap();
+        assert!(wants(&mut subscriptions, task_event(1)).await);
+
+        // Only topics the user can see
+        let reply = subscriptions
+            .handle(
+                &db,
+                &feed,
+                &format!(
+                    r#"{{"action": "subscribe", "topic": {{"type": "workspace", "id": "{}"}}}}"#,
+                    theirs.id
+                ),
+            )
+            .await;
+        assert!(matches!(reply, ServerMessage::Error { topic: Some(_), .. }));
+        let reply = subscriptions.handle(&db, &feed, "nonsense").await;
+        assert!(matches!(reply, ServerMessage::Error { topic: None, .. }));
+        assert!(!subscriptions.topics.contains(&Topic::Workspace(theirs.id)));
+    }
+
+    #[rocket::async_test]
+    async fn test_subtask_deletion_routing() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let mut projects = vec![];
+        for title in ["A project", "Another"] {
+            let project = project::create_project(&db, &workspace_id, title.to_string())
+                .await
+                .unwrap();
+            projects.push(project.id);
+        }
+        let parent = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &projects[0],
+        )
+        .await
+        .unwrap();
+        let subtask = task::create_subtask(&db, &parent.id, "A subtask".to_string())
+            .await
+            .unwrap();
+        task::add_to_project(&db, &user.id, &subtask.id, &projects[1])
+            .await
+            .unwrap();
+        let mut subscriptions = Subscriptions::new(&db, user.id, PresenceTable::new(16)).await;
+        subscriptions
+            .subscribe(&db, Topic::Project(projects[1]))
+            .await
+            .unwrap();
+
+        let client = test_helpers::init_server(Some(db.clone())).await.unwrap();
+        let mut updates = client.updates();
+        let response = client
+            .delete(format!("/tasks/{}", parent.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), rocket::http::Status::Ok);
+        let mut destroyed = vec![];
+        while let Ok(event) = updates.try_recv() {
+            if event.kind == UpdateKind::Destroy {
+                let wanted = subscriptions.wants(&db, &event).await.unwrap();
+                destroyed.push((event.entity_id, wanted));
+            }
+        }
+        // Only the subtask was in the subscribed project
+        assert_eq!(destroyed, [(parent.id, false), (subtask.id, true)]);
+    }
+
+    fn send_events(feed: &FeedWriter, count: usize) {
+        for _ in 0..count {
+            let id = Uuid::new_v4();
+            UpdateEvent::new(id, UpdateKind::Create, EntityType::Project, id).send(feed);
+        }
+    }
+
+    #[test]
+    fn test_resume() {
+        let feed = FeedWriter::new(4);
+        send_events(&feed, 3);
+        assert_eq!(feed.seq(), 3);
+
+        let mut resumed = feed.resume(Some(1));
+        let missed: Vec<u64> = resumed.missed.unwrap().iter().map(|e| e.seq).collect();
+        assert_eq!(missed, [2, 3]);
+        assert_eq!(resumed.seq, 3);
+        send_events(&feed, 1);
+        assert_eq!(resumed.receiver.try_recv().unwrap().seq, 4);
+
+        assert_eq!(feed.resume(Some(4)).missed.unwrap().len(), 0);
+        assert_eq!(feed.resume(None).missed.unwrap().len(), 0);
+        // From before a reset, or the future
+        assert!(feed.resume(Some(5)).missed.is_none());
+
+        send_events(&feed, EVENT_LOG_SIZE);
+        assert!(feed.resume(Some(3)).missed.is_none());
+        assert_eq!(feed.resume(Some(4)).missed.unwrap().len(), EVENT_LOG_SIZE);
+    }
+
+    #[rocket::async_test]
+    async fn test_event_log_persisted() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let feed = FeedWriter::load(&db, 4).await.unwrap();
+        super::start_event_log(db.clone(), &feed);
+        // More than the channel holds, so the log lags behind
+        send_events(&feed, 10);
+
+        let logged = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let logged = event_log::recent(&db, 100).await.unwrap();
+                if logged.len() == 10 {
+                    return logged;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Logged events");
+        assert_eq!(logged.first().unwrap().seq, 1);
+
+        // Numbering carries on after a restart
+        let restarted = FeedWriter::load(&db, 4).await.unwrap();
+        assert_eq!(restarted.seq(), 10);
+        let missed = restarted.resume(Some(8)).missed.unwrap();
+        assert_eq!(missed.iter().map(|e| e.seq).collect::<Vec<_>>(), [9, 10]);
+        assert_eq!(missed[0].entity_type, EntityType::Project);
+    }
+
+    #[rocket::async_test]
+    async fn test_description_messages() {
+        use base64::prelude::*;
+        use yrs::updates::decoder::Decode;
+        use yrs::{Doc, ReadTxn, Text, Transact, Update};
+
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let feed = FeedWriter::new(16);
+        let mut updates = feed.subscribe();
+        let mut subscriptions = Subscriptions::new(&db, user.id, PresenceTable::new(16)).await;
+
+        let reply = subscriptions
+            .handle(
+                &db,
+                &feed,
+                &format!(
+                    r#"{{"action": "description_sync", "task_id": "{}"}}"#,
+                    task.id
+                ),
+            )
+            .await;
+        let ServerMessage::DescriptionState { update, .. } = reply else {
+            panic!("Expected the description's state, got {reply:?}");
+        };
+        let doc = Doc::new();
+        doc.transact_mut()
+            .apply_update(Update::decode_v1(&BASE64_STANDARD.decode(update).unwrap()).unwrap())
+            .unwrap();
+        let before = doc.transact().state_vector();
+        doc.get_or_insert_text("description")
+            .insert(&mut doc.transact_mut(), 0, "Hello");
+        let update = BASE64_STANDARD.encode(doc.transact().encode_state_as_update_v1(&before));
+
+        let reply = subscriptions
+            .handle(
+                &db,
+                &feed,
+                &format!(
+                    r#"{{"action": "description_update", "task_id": "{}", "update": "{update}"}}"#,
+                    task.id
+                ),
+            )
+            .await;
+        assert!(matches!(reply, ServerMessage::DescriptionMerged { .. }));
+        let event = updates.try_recv().unwrap();
+        assert_eq!(event.entity_id, task.id);
+        assert_eq!(event.description_update, Some(update));
+        assert_eq!(event.changed_fields, Some(vec!["description".to_string()]));
+        assert_eq!(event.entity.unwrap()["description"], "Hello");
+
+        let reply = subscriptions
+            .handle(
+                &db,
+                &feed,
+                &format!(
+                    r#"{{"action": "description_update", "task_id": "{}", "update": "nonsense"}}"#,
+                    task.id
+                ),
+            )
+            .await;
+        assert!(matches!(reply, ServerMessage::Error { topic: Some(_), .. }));
+    }
+
+    #[rocket::async_test]
+    async fn test_presence_messages() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let feed = FeedWriter::new(16);
+        let presence = PresenceTable::new(16);
+        let mut events = presence.subscribe();
+        // Two tabs, one on the project's board and one on the task
+        let mut board = Subscriptions::new(&db, user.id, presence.clone()).await;
+        let mut detail = Subscriptions::new(&db, user.id, presence.clone()).await;
+        board
+            .subscribe(&db, Topic::Project(project.id))
+            .await
+            .unwrap();
+
+        let join = format!(
+            r#"{{"action": "join", "topic": {{"type": "task", "id": "{}"}}, "field": "description"}}"#,
+            task.id
+        );
+        let reply = detail.handle(&db, &feed, &join).await;
+        let ServerMessage::Joined { present, .. } = reply else {
+            panic!("Expected to join, got {reply:?}");
+        };
+        assert!(present.is_empty());
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.present.field.as_deref(), Some("description"));
+        // Heard through the subscribed project, but not by the tab itself
+        assert!(board.wants_presence(&event));
+        assert!(!detail.wants_presence(&event));
+
+        let reply = board
+            .handle(
+                &db,
+                &feed,
+                &join.replace(", \"field\": \"description\"", ""),
+            )
+            .await;
+        let ServerMessage::Joined { present, .. } = reply else {
+            panic!("Expected to join, got {reply:?}");
+        };
+        assert_eq!(present[0].connection_id, detail.connection_id);
+        assert_eq!(present[0].field.as_deref(), Some("description"));
+
+        let reply = board
+            .handle(
+                &db,
+                &feed,
+                &format!(r#"{{"action": "join", "topic": {{"type": "workspace", "id": "{workspace_id}"}}}}"#),
+            )
+            .await;
+        assert!(matches!(reply, ServerMessage::Error { topic: Some(_), .. }));
+
+        // Timing out forgets the join, while leaving on request doesn't come
+        // back as a timeout
+        let mut expired = events.try_recv().unwrap();
+        expired.kind = PresenceKind::Leave;
+        assert!(board.expired(&expired));
+        assert!(!board.expired(&expired));
+        assert!(!board.joined.contains(&Topic::Task(task.id)));
+        let join = join.replace(", \"field\": \"description\"", "");
+        board.handle(&db, &feed, &join).await;
+        board
+            .handle(&db, &feed, &join.replace("join", "leave"))
+            .await;
+        let left = std::iter::from_fn(|| events.try_recv().ok())
+            .last()
+            .unwrap();
+        assert_eq!(left.kind, PresenceKind::Leave);
+        assert!(!board.expired(&left));
+
+        // Closing the tab leaves
+        let connection_id = detail.connection_id;
+        drop(detail);
+        let left = std::iter::from_fn(|| events.try_recv().ok())
+            .find(|e| e.present.connection_id == connection_id)
+            .unwrap();
+        assert_eq!(left.kind, PresenceKind::Leave);
+    }
+}
@@ -0,0 +1,433 @@
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::auth::AuthUser;
+use super::helpers::{
+    broadcast_edit, broadcast_membership, broadcast_project_created, broadcast_project_deleted,
+    broadcast_project_edit, broadcast_task_created, broadcast_task_deleted, parse_uuid,
+};
+use super::subscription::FeedWriter;
+use crate::models::access;
+use crate::models::sync::{self, Effect, MutationResult, Pull, PushPayload};
+use crate::result::Result;
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Get the workspace's projects, tasks and memberships, or only the changes
+// since the cursor of an earlier pull
+#[get("/sync?<workspace_id>&<cursor>")]
+async fn pull(
+    workspace_id: &str,
+    cursor: Option<i32>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Pull>> {
+    let workspace_id = parse_uuid(workspace_id)?;
+    access::require_workspace(db.inner(), &auth.user.id, &workspace_id).await?;
+    Ok(Json(sync::pull(db.inner(), &workspace_id, cursor).await?))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PushResponse {
+    // In the order the mutations were sent
+    results: Vec<MutationResult>,
+}
+
+// Apply mutations made offline to the workspace, reporting which were applied
+#[post("/sync?<workspace_id>", format = "json", data = "<payload>")]
+async fn push(
+    workspace_id: &str,
+    payload: Json<PushPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<PushResponse>> {
+    let workspace_id = parse_uuid(workspace_id)?;
+    access::require_workspace(db.inner(), &auth.user.id, &workspace_id).await?;
+    let (results, effects) = sync::push(
+        db.inner(),
+        &auth.user.id,
+        &workspace_id,
+        payload.into_inner(),
+    )
+    .await?;
+    for effect in effects {
+        broadcast_effect(db.inner(), feed.inner(), workspace_id, effect).await?;
+    }
+    Ok(Json(PushResponse { results }))
+}
+
+// The same updates as the request making the change would send
+async fn broadcast_effect(
+    db: &DatabaseConnection,
+    feed: &FeedWriter,
+    workspace_id: Uuid,
+    effect: Effect,
+) -> Result<()> {
+    match effect {
+        Effect::ProjectCreated(project) => broadcast_project_created(feed, &project)?,
+        Effect::ProjectUpdated { old, project } => broadcast_project_edit(feed, &old, &project)?,
+        Effect::ProjectDeleted {
+            project,
+            task_ids,
+            detached_ids,
+        } => broadcast_project_deleted(db, feed, &project, task_ids, detached_ids).await?,
+        Effect::TaskCreated(task) => {
+            broadcast_task_created(db, feed, workspace_id, task).await?;
+        }
+        Effect::TaskUpdated { access, task, next } => {
+            broadcast_edit(db, feed, access, *task, next.map(|next| *next)).await?;
+        }
+        Effect::TaskDeleted {
+            access,
+            project_ids,
+            subtasks,
+            dependent_ids,
+        } => {
+            broadcast_task_deleted(db, feed, &access, project_ids, subtasks, dependent_ids).await?
+        }
+        Effect::AddedToProject { access, project_id } => {
+            let projects = (vec![project_id], vec![]);
+            broadcast_membership(db, feed, access, project_id, projects).await?;
+        }
+        Effect::RemovedFromProject { access, project_id } => {
+            let projects = (vec![], vec![project_id]);
+            broadcast_membership(db, feed, access, project_id, projects).await?;
+        }
+    }
+    Ok(())
+}
+
+// Prune the sync journal in the background for as long as the server runs
+pub fn start_pruning(db: DatabaseConnection) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let before = Utc::now().naive_utc() - sync::JOURNAL_RETENTION;
+            if let Err(e) = sync::prune(&db, before).await {
+                eprintln!("Failed to prune the sync journal: {e:?}");
+            }
+        }
+    });
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![pull, push]
+}
+
+#[cfg(test)]
+mod test {
+    use super::PushResponse;
+    use crate::api::subscription::UpdateKind;
+    use crate::models::access::Role;
+    use crate::models::dependency;
+    use crate::models::project;
+    use crate::models::sync::{self, MembershipKey, Outcome, Pull};
+    use crate::models::task;
+    use crate::test_helpers::{self, TestClient};
+    use chrono::{TimeDelta, Utc};
+    use rocket::http::{ContentType, Status};
+    use serde_json::{Value, json};
+    use uuid::Uuid;
+
+    async fn pull(client: &TestClient, cursor: Option<i32>) -> Pull {
+        let cursor = cursor.map(|c| format!("&cursor={c}")).unwrap_or_default();
+        let response = client
+            .get(format!(
+                "/sync?workspace_id={}{cursor}",
+                client.workspace_id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().await.expect("A pull")
+    }
+
+    async fn push(client: &TestClient, cursor: i32, mutations: Value) -> Vec<Outcome> {
+        push_as(client, Uuid::nil(), cursor, mutations).await
+    }
+
+    async fn push_as(
+        client: &TestClient,
+        client_id: Uuid,
+        cursor: i32,
+        mutations: Value,
+    ) -> Vec<Outcome> {
+        let body = json!({ "client_id": client_id, "cursor": cursor, "mutations": mutations });
+        let response = client
+            .post(format!("/sync?workspace_id={}", client.workspace_id))
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response: PushResponse = response.into_json().await.expect("Push results");
+        response.results.into_iter().map(|r| r.outcome).collect()
+    }
+
+    #[rocket::async_test]
+    async fn test_pull() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let proj = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        project::set_member_role(&db, &proj.id, &user.id, Role::Owner)
+            .await
+            .unwrap();
+        let kept = task::create_task_in_project(&db, "Kept".to_string(), "todo", &proj.id)
+            .await
+            .unwrap();
+        let deleted = task::create_task_in_project(&db, "Deleted".to_string(), "todo", &proj.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let snapshot = pull(&client, None).await;
+        assert_eq!(snapshot.projects.len(), 1);
+        assert_eq!(snapshot.tasks.len(), 2);
+        assert_eq!(snapshot.task_projects.len(), 2);
+        assert_eq!(pull(&client, Some(snapshot.cursor)).await.tasks.len(), 0);
+
+        client
+            .patch(format!("/tasks/{}", kept.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Renamed" }"#)
+            .dispatch()
+            .await;
+        client
+            .delete(format!("/tasks/{}", deleted.id))
+            .dispatch()
+            .await;
+        let changes = pull(&client, Some(snapshot.cursor)).await;
+        assert_eq!(changes.tasks.len(), 1);
+        assert_eq!(changes.tasks[0].title, "Renamed");
+        assert!(changes.projects.is_empty());
+        assert_eq!(changes.deleted.tasks, vec![deleted.id]);
+        assert_eq!(
+            changes.deleted.task_projects,
+            vec![MembershipKey {
+                project_id: proj.id,
+                task_id: deleted.id
+            }]
+        );
+
+        // Deleting the project deletes the tasks in no other project
+        client
+            .delete(format!("/projects/{}", proj.id))
+            .dispatch()
+            .await;
+        let changes = pull(&client, Some(changes.cursor)).await;
+        assert_eq!(changes.deleted.projects, vec![proj.id]);
+        assert_eq!(changes.deleted.tasks, vec![kept.id]);
+        assert_eq!(changes.deleted.task_projects.len(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn test_push() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let cursor = pull(&client, None).await.cursor;
+        let (project_id, task_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let mutations = json!([
+            {
+                "id": Uuid::new_v4(),
+                "at": "2025-11-20T09:00:00Z",
+                "type": "create_project",
+                "project_id": project_id,
+                "title": "Made offline",
+            },
+            {
+                "id": Uuid::new_v4(),
+                "at": "2025-11-20T09:01:00Z",
+                "type": "create_task",
+                "task_id": task_id,
+                "project_id": project_id,
+                "title": "Also offline",
+            },
+            {
+                "id": Uuid::new_v4(),
+                "at": "2025-11-20T09:02:00Z",
+                "type": "update_task",
+                "task_id": task_id,
+                "changes": { "status": "in_progress" },
+            },
+            {
+                "id": Uuid::new_v4(),
+                "at": "2025-11-20T09:03:00Z",
+                "type": "update_task",
+                "task_id": task_id,
+                "changes": { "status": "no_such_status" },
+            },
+        ]);
+        let outcomes = push(&client, cursor, mutations.clone()).await;
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Applied,
+                Outcome::Applied,
+                Outcome::Applied,
+                Outcome::Rejected
+            ]
+        );
+
+        let changes = pull(&client, Some(cursor)).await;
+        assert_eq!(changes.projects.len(), 1);
+        assert_eq!(changes.projects[0].id, project_id);
+        assert_eq!(changes.tasks.len(), 1);
+        assert_eq!(changes.tasks[0].id, task_id);
+        assert_eq!(changes.tasks[0].status, "in_progress");
+        assert_eq!(
+            changes.tasks[0].created_at.to_string(),
+            "2025-11-20 09:01:00"
+        );
+
+        // Sent again after a lost response, nothing is applied twice
+        let outcomes = push(&client, cursor, mutations).await;
+        assert_eq!(
+            outcomes[..3],
+            [Outcome::Applied, Outcome::Applied, Outcome::Applied]
+        );
+        let snapshot = pull(&client, None).await;
+        assert_eq!(snapshot.projects.len(), 1);
+        assert_eq!(snapshot.tasks.len(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn test_push_conflict() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let proj = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(&db, "A task".to_string(), "todo", &proj.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let cursor = pull(&client, None).await.cursor;
+        let rename = |title: &str| {
+            json!([{
+                "id": Uuid::new_v4(),
+                "at": "2025-11-20T09:00:00Z",
+                "type": "update_task",
+                "task_id": task.id,
+                "changes": { "title": title },
+            }])
+        };
+
+        // The client's own changes since its cursor don't conflict
+        let device = Uuid::new_v4();
+        let outcomes = push_as(&client, device, cursor, rename("First")).await;
+        assert_eq!(outcomes, vec![Outcome::Applied]);
+        let outcomes = push_as(&client, device, cursor, rename("Second")).await;
+        assert_eq!(outcomes, vec![Outcome::Applied]);
+
+        // Another device's do
+        let outcomes = push_as(&client, Uuid::new_v4(), cursor, rename("Third")).await;
+        assert_eq!(outcomes, vec![Outcome::Conflict]);
+
+        // As do changes made online
+        client
+            .patch(format!("/tasks/{}", task.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Online" }"#)
+            .dispatch()
+            .await;
+        let cursor = pull(&client, Some(cursor)).await.cursor;
+        let outcomes = push_as(&client, device, cursor - 1, rename("Offline")).await;
+        assert_eq!(outcomes, vec![Outcome::Conflict]);
+        let outcomes = push_as(&client, device, cursor, rename("Rebased")).await;
+        assert_eq!(outcomes, vec![Outcome::Applied]);
+    }
+
+    #[rocket::async_test]
+    async fn test_push_delete_updates_dependents() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let proj = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let blocker = task::create_task_in_project(&db, "Blocker".to_string(), "todo", &proj.id)
+            .await
+            .unwrap();
+        let blocked = task::create_task_in_project(&db, "Blocked".to_string(), "todo", &proj.id)
+            .await
+            .unwrap();
+        dependency::add_blocker(&db, &user.id, &blocked.id, &blocker.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let cursor = pull(&client, None).await.cursor;
+        let mut updates = client.updates();
+
+        let mutations = json!([{
+            "id": Uuid::new_v4(),
+            "at": "2025-11-20T09:00:00Z",
+            "type": "delete_task",
+            "task_id": blocker.id,
+        }]);
+        assert_eq!(
+            push(&client, cursor, mutations).await,
+            vec![Outcome::Applied]
+        );
+        assert_eq!(updates.try_recv().unwrap().kind, UpdateKind::Destroy);
+        let event = updates.try_recv().unwrap();
+        assert_eq!(event.kind, UpdateKind::Update);
+        assert_eq!(event.entity_id, blocked.id);
+        assert_eq!(event.changed_fields.unwrap(), ["blocked"]);
+    }
+
+    #[rocket::async_test]
+    async fn test_expired_cursor() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let proj = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(&db, "A task".to_string(), "todo", &proj.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db.clone())).await.unwrap();
+        let snapshot = pull(&client, None).await;
+        assert!(snapshot.snapshot);
+        let rename = |title: &str| {
+            client
+                .patch(format!("/tasks/{}", task.id))
+                .header(ContentType::JSON)
+                .body(json!({ "title": title }).to_string())
+                .dispatch()
+        };
+        rename("First").await;
+        rename("Second").await;
+        let latest = pull(&client, Some(snapshot.cursor)).await;
+        assert!(!latest.snapshot);
+
+        // Everything but the latest change is pruned
+        sync::prune(&db, Utc::now().naive_utc() + TimeDelta::days(1))
+            .await
+            .unwrap();
+        let changes = pull(&client, Some(latest.cursor)).await;
+        assert!(!changes.snapshot);
+        let changes = pull(&client, Some(snapshot.cursor)).await;
+        assert!(changes.snapshot);
+        assert_eq!(changes.tasks.len(), 1);
+        assert_eq!(changes.tasks[0].title, "Second");
+
+        let mutations = json!([{
+            "id": Uuid::new_v4(),
+            "at": "2025-11-20T09:00:00Z",
+            "type": "update_task",
+            "task_id": task.id,
+            "changes": { "title": "Offline" },
+        }]);
+        let outcomes = push(&client, snapshot.cursor, mutations).await;
+        assert_eq!(outcomes, vec![Outcome::Conflict]);
+    }
+}
@@ -1,55 +1,196 @@
 use rocket::serde::json::Json;
 use rocket::{Route, State};
-use sea_orm::{ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use sea_orm::prelude::Date;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, EntityTrait, Order, QueryFilter};
+use serde::Deserialize;
 
-use super::helpers::parse_uuid;
+use super::auth::AuthUser;
+use super::helpers::{
+    IfMatch, Tagged, TaskListQuery, broadcast_edit, broadcast_task_created, broadcast_task_deleted,
+    broadcast_task_updates, parse_uuid,
+};
 use super::subscription::{EntityType, FeedWriter, UpdateEvent, UpdateKind};
-use crate::models::project::{Project, ProjectModel};
-use crate::models::task::{self, EditTaskPayload, Task, TaskModel};
-use crate::result::{Result, error_response};
+use crate::models::access::{self, TaskAction};
+use crate::models::change_log::{self, HistoryEntry, LoggedEntity};
+use crate::models::dependency;
+use crate::models::listing::{self, Page, Sort};
+use crate::models::project::{self, Project, ProjectModel};
+use crate::models::task::{self, EditTaskPayload, Task, TaskResponse};
+use crate::models::workspace;
+use crate::result::{Error, Result};
+use uuid::Uuid;
 
 // Get task with the given ID
 #[get("/tasks/<id>")]
-async fn get_task(id: &str, db: &State<DatabaseConnection>) -> Result<Json<TaskModel>> {
+async fn get_task(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Tagged<TaskResponse>> {
     let id = parse_uuid(id)?;
-    let task = Task::find_by_id(id).one(db.inner()).await?;
-    match task {
-        Some(t) => Ok(Json(t)),
-        // TODO: this should 404
-        None => Err(error_response(format!("Task with id {id:?} not found!"))),
-    }
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
+    let version = access.task.version;
+    Ok(Tagged(
+        Json(task::response(db.inner(), access.task).await?),
+        version,
+    ))
 }
 
 // Get projects belonging to task with the given id
 #[get("/tasks/<id>/projects")]
 async fn get_task_projects(
     id: &str,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
 ) -> Result<Json<Vec<ProjectModel>>> {
     let id = parse_uuid(id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
     let tasks = Project::find()
         .has_related(Task, task::Column::Id.eq(id))
+        .filter(project::Column::WorkspaceId.eq(access.workspace_id))
         .all(db.inner())
         .await?;
 
     Ok(Json(tasks))
 }
 
-// Delete task with the given ID
+// Get the change history of task with the given id, oldest first. Stays
+// readable once the task is deleted.
+#[get("/tasks/<id>/history")]
+async fn get_task_history(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<HistoryEntry>>> {
+    let id = parse_uuid(id)?;
+    access::require_history(db.inner(), &auth.user.id, LoggedEntity::Task, &id).await?;
+    let history = change_log::history(db.inner(), LoggedEntity::Task, &id).await?;
+    Ok(Json(history))
+}
+
+// Get subtasks of the task with the given id
+#[get("/tasks/<id>/subtasks?<query..>")]
+async fn get_subtasks(
+    id: &str,
+    query: TaskListQuery,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Page<TaskResponse>>> {
+    let id = parse_uuid(id)?;
+    let params = query.params(Sort::CreatedAt, Order::Asc, None)?;
+    access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
+    let page = listing::list_tasks(db.inner(), task::subtasks(&id), &params).await?;
+    Ok(Json(task::response_page(db.inner(), page).await?))
+}
+
+// Create a subtask under the task with the given id.
+// It's added to all the projects of its parent.
+#[derive(Deserialize)]
+struct CreateSubtaskPayload {
+    title: String,
+}
+
+#[post("/tasks/<id>/subtasks", format = "json", data = "<task>")]
+async fn create_subtask(
+    id: &str,
+    task: Json<CreateSubtaskPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<TaskResponse>> {
+    let id = parse_uuid(id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Edit).await?;
+    let task = task::create_subtask(db.inner(), &id, task.title.clone()).await?;
+    let response =
+        broadcast_task_created(db.inner(), feed.inner(), access.workspace_id, task).await?;
+    Ok(Json(response))
+}
+
+// Move a task under another task of the same workspace, or to the top level
+// when parent_id is null. Its subtasks move along with it.
+#[derive(Deserialize)]
+struct SetParentPayload {
+    parent_id: Option<Uuid>,
+}
+
+#[put("/tasks/<id>/parent", format = "json", data = "<parent>")]
+async fn set_task_parent(
+    id: &str,
+    parent: Json<SetParentPayload>,
+    if_match: IfMatch,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Tagged<TaskResponse>> {
+    let id = parse_uuid(id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Edit).await?;
+    if let Some(parent_id) = parent.parent_id {
+        let parent =
+            access::require_task(db.inner(), &auth.user.id, &parent_id, TaskAction::Edit).await?;
+        if parent.workspace_id != access.workspace_id {
+            return Err(Error::validation(
+                "parent_id",
+                "Task belongs to a different workspace",
+            ));
+        }
+    }
+    let task =
+        task::set_parent(db.inner(), &auth.user.id, &id, parent.parent_id, if_match.0).await?;
+    let project_ids = task::project_ids(db.inner(), &id).await?;
+    let response = task::response(db.inner(), task).await?;
+    UpdateEvent::new(
+        access.workspace_id,
+        UpdateKind::Update,
+        EntityType::Task,
+        id,
+    )
+    .with_entity(&response)?
+    .with_changed_fields(["parent_id"])
+    .in_projects(project_ids)
+    .send(feed.inner());
+
+    // Both parents' subtask counts change
+    let parent_ids = [access.task.parent_id, parent.parent_id];
+    broadcast_task_updates(
+        db.inner(),
+        feed.inner(),
+        access.workspace_id,
+        parent_ids.into_iter().flatten(),
+        &["subtasks"],
+    )
+    .await?;
+    let version = response.task.version;
+    Ok(Tagged(Json(response), version))
+}
+
+// Delete task with the given ID, along with its subtasks
 #[delete("/tasks/<id>")]
 async fn delete_task(
     id: &str,
+    if_match: IfMatch,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
 ) -> Result<()> {
     let id = parse_uuid(id)?;
-    Task::delete_by_id(id).exec(db.inner()).await?;
-    UpdateEvent::broadcast(
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Delete).await?;
+    let dependent_ids = dependency::dependents(&id)
+        .all(db.inner())
+        .await?
+        .into_iter()
+        .map(|t| t.id)
+        .collect();
+    let project_ids = task::project_ids(db.inner(), &id).await?;
+    let subtasks = task::delete_task(db.inner(), &auth.user.id, &id, if_match.0).await?;
+    broadcast_task_deleted(
+        db.inner(),
         feed.inner(),
-        UpdateKind::Destroy,
-        EntityType::Task,
-        id.clone(),
-    )?;
+        &access,
+        project_ids,
+        subtasks,
+        dependent_ids,
+    )
+    .await?;
     Ok(())
 }
 
@@ -58,18 +199,17 @@ async fn delete_task(
 async fn edit_task(
     id: &str,
     task: Json<EditTaskPayload>,
+    if_match: IfMatch,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
-) -> Result<Json<TaskModel>> {
+) -> Result<Tagged<TaskResponse>> {
     let id = parse_uuid(id)?;
-    let task = task::edit_task(db.inner(), &id, task).await?;
-    UpdateEvent::broadcast(
-        feed.inner(),
-        UpdateKind::Update,
-        EntityType::Task,
-        id.clone(),
-    )?;
-    Ok(Json(task))
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Edit).await?;
+    let (task, next) = task::edit_task(db.inner(), &auth.user.id, &id, task, if_match.0).await?;
+    let response = broadcast_edit(db.inner(), feed.inner(), access, task, next).await?;
+    let version = response.task.version;
+    Ok(Tagged(Json(response), version))
 }
 
 // Clear fields listed in query param
@@ -77,57 +217,176 @@ async fn edit_task(
 async fn clear_task_fields(
     id: &str,
     fields: Vec<&str>,
+    if_match: IfMatch,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
     feed: &State<FeedWriter>,
-) -> Result<Json<TaskModel>> {
+) -> Result<Tagged<TaskResponse>> {
     let id = parse_uuid(id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Edit).await?;
     let fields = task::ClearableField::from_field_strs(fields)?;
-    let task = task::clear_fields(db.inner(), &id, fields).await?;
-    UpdateEvent::broadcast(
+    let task = task::clear_fields(db.inner(), &auth.user.id, &id, fields, if_match.0).await?;
+    let response = broadcast_edit(db.inner(), feed.inner(), access, task, None).await?;
+    let version = response.task.version;
+    Ok(Tagged(Json(response), version))
+}
+
+// Get due dates of the next occurrences of a recurring task, five unless
+// specified
+#[get("/tasks/<id>/occurrences?<count>")]
+async fn get_task_occurrences(
+    id: &str,
+    count: Option<u32>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<Date>>> {
+    let id = parse_uuid(id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
+    let dates = task::preview_occurrences(&access.task, count.unwrap_or(5))?;
+    Ok(Json(dates))
+}
+
+// Get tasks blocking the task with the given id
+#[get("/tasks/<id>/blockers?<query..>")]
+async fn get_task_blockers(
+    id: &str,
+    query: TaskListQuery,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Page<TaskResponse>>> {
+    let id = parse_uuid(id)?;
+    let params = query.params(Sort::CreatedAt, Order::Asc, None)?;
+    access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
+    let page = listing::list_tasks(db.inner(), dependency::blockers(&id), &params).await?;
+    Ok(Json(task::response_page(db.inner(), page).await?))
+}
+
+// Get tasks blocked by the task with the given id
+#[get("/tasks/<id>/dependents?<query..>")]
+async fn get_task_dependents(
+    id: &str,
+    query: TaskListQuery,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Page<TaskResponse>>> {
+    let id = parse_uuid(id)?;
+    let params = query.params(Sort::CreatedAt, Order::Asc, None)?;
+    access::require_task(db.inner(), &auth.user.id, &id, TaskAction::View).await?;
+    let page = listing::list_tasks(db.inner(), dependency::dependents(&id), &params).await?;
+    Ok(Json(task::response_page(db.inner(), page).await?))
+}
+
+// Mark the task as blocked by another task of the same workspace.
+// Rejected if the other task is already (indirectly) blocked by this one.
+#[post("/tasks/<id>/blockers?<blocker_id>")]
+async fn add_task_blocker(
+    id: &str,
+    blocker_id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let blocker_id = parse_uuid(blocker_id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Edit).await?;
+    let blocker =
+        access::require_task(db.inner(), &auth.user.id, &blocker_id, TaskAction::View).await?;
+    if blocker.workspace_id != access.workspace_id {
+        return Err(Error::validation(
+            "blocker_id",
+            "Task belongs to a different workspace",
+        ));
+    }
+    dependency::add_blocker(db.inner(), &auth.user.id, &id, &blocker_id).await?;
+    broadcast_task_updates(
+        db.inner(),
         feed.inner(),
-        UpdateKind::Update,
-        EntityType::Task,
-        id.clone(),
-    )?;
-    Ok(Json(task))
+        access.workspace_id,
+        [id],
+        &["blocked"],
+    )
+    .await?;
+    Ok(())
+}
+
+// Remove a blocker from the task
+#[delete("/tasks/<id>/blockers/<blocker_id>")]
+async fn remove_task_blocker(
+    id: &str,
+    blocker_id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let blocker_id = parse_uuid(blocker_id)?;
+    let access = access::require_task(db.inner(), &auth.user.id, &id, TaskAction::Edit).await?;
+    dependency::remove_blocker(db.inner(), &auth.user.id, &id, &blocker_id).await?;
+    broadcast_task_updates(
+        db.inner(),
+        feed.inner(),
+        access.workspace_id,
+        [id],
+        &["blocked"],
+    )
+    .await?;
+    Ok(())
 }
 
-// Search tasks by "search" text in query
-#[get("/tasks?<search>")]
+// Search tasks in the caller's workspaces by "search" text in query,
+// newest first unless sorted otherwise
+#[get("/tasks?<search>&<query..>")]
 async fn search_tasks(
     search: &str,
+    query: TaskListQuery,
+    auth: AuthUser,
     db: &State<DatabaseConnection>,
-) -> Result<Json<Vec<TaskModel>>> {
+) -> Result<Json<Page<TaskResponse>>> {
+    let params = query.params(Sort::CreatedAt, Order::Desc, None)?;
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
     let tasks = Task::find()
         .filter(
             Condition::any()
                 .add(task::Column::Title.contains(search))
                 .add(task::Column::Description.contains(search)),
         )
-        .order_by_desc(task::Column::CreatedAt)
-        .all(db.inner())
-        .await?;
+        .has_related(Project, project::Column::WorkspaceId.is_in(workspace_ids));
+    let page = listing::list_tasks(db.inner(), tasks, &params).await?;
 
-    Ok(Json(tasks))
+    Ok(Json(task::response_page(db.inner(), page).await?))
 }
 
 pub fn routes() -> Vec<Route> {
     routes![
         get_task,
         get_task_projects,
+        get_task_history,
+        get_task_occurrences,
+        get_subtasks,
+        create_subtask,
+        set_task_parent,
         delete_task,
         edit_task,
         clear_task_fields,
+        get_task_blockers,
+        get_task_dependents,
+        add_task_blocker,
+        remove_task_blocker,
         search_tasks
     ]
 }
 
 #[cfg(test)]
 mod test {
+    use crate::api::subscription::{EntityType, UpdateKind};
+    use crate::models::access::Role;
+    use crate::models::change_log::HistoryEntry;
+    use crate::models::listing::Page;
     use crate::models::project::{self, ProjectModel};
-    use crate::models::task::{self, TaskActiveModel, TaskModel};
+    use crate::models::task::{self, SubtaskStats, TaskActiveModel, TaskModel, TaskResponse};
+    use crate::result::ErrorBody;
     use crate::test_helpers;
-    use rocket::http::{ContentType, Status};
+    use rocket::http::{ContentType, Header, Status};
     use sea_orm::prelude::Date;
     use sea_orm::{ActiveModelTrait, ActiveValue};
     use serde_json;
@@ -135,11 +394,20 @@ mod test {
     #[rocket::async_test]
     async fn test_get_task() {
         let db = test_helpers::db_conn().await.unwrap();
-
-        let task = task::create_task(&db, "Task 1".to_string(), task::Status::InProgress)
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
 
+        let task = task::create_task_in_project(
+            &db,
+            "Task 1".to_string(),
+            task::Status::InProgress,
+            &project.id,
+        )
+        .await
+        .unwrap();
+
         let client = test_helpers::init_server(Some(db)).await.unwrap();
         let response = client
             .get(uri!(super::get_task(task.id.to_string())))
@@ -152,11 +420,40 @@ mod test {
         assert_eq!(res.id, task.id);
     }
 
+    #[rocket::async_test]
+    async fn test_get_missing_task() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let response = client
+            .get(uri!(super::get_task(uuid::Uuid::new_v4().to_string())))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::NotFound);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.code, "not_found");
+    }
+
+    #[rocket::async_test]
+    async fn test_get_task_invalid_id() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let response = client
+            .get(uri!(super::get_task("not-a-uuid")))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.code, "validation");
+    }
+
     #[rocket::async_test]
     async fn test_get_task_projects() {
         let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
 
-        let project = project::create_project(&db, "A project".to_string())
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
         let task = task::create_task_in_project(
@@ -169,7 +466,7 @@ mod test {
         .unwrap();
 
         // Not in project
-        project::create_project(&db, "Other project".to_string())
+        project::create_project(&db, &workspace_id, "Other project".to_string())
             .await
             .unwrap();
 
@@ -189,9 +486,18 @@ mod test {
     #[rocket::async_test]
     async fn test_edit_task() {
         let db = test_helpers::db_conn().await.unwrap();
-        let task = task::create_task(&db, "A task".to_string(), task::Status::Todo)
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
         let response = client
             .patch(uri!(super::edit_task(task.id.to_string())))
@@ -219,12 +525,107 @@ mod test {
         );
     }
 
+    #[rocket::async_test]
+    async fn test_edit_task_if_match() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let response = client
+            .get(uri!(super::get_task(task.id.to_string())))
+            .dispatch()
+            .await;
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(etag, "\"1\"");
+
+        let edit = async |body: &'static str, etag: &str| {
+            client
+                .patch(uri!(super::edit_task(task.id.to_string())))
+                .header(ContentType::JSON)
+                .header(Header::new("If-Match", etag.to_string()))
+                .body(body)
+                .dispatch()
+                .await
+        };
+        let response = edit(r#"{"title": "Mine"}"#, &etag).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+        let edited: TaskModel = response.into_json().await.unwrap();
+        assert_eq!(edited.version, 2);
+
+        // Made from the version before
+        let response = edit(r#"{"title": "Theirs"}"#, &etag).await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+        let err: ErrorBody = response.into_json().await.unwrap();
+        assert_eq!(err.code, "precondition_failed");
+        assert_eq!(err.current.unwrap()["title"], "Mine");
+        // Found stale before the change is checked
+        let response = edit(r#"{"status": "no_such_status"}"#, &etag).await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+
+        let response = client
+            .post(format!(
+                "/tasks/{}/clear_fields?fields=description",
+                task.id
+            ))
+            .header(Header::new("If-Match", etag.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let response = client
+            .put(uri!(super::set_task_parent(task.id.to_string())))
+            .header(ContentType::JSON)
+            .header(Header::new("If-Match", etag.clone()))
+            .body(r#"{"parent_id": null}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let response = client
+            .delete(uri!(super::delete_task(task.id.to_string())))
+            .header(Header::new("If-Match", etag.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PreconditionFailed);
+
+        let response = edit(r#"{"title": "Theirs"}"#, "nonsense").await;
+        assert_eq!(response.status(), Status::BadRequest);
+        // Without If-Match, or with *, edits apply to any version
+        let response = edit(r#"{"title": "Theirs"}"#, "*").await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .delete(uri!(super::delete_task(task.id.to_string())))
+            .header(Header::new("If-Match", "\"3\""))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
     #[rocket::async_test]
     async fn test_partial_edit_task() {
         let db = test_helpers::db_conn().await.unwrap();
-        let task = task::create_task(&db, "A task".to_string(), task::Status::Todo)
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
         let client = test_helpers::init_server(Some(db)).await.unwrap();
         let response = client
             .patch(uri!(super::edit_task(task.id.to_string())))
@@ -250,12 +651,62 @@ mod test {
         );
     }
 
+    #[rocket::async_test]
+    async fn test_edit_task_invalid_fields() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .patch(uri!(super::edit_task(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "status": "done-ish" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.code, "validation");
+        assert_eq!(err.field, Some("status".to_string()));
+
+        let response = client
+            .patch(uri!(super::edit_task(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "due_date": "12/01/2025" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.field, Some("due_date".to_string()));
+    }
+
     #[rocket::async_test]
     async fn test_clear_task_fields() {
         let db = test_helpers::db_conn().await.unwrap();
-        let task = task::create_task(&db, "A task".to_string(), task::Status::Todo)
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
         let mut task: TaskActiveModel = task.into();
         task.due_date = ActiveValue::Set(Some(Date::from_ymd_opt(2025, 12, 1).unwrap()));
         task.description = ActiveValue::Set(Some("A description!".to_string()));
@@ -282,14 +733,23 @@ mod test {
     #[rocket::async_test]
     async fn test_delete_task() {
         let db = test_helpers::db_conn().await.unwrap();
-
-        let task = task::create_task(&db, "Task 1".to_string(), task::Status::InProgress)
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
             .await
             .unwrap();
 
+        let task = task::create_task_in_project(
+            &db,
+            "Task 1".to_string(),
+            task::Status::InProgress,
+            &project.id,
+        )
+        .await
+        .unwrap();
+
         let client = test_helpers::init_server(Some(db)).await.unwrap();
         let response = client
-            .get(uri!(super::delete_task(task.id.to_string())))
+            .delete(uri!(super::delete_task(task.id.to_string())))
             .dispatch()
             .await;
 
@@ -300,47 +760,815 @@ mod test {
     #[rocket::async_test]
     async fn test_search_tasks() {
         let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
 
-        let flip = task::create_task(
+        let flip = task::create_task_in_project(
             &db,
             "Invert everything in universe".to_string(),
             task::Status::InProgress,
+            &project.id,
         )
         .await
         .unwrap();
 
-        let nothingness = task::create_task(
+        let nothingness = task::create_task_in_project(
             &db,
             "Comprehend nothingness".to_string(),
             task::Status::InProgress,
+            &project.id,
         )
         .await
         .unwrap();
 
-        let cohere = task::create_task(
+        let cohere = task::create_task_in_project(
             &db,
             "Collapse everything unto itself".to_string(),
             task::Status::InProgress,
+            &project.id,
         )
         .await
         .unwrap();
 
-        let other = task::create_task(&db, "Go on a walk".to_string(), task::Status::InProgress)
-            .await
-            .unwrap();
+        let other = task::create_task_in_project(
+            &db,
+            "Go on a walk".to_string(),
+            task::Status::InProgress,
+            &project.id,
+        )
+        .await
+        .unwrap();
 
         let client = test_helpers::init_server(Some(db)).await.unwrap();
-        let response = client
-            .get(uri!(super::search_tasks("thing")))
-            .dispatch()
-            .await;
+        let response = client.get("/tasks?search=thing").dispatch().await;
         assert_eq!(response.status(), Status::Ok);
         let response_str = response.into_string().await.unwrap();
-        let tasks: Vec<TaskModel> =
-            serde_json::from_str(&response_str).expect("Task search results");
+        let tasks = serde_json::from_str::<Page<TaskModel>>(&response_str)
+            .expect("Task search results")
+            .items;
         for task in [flip, nothingness, cohere] {
             assert!(tasks.contains(&task));
         }
         assert!(!tasks.contains(&other))
     }
+
+    #[rocket::async_test]
+    async fn test_deleted_task_history() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let response = client
+            .delete(uri!(super::delete_task(task.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get(uri!(super::get_task_history(task.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let history: Vec<HistoryEntry> = response.into_json().await.unwrap();
+        let deletion = history.last().unwrap();
+        assert_eq!(deletion.action, "delete");
+        assert_eq!(deletion.actor_id, Some(client.user.id));
+        assert_eq!(deletion.old_value.as_ref().unwrap()["title"], "A task");
+
+        // Never existed
+        let response = client
+            .get(uri!(super::get_task_history(
+                uuid::Uuid::new_v4().to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_get_task_history() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Complete,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .patch(uri!(super::edit_task(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "A task", "status": "todo", "description": "Again" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .post(uri!(super::clear_task_fields(
+                task.id.to_string(),
+                vec!["description"]
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get(uri!(super::get_task_history(task.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let history: Vec<HistoryEntry> = serde_json::from_str(&response_str).expect("History");
+
+        // Unchanged title isn't recorded
+        let changes: Vec<(&str, Option<&str>, Option<&str>)> = history
+            .iter()
+            .map(|e| {
+                (
+                    e.field.as_deref().unwrap(),
+                    e.old_value.as_ref().and_then(|v| v.as_str()),
+                    e.new_value.as_ref().and_then(|v| v.as_str()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("description", None, Some("Again")),
+                ("status", Some("complete"), Some("todo")),
+                ("description", Some("Again"), None),
+            ]
+        );
+        for entry in history {
+            assert_eq!(entry.action, "update");
+            assert_eq!(entry.actor_id, Some(client.user.id));
+            assert_eq!(entry.actor_username, Some(client.user.username.clone()));
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_task_dependencies() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let mut tasks = vec![];
+        for title in ["Ship", "Test", "Build"] {
+            let task = task::create_task_in_project(
+                &db,
+                title.to_string(),
+                task::Status::Todo,
+                &project.id,
+            )
+            .await
+            .unwrap();
+            tasks.push(task);
+        }
+        let (ship, test, build) = (&tasks[0], &tasks[1], &tasks[2]);
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        for (task, blocker) in [(ship, test), (test, build)] {
+            let response = client
+                .post(uri!(super::add_task_blocker(
+                    task.id.to_string(),
+                    blocker.id.to_string()
+                )))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+
+        // Already there
+        let response = client
+            .post(uri!(super::add_task_blocker(
+                ship.id.to_string(),
+                test.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+
+        // Would close the loop, directly or indirectly
+        for (task, blocker) in [(build, ship), (test, ship), (ship, ship)] {
+            let response = client
+                .post(uri!(super::add_task_blocker(
+                    task.id.to_string(),
+                    blocker.id.to_string()
+                )))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+            let response_str = response.into_string().await.unwrap();
+            let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+            assert_eq!(err.field, Some("blocker_id".to_string()));
+        }
+
+        let response = client
+            .get(format!("/tasks/{}/blockers", ship.id))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let blockers = serde_json::from_str::<Page<TaskResponse>>(&response_str)
+            .expect("Tasks")
+            .items;
+        assert_eq!(blockers.len(), 1);
+        assert_eq!(blockers[0].task.id, test.id);
+        assert!(blockers[0].blocked);
+
+        let response = client
+            .get(format!("/tasks/{}/dependents", build.id))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let dependents = serde_json::from_str::<Page<TaskResponse>>(&response_str)
+            .expect("Tasks")
+            .items;
+        assert_eq!(dependents.len(), 1);
+        assert_eq!(dependents[0].task.id, test.id);
+
+        // Completing the blocker unblocks
+        let response = client
+            .patch(uri!(super::edit_task(build.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "status": "complete" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(uri!(super::get_task(test.id.to_string())))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let res: TaskResponse = serde_json::from_str(&response_str).expect("A task");
+        assert!(!res.blocked);
+
+        let response = client
+            .delete(uri!(super::remove_task_blocker(
+                ship.id.to_string(),
+                test.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(uri!(super::get_task(ship.id.to_string())))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let res: TaskResponse = serde_json::from_str(&response_str).expect("A task");
+        assert!(!res.blocked);
+    }
+
+    #[rocket::async_test]
+    async fn test_block_completion() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(&db, "Task".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let blocker =
+            task::create_task_in_project(&db, "First".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .post(uri!(super::add_task_blocker(
+                task.id.to_string(),
+                blocker.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // Allowed until the project says otherwise
+        let complete = |id: uuid::Uuid| {
+            client
+                .patch(uri!(super::edit_task(id.to_string())))
+                .header(ContentType::JSON)
+                .body(r#"{ "status": "complete" }"#)
+        };
+        let reopen = client
+            .patch(uri!(super::edit_task(task.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "status": "todo" }"#);
+        assert_eq!(complete(task.id).dispatch().await.status(), Status::Ok);
+        assert_eq!(reopen.dispatch().await.status(), Status::Ok);
+
+        let response = client
+            .patch(format!("/projects/{}", project.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "block_completion": true }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = complete(task.id).dispatch().await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.field, Some("status".to_string()));
+
+        assert_eq!(complete(blocker.id).dispatch().await.status(), Status::Ok);
+        assert_eq!(complete(task.id).dispatch().await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_subtasks() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let parent = task::create_task_in_project(
+            &db,
+            "Big task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let mut subtasks = vec![];
+        for title in ["Step 1", "Step 2", "Step 3"] {
+            let response = client
+                .post(uri!(super::create_subtask(parent.id.to_string())))
+                .header(ContentType::JSON)
+                .body(format!(r#"{{ "title": "{title}" }}"#))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            let response_str = response.into_string().await.unwrap();
+            let subtask: TaskResponse = serde_json::from_str(&response_str).expect("A task");
+            assert_eq!(subtask.task.parent_id, Some(parent.id));
+            subtasks.push(subtask.task);
+        }
+
+        // Subtasks go in the parent's projects
+        let response = client
+            .get(uri!(super::get_task_projects(subtasks[0].id.to_string())))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let projects: Vec<ProjectModel> = serde_json::from_str(&response_str).expect("Projects");
+        assert_eq!(projects, vec![project]);
+
+        let response = client
+            .patch(uri!(super::edit_task(subtasks[0].id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "status": "complete" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // A sub-subtask only counts towards its own parent
+        let response = client
+            .post(uri!(super::create_subtask(subtasks[1].id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "title": "Step 2a" }"#)
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let nested: TaskResponse = serde_json::from_str(&response_str).expect("A task");
+
+        let response = client
+            .get(uri!(super::get_task(parent.id.to_string())))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let res: TaskResponse = serde_json::from_str(&response_str).expect("A task");
+        assert_eq!(
+            res.subtasks,
+            SubtaskStats {
+                complete: 1,
+                in_progress: 0,
+                todo: 2,
+                total: 3
+            }
+        );
+
+        let response = client
+            .get(format!("/tasks/{}/subtasks", parent.id))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let children = serde_json::from_str::<Page<TaskResponse>>(&response_str)
+            .expect("Tasks")
+            .items;
+        let ids: Vec<uuid::Uuid> = children.iter().map(|t| t.task.id).collect();
+        assert_eq!(ids, subtasks.iter().map(|t| t.id).collect::<Vec<_>>());
+        assert_eq!(children[1].subtasks.total, 1);
+
+        // Can't move a task under its own subtree
+        for parent_id in [parent.id, nested.task.id] {
+            let response = client
+                .put(uri!(super::set_task_parent(parent.id.to_string())))
+                .header(ContentType::JSON)
+                .body(format!(r#"{{ "parent_id": "{parent_id}" }}"#))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+        }
+
+        // Promote to the top level
+        let response = client
+            .put(uri!(super::set_task_parent(subtasks[2].id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"{ "parent_id": null }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let res: TaskResponse = serde_json::from_str(&response_str).expect("A task");
+        assert_eq!(res.task.parent_id, None);
+
+        // Deleting the parent takes the remaining subtree with it
+        let response = client
+            .delete(uri!(super::delete_task(parent.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        for id in [subtasks[0].id, subtasks[1].id, nested.task.id] {
+            let response = client
+                .get(uri!(super::get_task(id.to_string())))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::NotFound);
+        }
+        let response = client
+            .get(uri!(super::get_task(subtasks[2].id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_task_with_restricted_subtask() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let viewing = project::create_project(&db, &workspace_id, "Viewing".to_string())
+            .await
+            .unwrap();
+        project::set_member_role(&db, &viewing.id, &user.id, Role::Viewer)
+            .await
+            .unwrap();
+        let parent = task::create_task_in_project(
+            &db,
+            "Parent".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let subtask = task::create_subtask(&db, &parent.id, "Subtask".to_string())
+            .await
+            .unwrap();
+        task::add_to_project(&db, &user.id, &subtask.id, &viewing.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        // The subtask is also in a project the user only views
+        let response = client
+            .delete(uri!(super::delete_task(parent.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        for id in [parent.id, subtask.id] {
+            let response = client
+                .get(uri!(super::get_task(id.to_string())))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_recurring_task() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "Chores".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "Water plants".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let edit = async |id: uuid::Uuid, body: &str| {
+            client
+                .patch(uri!(super::edit_task(id.to_string())))
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await
+        };
+        let project_tasks = async || {
+            let response = client
+                .get(format!("/projects/{}/tasks", project.id))
+                .dispatch()
+                .await;
+            let response_str = response.into_string().await.unwrap();
+            serde_json::from_str::<Page<TaskModel>>(&response_str)
+                .expect("Tasks")
+                .items
+        };
+
+        let response = client
+            .get(uri!(super::get_task_occurrences(task.id.to_string(), _)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = edit(
+            task.id,
+            r#"{ "recurrence": { "freq": "weekly", "weekdays": [] } }"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        // Mondays and Thursdays, from a Monday
+        let response = edit(
+            task.id,
+            r#"{ "due_date": "2025-01-06", "recurrence": { "freq": "weekly", "weekdays": ["mon", "thu"] } }"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(uri!(super::get_task_occurrences(
+                task.id.to_string(),
+                Some(3)
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let dates: Vec<Date> = serde_json::from_str(&response_str).expect("Dates");
+        let date = |s| Date::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert_eq!(
+            dates,
+            vec![date("2025-01-09"), date("2025-01-13"), date("2025-01-16")]
+        );
+
+        // Completing it creates the next occurrence in the same project
+        assert_eq!(
+            edit(task.id, r#"{ "status": "complete" }"#).await.status(),
+            Status::Ok
+        );
+        let tasks = project_tasks().await;
+        assert_eq!(tasks.len(), 2);
+        let next = tasks.iter().find(|t| t.id != task.id).unwrap();
+        assert_eq!(next.title, "Water plants");
+        assert_eq!(next.status, "todo");
+        assert_eq!(next.due_date, Some(date("2025-01-09")));
+        assert_eq!(next.previous_occurrence_id, Some(task.id));
+        assert_eq!(next.recurrence, tasks[0].recurrence);
+
+        // But only once
+        assert_eq!(
+            edit(task.id, r#"{ "status": "todo" }"#).await.status(),
+            Status::Ok
+        );
+        assert_eq!(
+            edit(task.id, r#"{ "status": "complete" }"#).await.status(),
+            Status::Ok
+        );
+        assert_eq!(project_tasks().await.len(), 2);
+
+        // Tasks stop recurring once the rule is cleared
+        let response = client
+            .post(uri!(super::clear_task_fields(
+                next.id.to_string(),
+                vec!["recurrence"]
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            edit(next.id, r#"{ "status": "complete" }"#).await.status(),
+            Status::Ok
+        );
+        assert_eq!(project_tasks().await.len(), 2);
+
+        // Deleting an occurrence unlinks the one after it
+        let response = client
+            .delete(uri!(super::delete_task(task.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let tasks = project_tasks().await;
+        assert_eq!(tasks[0].previous_occurrence_id, None);
+    }
+
+    #[rocket::async_test]
+    async fn test_due_times() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "Deadlines".to_string())
+            .await
+            .unwrap();
+        let mut tasks = vec![];
+        for title in ["Call Berlin", "Early", "Late"] {
+            let task = task::create_task_in_project(
+                &db,
+                title.to_string(),
+                task::Status::Todo,
+                &project.id,
+            )
+            .await
+            .unwrap();
+            tasks.push(task);
+        }
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let edit = async |id: uuid::Uuid, body: String| {
+            client
+                .patch(uri!(super::edit_task(id.to_string())))
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await
+        };
+
+        let response = client
+            .patch("/me")
+            .header(ContentType::JSON)
+            .body(r#"{ "time_zone": "Mars/Olympus_Mons" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = client
+            .patch("/me")
+            .header(ContentType::JSON)
+            .body(r#"{ "time_zone": "Europe/Berlin" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // Deadlines are in the time zone of the user who sets them
+        let response = edit(
+            tasks[0].id,
+            r#"{ "due_date": "2025-10-21", "due_time": "15:00" }"#.to_string(),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let res: serde_json::Value = serde_json::from_str(&response_str).expect("A task");
+        assert_eq!(res["time_zone"], "Europe/Berlin");
+        assert_eq!(res["due"]["at"], "2025-10-21T13:00:00Z");
+        assert_eq!(res["due"]["local"], "2025-10-21T15:00:00+02:00");
+        assert_eq!(res["due"]["all_day"], false);
+
+        let response = client
+            .post(uri!(super::clear_task_fields(
+                tasks[0].id.to_string(),
+                vec!["due_date"]
+            )))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let res: serde_json::Value = serde_json::from_str(&response_str).expect("A task");
+        assert_eq!(res["due_time"], serde_json::Value::Null);
+        assert_eq!(res["due"], serde_json::Value::Null);
+        let response = edit(tasks[0].id, r#"{ "due_time": "15:00" }"#.to_string()).await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        // Due today, but already past in a time zone ahead of UTC and still
+        // to come in one behind it
+        let today = chrono::Utc::now().date_naive();
+        let response = edit(
+            tasks[1].id,
+            format!(r#"{{ "due_date": "{today}", "due_time": "00:00", "time_zone": "Pacific/Kiritimati" }}"#),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = edit(
+            tasks[2].id,
+            format!(
+                r#"{{ "due_date": "{today}", "due_time": "23:59", "time_zone": "Etc/GMT+12" }}"#
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get(format!("/projects/{}/tasks?overdue=true", project.id))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let page: Page<TaskModel> = serde_json::from_str(&response_str).expect("Tasks");
+        let titles: Vec<_> = page.items.iter().map(|t| t.title.as_str()).collect();
+        assert_eq!(titles, vec!["Early"]);
+
+        let response = client
+            .get(format!("/projects/stats?ids={}", project.id))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let stats: serde_json::Value = serde_json::from_str(&response_str).expect("Stats");
+        assert_eq!(stats[0]["overdue"], 1);
+    }
+
+    #[rocket::async_test]
+    async fn test_task_event_payloads() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let other = project::create_project(&db, &workspace_id, "Another".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let mut updates = client.updates();
+
+        let response = client
+            .patch(format!("/tasks/{}", task.id))
+            .header(ContentType::JSON)
+            .body(r#"{"title": "Renamed", "due_date": "2025-10-21"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let event = updates.try_recv().unwrap();
+        assert_eq!(event.kind, UpdateKind::Update);
+        assert_eq!(event.entity_id, task.id);
+        let entity: TaskResponse = serde_json::from_value(event.entity.unwrap()).unwrap();
+        assert_eq!(entity.task.title, "Renamed");
+        assert!(entity.due.is_some());
+        let mut changed = event.changed_fields.unwrap();
+        changed.sort();
+        assert_eq!(changed, ["due", "due_at", "due_date", "title"]);
+        assert!(event.projects.is_none());
+
+        let response = client
+            .post(format!(
+                "/projects/{}/add_task?task_id={}",
+                other.id, task.id
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let event = loop {
+            let event = updates.try_recv().unwrap();
+            if event.entity_type == EntityType::Task {
+                break event;
+            }
+        };
+        let projects = event.projects.unwrap();
+        assert_eq!(projects.added, [other.id]);
+        assert!(projects.removed.is_empty());
+        assert!(event.entity.is_some());
+
+        let response = client
+            .delete(format!("/tasks/{}", task.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let event = updates.try_recv().unwrap();
+        assert_eq!(event.kind, UpdateKind::Destroy);
+        assert!(event.entity.is_none());
+        let mut removed = event.projects.unwrap().removed;
+        removed.sort();
+        let mut expected = vec![project.id, other.id];
+        expected.sort();
+        assert_eq!(removed, expected);
+    }
 }
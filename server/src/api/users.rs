@@ -0,0 +1,190 @@
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+
+use super::auth::AuthUser;
+use crate::models::user::{self, UserModel};
+use crate::result::Result;
+
+#[derive(Deserialize)]
+struct CredentialsPayload {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct LoginResponse {
+    token: String,
+    user: UserModel,
+}
+
+// Register a new user
+#[post("/users", format = "json", data = "<credentials>")]
+async fn create_user(
+    credentials: Json<CredentialsPayload>,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<UserModel>> {
+    let credentials = credentials.into_inner();
+    let user = user::create_user(db.inner(), credentials.username, &credentials.password).await?;
+    Ok(Json(user))
+}
+
+// Exchange username and password for a bearer token
+#[post("/login", format = "json", data = "<credentials>")]
+async fn login(
+    credentials: Json<CredentialsPayload>,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<LoginResponse>> {
+    let user = user::authenticate(db.inner(), &credentials.username, &credentials.password).await?;
+    let token = user::create_token(db.inner(), &user.id).await?;
+    Ok(Json(LoginResponse { token, user }))
+}
+
+// Revoke the token used to make this request
+#[post("/logout")]
+async fn logout(auth: AuthUser, db: &State<DatabaseConnection>) -> Result<()> {
+    user::revoke_token(db.inner(), &auth.token).await?;
+    Ok(())
+}
+
+// Get the currently authenticated user
+#[get("/me")]
+async fn me(auth: AuthUser) -> Json<UserModel> {
+    Json(auth.user)
+}
+
+// Change settings of the currently authenticated user
+#[derive(Deserialize)]
+struct EditMePayload {
+    // IANA name like Europe/Berlin, or null to use UTC
+    time_zone: Option<String>,
+}
+
+#[patch("/me", format = "json", data = "<settings>")]
+async fn edit_me(
+    settings: Json<EditMePayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<UserModel>> {
+    let settings = settings.into_inner();
+    let user = user::set_time_zone(db.inner(), &auth.user.id, settings.time_zone).await?;
+    Ok(Json(user))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create_user, login, logout, me, edit_me]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::user;
+    use crate::result::ErrorBody;
+    use crate::test_helpers;
+    use rocket::http::{ContentType, Header, Status};
+    use serde_json;
+
+    #[rocket::async_test]
+    async fn test_create_user() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let response = client
+            .unauthenticated()
+            .post(uri!(super::create_user))
+            .header(ContentType::JSON)
+            .body(r#"{ "username": "ada", "password": "correct horse" }"#)
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let user: serde_json::Value = serde_json::from_str(&response_str).expect("The user");
+        assert_eq!(user["username"], "ada");
+        assert!(user.get("password_hash").is_none());
+    }
+
+    #[rocket::async_test]
+    async fn test_create_user_short_password() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let response = client
+            .unauthenticated()
+            .post(uri!(super::create_user))
+            .header(ContentType::JSON)
+            .body(r#"{ "username": "ada", "password": "short" }"#)
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.field, Some("password".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn test_login_and_logout() {
+        let db = test_helpers::db_conn().await.unwrap();
+        user::create_user(&db, "ada".to_string(), "correct horse")
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let client = client.unauthenticated();
+
+        let response = client
+            .post(uri!(super::login))
+            .header(ContentType::JSON)
+            .body(r#"{ "username": "ada", "password": "wrong horse" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .post(uri!(super::login))
+            .header(ContentType::JSON)
+            .body(r#"{ "username": "ada", "password": "correct horse" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let login: serde_json::Value = serde_json::from_str(&response_str).expect("A token");
+        let token = login["token"].as_str().unwrap().to_string();
+        let auth = Header::new("Authorization", format!("Bearer {token}"));
+
+        let response = client
+            .get(uri!(super::me))
+            .header(auth.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .post(uri!(super::logout))
+            .header(auth.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get(uri!(super::me)).header(auth).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn test_me() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let response = client.get(uri!(super::me)).dispatch().await;
+
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let me: serde_json::Value = serde_json::from_str(&response_str).expect("The user");
+        assert_eq!(me["id"], client.user.id.to_string());
+    }
+
+    #[rocket::async_test]
+    async fn test_requires_token() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let response = client.unauthenticated().get("/projects").dispatch().await;
+
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response_str = response.into_string().await.unwrap();
+        let err: ErrorBody = serde_json::from_str(&response_str).expect("An error");
+        assert_eq!(err.code, "unauthorized");
+    }
+}
@@ -0,0 +1,557 @@
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use super::auth::AuthUser;
+use super::helpers::parse_uuid;
+use super::subscription::{EntityType, FeedWriter, UpdateEvent, UpdateKind};
+use crate::models::access;
+use crate::models::listing::{self, Page};
+use crate::models::task::{self, TaskResponse};
+use crate::models::view::{self, SavedView, SavedViewModel, ViewDefinition};
+use crate::models::workspace;
+use crate::result::Result;
+
+// Get saved views in the caller's workspaces, or only the one specified in query
+#[get("/views?<workspace_id>")]
+async fn views(
+    workspace_id: Option<&str>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<SavedViewModel>>> {
+    let workspace_ids = match workspace_id {
+        Some(id) => {
+            let id = parse_uuid(id)?;
+            access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+            vec![id]
+        }
+        None => workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?,
+    };
+    let views = view::views_for_workspaces(db.inner(), workspace_ids).await?;
+    Ok(Json(views))
+}
+
+// Get saved view with the given id
+#[get("/views/<id>")]
+async fn get_view(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<SavedViewModel>> {
+    let id = parse_uuid(id)?;
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
+    let view = view::find_view(db.inner(), workspace_ids, &id).await?;
+    Ok(Json(view))
+}
+
+// Get a page of the tasks currently in the view, in the view's order
+#[get("/views/<id>/tasks?<cursor>&<limit>")]
+async fn get_view_tasks(
+    id: &str,
+    cursor: Option<String>,
+    limit: Option<u64>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Page<TaskResponse>>> {
+    let id = parse_uuid(id)?;
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
+    let view = view::find_view(db.inner(), workspace_ids, &id).await?;
+    let (tasks, params) = view::tasks(&view, cursor, listing::page_limit(limit)?)?;
+    let page = listing::list_tasks(db.inner(), tasks, &params).await?;
+    Ok(Json(task::response_page(db.inner(), page).await?))
+}
+
+// Save a view for everyone in the workspace
+#[derive(Deserialize)]
+struct CreateViewPayload {
+    workspace_id: Uuid,
+    name: String,
+    #[serde(default)]
+    definition: ViewDefinition,
+}
+
+#[post("/views", format = "json", data = "<payload>")]
+async fn create_view(
+    payload: Json<CreateViewPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<SavedViewModel>> {
+    let payload = payload.into_inner();
+    access::require_workspace(db.inner(), &auth.user.id, &payload.workspace_id).await?;
+    let view = view::create_view(
+        db.inner(),
+        &payload.workspace_id,
+        &auth.user.id,
+        payload.name,
+        payload.definition,
+    )
+    .await?;
+    UpdateEvent::new(
+        view.workspace_id,
+        UpdateKind::Create,
+        EntityType::View,
+        view.id,
+    )
+    .with_entity(&view)?
+    .send(feed.inner());
+    Ok(Json(view))
+}
+
+// Rename a view or replace its definition
+#[derive(Deserialize)]
+struct EditViewPayload {
+    name: Option<String>,
+    definition: Option<ViewDefinition>,
+}
+
+#[patch("/views/<id>", format = "json", data = "<payload>")]
+async fn edit_view(
+    id: &str,
+    payload: Json<EditViewPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<SavedViewModel>> {
+    let id = parse_uuid(id)?;
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
+    let view = view::find_view(db.inner(), workspace_ids, &id).await?;
+    let payload = payload.into_inner();
+    let view = view::edit_view(db.inner(), view, payload.name, payload.definition).await?;
+    UpdateEvent::new(
+        view.workspace_id,
+        UpdateKind::Update,
+        EntityType::View,
+        view.id,
+    )
+    .with_entity(&view)?
+    .send(feed.inner());
+    Ok(Json(view))
+}
+
+// Delete saved view with the given id. Its tasks are left alone.
+#[delete("/views/<id>")]
+async fn delete_view(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let workspace_ids = workspace::workspace_ids_for_user(db.inner(), &auth.user.id).await?;
+    let view = view::find_view(db.inner(), workspace_ids, &id).await?;
+    view::delete_view(db.inner(), &id).await?;
+    UpdateEvent::broadcast(
+        feed.inner(),
+        view.workspace_id,
+        UpdateKind::Destroy,
+        EntityType::View,
+        id,
+    );
+    Ok(())
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        views,
+        get_view,
+        get_view_tasks,
+        create_view,
+        edit_view,
+        delete_view
+    ]
+}
+
+// The tasks each saved view held when last checked
+struct ViewMembers {
+    views: HashMap<Uuid, (SavedViewModel, HashSet<Uuid>)>,
+}
+
+impl ViewMembers {
+    async fn load(db: &DatabaseConnection) -> Result<Self> {
+        let mut views = HashMap::new();
+        for view in SavedView::find().all(db).await? {
+            let ids = view::task_ids(db, &view).await?;
+            views.insert(view.id, (view, ids));
+        }
+        Ok(ViewMembers { views })
+    }
+
+    fn in_workspace(
+        &mut self,
+        workspace_id: &Uuid,
+    ) -> impl Iterator<Item = &mut (SavedViewModel, HashSet<Uuid>)> {
+        self.views
+            .values_mut()
+            .filter(move |(view, _)| view.workspace_id == *workspace_id)
+    }
+
+    // Announce tasks that entered or left views because of the event
+    async fn apply(
+        &mut self,
+        db: &DatabaseConnection,
+        feed: &FeedWriter,
+        event: &UpdateEvent,
+    ) -> Result<()> {
+        match (&event.entity_type, &event.kind) {
+            (EntityType::View, UpdateKind::Create | UpdateKind::Update) => {
+                // Clients refetch the whole view, so there's nothing to announce
+                if let Some(view) = SavedView::find_by_id(event.entity_id).one(db).await? {
+                    let ids = view::task_ids(db, &view).await?;
+                    self.views.insert(view.id, (view, ids));
+                }
+            }
+            (EntityType::View, UpdateKind::Destroy) => {
+                self.views.remove(&event.entity_id);
+            }
+            (EntityType::Task, kind) => {
+                let task_id = event.entity_id;
+                for (view, ids) in self.in_workspace(&event.workspace_id) {
+                    let contains = !matches!(kind, UpdateKind::Destroy)
+                        && view::contains(db, view, &task_id).await?;
+                    if contains != ids.contains(&task_id) {
+                        let kind = if contains {
+                            ids.insert(task_id);
+                            UpdateKind::Add
+                        } else {
+                            ids.remove(&task_id);
+                            UpdateKind::Remove
+                        };
+                        UpdateEvent::broadcast_view_change(
+                            feed,
+                            view.workspace_id,
+                            kind,
+                            view.id,
+                            task_id,
+                        );
+                    }
+                }
+            }
+            // Tasks may have been added to or removed from the project
+            (EntityType::Project, _) => {
+                for (view, ids) in self.in_workspace(&event.workspace_id) {
+                    let current = view::task_ids(db, view).await?;
+                    announce_changes(feed, view, ids, &current);
+                    *ids = current;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // After missing events, compare every view with how it was
+    async fn resync(&mut self, db: &DatabaseConnection, feed: &FeedWriter) -> Result<()> {
+        let latest = ViewMembers::load(db).await?;
+        for (id, (view, current)) in &latest.views {
+            if let Some((_, ids)) = self.views.get(id) {
+                announce_changes(feed, view, ids, current);
+            }
+        }
+        *self = latest;
+        Ok(())
+    }
+}
+
+fn announce_changes(
+    feed: &FeedWriter,
+    view: &SavedViewModel,
+    before: &HashSet<Uuid>,
+    after: &HashSet<Uuid>,
+) {
+    for id in after.difference(before) {
+        UpdateEvent::broadcast_view_change(feed, view.workspace_id, UpdateKind::Add, view.id, *id);
+    }
+    for id in before.difference(after) {
+        UpdateEvent::broadcast_view_change(
+            feed,
+            view.workspace_id,
+            UpdateKind::Remove,
+            view.id,
+            *id,
+        );
+    }
+}
+
+// Follow the update feed in the background, announcing when a task enters or
+// leaves a saved view. Tasks that become overdue just by the passing of time
+// show up when the view is next listed.
+pub fn watch_views(db: DatabaseConnection, feed: &FeedWriter) {
+    let mut updates = feed.subscribe();
+    let feed = feed.clone();
+    tokio::spawn(async move {
+        let mut members = None;
+        loop {
+            if members.is_none() {
+                match ViewMembers::load(&db).await {
+                    Ok(loaded) => members = Some(loaded),
+                    Err(e) => eprintln!("Failed to load saved views: {e:?}"),
+                }
+            }
+
+            let res = match (updates.recv().await, members.as_mut()) {
+                (Err(RecvError::Closed), _) => return,
+                (_, None) => Ok(()),
+                (Ok(event), Some(members)) => members.apply(&db, &feed, &event).await,
+                (Err(RecvError::Lagged(_)), Some(members)) => members.resync(&db, &feed).await,
+            };
+            if let Err(e) = res {
+                eprintln!("Failed to update saved views: {e:?}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use crate::api::subscription::{EntityType, UpdateEvent, UpdateKind};
+    use crate::models::listing::Page;
+    use crate::models::task::{self, TaskActiveModel, TaskModel};
+    use crate::models::user;
+    use crate::models::view::SavedViewModel;
+    use crate::models::workspace;
+    use crate::models::{deadline, project};
+    use crate::test_helpers;
+    use rocket::http::{ContentType, Status};
+    use sea_orm::{ActiveModelTrait, ActiveValue};
+    use serde_json::{self, json};
+    use std::time::Duration;
+    use tokio::sync::broadcast::Receiver;
+    use uuid::Uuid;
+
+    // Next task to enter or leave a view
+    async fn next_view_change(updates: &mut Receiver<UpdateEvent>) -> UpdateEvent {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let event = updates.recv().await.unwrap();
+                if event.entity_type == EntityType::View && event.task_id.is_some() {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("View change")
+    }
+
+    #[rocket::async_test]
+    async fn test_view_tasks() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let home = project::create_project(&db, &workspace_id, "Home".to_string())
+            .await
+            .unwrap();
+        let work = project::create_project(&db, &workspace_id, "Work".to_string())
+            .await
+            .unwrap();
+        let mut tasks = vec![];
+        for (title, status, project_id) in [
+            ("Fix sink", task::Status::Todo, home.id),
+            ("Paint fence", task::Status::Complete, home.id),
+            ("Write report", task::Status::InProgress, work.id),
+        ] {
+            let task = task::create_task_in_project(&db, title.to_string(), status, &project_id)
+                .await
+                .unwrap();
+            let mut task: TaskActiveModel = task.into();
+            let due_date = Some("2000-01-01".parse().unwrap());
+            task.due_date = ActiveValue::Set(due_date);
+            task.due_at = ActiveValue::Set(deadline::due_at(due_date, None, None));
+            tasks.push(task.update(&db).await.unwrap());
+        }
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let titles = async |view: &SavedViewModel| {
+            let response = client
+                .get(format!("/views/{}/tasks", view.id))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            let response_str = response.into_string().await.unwrap();
+            let page: Page<TaskModel> = serde_json::from_str(&response_str).expect("Tasks");
+            page.items.into_iter().map(|t| t.title).collect::<Vec<_>>()
+        };
+        let create = async |body: serde_json::Value| {
+            let response = client
+                .post("/views")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+                .await;
+            let status = response.status();
+            let response_str = response.into_string().await.unwrap();
+            (
+                status,
+                serde_json::from_str::<SavedViewModel>(&response_str).ok(),
+            )
+        };
+
+        let (status, overdue) = create(json!({
+            "workspace_id": workspace_id,
+            "name": "Overdue",
+            "definition": { "overdue": true, "sort": "title", "order": "desc" },
+        }))
+        .await;
+        assert_eq!(status, Status::Ok);
+        let overdue = overdue.unwrap();
+        assert_eq!(titles(&overdue).await, vec!["Write report", "Fix sink"]);
+
+        let (_, home_report) = create(json!({
+            "workspace_id": workspace_id,
+            "name": "Home reports",
+            "definition": { "project_ids": [home.id], "query": "report" },
+        }))
+        .await;
+        assert!(titles(&home_report.unwrap()).await.is_empty());
+
+        let response = client
+            .get(format!("/views?workspace_id={workspace_id}"))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let views: Vec<SavedViewModel> = serde_json::from_str(&response_str).expect("Views");
+        let names: Vec<&str> = views.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["Home reports", "Overdue"]);
+
+        let response = client
+            .patch(format!("/views/{}", overdue.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "definition": { "statuses": ["complete"] } }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(titles(&overdue).await, vec!["Paint fence"]);
+
+        // Definitions are checked up front
+        let (status, _) = create(json!({
+            "workspace_id": workspace_id,
+            "name": "Bad",
+            "definition": { "categories": ["finished"] },
+        }))
+        .await;
+        assert_eq!(status, Status::UnprocessableEntity);
+        let (status, _) = create(json!({
+            "workspace_id": workspace_id,
+            "name": "Bad",
+            "definition": { "project_ids": [Uuid::new_v4()] },
+        }))
+        .await;
+        assert_eq!(status, Status::UnprocessableEntity);
+
+        let response = client
+            .delete(format!("/views/{}", overdue.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(format!("/views/{}/tasks", overdue.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_views_of_other_workspaces() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let them = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let other = workspace::create_workspace(&db, "Other".to_string(), &them.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .post("/views")
+            .header(ContentType::JSON)
+            .body(json!({ "workspace_id": other.id, "name": "Sneaky" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_view_live_updates() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let other = project::create_project(&db, &workspace_id, "Other".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        task::add_to_project(&db, &user.id, &task.id, &other.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let mut updates = client.updates();
+
+        let response = client
+            .post("/views")
+            .header(ContentType::JSON)
+            .body(
+                json!({
+                    "workspace_id": workspace_id,
+                    "name": "In progress",
+                    "definition": {
+                        "statuses": ["in_progress"],
+                        "project_ids": [project.id],
+                    },
+                })
+                .to_string(),
+            )
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let view: SavedViewModel = serde_json::from_str(&response_str).expect("A view");
+
+        for (status, kind) in [
+            ("in_progress", UpdateKind::Add),
+            ("complete", UpdateKind::Remove),
+        ] {
+            let response = client
+                .patch(format!("/tasks/{}", task.id))
+                .header(ContentType::JSON)
+                .body(json!({ "status": status }).to_string())
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+
+            let event = next_view_change(&mut updates).await;
+            assert_eq!(event.entity_id, view.id);
+            assert_eq!(event.task_id, Some(task.id));
+            assert_eq!(event.kind, kind);
+        }
+
+        // Leaving the view's projects counts too
+        client
+            .patch(format!("/tasks/{}", task.id))
+            .header(ContentType::JSON)
+            .body(r#"{ "status": "in_progress" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(next_view_change(&mut updates).await.kind, UpdateKind::Add);
+        client
+            .post(format!(
+                "/projects/{}/remove_task?task_id={}",
+                project.id, task.id
+            ))
+            .dispatch()
+            .await;
+        let event = next_view_change(&mut updates).await;
+        assert_eq!(event.kind, UpdateKind::Remove);
+        assert_eq!(event.task_id, Some(task.id));
+    }
+}
@@ -0,0 +1,570 @@
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use super::auth::AuthUser;
+use super::helpers::parse_uuid;
+use super::subscription::{EntityType, FeedWriter, UpdateEvent, UpdateKind};
+use crate::models::access::{self, ProjectAction};
+use crate::models::comment::Comment;
+use crate::models::project::Project;
+use crate::models::task::{self, Task, TaskProject, TaskProjectColumn};
+use crate::models::view::SavedView;
+use crate::models::webhook::{self, CreateWebhook, DeliveryLog, WebhookModel};
+use crate::models::workspace::Workspace;
+use crate::result::{Error, Result};
+
+// How often deliveries due for a retry are looked for
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// Webhooks of the workspace with the given id
+#[get("/workspaces/<id>/webhooks")]
+async fn workspace_webhooks(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<WebhookModel>>> {
+    let id = parse_uuid(id)?;
+    access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+    let webhooks = webhook::webhooks_for_workspace(db.inner(), &id).await?;
+    Ok(Json(webhooks))
+}
+
+// Register a webhook for events in the workspace, or in one of its projects.
+// Empty lists of entity types or kinds match all of them.
+#[derive(Deserialize)]
+struct CreateWebhookPayload {
+    url: String,
+    project_id: Option<Uuid>,
+    #[serde(default)]
+    entity_types: Vec<EntityType>,
+    #[serde(default)]
+    kinds: Vec<UpdateKind>,
+}
+
+// The secret is only ever shown when the webhook is created
+#[derive(Debug, Deserialize, Serialize)]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: WebhookModel,
+    secret: String,
+}
+
+#[post("/workspaces/<id>/webhooks", format = "json", data = "<webhook>")]
+async fn create_webhook(
+    id: &str,
+    webhook: Json<CreateWebhookPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<CreatedWebhook>> {
+    let id = parse_uuid(id)?;
+    let webhook = webhook.into_inner();
+    access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+    if let Some(project_id) = webhook.project_id {
+        let project = access::require_project(
+            db.inner(),
+            &auth.user.id,
+            &project_id,
+            ProjectAction::ManageWebhooks,
+        )
+        .await?;
+        if project.workspace_id != id {
+            return Err(Error::validation(
+                "project_id",
+                "Project belongs to another workspace",
+            ));
+        }
+    }
+
+    let webhook = webhook::create_webhook(
+        db.inner(),
+        &id,
+        &auth.user.id,
+        CreateWebhook {
+            url: webhook.url,
+            project_id: webhook.project_id,
+            entity_types: serde_json::to_value(webhook.entity_types)?,
+            kinds: serde_json::to_value(webhook.kinds)?,
+        },
+    )
+    .await?;
+    Ok(Json(CreatedWebhook {
+        secret: webhook.secret.clone(),
+        webhook,
+    }))
+}
+
+// Webhooks are managed by workspace members, and for webhooks of a project,
+// its owners
+async fn require_webhook(
+    db: &DatabaseConnection,
+    user_id: &Uuid,
+    id: &Uuid,
+) -> Result<WebhookModel> {
+    let webhook = webhook::find_webhook(db, id).await?;
+    access::require_workspace(db, user_id, &webhook.workspace_id)
+        .await
+        .map_err(|_| Error::not_found(format!("Webhook with id {id:?} not found!")))?;
+    if let Some(project_id) = webhook.project_id {
+        access::require_project(db, user_id, &project_id, ProjectAction::ManageWebhooks).await?;
+    }
+    Ok(webhook)
+}
+
+#[delete("/webhooks/<id>")]
+async fn delete_webhook(id: &str, auth: AuthUser, db: &State<DatabaseConnection>) -> Result<()> {
+    let id = parse_uuid(id)?;
+    require_webhook(db.inner(), &auth.user.id, &id).await?;
+    webhook::delete_webhook(db.inner(), &id).await
+}
+
+// Latest deliveries of the webhook with every attempt made at them
+#[get("/webhooks/<id>/deliveries")]
+async fn webhook_deliveries(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<DeliveryLog>>> {
+    let id = parse_uuid(id)?;
+    require_webhook(db.inner(), &auth.user.id, &id).await?;
+    Ok(Json(webhook::delivery_log(db.inner(), &id).await?))
+}
+
+// Like task.update, or view.add for a task entering a view
+fn event_name(event: &UpdateEvent) -> String {
+    format!("{:?}.{:?}", event.entity_type, event.kind).to_lowercase()
+}
+
+// Projects the event concerns, or None if it could be any of the workspace's
+async fn event_projects(
+    db: &DatabaseConnection,
+    event: &UpdateEvent,
+) -> Result<Option<HashSet<Uuid>>> {
+    let task_id = match event.entity_type {
+        EntityType::Project => return Ok(Some(HashSet::from([event.entity_id]))),
+        // Deleted tasks have already left their projects, which the event
+        // lists when known
+        EntityType::Task if event.kind == UpdateKind::Destroy => {
+            return Ok(event
+                .projects
+                .as_ref()
+                .map(|p| p.removed.iter().copied().collect()));
+        }
+        EntityType::Task => event.entity_id,
+        EntityType::Comment => match event.task_id {
+            Some(task_id) => task_id,
+            None => return Ok(None),
+        },
+        EntityType::View | EntityType::Workspace | EntityType::Notification => {
+            return Ok(Some(HashSet::new()));
+        }
+    };
+    let ids: Vec<Uuid> = TaskProject::find()
+        .select_only()
+        .column(TaskProjectColumn::ProjectId)
+        .filter(TaskProjectColumn::TaskId.eq(task_id))
+        .into_tuple()
+        .all(db)
+        .await?;
+    // Including a project the task just left
+    let removed = event
+        .projects
+        .iter()
+        .flat_map(|p| p.removed.iter().copied());
+    Ok(Some(ids.into_iter().chain(removed).collect()))
+}
+
+fn wants(webhook: &WebhookModel, event: &UpdateEvent, projects: &Option<HashSet<Uuid>>) -> bool {
+    let entity_types: Vec<EntityType> =
+        serde_json::from_value(webhook.entity_types.clone()).unwrap_or_default();
+    let kinds: Vec<UpdateKind> = serde_json::from_value(webhook.kinds.clone()).unwrap_or_default();
+    let in_project = match (webhook.project_id, projects) {
+        (None, _) | (_, None) => true,
+        (Some(id), Some(projects)) => projects.contains(&id),
+    };
+    in_project
+        && (entity_types.is_empty() || entity_types.contains(&event.entity_type))
+        && (kinds.is_empty() || kinds.contains(&event.kind))
+}
+
+// The entity as the API returns it, unless it's gone. Taken from the event
+// when it carries one.
+async fn entity(db: &DatabaseConnection, event: &UpdateEvent) -> Result<serde_json::Value> {
+    if let Some(entity) = &event.entity {
+        return Ok(entity.clone());
+    }
+    let id = event.entity_id;
+    let entity = match event.entity_type {
+        EntityType::Task => match Task::find_by_id(id).one(db).await? {
+            Some(t) => Some(serde_json::to_value(task::response(db, t).await?)?),
+            None => None,
+        },
+        EntityType::Project => Project::find_by_id(id)
+            .one(db)
+            .await?
+            .map(serde_json::to_value)
+            .transpose()?,
+        EntityType::Comment => Comment::find_by_id(id)
+            .one(db)
+            .await?
+            .map(serde_json::to_value)
+            .transpose()?,
+        EntityType::View => SavedView::find_by_id(id)
+            .one(db)
+            .await?
+            .map(serde_json::to_value)
+            .transpose()?,
+        EntityType::Workspace => Workspace::find_by_id(id)
+            .one(db)
+            .await?
+            .map(serde_json::to_value)
+            .transpose()?,
+        EntityType::Notification => None,
+    };
+    Ok(entity.unwrap_or_default())
+}
+
+// Record deliveries of the event to the webhooks that want it
+async fn enqueue(db: &DatabaseConnection, event: &UpdateEvent) -> Result<bool> {
+    // Notifications are personal
+    if event.user_id.is_some() {
+        return Ok(false);
+    }
+    let webhooks = webhook::webhooks_for_workspace(db, &event.workspace_id).await?;
+    if webhooks.is_empty() {
+        return Ok(false);
+    }
+    let projects = event_projects(db, event).await?;
+    let webhooks: Vec<WebhookModel> = webhooks
+        .into_iter()
+        .filter(|w| wants(w, event, &projects))
+        .collect();
+    if webhooks.is_empty() {
+        return Ok(false);
+    }
+
+    let name = event_name(event);
+    let mut payload = json!({
+        "event": name,
+        "kind": event.kind,
+        "entity_type": event.entity_type,
+        "entity_id": event.entity_id,
+        "workspace_id": event.workspace_id,
+        "entity": entity(db, event).await?,
+        "occurred_at": Utc::now(),
+    });
+    if let Some(task_id) = event.task_id {
+        payload["task_id"] = json!(task_id);
+    }
+    if let Some(fields) = &event.changed_fields {
+        payload["changed_fields"] = json!(fields);
+    }
+    if let Some(projects) = &event.projects {
+        payload["projects"] = json!(projects);
+    }
+    webhook::enqueue(db, &webhooks, &name, &payload).await?;
+    Ok(true)
+}
+
+// Queue deliveries as events come in, and send them in the background. Events
+// broadcast before the server stops are delivered once it's back up.
+pub fn start_webhooks(db: DatabaseConnection, feed: &FeedWriter) {
+    let mut updates = feed.subscribe();
+    let queued = Arc::new(Notify::new());
+
+    let enqueuer_db = db.clone();
+    let enqueued = queued.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match updates.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("Webhooks missed {n} events");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            match enqueue(&enqueuer_db, &event).await {
+                Ok(true) => enqueued.notify_one(),
+                Ok(false) => {}
+                Err(e) => eprintln!("Failed to queue webhook deliveries: {e:?}"),
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let client = webhook::client();
+        let mut retries = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = queued.notified() => {},
+                _ = retries.tick() => {},
+            }
+            if let Err(e) = webhook::deliver_due(&db, &client, Utc::now().naive_utc()).await {
+                eprintln!("Failed to deliver webhooks: {e:?}");
+            }
+        }
+    });
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        workspace_webhooks,
+        create_webhook,
+        delete_webhook,
+        webhook_deliveries
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::access::Role;
+    use crate::models::project;
+    use crate::models::webhook::{self, DeliveryLog};
+    use crate::test_helpers::{self, TestClient};
+    use chrono::{Duration, Utc};
+    use rocket::http::{ContentType, Status};
+    use serde_json::{self, Value, json};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    struct Request {
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    // Local stand-in for a webhook receiver. Answers requests with the given
+    // statuses in turn, then with 200s.
+    async fn receiver(statuses: Vec<u16>) -> (String, UnboundedReceiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut statuses = statuses.into_iter();
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![];
+                let mut chunk = [0u8; 4096];
+                let request = loop {
+                    let n = socket.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                        continue;
+                    };
+                    let head = String::from_utf8_lossy(&buf[..end]).to_string();
+                    let headers: HashMap<String, String> = head
+                        .lines()
+                        .skip(1)
+                        .filter_map(|line| line.split_once(": "))
+                        .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+                        .collect();
+                    let length: usize = headers["content-length"].parse().unwrap();
+                    if buf.len() >= end + 4 + length {
+                        let body = buf[end + 4..end + 4 + length].to_vec();
+                        break Request { headers, body };
+                    }
+                };
+                let status = statuses.next().unwrap_or(200);
+                let response = format!(
+                    "HTTP/1.1 {status} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                sender.send(request).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    async fn next_request(requests: &mut UnboundedReceiver<Request>) -> Request {
+        tokio::time::timeout(std::time::Duration::from_secs(5), requests.recv())
+            .await
+            .expect("A webhook request")
+            .unwrap()
+    }
+
+    async fn deliveries(client: &TestClient, webhook_id: &str) -> Vec<DeliveryLog> {
+        let response = client
+            .get(format!("/webhooks/{webhook_id}/deliveries"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        serde_json::from_str(&response_str).expect("Deliveries")
+    }
+
+    // Deliveries once the latest attempt at each has been recorded
+    async fn settled_deliveries(client: &TestClient, webhook_id: &str) -> Vec<DeliveryLog> {
+        for _ in 0..50 {
+            let log = deliveries(client, webhook_id).await;
+            if log.iter().all(|d| {
+                d.attempts.len() as i32 == d.delivery.attempt_count && d.delivery.attempt_count > 0
+            }) {
+                return log;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("Deliveries never settled");
+    }
+
+    #[rocket::async_test]
+    async fn test_webhook_delivery() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let (url, mut requests) = receiver(vec![]).await;
+
+        let response = client
+            .post(format!("/workspaces/{workspace_id}/webhooks"))
+            .header(ContentType::JSON)
+            .body(json!({ "url": url, "entity_types": ["Task"], "kinds": ["Create"] }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let created: Value = serde_json::from_str(&response_str).expect("A webhook");
+        let secret = created["secret"].as_str().unwrap().to_string();
+        let webhook_id = created["id"].as_str().unwrap().to_string();
+
+        let response = client
+            .get(format!("/workspaces/{workspace_id}/webhooks"))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let webhooks: Value = serde_json::from_str(&response_str).expect("Webhooks");
+        assert_eq!(webhooks[0]["id"], webhook_id);
+        assert!(webhooks[0].get("secret").is_none());
+
+        let response = client
+            .post(format!("/projects/{}/tasks", project.id))
+            .header(ContentType::JSON)
+            .body(json!({ "title": "A task" }).to_string())
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let task: Value = serde_json::from_str(&response_str).expect("A task");
+        // Not wanted by the webhook
+        client
+            .patch(format!("/tasks/{}", task["id"].as_str().unwrap()))
+            .header(ContentType::JSON)
+            .body(json!({ "title": "Renamed" }).to_string())
+            .dispatch()
+            .await;
+
+        let request = next_request(&mut requests).await;
+        assert_eq!(request.headers["x-tasked-event"], "task.create");
+        assert_eq!(
+            request.headers["x-tasked-signature"],
+            webhook::sign(&secret, &request.body)
+        );
+        let payload: Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(payload["entity_id"], task["id"]);
+        assert_eq!(payload["entity"]["title"], "A task");
+        assert_eq!(payload["workspace_id"], workspace_id.to_string());
+
+        let log = settled_deliveries(&client, &webhook_id).await;
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].delivery.state, "succeeded");
+        assert_eq!(log[0].attempts[0].status_code, Some(200));
+        assert_eq!(
+            request.headers["x-tasked-delivery"],
+            log[0].delivery.id.to_string()
+        );
+
+        let response = client
+            .post(format!("/workspaces/{workspace_id}/webhooks"))
+            .header(ContentType::JSON)
+            .body(json!({ "url": "ftp://example.com" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    #[rocket::async_test]
+    async fn test_webhook_retries() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let hooked = project::create_project(&db, &workspace_id, "Hooked".to_string())
+            .await
+            .unwrap();
+        let other = project::create_project(&db, &workspace_id, "Other".to_string())
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db.clone())).await.unwrap();
+        let (url, mut requests) = receiver(vec![500]).await;
+        let body = json!({ "url": url, "project_id": hooked.id, "kinds": ["Create"] }).to_string();
+
+        // Project webhooks are managed by the project's owners
+        let response = client
+            .post(format!("/workspaces/{workspace_id}/webhooks"))
+            .header(ContentType::JSON)
+            .body(body.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+        project::set_member_role(&db, &hooked.id, &user.id, Role::Owner)
+            .await
+            .unwrap();
+        let response = client
+            .post(format!("/workspaces/{workspace_id}/webhooks"))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let created: Value = serde_json::from_str(&response_str).expect("A webhook");
+        let webhook_id = created["id"].as_str().unwrap().to_string();
+
+        for project_id in [other.id, hooked.id] {
+            client
+                .post(format!("/projects/{project_id}/tasks"))
+                .header(ContentType::JSON)
+                .body(json!({ "title": "A task" }).to_string())
+                .dispatch()
+                .await;
+        }
+
+        // Answered with a 500, so retried later
+        let request = next_request(&mut requests).await;
+        let payload: Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(payload["event"], "task.create");
+        let log = settled_deliveries(&client, &webhook_id).await;
+        assert_eq!(log.len(), 1);
+        let delivery = &log[0].delivery;
+        assert_eq!(delivery.state, "pending");
+        let retry_at = delivery.next_attempt_at.unwrap();
+        let attempted_at = log[0].attempts[0].attempted_at;
+        assert_eq!(retry_at - attempted_at, Duration::seconds(30));
+
+        let later = Utc::now().naive_utc() + Duration::minutes(1);
+        webhook::deliver_due(&db, &webhook::client(), later)
+            .await
+            .unwrap();
+        let retry = next_request(&mut requests).await;
+        assert_eq!(retry.body, request.body);
+        assert_eq!(
+            retry.headers["x-tasked-delivery"],
+            request.headers["x-tasked-delivery"]
+        );
+
+        let log = deliveries(&client, &webhook_id).await;
+        assert_eq!(log[0].delivery.state, "succeeded");
+        assert_eq!(log[0].delivery.next_attempt_at, None);
+        let statuses: Vec<_> = log[0].attempts.iter().map(|a| a.status_code).collect();
+        assert_eq!(statuses, vec![Some(500), Some(200)]);
+    }
+}
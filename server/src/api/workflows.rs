@@ -0,0 +1,422 @@
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::DatabaseConnection;
+
+use super::auth::AuthUser;
+use super::helpers::{broadcast_task_updates, parse_uuid};
+use super::subscription::{EntityType, FeedWriter, UpdateEvent, UpdateKind};
+use crate::models::access::{self, ProjectAction};
+use crate::models::project::ProjectModel;
+use crate::models::workflow::{
+    self, CreateStatusPayload, EditStatusPayload, TransitionPayload, Workflow, WorkflowStatusModel,
+    WorkflowTransitionModel,
+};
+use crate::result::Result;
+
+// Workflow changes show up as updates to the project
+fn broadcast_change(feed: &FeedWriter, project: &ProjectModel) {
+    UpdateEvent::broadcast(
+        feed,
+        project.workspace_id,
+        UpdateKind::Update,
+        EntityType::Project,
+        project.id,
+    );
+}
+
+// Get the statuses of the project, in order, and the moves allowed between them
+#[get("/projects/<id>/workflow")]
+async fn get_workflow(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Workflow>> {
+    let id = parse_uuid(id)?;
+    access::require_project(db.inner(), &auth.user.id, &id, ProjectAction::View).await?;
+    Ok(Json(workflow::workflow(db.inner(), &id).await?))
+}
+
+// Add a status at the end of the project's workflow
+#[post("/projects/<id>/workflow/statuses", format = "json", data = "<status>")]
+async fn create_status(
+    id: &str,
+    status: Json<CreateStatusPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<WorkflowStatusModel>> {
+    let id = parse_uuid(id)?;
+    let project = access::require_project(
+        db.inner(),
+        &auth.user.id,
+        &id,
+        ProjectAction::ManageWorkflow,
+    )
+    .await?;
+    let status = workflow::create_status(db.inner(), &id, status.into_inner()).await?;
+    broadcast_change(feed.inner(), &project);
+    Ok(Json(status))
+}
+
+// Rename, recolour, reorder or recategorize one of the project's statuses
+#[patch(
+    "/projects/<id>/workflow/statuses/<key>",
+    format = "json",
+    data = "<status>"
+)]
+async fn edit_status(
+    id: &str,
+    key: &str,
+    status: Json<EditStatusPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<WorkflowStatusModel>> {
+    let id = parse_uuid(id)?;
+    let project = access::require_project(
+        db.inner(),
+        &auth.user.id,
+        &id,
+        ProjectAction::ManageWorkflow,
+    )
+    .await?;
+    let (status, task_ids) =
+        workflow::edit_status(db.inner(), &id, key, status.into_inner()).await?;
+    broadcast_change(feed.inner(), &project);
+    // Recategorized along with the status
+    let workspace_id = project.workspace_id;
+    broadcast_task_updates(
+        db.inner(),
+        feed.inner(),
+        workspace_id,
+        task_ids,
+        &["status_category"],
+    )
+    .await?;
+    Ok(Json(status))
+}
+
+// Remove a status no task of the project is in
+#[delete("/projects/<id>/workflow/statuses/<key>")]
+async fn delete_status(
+    id: &str,
+    key: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let project = access::require_project(
+        db.inner(),
+        &auth.user.id,
+        &id,
+        ProjectAction::ManageWorkflow,
+    )
+    .await?;
+    workflow::delete_status(db.inner(), &id, key).await?;
+    broadcast_change(feed.inner(), &project);
+    Ok(())
+}
+
+// Replace the moves allowed between the project's statuses. An empty list
+// allows any move.
+#[put(
+    "/projects/<id>/workflow/transitions",
+    format = "json",
+    data = "<transitions>"
+)]
+async fn set_transitions(
+    id: &str,
+    transitions: Json<Vec<TransitionPayload>>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<Vec<WorkflowTransitionModel>>> {
+    let id = parse_uuid(id)?;
+    let project = access::require_project(
+        db.inner(),
+        &auth.user.id,
+        &id,
+        ProjectAction::ManageWorkflow,
+    )
+    .await?;
+    let transitions = workflow::set_transitions(db.inner(), &id, transitions.into_inner()).await?;
+    broadcast_change(feed.inner(), &project);
+    Ok(Json(transitions))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_workflow,
+        create_status,
+        edit_status,
+        delete_status,
+        set_transitions
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::api::subscription::{EntityType, UpdateKind};
+    use crate::models::access::Role;
+    use crate::models::workflow::{Workflow, WorkflowStatusModel};
+    use crate::models::{project, task};
+    use crate::test_helpers;
+    use rocket::http::{ContentType, Status};
+    use serde_json::Value;
+
+    #[rocket::async_test]
+    async fn test_custom_workflow() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "Hiring".to_string())
+            .await
+            .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        project::set_member_role(&db, &project.id, &user.id, Role::Owner)
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(&db, "Alice".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let edit_status = async |status: &str| {
+            client
+                .patch(format!("/tasks/{}", task.id))
+                .header(ContentType::JSON)
+                .body(format!(r#"{{ "status": "{status}" }}"#))
+                .dispatch()
+                .await
+        };
+
+        // Projects start with the default statuses
+        let response = client
+            .get(uri!(super::get_workflow(project.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let workflow: Workflow = response.into_json().await.unwrap();
+        let keys: Vec<_> = workflow.statuses.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(keys, vec!["todo", "in_progress", "complete"]);
+        assert!(workflow.transitions.is_empty());
+
+        let response = client
+            .post(uri!(super::create_status(project.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r##"{ "key": "interview", "name": "Interview", "category": "active", "color": "#AA00FF" }"##)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let status: WorkflowStatusModel = response.into_json().await.unwrap();
+        assert_eq!(status.position, 3);
+        assert_eq!(status.color, "#aa00ff");
+
+        for body in [
+            r##"{ "key": "interview", "name": "Again", "category": "active", "color": "#aa00ff" }"##,
+            r##"{ "key": "Bad key", "name": "Bad", "category": "active", "color": "#aa00ff" }"##,
+            r##"{ "key": "hold", "name": "On hold", "category": "paused", "color": "#aa00ff" }"##,
+            r##"{ "key": "hold", "name": "On hold", "category": "active", "color": "red" }"##,
+        ] {
+            let response = client
+                .post(uri!(super::create_status(project.id.to_string())))
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await;
+            assert!(
+                [Status::Conflict, Status::UnprocessableEntity].contains(&response.status()),
+                "{body}"
+            );
+        }
+
+        // Statuses outside the workflow are rejected
+        assert_eq!(
+            edit_status("shortlisted").await.status(),
+            Status::UnprocessableEntity
+        );
+        let response = edit_status("interview").await;
+        assert_eq!(response.status(), Status::Ok);
+        let res: Value = response.into_json().await.unwrap();
+        assert_eq!(res["status"], "interview");
+        assert_eq!(res["status_category"], "active");
+
+        // Recategorizing a status carries over to its tasks
+        let mut updates = client.updates();
+        let response = client
+            .patch(uri!(super::edit_status(
+                project.id.to_string(),
+                "interview"
+            )))
+            .header(ContentType::JSON)
+            .body(r#"{ "name": "Interviewed", "category": "done" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get(format!("/tasks/{}", task.id)).dispatch().await;
+        let res: Value = response.into_json().await.unwrap();
+        assert_eq!(res["status_category"], "done");
+        let task_updates: Vec<_> = std::iter::from_fn(|| updates.try_recv().ok())
+            .filter(|e| e.entity_type == EntityType::Task)
+            .collect();
+        assert_eq!(task_updates.len(), 1);
+        assert_eq!(task_updates[0].entity_id, task.id);
+        assert_eq!(task_updates[0].kind, UpdateKind::Update);
+
+        // Statuses in use can't be deleted
+        let response = client
+            .delete(uri!(super::delete_status(
+                project.id.to_string(),
+                "interview"
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+
+        // Project stats count every configured status
+        let response = client
+            .get(format!("/projects/stats?ids={}", project.id))
+            .dispatch()
+            .await;
+        let res: Value = response.into_json().await.unwrap();
+        let counts: Vec<_> = res[0]["statuses"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["key"].as_str().unwrap(), s["count"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("todo", 0),
+                ("in_progress", 0),
+                ("complete", 0),
+                ("interview", 1)
+            ]
+        );
+        assert_eq!(res[0]["complete"], 1);
+
+        assert_eq!(edit_status("todo").await.status(), Status::Ok);
+        let response = client
+            .delete(uri!(super::delete_status(
+                project.id.to_string(),
+                "interview"
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_transitions() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "Strict".to_string())
+            .await
+            .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        project::set_member_role(&db, &project.id, &user.id, Role::Owner)
+            .await
+            .unwrap();
+        let task =
+            task::create_task_in_project(&db, "Task".to_string(), task::Status::Todo, &project.id)
+                .await
+                .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let edit_status = async |status: &str| {
+            client
+                .patch(format!("/tasks/{}", task.id))
+                .header(ContentType::JSON)
+                .body(format!(r#"{{ "status": "{status}" }}"#))
+                .dispatch()
+                .await
+                .status()
+        };
+
+        let response = client
+            .put(uri!(super::set_transitions(project.id.to_string())))
+            .header(ContentType::JSON)
+            .body(r#"[{ "from": "todo", "to": "unknown" }]"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let response = client
+            .put(uri!(super::set_transitions(project.id.to_string())))
+            .header(ContentType::JSON)
+            .body(
+                r#"[
+                    { "from": "todo", "to": "in_progress" },
+                    { "from": "in_progress", "to": "complete" },
+                    { "from": "complete", "to": "todo" },
+                    { "from": "todo", "to": "in_progress" }
+                ]"#,
+            )
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        // Repeated moves are only kept once
+        let transitions: Vec<serde_json::Value> = response.into_json().await.unwrap();
+        assert_eq!(transitions.len(), 3);
+
+        assert_eq!(edit_status("complete").await, Status::UnprocessableEntity);
+        assert_eq!(edit_status("in_progress").await, Status::Ok);
+        assert_eq!(edit_status("todo").await, Status::UnprocessableEntity);
+        assert_eq!(edit_status("complete").await, Status::Ok);
+        assert_eq!(edit_status("todo").await, Status::Ok);
+
+        // Statuses that have transitions can be deleted along with them
+        let response = client
+            .delete(uri!(super::delete_status(
+                project.id.to_string(),
+                "in_progress"
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(uri!(super::get_workflow(project.id.to_string())))
+            .dispatch()
+            .await;
+        let workflow: Workflow = response.into_json().await.unwrap();
+        assert_eq!(workflow.statuses.len(), 2);
+        assert_eq!(workflow.transitions.len(), 1);
+
+        // Clearing the transitions allows any move again
+        let response = client
+            .put(uri!(super::set_transitions(project.id.to_string())))
+            .header(ContentType::JSON)
+            .body("[]")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(edit_status("complete").await, Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_manage_workflow_requires_owner() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "Shared".to_string())
+            .await
+            .unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        project::set_member_role(&db, &project.id, &user.id, Role::Editor)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .get(uri!(super::get_workflow(project.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .put(uri!(super::set_transitions(project.id.to_string())))
+            .header(ContentType::JSON)
+            .body("[]")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+}
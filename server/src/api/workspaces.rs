@@ -0,0 +1,267 @@
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::Deserialize;
+
+use super::auth::AuthUser;
+use super::helpers::parse_uuid;
+use super::subscription::{EntityType, FeedWriter, UpdateEvent, UpdateKind};
+use crate::models::access;
+use crate::models::user::{self, User, UserModel};
+use crate::models::workspace::{self, Workspace, WorkspaceModel, WorkspaceRole};
+use crate::result::{Error, Result};
+
+// Get workspaces the caller is a member of
+#[get("/workspaces")]
+async fn workspaces(
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<WorkspaceModel>>> {
+    let workspaces = Workspace::find()
+        .has_related(User, user::Column::Id.eq(auth.user.id))
+        .order_by_asc(workspace::Column::CreatedAt)
+        .all(db.inner())
+        .await?;
+    Ok(Json(workspaces))
+}
+
+// Create a new workspace with the caller as its only member
+#[derive(Deserialize)]
+struct CreateWorkspacePayload {
+    name: String,
+}
+
+#[post("/workspaces", format = "json", data = "<workspace>")]
+async fn create_workspace(
+    workspace: Json<CreateWorkspacePayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<WorkspaceModel>> {
+    let workspace =
+        workspace::create_workspace(db.inner(), workspace.name.clone(), &auth.user.id).await?;
+    UpdateEvent::new(
+        workspace.id,
+        UpdateKind::Create,
+        EntityType::Workspace,
+        workspace.id,
+    )
+    .with_entity(&workspace)?
+    .with_member(auth.user.id)
+    .send(feed.inner());
+    Ok(Json(workspace))
+}
+
+// Get members of the workspace with the given id
+#[get("/workspaces/<id>/members")]
+async fn workspace_members(
+    id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+) -> Result<Json<Vec<UserModel>>> {
+    let id = parse_uuid(id)?;
+    access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+    let members = User::find()
+        .has_related(Workspace, workspace::Column::Id.eq(id))
+        .order_by_asc(user::Column::Username)
+        .all(db.inner())
+        .await?;
+    Ok(Json(members))
+}
+
+// Add the user with the given username to a workspace
+#[derive(Deserialize)]
+struct AddMemberPayload {
+    username: String,
+}
+
+#[post("/workspaces/<id>/members", format = "json", data = "<member>")]
+async fn add_workspace_member(
+    id: &str,
+    member: Json<AddMemberPayload>,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<Json<UserModel>> {
+    let id = parse_uuid(id)?;
+    access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+    let member = User::find()
+        .filter(user::Column::Username.eq(member.username.trim()))
+        .one(db.inner())
+        .await?
+        .ok_or_else(|| Error::validation("username", "No user with that username"))?;
+    workspace::add_member(db.inner(), &id, &member.id, WorkspaceRole::Member).await?;
+    UpdateEvent::new(id, UpdateKind::Update, EntityType::Workspace, id)
+        .with_member(member.id)
+        .send(feed.inner());
+    Ok(Json(member))
+}
+
+// Remove a user from a workspace. Members may remove themselves, and owners
+// anyone but the last owner.
+#[delete("/workspaces/<id>/members/<user_id>")]
+async fn remove_workspace_member(
+    id: &str,
+    user_id: &str,
+    auth: AuthUser,
+    db: &State<DatabaseConnection>,
+    feed: &State<FeedWriter>,
+) -> Result<()> {
+    let id = parse_uuid(id)?;
+    let user_id = parse_uuid(user_id)?;
+    access::require_workspace(db.inner(), &auth.user.id, &id).await?;
+    workspace::remove_member(db.inner(), &auth.user.id, &id, &user_id).await?;
+    UpdateEvent::new(id, UpdateKind::Update, EntityType::Workspace, id)
+        .with_member(user_id)
+        .send(feed.inner());
+    Ok(())
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        workspaces,
+        create_workspace,
+        workspace_members,
+        add_workspace_member,
+        remove_workspace_member
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::user::{self, UserModel};
+    use crate::models::workspace::{self, WorkspaceModel, WorkspaceRole};
+    use crate::test_helpers;
+    use rocket::http::{ContentType, Status};
+    use serde_json;
+
+    #[rocket::async_test]
+    async fn test_workspaces() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        workspace::create_workspace(&db, "Not mine".to_string(), &other.id)
+            .await
+            .unwrap();
+
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let response = client.get(uri!(super::workspaces)).dispatch().await;
+
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let res: Vec<WorkspaceModel> = serde_json::from_str(&response_str).expect("Workspace list");
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, client.workspace_id);
+    }
+
+    #[rocket::async_test]
+    async fn test_create_workspace() {
+        let client = test_helpers::init_server(None).await.unwrap();
+        let response = client
+            .post(uri!(super::create_workspace))
+            .header(ContentType::JSON)
+            .body(r#"{ "name": "Team B" }"#)
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Ok);
+        let response_str = response.into_string().await.unwrap();
+        let res: WorkspaceModel = serde_json::from_str(&response_str).expect("A workspace");
+        assert_eq!(res.name, "Team B");
+
+        let response = client.get(uri!(super::workspaces)).dispatch().await;
+        let response_str = response.into_string().await.unwrap();
+        let res: Vec<WorkspaceModel> = serde_json::from_str(&response_str).expect("Workspace list");
+        assert_eq!(res.len(), 2);
+    }
+
+    #[rocket::async_test]
+    async fn test_add_and_remove_workspace_member() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .post(uri!(super::add_workspace_member(
+                client.workspace_id.to_string()
+            )))
+            .header(ContentType::JSON)
+            .body(r#"{ "username": "other" }"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get(uri!(super::workspace_members(
+                client.workspace_id.to_string()
+            )))
+            .dispatch()
+            .await;
+        let response_str = response.into_string().await.unwrap();
+        let members: Vec<serde_json::Value> =
+            serde_json::from_str(&response_str).expect("Member list");
+        assert_eq!(members.len(), 2);
+
+        let response = client
+            .delete(uri!(super::remove_workspace_member(
+                client.workspace_id.to_string(),
+                other.id.to_string()
+            )))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_remove_workspace_member_rules() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let other = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let theirs = workspace::create_workspace(&db, "Theirs".to_string(), &other.id)
+            .await
+            .unwrap();
+        workspace::add_member(&db, &theirs.id, &user.id, WorkspaceRole::Member)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+        let remove = async |workspace_id: uuid::Uuid, user_id: uuid::Uuid| {
+            client
+                .delete(uri!(super::remove_workspace_member(
+                    workspace_id.to_string(),
+                    user_id.to_string()
+                )))
+                .dispatch()
+                .await
+                .status()
+        };
+
+        // Members can't remove others, only themselves
+        assert_eq!(remove(theirs.id, other.id).await, Status::Forbidden);
+        assert_eq!(remove(theirs.id, user.id).await, Status::Ok);
+        // Nor can the last owner, and so the last member, leave
+        assert_eq!(remove(client.workspace_id, user.id).await, Status::Conflict);
+    }
+
+    #[rocket::async_test]
+    async fn test_other_workspace_not_found() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let other: UserModel = user::create_user(&db, "other".to_string(), "password123")
+            .await
+            .unwrap();
+        let theirs = workspace::create_workspace(&db, "Theirs".to_string(), &other.id)
+            .await
+            .unwrap();
+        let client = test_helpers::init_server(Some(db)).await.unwrap();
+
+        let response = client
+            .get(uri!(super::workspace_members(theirs.id.to_string())))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
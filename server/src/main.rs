@@ -1,6 +1,7 @@
 #[macro_use]
 extern crate rocket;
 
+use api::presence::PresenceTable;
 use api::subscription::FeedWriter;
 use dotenv::dotenv;
 use migration::{Migrator, MigratorTrait};
@@ -8,12 +9,20 @@ use rocket::{Build, Rocket};
 use rocket_cors::{AllowedOrigins, CorsOptions};
 use sea_orm::{Database, DatabaseConnection};
 use std::env;
-use tokio::sync::broadcast;
 
 mod api;
+use api::comments;
+use api::notifications;
 use api::projects;
+use api::search;
 use api::subscription;
+use api::sync;
 use api::tasks;
+use api::users;
+use api::views;
+use api::webhooks;
+use api::workflows;
+use api::workspaces;
 
 mod models;
 mod result;
@@ -30,13 +39,32 @@ async fn initialize_rocket(
     db_conn: DatabaseConnection,
     update_feed: FeedWriter,
 ) -> anyhow::Result<Rocket<Build>> {
+    subscription::start_event_log(db_conn.clone(), &update_feed);
+    let fuzzy_search = search::FuzzySearch::start(db_conn.clone(), &update_feed);
+    views::watch_views(db_conn.clone(), &update_feed);
+    notifications::start_reminders(db_conn.clone(), &update_feed);
+    webhooks::start_webhooks(db_conn.clone(), &update_feed);
+    sync::start_pruning(db_conn.clone());
+    let presence = PresenceTable::start();
     let rocket = rocket::build()
         .manage(db_conn)
         .manage(update_feed)
+        .manage(fuzzy_search)
+        .manage(presence)
         .mount("/", routes![index])
         .mount("/", projects::routes())
+        .mount("/", search::routes())
         .mount("/", tasks::routes())
-        .mount("/", subscription::routes());
+        .mount("/", comments::routes())
+        .mount("/", notifications::routes())
+        .mount("/", subscription::routes())
+        .mount("/", sync::routes())
+        .mount("/", users::routes())
+        .mount("/", views::routes())
+        .mount("/", webhooks::routes())
+        .mount("/", workflows::routes())
+        .mount("/", workspaces::routes())
+        .register("/", result::catchers());
 
     Ok(rocket)
 }
@@ -51,7 +79,7 @@ async fn main() -> anyhow::Result<()> {
     let conn = Database::connect(db_uri).await?;
     Migrator::up(&conn, None).await?;
 
-    let update_feed = broadcast::channel(8).0;
+    let update_feed = FeedWriter::load(&conn, 64).await?;
     let rocket = initialize_rocket(conn, update_feed).await?;
 
     let allowed_origins = env::var("ALLOWED_ORIGINS").unwrap();
@@ -0,0 +1,239 @@
+// Central rules for what a user may see and do.
+//
+// Users see workspaces they are members of, projects in those workspaces, and
+// tasks belonging to any of those projects. Anything else is reported as not
+// found, so ids from other workspaces can't be probed.
+//
+// Within a workspace, each user has a role per project: the one set on their
+// project membership, or `DEFAULT_ROLE` if they have none.
+// Since tasks can be shared across projects:
+// - a task can be viewed or edited with the needed role on any of its projects
+// - deleting a task removes it from every project, so needs editor on all of them
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+use super::change_log::{self, LoggedEntity};
+use super::project::{self, Project, ProjectMember, ProjectMemberColumn, ProjectModel};
+use super::task::{Task, TaskModel};
+use super::workspace;
+use crate::result::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+// Role of workspace members without an explicit project membership
+pub const DEFAULT_ROLE: Role = Role::Editor;
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Role {
+    pub fn parse(s: &str) -> Result<Self> {
+        let res = match s {
+            "viewer" => Role::Viewer,
+            "editor" => Role::Editor,
+            "owner" => Role::Owner,
+            _ => return Err(Error::validation("role", format!("Invalid role {s}"))),
+        };
+
+        Ok(res)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProjectAction {
+    View,
+    // Edit project fields and add, remove or create its tasks
+    Edit,
+    Delete,
+    ManageMembers,
+    // Change the statuses of the project and the moves allowed between them
+    ManageWorkflow,
+    // Add or remove webhooks sent the project's events
+    ManageWebhooks,
+}
+
+impl ProjectAction {
+    fn required_role(&self) -> Role {
+        match self {
+            ProjectAction::View => Role::Viewer,
+            ProjectAction::Edit => Role::Editor,
+            ProjectAction::Delete
+            | ProjectAction::ManageMembers
+            | ProjectAction::ManageWorkflow
+            | ProjectAction::ManageWebhooks => Role::Owner,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskAction {
+    View,
+    Edit,
+    Delete,
+}
+
+pub async fn require_workspace<C: ConnectionTrait>(
+    db: &C,
+    user_id: &Uuid,
+    workspace_id: &Uuid,
+) -> Result<()> {
+    if workspace::is_member(db, workspace_id, user_id).await? {
+        Ok(())
+    } else {
+        Err(Error::not_found(format!(
+            "Workspace with id {workspace_id:?} not found!"
+        )))
+    }
+}
+
+// Roles the user has on each of the given (visible) projects
+async fn project_roles<C: ConnectionTrait>(
+    db: &C,
+    user_id: &Uuid,
+    project_ids: Vec<Uuid>,
+) -> Result<HashMap<Uuid, Role>> {
+    let explicit = ProjectMember::find()
+        .filter(ProjectMemberColumn::UserId.eq(*user_id))
+        .filter(ProjectMemberColumn::ProjectId.is_in(project_ids.clone()))
+        .all(db)
+        .await?;
+    let mut roles: HashMap<Uuid, Role> = project_ids
+        .into_iter()
+        .map(|id| (id, DEFAULT_ROLE))
+        .collect();
+    for member in explicit {
+        roles.insert(member.project_id, Role::parse(&member.role)?);
+    }
+    Ok(roles)
+}
+
+pub async fn project_role<C: ConnectionTrait>(
+    db: &C,
+    user_id: &Uuid,
+    project_id: &Uuid,
+) -> Result<Role> {
+    let roles = project_roles(db, user_id, vec![*project_id]).await?;
+    Ok(roles[project_id])
+}
+
+pub async fn require_project<C: ConnectionTrait>(
+    db: &C,
+    user_id: &Uuid,
+    project_id: &Uuid,
+    action: ProjectAction,
+) -> Result<ProjectModel> {
+    let workspace_ids = workspace::workspace_ids_for_user(db, user_id).await?;
+    let project = Project::find_by_id(*project_id)
+        .filter(project::Column::WorkspaceId.is_in(workspace_ids))
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Project with id {project_id:?} not found!")))?;
+
+    let role = project_role(db, user_id, project_id).await?;
+    if role < action.required_role() {
+        return Err(Error::forbidden(format!(
+            "Requires {} role on project",
+            action.required_role()
+        )));
+    }
+
+    Ok(project)
+}
+
+// Who may read an entity's history: anyone who may view it, or once it's
+// deleted, the members of the workspace it was in
+pub async fn require_history<C: ConnectionTrait>(
+    db: &C,
+    user_id: &Uuid,
+    entity: LoggedEntity,
+    id: &Uuid,
+) -> Result<()> {
+    let (live, exists) = match entity {
+        LoggedEntity::Task => (
+            require_task(db, user_id, id, TaskAction::View)
+                .await
+                .map(|_| ()),
+            Task::find_by_id(*id).one(db).await?.is_some(),
+        ),
+        LoggedEntity::Project => (
+            require_project(db, user_id, id, ProjectAction::View)
+                .await
+                .map(|_| ()),
+            Project::find_by_id(*id).one(db).await?.is_some(),
+        ),
+    };
+    match live {
+        Err(Error::NotFound(message)) if !exists => {
+            let workspace_id = change_log::logged_workspace_id(db, entity, id)
+                .await?
+                .ok_or(Error::NotFound(message))?;
+            require_workspace(db, user_id, &workspace_id).await
+        }
+        live => live,
+    }
+}
+
+pub struct TaskAccess {
+    pub task: TaskModel,
+    // Tasks only ever belong to projects of a single workspace
+    pub workspace_id: Uuid,
+}
+
+pub async fn require_task<C: ConnectionTrait>(
+    db: &C,
+    user_id: &Uuid,
+    task_id: &Uuid,
+    action: TaskAction,
+) -> Result<TaskAccess> {
+    let not_found = || Error::not_found(format!("Task with id {task_id:?} not found!"));
+    let workspace_ids = workspace::workspace_ids_for_user(db, user_id).await?;
+    let (task, projects) = Task::find_by_id(*task_id)
+        .find_with_related(Project)
+        .all(db)
+        .await?
+        .pop()
+        .ok_or_else(not_found)?;
+
+    let projects: Vec<ProjectModel> = projects
+        .into_iter()
+        .filter(|p| workspace_ids.contains(&p.workspace_id))
+        .collect();
+    let Some(workspace_id) = projects.first().map(|p| p.workspace_id) else {
+        return Err(not_found());
+    };
+
+    let roles = project_roles(db, user_id, projects.iter().map(|p| p.id).collect()).await?;
+    let max_role = *roles.values().max().unwrap_or(&Role::Viewer);
+    let min_role = *roles.values().min().unwrap_or(&Role::Viewer);
+    let allowed = match action {
+        TaskAction::View => max_role >= Role::Viewer,
+        TaskAction::Edit => max_role >= Role::Editor,
+        TaskAction::Delete => min_role >= Role::Editor,
+    };
+    if !allowed {
+        let message = match action {
+            TaskAction::Delete => "Requires editor role on every project of the task",
+            _ => "Requires editor role on a project of the task",
+        };
+        return Err(Error::forbidden(message));
+    }
+
+    Ok(TaskAccess { task, workspace_id })
+}
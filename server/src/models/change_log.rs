@@ -0,0 +1,225 @@
+// Append-only record of who changed what, and when
+use sea_orm::prelude::DateTime;
+use sea_orm::{
+    ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::project::Project;
+use super::task::{self, TaskProject};
+use super::user::{User, UserModel};
+use crate::result::Result;
+
+pub use entity::change_log::{
+    ActiveModel as ChangeLogActiveModel, Column, Entity as ChangeLog, Model as ChangeLogModel,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoggedEntity {
+    Project,
+    Task,
+}
+
+impl LoggedEntity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LoggedEntity::Project => "project",
+            LoggedEntity::Task => "task",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Update,
+    Delete,
+    // Relationships, like a task joining a project or gaining a blocker
+    Add,
+    Remove,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Add => "add",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+pub struct Change {
+    pub action: Action,
+    pub field: Option<String>,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+}
+
+// Workspace the entity is in, or was last logged in if it can no longer be
+// traced to one
+async fn workspace_id<C: ConnectionTrait>(
+    db: &C,
+    entity: LoggedEntity,
+    entity_id: &Uuid,
+) -> Result<Option<Uuid>> {
+    let current = match entity {
+        LoggedEntity::Project => Project::find_by_id(*entity_id)
+            .one(db)
+            .await?
+            .map(|p| p.workspace_id),
+        LoggedEntity::Task => TaskProject::find()
+            .filter(task::TaskProjectColumn::TaskId.eq(*entity_id))
+            .find_also_related(Project)
+            .one(db)
+            .await?
+            .and_then(|(_, project)| project)
+            .map(|p| p.workspace_id),
+    };
+    match current {
+        Some(workspace_id) => Ok(Some(workspace_id)),
+        None => logged_workspace_id(db, entity, entity_id).await,
+    }
+}
+
+// Workspace the entity was in when last logged
+pub async fn logged_workspace_id<C: ConnectionTrait>(
+    db: &C,
+    entity: LoggedEntity,
+    entity_id: &Uuid,
+) -> Result<Option<Uuid>> {
+    let last = ChangeLog::find()
+        .filter(Column::EntityType.eq(entity.as_str()))
+        .filter(Column::EntityId.eq(*entity_id))
+        .filter(Column::WorkspaceId.is_not_null())
+        .order_by_desc(Column::Id)
+        .one(db)
+        .await?;
+    Ok(last.and_then(|e| e.workspace_id))
+}
+
+// Record changes to the entity. Deletions are recorded before the entity is
+// deleted, so they're logged with its workspace.
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    entity: LoggedEntity,
+    entity_id: &Uuid,
+    changes: Vec<Change>,
+) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let workspace_id = workspace_id(db, entity, entity_id).await?;
+
+    let entries = changes.into_iter().map(|change| ChangeLogActiveModel {
+        actor_id: ActiveValue::Set(Some(*actor_id)),
+        entity_type: ActiveValue::Set(entity.as_str().to_string()),
+        entity_id: ActiveValue::Set(*entity_id),
+        action: ActiveValue::Set(change.action.as_str().to_string()),
+        field: ActiveValue::Set(change.field),
+        old_value: ActiveValue::Set(change.old_value),
+        new_value: ActiveValue::Set(change.new_value),
+        workspace_id: ActiveValue::Set(workspace_id),
+        ..Default::default()
+    });
+    ChangeLog::insert_many(entries).exec(db).await?;
+    Ok(())
+}
+
+// One update per top level field that differs between the serialized models
+pub fn field_changes<M: Serialize>(old: &M, new: &M) -> Result<Vec<Change>> {
+    let (Value::Object(old), Value::Object(mut new)) =
+        (serde_json::to_value(old)?, serde_json::to_value(new)?)
+    else {
+        return Ok(vec![]);
+    };
+
+    // Versions go up with every edit, so aren't a change of their own
+    let changes = old
+        .into_iter()
+        .filter(|(field, _)| field != "version")
+        .filter_map(|(field, old_value)| {
+            let new_value = new.remove(&field).unwrap_or(Value::Null);
+            (old_value != new_value).then_some(Change {
+                action: Action::Update,
+                field: Some(field),
+                old_value: Some(old_value),
+                new_value: Some(new_value),
+            })
+        })
+        .collect();
+    Ok(changes)
+}
+
+// Snapshot of an entity as it was just before deletion
+pub fn deletion<M: Serialize>(old: &M) -> Result<Change> {
+    Ok(Change {
+        action: Action::Delete,
+        field: None,
+        old_value: Some(serde_json::to_value(old)?),
+        new_value: None,
+    })
+}
+
+pub fn membership(action: Action, field: &str, related_id: &Uuid) -> Change {
+    let related_id = Some(Value::String(related_id.to_string()));
+    let (old_value, new_value) = match action {
+        Action::Remove => (related_id, None),
+        _ => (None, related_id),
+    };
+    Change {
+        action,
+        field: Some(field.to_string()),
+        old_value,
+        new_value,
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub id: i32,
+    pub action: String,
+    pub field: Option<String>,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub actor_id: Option<Uuid>,
+    // Unset if the user has since been deleted
+    pub actor_username: Option<String>,
+    pub created_at: DateTime,
+}
+
+// Changes to the entity, oldest first, with the user who made each
+pub async fn history(
+    db: &DatabaseConnection,
+    entity: LoggedEntity,
+    entity_id: &Uuid,
+) -> Result<Vec<HistoryEntry>> {
+    let entries = ChangeLog::find()
+        .filter(Column::EntityType.eq(entity.as_str()))
+        .filter(Column::EntityId.eq(*entity_id))
+        .order_by_asc(Column::Id)
+        .find_also_related(User)
+        .all(db)
+        .await?;
+
+    let entries = entries
+        .into_iter()
+        .map(
+            |(entry, actor): (ChangeLogModel, Option<UserModel>)| HistoryEntry {
+                id: entry.id,
+                action: entry.action,
+                field: entry.field,
+                old_value: entry.old_value,
+                new_value: entry.new_value,
+                actor_id: entry.actor_id,
+                actor_username: actor.map(|u| u.username),
+                created_at: entry.created_at,
+            },
+        )
+        .collect();
+    Ok(entries)
+}
@@ -0,0 +1,116 @@
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, TransactionTrait,
+};
+use uuid::Uuid;
+
+use crate::result::{Error, Result};
+
+pub use entity::comment::{
+    ActiveModel as CommentActiveModel, Column, Entity as Comment, Model as CommentModel,
+};
+
+fn validate_body(body: String) -> Result<String> {
+    let body = body.trim().to_string();
+    if body.is_empty() {
+        return Err(Error::validation("body", "Comment cannot be empty"));
+    }
+    Ok(body)
+}
+
+// Comments on the task, oldest first. Replies reference their parent.
+pub async fn comments_for_task(
+    db: &DatabaseConnection,
+    task_id: &Uuid,
+) -> Result<Vec<CommentModel>> {
+    let comments = Comment::find()
+        .filter(Column::TaskId.eq(*task_id))
+        .order_by_asc(Column::CreatedAt)
+        .all(db)
+        .await?;
+    Ok(comments)
+}
+
+// Find a comment, making sure it belongs to the given task
+pub async fn find_comment(
+    db: &DatabaseConnection,
+    task_id: &Uuid,
+    id: &Uuid,
+) -> Result<CommentModel> {
+    Comment::find_by_id(*id)
+        .filter(Column::TaskId.eq(*task_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Comment with id {id:?} not found!")))
+}
+
+pub async fn create_comment(
+    db: &DatabaseConnection,
+    task_id: &Uuid,
+    author_id: &Uuid,
+    body: String,
+    parent_id: Option<Uuid>,
+) -> Result<CommentModel> {
+    let body = validate_body(body)?;
+    if let Some(parent_id) = parent_id {
+        let parent = Comment::find_by_id(parent_id).one(db).await?;
+        if parent.is_none_or(|p| p.task_id != *task_id) {
+            return Err(Error::validation(
+                "parent_id",
+                "Can only reply to comments on the same task",
+            ));
+        }
+    }
+
+    let comment = CommentActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        task_id: ActiveValue::Set(*task_id),
+        author_id: ActiveValue::Set(*author_id),
+        parent_id: ActiveValue::Set(parent_id),
+        body: ActiveValue::Set(body),
+        // With sub-second precision, so comments in a busy thread stay in order
+        created_at: ActiveValue::Set(Utc::now().naive_utc()),
+        ..Default::default()
+    };
+    let comment = comment.insert(db).await?;
+
+    Ok(comment)
+}
+
+pub async fn edit_comment(
+    db: &DatabaseConnection,
+    id: &Uuid,
+    body: String,
+) -> Result<CommentModel> {
+    let comment = CommentActiveModel {
+        id: ActiveValue::Set(*id),
+        body: ActiveValue::Set(validate_body(body)?),
+        edited_at: ActiveValue::Set(Some(Utc::now().naive_utc())),
+        ..Default::default()
+    };
+    let comment = comment.update(db).await?;
+
+    Ok(comment)
+}
+
+// Also deletes any replies to the comment, and replies to those, returning
+// their ids
+pub async fn delete_comment(db: &DatabaseConnection, id: &Uuid) -> Result<Vec<Uuid>> {
+    let txn = db.begin().await?;
+    let mut reply_ids = vec![];
+    let mut frontier = vec![*id];
+    while !frontier.is_empty() {
+        frontier = Comment::find()
+            .filter(Column::ParentId.is_in(frontier))
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        reply_ids.extend(frontier.iter());
+    }
+    Comment::delete_by_id(*id).exec(&txn).await?;
+    txn.commit().await?;
+    Ok(reply_ids)
+}
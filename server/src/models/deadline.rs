@@ -0,0 +1,115 @@
+// Task deadlines: a due date, optionally with a time of day, in a time zone.
+//
+// The date and time are kept as entered, in the task's time zone or UTC
+// failing that. Alongside them the task stores the instant they fall at, so
+// overdue tasks can be found by comparing against the current time. Tasks due
+// on a date without a time are due by the end of that day.
+use chrono::{Days, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use sea_orm::prelude::{Date, DateTime, DateTimeUtc, DateTimeWithTimeZone, Time};
+use serde::{Deserialize, Serialize};
+
+use super::task::TaskModel;
+use crate::result::{Error, Result};
+
+// IANA name like Europe/Berlin
+pub fn parse_time_zone(s: &str) -> Result<Tz> {
+    s.parse().map_err(|_| {
+        Error::validation(
+            "time_zone",
+            format!("Unknown time zone {s}, expected a name like Europe/Berlin"),
+        )
+    })
+}
+
+pub fn parse_due_time(s: &str) -> Result<Time> {
+    Time::parse_from_str(s, "%H:%M:%S")
+        .or_else(|_| Time::parse_from_str(s, "%H:%M"))
+        .map_err(|_| {
+            Error::validation(
+                "due_time",
+                format!("Invalid due time {s}, expected HH:MM or HH:MM:SS"),
+            )
+        })
+}
+
+fn deadline(
+    date: Option<Date>,
+    time: Option<Time>,
+    time_zone: Option<&str>,
+) -> Option<chrono::DateTime<Tz>> {
+    let tz = time_zone.and_then(|tz| tz.parse().ok()).unwrap_or(Tz::UTC);
+    let local = match time {
+        Some(time) => date?.and_time(time),
+        None => (date? + Days::new(1)).and_time(NaiveTime::MIN),
+    };
+    // Times skipped when clocks go forward are taken to mean an hour later
+    let deadline = tz
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .unwrap_or_else(|| tz.from_utc_datetime(&local));
+    Some(deadline)
+}
+
+// The instant a task with the given deadline is due, in UTC
+pub fn due_at(date: Option<Date>, time: Option<Time>, time_zone: Option<&str>) -> Option<DateTime> {
+    deadline(date, time, time_zone).map(|d| d.naive_utc())
+}
+
+// Deadline of a task as returned by the API
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Due {
+    pub at: DateTimeUtc,
+    // The same instant in the task's time zone
+    pub local: DateTimeWithTimeZone,
+    pub time_zone: String,
+    // Due by the end of the due date rather than at a set time
+    pub all_day: bool,
+}
+
+pub fn due(task: &TaskModel) -> Option<Due> {
+    let deadline = deadline(task.due_date, task.due_time, task.time_zone.as_deref())?;
+    Some(Due {
+        at: deadline.with_timezone(&Utc),
+        local: deadline.fixed_offset(),
+        time_zone: deadline.timezone().name().to_string(),
+        all_day: task.due_time.is_none(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::due_at;
+    use sea_orm::prelude::{Date, DateTime, Time};
+
+    #[test]
+    fn test_due_at() {
+        let date = Date::from_ymd_opt(2025, 10, 21);
+        let time = Time::from_hms_opt(15, 0, 0);
+        let at = |s| Some(DateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap());
+
+        assert_eq!(
+            due_at(date, time, Some("Europe/Berlin")),
+            at("2025-10-21 13:00")
+        );
+        assert_eq!(due_at(date, time, None), at("2025-10-21 15:00"));
+        // By the end of the day
+        assert_eq!(
+            due_at(date, None, Some("America/New_York")),
+            at("2025-10-22 04:00")
+        );
+        assert_eq!(due_at(None, time, Some("Europe/Berlin")), None);
+
+        // 02:30 doesn't exist in Berlin the day clocks go forward
+        let date = Date::from_ymd_opt(2025, 3, 30);
+        let time = Time::from_hms_opt(2, 30, 0);
+        assert_eq!(
+            due_at(date, time, Some("Europe/Berlin")),
+            at("2025-03-30 01:30")
+        );
+    }
+}
@@ -0,0 +1,160 @@
+// "Task A is blocked by task B" relationships between tasks of a workspace.
+// The graph is kept acyclic, so there's always some task that can be done first.
+use sea_orm::sea_query::Query;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    JoinType, QueryFilter, QuerySelect, RelationTrait, Select, TransactionTrait,
+};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::change_log::{self, Action, LoggedEntity};
+use super::project::{self, Project};
+use super::task::{self, Task};
+use super::workflow::Category;
+use crate::result::{Error, Result};
+
+pub use entity::task_dependency::{
+    ActiveModel as TaskDependencyActiveModel, Column, Entity as TaskDependency, Relation,
+};
+
+// Tasks blocking the given task
+pub fn blockers(task_id: &Uuid) -> Select<Task> {
+    Task::find().filter(
+        task::Column::Id.in_subquery(
+            Query::select()
+                .column(Column::BlockerId)
+                .from(TaskDependency)
+                .and_where(Column::TaskId.eq(*task_id))
+                .to_owned(),
+        ),
+    )
+}
+
+// Tasks blocked by the given task
+pub fn dependents(task_id: &Uuid) -> Select<Task> {
+    Task::find().filter(
+        task::Column::Id.in_subquery(
+            Query::select()
+                .column(Column::TaskId)
+                .from(TaskDependency)
+                .and_where(Column::BlockerId.eq(*task_id))
+                .to_owned(),
+        ),
+    )
+}
+
+// Those of the given tasks with at least one blocker that isn't done
+pub async fn blocked_task_ids<C: ConnectionTrait>(
+    db: &C,
+    task_ids: Vec<Uuid>,
+) -> Result<HashSet<Uuid>> {
+    let blocked = TaskDependency::find()
+        .join(JoinType::InnerJoin, Relation::Blocker.def())
+        .filter(Column::TaskId.is_in(task_ids))
+        .filter(task::Column::StatusCategory.ne(Category::Done.to_string()))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|d| d.task_id)
+        .collect();
+    Ok(blocked)
+}
+
+// Whether the task is already, possibly indirectly, blocking the would-be blocker
+async fn creates_cycle<C: ConnectionTrait>(
+    db: &C,
+    task_id: &Uuid,
+    blocker_id: &Uuid,
+) -> Result<bool> {
+    let mut seen = HashSet::from([*blocker_id]);
+    let mut frontier = vec![*blocker_id];
+    while !frontier.is_empty() {
+        let next: Vec<Uuid> = TaskDependency::find()
+            .filter(Column::TaskId.is_in(frontier))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|d| d.blocker_id)
+            .filter(|id| seen.insert(*id))
+            .collect();
+        if next.contains(task_id) {
+            return Ok(true);
+        }
+        frontier = next;
+    }
+    Ok(false)
+}
+
+pub async fn add_blocker(
+    db: &DatabaseConnection,
+    actor_id: &Uuid,
+    task_id: &Uuid,
+    blocker_id: &Uuid,
+) -> Result<()> {
+    let cycle = || Error::validation("blocker_id", "Dependency would create a cycle");
+    if task_id == blocker_id {
+        return Err(cycle());
+    }
+
+    // Inserting first takes the write lock, so concurrent additions are
+    // checked for cycles one after another
+    let txn = db.begin().await?;
+    let dependency = TaskDependencyActiveModel {
+        task_id: ActiveValue::Set(*task_id),
+        blocker_id: ActiveValue::Set(*blocker_id),
+        ..Default::default()
+    };
+    dependency.insert(&txn).await?;
+    if creates_cycle(&txn, task_id, blocker_id).await? {
+        return Err(cycle());
+    }
+    let change = change_log::membership(Action::Add, "blocker_id", blocker_id);
+    change_log::record(&txn, actor_id, LoggedEntity::Task, task_id, vec![change]).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+pub async fn remove_blocker(
+    db: &DatabaseConnection,
+    actor_id: &Uuid,
+    task_id: &Uuid,
+    blocker_id: &Uuid,
+) -> Result<()> {
+    let txn = db.begin().await?;
+    let res = TaskDependency::delete_by_id((*task_id, *blocker_id))
+        .exec(&txn)
+        .await?;
+    if res.rows_affected == 0 {
+        return Err(Error::not_found(format!(
+            "Task {task_id:?} is not blocked by {blocker_id:?}"
+        )));
+    }
+    let change = change_log::membership(Action::Remove, "blocker_id", blocker_id);
+    change_log::record(&txn, actor_id, LoggedEntity::Task, task_id, vec![change]).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+// Refuse completing a blocked task if any of its projects asks for it
+pub async fn check_can_complete<C: ConnectionTrait>(db: &C, task_id: &Uuid) -> Result<()> {
+    let enforced = Project::find()
+        .has_related(Task, task::Column::Id.eq(*task_id))
+        .filter(project::Column::BlockCompletion.eq(true))
+        .one(db)
+        .await?;
+    if let Some(project) = enforced
+        && blocked_task_ids(db, vec![*task_id])
+            .await?
+            .contains(task_id)
+    {
+        return Err(Error::validation(
+            "status",
+            format!(
+                "Project {} requires blockers to be complete first",
+                project.title
+            ),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,355 @@
+// Task descriptions as collaborative text documents (CRDTs), so concurrent
+// edits merge rather than the last one overwriting the rest.
+//
+// Clients editing a description collaboratively exchange updates to its
+// document, which the server merges and persists as a single update. The
+// merged text is also kept as the task's description, and edits replacing the
+// description as a whole are applied to the document as the smallest change.
+use chrono::Utc;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ConnectionTrait, EntityTrait, TransactionSession,
+    TransactionTrait,
+};
+use uuid::Uuid;
+use yrs::updates::decoder::Decode;
+use yrs::{
+    Doc, GetString, OffsetKind, Options, ReadTxn, StateVector, Text, TextRef, Transact, Update,
+};
+
+use super::change_log::{self, LoggedEntity};
+use super::task::{Task, TaskActiveModel, TaskModel};
+use crate::result::{Error, Result};
+
+pub use entity::description_doc::{
+    ActiveModel as DescriptionDocActiveModel, Column, Entity as DescriptionDoc,
+};
+
+// Name of the text within each document
+const TEXT: &str = "description";
+
+// Offsets within the text count UTF-16 code units, as Yjs clients in browsers do
+fn new_doc() -> Doc {
+    Doc::with_options(Options {
+        offset_kind: OffsetKind::Utf16,
+        ..Options::default()
+    })
+}
+
+fn text(doc: &Doc) -> TextRef {
+    doc.get_or_insert_text(TEXT)
+}
+
+fn decode_update(update: &[u8]) -> Result<Update> {
+    Update::decode_v1(update)
+        .map_err(|e| Error::validation("update", format!("Invalid description update: {e}")))
+}
+
+fn load(state: &[u8]) -> Result<Doc> {
+    let doc = new_doc();
+    if !state.is_empty() {
+        doc.transact_mut()
+            .apply_update(decode_update(state)?)
+            .map_err(|e| anyhow::Error::msg(format!("Corrupt description document: {e}")))?;
+    }
+    Ok(doc)
+}
+
+// Take the database's write lock for the rest of the transaction, by writing
+// first, so documents are read, merged and saved one update after another
+async fn lock<C: ConnectionTrait>(db: &C, task_id: &Uuid) -> Result<()> {
+    let placeholder = DescriptionDocActiveModel {
+        task_id: ActiveValue::Set(*task_id),
+        state: ActiveValue::Set(vec![]),
+        updated_at: ActiveValue::Set(Utc::now().naive_utc()),
+    };
+    DescriptionDoc::insert(placeholder)
+        .on_conflict(OnConflict::column(Column::TaskId).do_nothing().to_owned())
+        .exec_without_returning(db)
+        .await?;
+    Ok(())
+}
+
+// The task's document, started from its description if it had none yet. Needs
+// the lock, so every client starts from the same document.
+async fn find_doc<C: ConnectionTrait>(db: &C, task: &TaskModel) -> Result<Doc> {
+    let existing = DescriptionDoc::find_by_id(task.id).one(db).await?;
+    if let Some(existing) = existing
+        && !existing.state.is_empty()
+    {
+        return load(&existing.state);
+    }
+    let doc = new_doc();
+    if let Some(description) = &task.description {
+        text(&doc).insert(&mut doc.transact_mut(), 0, description);
+    }
+    save(db, &task.id, &doc).await?;
+    Ok(doc)
+}
+
+// Store the document compacted into a single update
+async fn save<C: ConnectionTrait>(db: &C, task_id: &Uuid, doc: &Doc) -> Result<()> {
+    let state = doc
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default());
+    let row = DescriptionDocActiveModel {
+        task_id: ActiveValue::Set(*task_id),
+        state: ActiveValue::Set(state),
+        updated_at: ActiveValue::Set(Utc::now().naive_utc()),
+    };
+    DescriptionDoc::insert(row)
+        .on_conflict(
+            OnConflict::column(Column::TaskId)
+                .update_columns([Column::State, Column::UpdatedAt])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+fn description(doc: &Doc) -> Option<String> {
+    let text = text(doc).get_string(&doc.transact());
+    (!text.is_empty()).then_some(text)
+}
+
+// What of the task's document a client with the given state vector is missing,
+// or all of it without one
+pub async fn missing_update<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    task: &TaskModel,
+    state_vector: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    let state_vector = match state_vector {
+        Some(sv) => StateVector::decode_v1(sv)
+            .map_err(|e| Error::validation("state_vector", format!("Invalid state vector: {e}")))?,
+        None => StateVector::default(),
+    };
+    let txn = db.begin().await?;
+    lock(&txn, &task.id).await?;
+    let doc = find_doc(&txn, task).await?;
+    txn.commit().await?;
+    let update = doc.transact().encode_state_as_update_v1(&state_vector);
+    Ok(update)
+}
+
+// Merge a client's update into the task's document, returning the task with
+// its new description
+pub async fn apply_update<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    task_id: &Uuid,
+    update: &[u8],
+) -> Result<TaskModel> {
+    let txn = db.begin().await?;
+    lock(&txn, task_id).await?;
+    let task = Task::find_by_id(*task_id)
+        .one(&txn)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Task with id {task_id:?} not found!")))?;
+
+    // Decoded updates can't be held across awaits
+    let doc = find_doc(&txn, &task).await?;
+    doc.transact_mut()
+        .apply_update(decode_update(update)?)
+        .map_err(|e| Error::validation("update", format!("Invalid description update: {e}")))?;
+    save(&txn, task_id, &doc).await?;
+
+    let description = description(&doc);
+    let task = if description != task.description {
+        let old = task.clone();
+        let mut edited: TaskActiveModel = task.into();
+        edited.description = ActiveValue::Set(description);
+        edited.version = ActiveValue::Set(old.version + 1);
+        let edited = edited.update(&txn).await?;
+        let changes = change_log::field_changes(&old, &edited)?;
+        change_log::record(&txn, actor_id, LoggedEntity::Task, task_id, changes).await?;
+        edited
+    } else {
+        task
+    };
+    txn.commit().await?;
+    Ok(task)
+}
+
+// Length of the characters both start with, in bytes and UTF-16 units
+fn common(a: impl Iterator<Item = char>, b: impl Iterator<Item = char>) -> (usize, usize) {
+    a.zip(b)
+        .take_while(|(a, b)| a == b)
+        .fold((0, 0), |(bytes, units), (c, _)| {
+            (bytes + c.len_utf8(), units + c.len_utf16())
+        })
+}
+
+// Apply an edit of the description as a whole to the task's document, if it
+// has one, changing only the text between what's unchanged at either end.
+// Called after writing the task, so with the lock held.
+pub async fn replace<C: ConnectionTrait>(
+    db: &C,
+    task: &TaskModel,
+    new: Option<&str>,
+) -> Result<()> {
+    if DescriptionDoc::find_by_id(task.id).one(db).await?.is_none() {
+        return Ok(());
+    }
+    let doc = find_doc(db, task).await?;
+    let text = text(&doc);
+    let old = text.get_string(&doc.transact());
+    let new = new.unwrap_or_default();
+    if old == new {
+        return Ok(());
+    }
+
+    let (prefix, prefix_units) = common(old.chars(), new.chars());
+    let (suffix, _) = common(old[prefix..].chars().rev(), new[prefix..].chars().rev());
+    {
+        let mut txn = doc.transact_mut();
+        let removed = old[prefix..old.len() - suffix].encode_utf16().count();
+        if removed > 0 {
+            text.remove_range(&mut txn, prefix_units as u32, removed as u32);
+        }
+        let inserted = &new[prefix..new.len() - suffix];
+        if !inserted.is_empty() {
+            text.insert(&mut txn, prefix_units as u32, inserted);
+        }
+    }
+    save(db, &task.id, &doc).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply_update, missing_update, new_doc};
+    use crate::models::change_log::{self, LoggedEntity};
+    use crate::models::{project, task};
+    use crate::test_helpers;
+    use rocket::serde::json::Json;
+    use yrs::updates::decoder::Decode;
+    use yrs::updates::encoder::Encode;
+    use yrs::{Doc, GetString, ReadTxn, Text, Transact, Update};
+
+    // Counting offsets in UTF-16 units, like Yjs in a browser
+    fn client_doc(update: &[u8]) -> Doc {
+        let doc = new_doc();
+        doc.transact_mut()
+            .apply_update(Update::decode_v1(update).unwrap())
+            .unwrap();
+        doc
+    }
+
+    // Insert into a client's copy, returning the update to send
+    fn insert(doc: &Doc, index: u32, s: &str) -> Vec<u8> {
+        let before = doc.transact().state_vector();
+        let text = doc.get_or_insert_text("description");
+        text.insert(&mut doc.transact_mut(), index, s);
+        doc.transact().encode_state_as_update_v1(&before)
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_edits() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let payload = serde_json::from_str(r#"{"description": "Buy milk"}"#).unwrap();
+        let (task, _) = task::edit_task(&db, &user.id, &task.id, Json(payload), None)
+            .await
+            .unwrap();
+
+        // Both clients start from the same document, and edit it at once
+        let alice = client_doc(&missing_update(&db, &task, None).await.unwrap());
+        let bob = client_doc(&missing_update(&db, &task, None).await.unwrap());
+        let from_alice = insert(&alice, 0, "Go and ");
+        let from_bob = insert(&bob, 8, " and eggs");
+        apply_update(&db, &user.id, &task.id, &from_alice)
+            .await
+            .unwrap();
+        let task = apply_update(&db, &user.id, &task.id, &from_bob)
+            .await
+            .unwrap();
+        assert_eq!(
+            task.description.as_deref(),
+            Some("Go and Buy milk and eggs")
+        );
+
+        // Replacing the description as a whole reaches clients as an edit
+        let payload =
+            serde_json::from_str(r#"{"description": "Go and buy oat milk and eggs"}"#).unwrap();
+        let (task, _) = task::edit_task(&db, &user.id, &task.id, Json(payload), None)
+            .await
+            .unwrap();
+        let state_vector = alice.transact().state_vector();
+        let update = missing_update(&db, &task, Some(&state_vector.encode_v1()))
+            .await
+            .unwrap();
+        alice
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap())
+            .unwrap();
+        let text = alice.get_or_insert_text("description");
+        assert_eq!(
+            text.get_string(&alice.transact()),
+            "Go and buy oat milk and eggs"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_multibyte_edits() {
+        let db = test_helpers::db_conn().await.unwrap();
+        let user = test_helpers::test_user(&db).await.unwrap();
+        let workspace_id = test_helpers::workspace_id(&db).await.unwrap();
+        let project = project::create_project(&db, &workspace_id, "A project".to_string())
+            .await
+            .unwrap();
+        let task = task::create_task_in_project(
+            &db,
+            "A task".to_string(),
+            task::Status::Todo,
+            &project.id,
+        )
+        .await
+        .unwrap();
+        let payload = serde_json::from_str(r#"{"description": "Café 🥛 milk"}"#).unwrap();
+        let (task, _) = task::edit_task(&db, &user.id, &task.id, Json(payload), None)
+            .await
+            .unwrap();
+
+        // Before "milk" is 8 UTF-16 units in, but 11 bytes
+        let alice = client_doc(&missing_update(&db, &task, None).await.unwrap());
+        let update = insert(&alice, 8, "oat ");
+        let task = apply_update(&db, &user.id, &task.id, &update)
+            .await
+            .unwrap();
+        assert_eq!(task.description.as_deref(), Some("Café 🥛 oat milk"));
+        let history = change_log::history(&db, LoggedEntity::Task, &task.id)
+            .await
+            .unwrap();
+        let last = history.last().unwrap();
+        assert_eq!(last.field.as_deref(), Some("description"));
+        assert_eq!(last.actor_id, Some(user.id));
+
+        let payload = serde_json::from_str(r#"{"description": "Café 🥛🍪 oat milk"}"#).unwrap();
+        let (task, _) = task::edit_task(&db, &user.id, &task.id, Json(payload), None)
+            .await
+            .unwrap();
+        let state_vector = alice.transact().state_vector();
+        let update = missing_update(&db, &task, Some(&state_vector.encode_v1()))
+            .await
+            .unwrap();
+        alice
+            .transact_mut()
+            .apply_update(Update::decode_v1(&update).unwrap())
+            .unwrap();
+        let text = alice.get_or_insert_text("description");
+        assert_eq!(text.get_string(&alice.transact()), "Café 🥛🍪 oat milk");
+    }
+}
@@ -0,0 +1,42 @@
+// The tail of the update feed, kept so clients that reconnect can be sent the
+// events they missed, including across restarts
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+use crate::result::Result;
+
+pub use entity::event_log::{
+    ActiveModel as EventLogActiveModel, Column, Entity as EventLog, Model as EventLogModel,
+};
+
+// The latest logged events, oldest first
+pub async fn recent(db: &DatabaseConnection, limit: u64) -> Result<Vec<EventLogModel>> {
+    let mut events = EventLog::find()
+        .order_by_desc(Column::Seq)
+        .limit(limit)
+        .all(db)
+        .await?;
+    events.reverse();
+    Ok(events)
+}
+
+// Events already logged are left alone
+pub async fn append(db: &DatabaseConnection, events: Vec<EventLogModel>) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    EventLog::insert_many(events.into_iter().map(EventLogActiveModel::from))
+        .on_conflict(OnConflict::column(Column::Seq).do_nothing().to_owned())
+        .exec_without_returning(db)
+        .await?;
+    Ok(())
+}
+
+// Forget events before the given one
+pub async fn prune(db: &DatabaseConnection, before_seq: i64) -> Result<()> {
+    EventLog::delete_many()
+        .filter(Column::Seq.lt(before_seq))
+        .exec(db)
+        .await?;
+    Ok(())
+}
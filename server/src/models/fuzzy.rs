@@ -0,0 +1,323 @@
+// In-memory index for typo tolerant search over tasks, projects and comments.
+//
+// Documents are tokenized into lowercase words, and each query word is matched
+// against the closest word of each document, allowing a few edits depending on
+// its length. Every query word has to match somewhere, in any order.
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use super::comment::{self, Comment, CommentModel};
+use super::project::{self, Project, ProjectModel};
+use super::task::{Task, TaskModel, TaskProject, TaskProjectColumn};
+use crate::result::{Error, Result};
+
+const TITLE_WEIGHT: f64 = 1.0;
+const BODY_WEIGHT: f64 = 0.6;
+const EXCERPT_LENGTH: usize = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocKind {
+    Task,
+    Project,
+    Comment,
+}
+
+struct Doc {
+    workspace_id: Uuid,
+    // The task a comment is on
+    task_id: Option<Uuid>,
+    title: String,
+    fields: Vec<(f64, Vec<String>)>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FuzzyHit {
+    pub entity_type: DocKind,
+    pub id: Uuid,
+    pub task_id: Option<Uuid>,
+    // Title of the task or project, or the start of a comment
+    pub title: String,
+    // Between 0 and 1, higher is a better match
+    pub score: f64,
+}
+
+#[derive(Default)]
+pub struct FuzzyIndex {
+    docs: HashMap<(DocKind, Uuid), Doc>,
+}
+
+fn tokenize(s: &str) -> Vec<String> {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn excerpt(s: &str) -> String {
+    match s.char_indices().nth(EXCERPT_LENGTH) {
+        Some((end, _)) => format!("{}…", &s[..end]),
+        None => s.to_string(),
+    }
+}
+
+// Edit distance counting insertions, deletions, substitutions and swaps of
+// adjacent characters
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+fn allowed_edits(len: usize) -> usize {
+    match len {
+        0..=2 => 0,
+        3..=5 => 1,
+        6..=9 => 2,
+        _ => 3,
+    }
+}
+
+// How well a query word matches a document word, 0 if not at all
+fn word_score(query: &str, word: &str) -> f64 {
+    if query == word {
+        return 1.0;
+    }
+    if query.len() >= 2 && word.starts_with(query) {
+        return 0.9;
+    }
+
+    let q: Vec<char> = query.chars().collect();
+    let w: Vec<char> = word.chars().collect();
+    let allowed = allowed_edits(q.len());
+    if allowed == 0 {
+        return 0.0;
+    }
+    let edits = edit_distance(&q, &w);
+    if edits <= allowed {
+        return 0.8 - 0.1 * edits as f64;
+    }
+    // The query word may be a misspelt start of the document word
+    let prefix_edits = (q.len().saturating_sub(1)..=q.len() + 1)
+        .filter(|len| *len < w.len())
+        .map(|len| edit_distance(&q, &w[..len]))
+        .min();
+    match prefix_edits {
+        Some(edits) if edits <= allowed => 0.7 - 0.1 * edits as f64,
+        _ => 0.0,
+    }
+}
+
+impl Doc {
+    fn score(&self, query: &[String]) -> Option<f64> {
+        let mut total = 0.0;
+        for q in query {
+            let best = self
+                .fields
+                .iter()
+                .flat_map(|(weight, words)| words.iter().map(move |w| weight * word_score(q, w)))
+                .fold(0.0, f64::max);
+            if best == 0.0 {
+                return None;
+            }
+            total += best;
+        }
+        Some(total / query.len() as f64)
+    }
+}
+
+fn task_doc(task: &TaskModel, workspace_id: Uuid) -> Doc {
+    Doc {
+        workspace_id,
+        task_id: None,
+        title: task.title.clone(),
+        fields: vec![
+            (TITLE_WEIGHT, tokenize(&task.title)),
+            (
+                BODY_WEIGHT,
+                tokenize(task.description.as_deref().unwrap_or("")),
+            ),
+        ],
+    }
+}
+
+fn project_doc(project: &ProjectModel) -> Doc {
+    Doc {
+        workspace_id: project.workspace_id,
+        task_id: None,
+        title: project.title.clone(),
+        fields: vec![
+            (TITLE_WEIGHT, tokenize(&project.title)),
+            (
+                BODY_WEIGHT,
+                tokenize(project.description.as_deref().unwrap_or("")),
+            ),
+        ],
+    }
+}
+
+fn comment_doc(comment: &CommentModel, workspace_id: Uuid) -> Doc {
+    Doc {
+        workspace_id,
+        task_id: Some(comment.task_id),
+        title: excerpt(&comment.body),
+        fields: vec![(BODY_WEIGHT, tokenize(&comment.body))],
+    }
+}
+
+// Workspace of a task, if it's in any project
+async fn task_workspace(db: &DatabaseConnection, task_id: &Uuid) -> Result<Option<Uuid>> {
+    let project = Project::find()
+        .has_related(Task, super::task::Column::Id.eq(*task_id))
+        .one(db)
+        .await?;
+    Ok(project.map(|p| p.workspace_id))
+}
+
+impl FuzzyIndex {
+    // Index everything currently in the database
+    pub async fn load(db: &DatabaseConnection) -> Result<Self> {
+        let mut index = FuzzyIndex::default();
+        let mut task_workspaces = HashMap::new();
+        for (task, projects) in Task::find().find_with_related(Project).all(db).await? {
+            if let Some(project) = projects.first() {
+                task_workspaces.insert(task.id, project.workspace_id);
+                let doc = task_doc(&task, project.workspace_id);
+                index.docs.insert((DocKind::Task, task.id), doc);
+            }
+        }
+        for project in Project::find().all(db).await? {
+            index
+                .docs
+                .insert((DocKind::Project, project.id), project_doc(&project));
+        }
+        for comment in Comment::find().all(db).await? {
+            if let Some(workspace_id) = task_workspaces.get(&comment.task_id) {
+                let doc = comment_doc(&comment, *workspace_id);
+                index.docs.insert((DocKind::Comment, comment.id), doc);
+            }
+        }
+        Ok(index)
+    }
+
+    pub async fn refresh_task(&mut self, db: &DatabaseConnection, id: &Uuid) -> Result<()> {
+        let task = Task::find_by_id(*id).one(db).await?;
+        let workspace_id = task_workspace(db, id).await?;
+        match (task, workspace_id) {
+            (Some(task), Some(workspace_id)) => {
+                self.docs
+                    .insert((DocKind::Task, *id), task_doc(&task, workspace_id));
+            }
+            _ => {
+                self.docs.remove(&(DocKind::Task, *id));
+            }
+        }
+        // Comments go along with their task
+        self.refresh_task_comments(db, id).await
+    }
+
+    pub async fn refresh_project(&mut self, db: &DatabaseConnection, id: &Uuid) -> Result<()> {
+        match Project::find_by_id(*id).one(db).await? {
+            Some(project) => {
+                self.docs
+                    .insert((DocKind::Project, *id), project_doc(&project));
+            }
+            None => {
+                self.docs.remove(&(DocKind::Project, *id));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn refresh_task_comments(
+        &mut self,
+        db: &DatabaseConnection,
+        task_id: &Uuid,
+    ) -> Result<()> {
+        self.docs.retain(|_, doc| doc.task_id != Some(*task_id));
+        let Some(workspace_id) = task_workspace(db, task_id).await? else {
+            return Ok(());
+        };
+        let comments = Comment::find()
+            .filter(comment::Column::TaskId.eq(*task_id))
+            .all(db)
+            .await?;
+        for comment in comments {
+            let doc = comment_doc(&comment, workspace_id);
+            self.docs.insert((DocKind::Comment, comment.id), doc);
+        }
+        Ok(())
+    }
+
+    // Best matches in the given workspaces
+    pub async fn search(
+        &self,
+        db: &DatabaseConnection,
+        workspace_ids: &[Uuid],
+        q: &str,
+        limit: usize,
+    ) -> Result<Vec<FuzzyHit>> {
+        let query = tokenize(q);
+        if query.is_empty() {
+            return Err(Error::validation("q", "Search query cannot be empty"));
+        }
+
+        let mut hits: Vec<FuzzyHit> = self
+            .docs
+            .iter()
+            .filter(|(_, doc)| workspace_ids.contains(&doc.workspace_id))
+            .filter_map(|((kind, id), doc)| {
+                doc.score(&query).map(|score| FuzzyHit {
+                    entity_type: *kind,
+                    id: *id,
+                    task_id: doc.task_id,
+                    title: doc.title.clone(),
+                    score,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        // Tasks can drop out of their last project without being edited, say
+        // when the project is deleted, so check they're still visible
+        let task_ids: Vec<Uuid> = hits
+            .iter()
+            .filter_map(|h| match h.entity_type {
+                DocKind::Task => Some(h.id),
+                DocKind::Comment => h.task_id,
+                DocKind::Project => None,
+            })
+            .collect();
+        let visible: HashSet<Uuid> = TaskProject::find()
+            .inner_join(Project)
+            .filter(TaskProjectColumn::TaskId.is_in(task_ids))
+            .filter(project::Column::WorkspaceId.is_in(workspace_ids.to_vec()))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|tp| tp.task_id)
+            .collect();
+        hits.retain(|h| match h.entity_type {
+            DocKind::Task => visible.contains(&h.id),
+            DocKind::Comment => h.task_id.is_some_and(|id| visible.contains(&id)),
+            DocKind::Project => true,
+        });
+        hits.truncate(limit);
+
+        Ok(hits)
+    }
+}
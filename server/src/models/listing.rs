@@ -0,0 +1,300 @@
+// Filtering, sorting and pagination shared by the task listings.
+//
+// Pages are cut with a keyset cursor rather than an offset: the cursor holds
+// the sort key and id of the last task returned, and the next page starts
+// strictly after it. Task ids break ties so the order is total.
+use chrono::{NaiveTime, Utc};
+use sea_orm::prelude::{Date, DateTime, Expr};
+use sea_orm::sea_query::{ExprTrait, Func};
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, Order, QueryFilter, QueryOrder,
+    QuerySelect, Select, Value,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use super::task::{Column, Task, TaskModel, TaskProject, TaskProjectColumn};
+use super::workflow::Category;
+use crate::result::{Error, Result};
+
+const DEFAULT_LIMIT: u64 = 50;
+const MAX_LIMIT: u64 = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sort {
+    // The order tasks were arranged in within a project
+    Position(Uuid),
+    CreatedAt,
+    DueDate,
+    Title,
+    // Not started, then active, then done, by status category
+    Status,
+}
+
+impl Sort {
+    fn name(&self) -> &'static str {
+        match self {
+            Sort::Position(_) => "position",
+            Sort::CreatedAt => "created_at",
+            Sort::DueDate => "due_date",
+            Sort::Title => "title",
+            Sort::Status => "status",
+        }
+    }
+
+    // Position only means something when listing the tasks of a project
+    pub fn parse(s: &str, project_id: Option<Uuid>) -> Result<Self> {
+        let res = match (s, project_id) {
+            ("position", Some(project_id)) => Sort::Position(project_id),
+            ("created_at", _) => Sort::CreatedAt,
+            ("due_date", _) => Sort::DueDate,
+            ("title", _) => Sort::Title,
+            ("status", _) => Sort::Status,
+            _ => return Err(Error::validation("sort", format!("Invalid sort {s}"))),
+        };
+        Ok(res)
+    }
+
+    // Tasks without a due date come last in either direction
+    fn undated(order: &Order) -> DateTime {
+        let (y, m, d) = match order {
+            Order::Desc => (1, 1, 1),
+            _ => (9999, 12, 31),
+        };
+        Date::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_time(NaiveTime::MIN)
+    }
+
+    fn expr(&self, order: &Order) -> Expr {
+        match self {
+            Sort::Position(_) => Expr::col(TaskProjectColumn::Position.as_column_ref()),
+            Sort::CreatedAt => Expr::col(Column::CreatedAt.as_column_ref()),
+            Sort::Title => Expr::col(Column::Title.as_column_ref()),
+            // By the instant tasks are due, wherever they are
+            Sort::DueDate => Func::coalesce([
+                Expr::col(Column::DueAt.as_column_ref()),
+                Expr::val(Self::undated(order)),
+            ])
+            .into(),
+            Sort::Status => Expr::case(
+                Expr::col(Column::StatusCategory.as_column_ref())
+                    .eq(Category::NotStarted.to_string()),
+                Category::NotStarted as i32,
+            )
+            .case(
+                Expr::col(Column::StatusCategory.as_column_ref()).eq(Category::Active.to_string()),
+                Category::Active as i32,
+            )
+            .finally(Category::Done as i32)
+            .into(),
+        }
+    }
+
+    // Sort key of the task, as stored in a cursor
+    async fn key(&self, db: &DatabaseConnection, task: &TaskModel) -> Result<JsonValue> {
+        let key = match self {
+            Sort::Position(project_id) => {
+                let position = TaskProject::find_by_id((*project_id, task.id))
+                    .one(db)
+                    .await?
+                    .map(|tp| tp.position);
+                serde_json::to_value(position)?
+            }
+            Sort::CreatedAt => serde_json::to_value(task.created_at)?,
+            Sort::Title => serde_json::to_value(&task.title)?,
+            Sort::DueDate => serde_json::to_value(task.due_at)?,
+            Sort::Status => serde_json::to_value(Category::parse(&task.status_category)? as i32)?,
+        };
+        Ok(key)
+    }
+
+    // The inverse of `key`, for comparing against `expr`
+    fn key_value(&self, key: JsonValue, order: &Order) -> Result<Value> {
+        let value = match self {
+            Sort::Position(_) | Sort::Title => serde_json::from_value::<String>(key)?.into(),
+            Sort::CreatedAt => serde_json::from_value::<DateTime>(key)?.into(),
+            Sort::DueDate => serde_json::from_value::<Option<DateTime>>(key)?
+                .unwrap_or(Self::undated(order))
+                .into(),
+            Sort::Status => serde_json::from_value::<i32>(key)?.into(),
+        };
+        Ok(value)
+    }
+}
+
+pub fn page_limit(limit: Option<u64>) -> Result<u64> {
+    match limit {
+        Some(0) => Err(Error::validation("limit", "Limit must be positive")),
+        Some(limit) => Ok(limit.min(MAX_LIMIT)),
+        None => Ok(DEFAULT_LIMIT),
+    }
+}
+
+pub fn parse_order(s: &str) -> Result<Order> {
+    match s {
+        "asc" => Ok(Order::Asc),
+        "desc" => Ok(Order::Desc),
+        _ => Err(Error::validation("order", format!("Invalid order {s}"))),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TaskFilter {
+    // Any of these status keys, or any status if empty
+    pub statuses: Vec<String>,
+    // Any of these status categories, or any category if empty
+    pub categories: Vec<Category>,
+    pub due_before: Option<Date>,
+    pub due_after: Option<Date>,
+    // Past the instant it's due and not done
+    pub overdue: Option<bool>,
+    pub has_description: Option<bool>,
+    pub created_after: Option<DateTime>,
+}
+
+impl TaskFilter {
+    pub fn condition(&self) -> Condition {
+        let mut cond = Condition::all();
+        if !self.statuses.is_empty() {
+            cond = cond.add(Column::Status.is_in(self.statuses.clone()));
+        }
+        if !self.categories.is_empty() {
+            cond = cond
+                .add(Column::StatusCategory.is_in(self.categories.iter().map(|c| c.to_string())));
+        }
+        if let Some(date) = self.due_before {
+            cond = cond.add(Column::DueDate.lt(date));
+        }
+        if let Some(date) = self.due_after {
+            cond = cond.add(Column::DueDate.gt(date));
+        }
+        if let Some(overdue) = self.overdue {
+            let now = Utc::now().naive_utc();
+            let done = Category::Done.to_string();
+            cond = cond.add(if overdue {
+                Condition::all()
+                    .add(Column::DueAt.lt(now))
+                    .add(Column::StatusCategory.ne(done))
+            } else {
+                Condition::any()
+                    .add(Column::DueAt.is_null())
+                    .add(Column::DueAt.gte(now))
+                    .add(Column::StatusCategory.eq(done))
+            });
+        }
+        if let Some(has_description) = self.has_description {
+            let has = Condition::all()
+                .add(Column::Description.is_not_null())
+                .add(Column::Description.ne(""));
+            cond = cond.add(if has_description { has } else { has.not() });
+        }
+        if let Some(created_after) = self.created_after {
+            cond = cond.add(Column::CreatedAt.gt(created_after));
+        }
+        cond
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Cursor {
+    sort: String,
+    key: JsonValue,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> Result<String> {
+        Ok(hex::encode(serde_json::to_vec(self)?))
+    }
+
+    fn decode(s: &str, sort: &Sort) -> Result<Self> {
+        let invalid = || Error::validation("cursor", "Invalid cursor");
+        let bytes = hex::decode(s).map_err(|_| invalid())?;
+        let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if cursor.sort != sort.name() {
+            return Err(Error::validation(
+                "cursor",
+                "Cursor was issued for a different sort",
+            ));
+        }
+        Ok(cursor)
+    }
+}
+
+#[derive(Debug)]
+pub struct TaskListParams {
+    pub filter: TaskFilter,
+    pub sort: Sort,
+    pub order: Order,
+    pub cursor: Option<String>,
+    pub limit: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    // Pass as `cursor` to get the following page. Unset on the last page.
+    pub next_cursor: Option<String>,
+}
+
+// One page of the tasks selected by `query`.
+// Sorting by position expects `query` to be joined with the project's task_project rows.
+pub async fn list_tasks(
+    db: &DatabaseConnection,
+    query: Select<Task>,
+    params: &TaskListParams,
+) -> Result<Page<TaskModel>> {
+    let TaskListParams {
+        filter,
+        sort,
+        order,
+        cursor,
+        limit,
+    } = params;
+    let key = sort.expr(order);
+    let mut query = query.filter(filter.condition());
+
+    if let Some(cursor) = cursor {
+        let cursor = Cursor::decode(cursor, sort)?;
+        let value = sort
+            .key_value(cursor.key, order)
+            .map_err(|_| Error::validation("cursor", "Invalid cursor"))?;
+        let id = Expr::col(Column::Id.as_column_ref());
+        let after = match order {
+            Order::Desc => Condition::any()
+                .add(key.clone().lt(value.clone()))
+                .add(key.clone().eq(value).and(id.lt(cursor.id))),
+            _ => Condition::any()
+                .add(key.clone().gt(value.clone()))
+                .add(key.clone().eq(value).and(id.gt(cursor.id))),
+        };
+        query = query.filter(after);
+    }
+
+    let mut tasks = query
+        .order_by(key, order.clone())
+        .order_by(Column::Id, order.clone())
+        .limit(limit + 1)
+        .all(db)
+        .await?;
+
+    let mut next_cursor = None;
+    if tasks.len() as u64 > *limit {
+        tasks.truncate(*limit as usize);
+        if let Some(last) = tasks.last() {
+            let cursor = Cursor {
+                sort: sort.name().to_string(),
+                key: sort.key(db, last).await?,
+                id: last.id,
+            };
+            next_cursor = Some(cursor.encode()?);
+        }
+    }
+
+    Ok(Page {
+        items: tasks,
+        next_cursor,
+    })
+}
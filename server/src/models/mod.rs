@@ -1,2 +1,22 @@
+pub mod access;
+pub mod change_log;
+pub mod comment;
+pub mod deadline;
+pub mod dependency;
+pub mod description;
+pub mod event_log;
+pub mod fuzzy;
+pub mod listing;
+pub mod notification;
+pub mod ordering;
 pub mod project;
+pub mod recurrence;
+pub mod reminder;
+pub mod search;
+pub mod sync;
 pub mod task;
+pub mod user;
+pub mod view;
+pub mod webhook;
+pub mod workflow;
+pub mod workspace;
@@ -0,0 +1,52 @@
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder,
+};
+use uuid::Uuid;
+
+use crate::result::{Error, Result};
+
+pub use entity::notification::{
+    ActiveModel as NotificationActiveModel, Column, Entity as Notification,
+    Model as NotificationModel,
+};
+
+// The user's notifications, newest first
+pub async fn notifications_for_user(
+    db: &DatabaseConnection,
+    user_id: &Uuid,
+    unread_only: bool,
+) -> Result<Vec<NotificationModel>> {
+    let mut query = Notification::find().filter(Column::UserId.eq(*user_id));
+    if unread_only {
+        query = query.filter(Column::ReadAt.is_null());
+    }
+    let notifications = query
+        .order_by_desc(Column::RemindAt)
+        .order_by_desc(Column::CreatedAt)
+        .all(db)
+        .await?;
+    Ok(notifications)
+}
+
+// Mark one of the user's notifications read. Already read ones keep their
+// original read time.
+pub async fn mark_read(
+    db: &DatabaseConnection,
+    user_id: &Uuid,
+    id: &Uuid,
+) -> Result<NotificationModel> {
+    let notification = Notification::find_by_id(*id)
+        .filter(Column::UserId.eq(*user_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Notification with id {id:?} not found!")))?;
+    if notification.read_at.is_some() {
+        return Ok(notification);
+    }
+
+    let mut notification: NotificationActiveModel = notification.into();
+    notification.read_at = ActiveValue::Set(Some(Utc::now().naive_utc()));
+    Ok(notification.update(db).await?)
+}
@@ -0,0 +1,99 @@
+// Fractional indexes: sort keys which always leave room for another key in
+// between, so an item can be moved without renumbering its neighbours.
+//
+// Keys are strings of base 62 digits, compared lexicographically. They never
+// end in the zero digit, since nothing would sort between "a" and "a0".
+use crate::result::{Error, Result};
+
+const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn digit_value(c: u8) -> Result<usize> {
+    DIGITS
+        .iter()
+        .position(|d| *d == c)
+        .ok_or_else(|| Error::Internal(anyhow::anyhow!("Invalid sort key digit {c}")))
+}
+
+// A key sorting after `a` (or first, if None) and before `b` (or last)
+pub fn key_between(a: Option<&str>, b: Option<&str>) -> Result<String> {
+    let a = a.unwrap_or("");
+    if let Some(b) = b
+        && a >= b
+    {
+        return Err(Error::conflict(format!("Sort key {a} is not before {b}")));
+    }
+    let key = midpoint(a.as_bytes(), b.map(str::as_bytes))?;
+    Ok(String::from_utf8(key).expect("Sort keys are ASCII"))
+}
+
+fn midpoint(a: &[u8], b: Option<&[u8]>) -> Result<Vec<u8>> {
+    // Keep any shared prefix, a's missing digits counting as zeros
+    if let Some(b) = b {
+        let shared = b
+            .iter()
+            .enumerate()
+            .take_while(|(i, d)| a.get(*i).unwrap_or(&DIGITS[0]) == *d)
+            .count();
+        if shared > 0 {
+            let rest = midpoint(a.get(shared..).unwrap_or(&[]), Some(&b[shared..]))?;
+            return Ok([&b[..shared], &rest].concat());
+        }
+    }
+
+    let digit_a = match a.first() {
+        Some(d) => digit_value(*d)?,
+        None => 0,
+    };
+    let digit_b = match b {
+        Some(b) => digit_value(b[0])?,
+        None => DIGITS.len(),
+    };
+    if digit_b - digit_a > 1 {
+        return Ok(vec![DIGITS[(digit_a + digit_b).div_ceil(2)]]);
+    }
+
+    // Adjacent digits: b's first digit alone fits if b is longer, otherwise
+    // extend a with a digit past the rest of it
+    match b {
+        Some(b) if b.len() > 1 => Ok(vec![b[0]]),
+        _ => {
+            let rest = midpoint(a.get(1..).unwrap_or(&[]), None)?;
+            Ok([&[DIGITS[digit_a]][..], &rest].concat())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::key_between;
+
+    #[test]
+    fn test_key_between() {
+        assert_eq!(key_between(None, None).unwrap(), "V");
+        assert_eq!(key_between(Some("V"), None).unwrap(), "l");
+        assert_eq!(key_between(None, Some("V")).unwrap(), "G");
+        assert_eq!(key_between(Some("V"), Some("W")).unwrap(), "VV");
+        assert_eq!(key_between(Some("V"), Some("V1")).unwrap(), "V0V");
+        assert!(key_between(Some("W"), Some("V")).is_err());
+        assert!(key_between(Some("V"), Some("V")).is_err());
+    }
+
+    #[test]
+    fn test_repeated_inserts_stay_ordered() {
+        // Always inserting at the same spot is the worst case for key length
+        let (low, mut high) = ("V".to_string(), "W".to_string());
+        for _ in 0..200 {
+            let key = key_between(Some(&low), Some(&high)).unwrap();
+            assert!(low < key && key < high);
+            assert!(!key.ends_with('0'));
+            high = key;
+        }
+
+        let mut last = "V".to_string();
+        for _ in 0..200 {
+            let key = key_between(Some(&last), None).unwrap();
+            assert!(last < key);
+            last = key;
+        }
+    }
+}
@@ -1,21 +1,71 @@
-use anyhow::Result;
+use chrono::Utc;
 use rocket::serde::json::Json;
-use sea_orm::{ActiveModelTrait, ActiveValue, DatabaseConnection};
+use sea_orm::prelude::DateTime;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    PaginatorTrait, QueryFilter, TransactionSession, TransactionTrait,
+};
 use serde::Deserialize;
 use uuid::Uuid;
 
+use super::access::Role;
+use super::change_log::{self, LoggedEntity};
+use super::task;
+use super::workflow;
+use crate::result::{Error, Result};
+
 pub use entity::project::{
     ActiveModel as ProjectActiveModel, Column, Entity as Project, Model as ProjectModel,
 };
+pub use entity::project_member::{
+    ActiveModel as ProjectMemberActiveModel, Column as ProjectMemberColumn, Entity as ProjectMember,
+};
 
-pub async fn create_project(db: &DatabaseConnection, title: String) -> Result<ProjectModel> {
+pub async fn create_project<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    workspace_id: &Uuid,
+    title: String,
+) -> Result<ProjectModel> {
+    let created_at = Utc::now().naive_utc();
+    create_project_with_id(db, Uuid::new_v4(), created_at, workspace_id, title).await
+}
+
+// Create a project whose id and creation time were picked by the client, like
+// one made while offline
+pub async fn create_project_with_id<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    id: Uuid,
+    created_at: DateTime,
+    workspace_id: &Uuid,
+    title: String,
+) -> Result<ProjectModel> {
     let proj = ProjectActiveModel {
-        id: ActiveValue::Set(Uuid::new_v4()),
+        id: ActiveValue::Set(id),
         title: ActiveValue::Set(title),
+        workspace_id: ActiveValue::Set(*workspace_id),
+        created_at: ActiveValue::Set(created_at),
         ..Default::default()
     };
-    let proj = proj.insert(db).await?;
+    let txn = db.begin().await?;
+    let proj = proj.insert(&txn).await?;
+    workflow::create_default_workflow(&txn, &proj.id).await?;
+    txn.commit().await?;
+
+    Ok(proj)
+}
 
+// Create a project along with its owner, so it's never left without one
+pub async fn create_owned_project(
+    db: &DatabaseConnection,
+    workspace_id: &Uuid,
+    title: String,
+    owner_id: &Uuid,
+) -> Result<ProjectModel> {
+    let txn = db.begin().await?;
+    let proj = create_project(&txn, workspace_id, title).await?;
+    set_member_role(&txn, &proj.id, owner_id, Role::Owner).await?;
+    txn.commit().await?;
     Ok(proj)
 }
 
@@ -23,15 +73,34 @@ pub async fn create_project(db: &DatabaseConnection, title: String) -> Result<Pr
 pub struct EditProjectPayload {
     title: Option<String>,
     description: Option<String>,
+    block_completion: Option<bool>,
 }
 
-pub async fn edit_project(
-    db: &DatabaseConnection,
+async fn find_project<C: ConnectionTrait>(db: &C, id: &Uuid) -> Result<ProjectModel> {
+    Project::find_by_id(*id)
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Project with id {id:?} not found!")))
+}
+
+// Fail if the project isn't at the version given, if any
+fn check_version(project: &ProjectModel, version: Option<i32>) -> Result<()> {
+    match version {
+        Some(version) if version != project.version => Err(Error::precondition_failed(project)),
+        _ => Ok(()),
+    }
+}
+
+// Edit the project, if it's at the given version
+pub async fn edit_project<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
     id: &Uuid,
     payload: Json<EditProjectPayload>,
+    version: Option<i32>,
 ) -> Result<ProjectModel> {
-    let proj = ProjectActiveModel {
-        id: ActiveValue::Set(id.clone()),
+    let mut proj = ProjectActiveModel {
+        id: ActiveValue::Set(*id),
         title: match payload.title.clone() {
             Some(title) => ActiveValue::Set(title.to_owned()),
             None => ActiveValue::NotSet,
@@ -40,9 +109,106 @@ pub async fn edit_project(
             Some(description) => ActiveValue::Set(Some(description.to_owned())),
             None => ActiveValue::NotSet,
         },
+        block_completion: match payload.block_completion {
+            Some(block_completion) => ActiveValue::Set(block_completion),
+            None => ActiveValue::NotSet,
+        },
         ..Default::default()
     };
-    let proj = proj.update(db).await?;
+
+    let txn = db.begin().await?;
+    let old = find_project(&txn, id).await?;
+    check_version(&old, version)?;
+    proj.version = ActiveValue::Set(old.version + 1);
+    let proj = proj.update(&txn).await?;
+    let changes = change_log::field_changes(&old, &proj)?;
+    change_log::record(&txn, actor_id, LoggedEntity::Project, id, changes).await?;
+    txn.commit().await?;
 
     Ok(proj)
 }
+
+pub async fn delete_project<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    id: &Uuid,
+    version: Option<i32>,
+) -> Result<(Vec<Uuid>, Vec<Uuid>)> {
+    let txn = db.begin().await?;
+    let proj = find_project(&txn, id).await?;
+    check_version(&proj, version)?;
+    // Its tasks in no other project go with it
+    let tasks = task::delete_only_in_project(&txn, actor_id, id).await?;
+    let changes = vec![change_log::deletion(&proj)?];
+    change_log::record(&txn, actor_id, LoggedEntity::Project, id, changes).await?;
+    Project::delete_by_id(*id).exec(&txn).await?;
+    txn.commit().await?;
+    Ok(tasks)
+}
+
+// Give user an explicit role on the project, replacing any previous one
+pub async fn set_member_role<C: ConnectionTrait>(
+    db: &C,
+    project_id: &Uuid,
+    user_id: &Uuid,
+    role: Role,
+) -> Result<()> {
+    if role != Role::Owner {
+        ensure_other_owner(db, project_id, user_id).await?;
+    }
+
+    let member = ProjectMemberActiveModel {
+        project_id: ActiveValue::Set(*project_id),
+        user_id: ActiveValue::Set(*user_id),
+        role: ActiveValue::Set(role.to_string()),
+        ..Default::default()
+    };
+    ProjectMember::insert(member)
+        .on_conflict(
+            OnConflict::columns([ProjectMemberColumn::ProjectId, ProjectMemberColumn::UserId])
+                .update_column(ProjectMemberColumn::Role)
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+// Drop user's explicit role, so they fall back to the default for the workspace
+pub async fn remove_member(
+    db: &DatabaseConnection,
+    project_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<()> {
+    ensure_other_owner(db, project_id, user_id).await?;
+    let res = ProjectMember::delete_by_id((*project_id, *user_id))
+        .exec(db)
+        .await?;
+    if res.rows_affected == 0 {
+        return Err(Error::not_found("User has no role on this project"));
+    }
+    Ok(())
+}
+
+// Projects can't be left without an owner
+async fn ensure_other_owner<C: ConnectionTrait>(
+    db: &C,
+    project_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<()> {
+    let owners = ProjectMember::find()
+        .filter(ProjectMemberColumn::ProjectId.eq(*project_id))
+        .filter(ProjectMemberColumn::Role.eq(Role::Owner.to_string()))
+        .filter(ProjectMemberColumn::UserId.ne(*user_id))
+        .count(db)
+        .await?;
+    let is_owner = ProjectMember::find_by_id((*project_id, *user_id))
+        .one(db)
+        .await?
+        .is_some_and(|m| m.role == Role::Owner.to_string());
+
+    if is_owner && owners == 0 {
+        return Err(Error::conflict("Project must keep at least one owner"));
+    }
+    Ok(())
+}
@@ -0,0 +1,239 @@
+// Recurrence rules for tasks, modelled on the common cases of iCalendar's
+// RRULE. Once a recurring task is done, a new occurrence of it is created, due
+// at the next date the rule gives.
+use chrono::{Datelike, Days, Months, Weekday};
+use sea_orm::prelude::{Date, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::result::{Error, Result};
+
+const MAX_PREVIEW: u32 = 100;
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "freq", rename_all = "snake_case")]
+pub enum Recurrence {
+    // Every `interval` days
+    Daily {
+        #[serde(default = "one")]
+        interval: u32,
+    },
+    // On the given weekdays, every `interval` weeks
+    Weekly {
+        #[serde(default = "one")]
+        interval: u32,
+        weekdays: Vec<Weekday>,
+    },
+    // On day `day` of every `interval` months, or their last day if shorter
+    Monthly {
+        #[serde(default = "one")]
+        interval: u32,
+        day: u32,
+    },
+    // `days` after the previous occurrence was completed
+    AfterCompletion {
+        days: u32,
+    },
+}
+
+impl Recurrence {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::validation("recurrence", message));
+        match self {
+            Recurrence::Daily { interval: 0 }
+            | Recurrence::Weekly { interval: 0, .. }
+            | Recurrence::Monthly { interval: 0, .. } => invalid("Interval must be positive"),
+            Recurrence::Weekly { weekdays, .. } if weekdays.is_empty() => {
+                invalid("Weekly recurrence needs at least one weekday")
+            }
+            Recurrence::Monthly { day, .. } if !(1..=31).contains(day) => {
+                invalid("Day of the month must be between 1 and 31")
+            }
+            Recurrence::AfterCompletion { days: 0 } => invalid("Days must be positive"),
+            _ => Ok(()),
+        }
+    }
+
+    // The rule as stored on a task
+    pub fn from_json(json: &Json) -> Result<Self> {
+        Ok(serde_json::from_value(json.clone())?)
+    }
+
+    pub fn to_json(&self) -> Result<Json> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    // Due date of the occurrence following one due on `due` and completed on
+    // `completed_on`. Occurrences without a due date are scheduled from their
+    // completion.
+    pub fn next_due(&self, due: Option<Date>, completed_on: Date) -> Date {
+        let after = due.unwrap_or(completed_on);
+        match self {
+            Recurrence::Daily { interval } => after + Days::new(*interval as u64),
+            Recurrence::Weekly { interval, weekdays } => {
+                let offset = |d: &Weekday| d.num_days_from_monday() as u64;
+                let week_start = after - Days::new(offset(&after.weekday()));
+                let later_this_week = weekdays
+                    .iter()
+                    .map(offset)
+                    .filter(|o| *o > offset(&after.weekday()))
+                    .min();
+                match later_this_week {
+                    Some(o) => week_start + Days::new(o),
+                    None => {
+                        let first = weekdays.iter().map(offset).min().unwrap_or(0);
+                        week_start + Days::new(7 * *interval as u64 + first)
+                    }
+                }
+            }
+            Recurrence::Monthly { interval, day } => {
+                let this_month = day_of_month(after, *day);
+                if this_month > after {
+                    this_month
+                } else {
+                    let month_start = after.with_day(1).unwrap();
+                    day_of_month(month_start + Months::new(*interval), *day)
+                }
+            }
+            Recurrence::AfterCompletion { days } => completed_on + Days::new(*days as u64),
+        }
+    }
+
+    // Due dates of the next `count` occurrences, supposing the current one is
+    // completed today and each later one on the day it's due
+    pub fn preview(&self, due: Option<Date>, today: Date, count: u32) -> Result<Vec<Date>> {
+        if count == 0 || count > MAX_PREVIEW {
+            return Err(Error::validation(
+                "count",
+                format!("Count must be between 1 and {MAX_PREVIEW}"),
+            ));
+        }
+        let mut dates = Vec::with_capacity(count as usize);
+        let mut next = self.next_due(due, today);
+        for _ in 0..count {
+            dates.push(next);
+            next = self.next_due(Some(next), next);
+        }
+        Ok(dates)
+    }
+}
+
+// The given day of the date's month, or the month's last day if it's shorter
+fn day_of_month(date: Date, day: u32) -> Date {
+    (1..=day)
+        .rev()
+        .find_map(|d| date.with_day(d))
+        .unwrap_or(date)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Recurrence;
+    use chrono::Weekday;
+    use sea_orm::prelude::Date;
+
+    fn date(s: &str) -> Date {
+        Date::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_next_due() {
+        let today = date("2025-01-01");
+        let cases = [
+            (
+                Recurrence::Daily { interval: 2 },
+                "2025-01-30",
+                "2025-02-01",
+            ),
+            (
+                // From a Wednesday
+                Recurrence::Weekly {
+                    interval: 1,
+                    weekdays: vec![Weekday::Mon, Weekday::Fri],
+                },
+                "2025-01-15",
+                "2025-01-17",
+            ),
+            (
+                Recurrence::Weekly {
+                    interval: 2,
+                    weekdays: vec![Weekday::Mon, Weekday::Fri],
+                },
+                "2025-01-17",
+                "2025-01-27",
+            ),
+            (
+                Recurrence::Monthly {
+                    interval: 1,
+                    day: 31,
+                },
+                "2025-01-31",
+                "2025-02-28",
+            ),
+            (
+                Recurrence::Monthly {
+                    interval: 1,
+                    day: 31,
+                },
+                "2025-02-28",
+                "2025-03-31",
+            ),
+            (
+                Recurrence::Monthly {
+                    interval: 3,
+                    day: 15,
+                },
+                "2025-01-20",
+                "2025-04-15",
+            ),
+            (
+                Recurrence::AfterCompletion { days: 3 },
+                "2024-12-01",
+                "2025-01-04",
+            ),
+        ];
+        for (rule, due, next) in cases {
+            assert_eq!(
+                rule.next_due(Some(date(due)), today),
+                date(next),
+                "{rule:?}"
+            );
+        }
+
+        // Without a due date, the schedule starts from completion
+        let rule = Recurrence::Daily { interval: 1 };
+        assert_eq!(rule.next_due(None, today), date("2025-01-02"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let json = r#"{ "freq": "weekly", "weekdays": ["mon", "Thursday"] }"#;
+        let rule: Recurrence = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            rule,
+            Recurrence::Weekly {
+                interval: 1,
+                weekdays: vec![Weekday::Mon, Weekday::Thu]
+            }
+        );
+        assert!(rule.validate().is_ok());
+
+        for rule in [
+            Recurrence::Daily { interval: 0 },
+            Recurrence::Weekly {
+                interval: 1,
+                weekdays: vec![],
+            },
+            Recurrence::Monthly {
+                interval: 1,
+                day: 32,
+            },
+            Recurrence::AfterCompletion { days: 0 },
+        ] {
+            assert!(rule.validate().is_err(), "{rule:?}");
+        }
+    }
+}
@@ -0,0 +1,306 @@
+// Reminders of upcoming deadlines.
+//
+// Users choose when they're reminded of tasks as offsets from the deadline.
+// A background job periodically sends every reminder that fell due since its
+// last run, and records how far it got. After downtime it picks up from there,
+// so missed reminders go out late rather than not at all, while the unique
+// index on notifications keeps any from going out twice.
+use chrono::{Duration, Utc};
+use sea_orm::prelude::{DateTime, Time};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QuerySelect, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use super::deadline;
+use super::notification::{
+    Column as NotificationColumn, Notification, NotificationActiveModel, NotificationModel,
+};
+use super::project::{self, Project};
+use super::task::{self, Task, TaskModel, TaskProject, TaskProjectColumn};
+use super::user::{User, UserActiveModel, UserModel};
+use super::workflow::Category;
+use super::workspace::{WorkspaceMember, WorkspaceMemberColumn};
+use crate::result::{Error, Result};
+
+pub use entity::job_checkpoint::{
+    ActiveModel as JobCheckpointActiveModel, Column as JobCheckpointColumn, Entity as JobCheckpoint,
+};
+
+const JOB: &str = "reminders";
+const MAX_MINUTES_BEFORE: u32 = 7 * 24 * 60;
+const MAX_OFFSETS: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReminderOffset {
+    // Some minutes before the deadline
+    Before { minutes: u32 },
+    // At a time of day on the due date, in the task's time zone. Skipped for
+    // tasks due before then.
+    OnDay { time: Time },
+}
+
+impl ReminderOffset {
+    // Identifies the reminder among those sent for a deadline
+    pub fn key(&self) -> String {
+        match self {
+            ReminderOffset::Before { minutes } => format!("before:{minutes}"),
+            ReminderOffset::OnDay { time } => format!("on_day:{}", time.format("%H:%M")),
+        }
+    }
+
+    // When the reminder for the task's current deadline goes out, if at all
+    pub fn remind_at(&self, task: &TaskModel) -> Option<DateTime> {
+        let due_at = task.due_at?;
+        let remind_at = match self {
+            ReminderOffset::Before { minutes } => due_at - Duration::minutes(*minutes as i64),
+            ReminderOffset::OnDay { time } => {
+                deadline::due_at(task.due_date, Some(*time), task.time_zone.as_deref())?
+            }
+        };
+        (remind_at < due_at).then_some(remind_at)
+    }
+}
+
+// 1 day before, and on the morning of the due date
+pub fn default_offsets() -> Vec<ReminderOffset> {
+    vec![
+        ReminderOffset::Before { minutes: 24 * 60 },
+        ReminderOffset::OnDay {
+            time: Time::from_hms_opt(9, 0, 0).unwrap(),
+        },
+    ]
+}
+
+pub fn offsets(user: &UserModel) -> Vec<ReminderOffset> {
+    user.reminder_offsets
+        .as_ref()
+        .and_then(|json| serde_json::from_value(json.clone()).ok())
+        .unwrap_or_else(default_offsets)
+}
+
+// Replace the user's reminder offsets. None restores the defaults.
+pub async fn set_offsets(
+    db: &DatabaseConnection,
+    user_id: &Uuid,
+    offsets: Option<Vec<ReminderOffset>>,
+) -> Result<UserModel> {
+    let json = match offsets {
+        Some(mut offsets) => {
+            validate(&offsets)?;
+            let mut keys = HashSet::new();
+            offsets.retain(|o| keys.insert(o.key()));
+            Some(serde_json::to_value(offsets)?)
+        }
+        None => None,
+    };
+    let user = UserActiveModel {
+        id: ActiveValue::Set(*user_id),
+        reminder_offsets: ActiveValue::Set(json),
+        ..Default::default()
+    };
+    Ok(user.update(db).await?)
+}
+
+fn validate(offsets: &[ReminderOffset]) -> Result<()> {
+    if offsets.len() > MAX_OFFSETS {
+        return Err(Error::validation(
+            "offsets",
+            format!("At most {MAX_OFFSETS} reminders can be set"),
+        ));
+    }
+    for offset in offsets {
+        if let ReminderOffset::Before { minutes } = offset
+            && !(1..=MAX_MINUTES_BEFORE).contains(minutes)
+        {
+            return Err(Error::validation(
+                "offsets",
+                format!("Reminders must be between 1 and {MAX_MINUTES_BEFORE} minutes before"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn checkpoint<C: ConnectionTrait>(db: &C) -> Result<Option<DateTime>> {
+    let checkpoint = JobCheckpoint::find_by_id(JOB).one(db).await?;
+    Ok(checkpoint.map(|c| c.checkpoint))
+}
+
+async fn save_checkpoint<C: ConnectionTrait>(db: &C, at: DateTime) -> Result<()> {
+    let checkpoint = JobCheckpointActiveModel {
+        job: ActiveValue::Set(JOB.to_string()),
+        checkpoint: ActiveValue::Set(at),
+    };
+    JobCheckpoint::insert(checkpoint)
+        .on_conflict(
+            OnConflict::column(JobCheckpointColumn::Job)
+                .update_column(JobCheckpointColumn::Checkpoint)
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+// A notification and the workspace its task was seen through
+pub type SentReminder = (NotificationModel, Uuid);
+
+// Create notifications for the reminders due since the last run, up to `now`.
+// The first run only sets the starting point.
+pub async fn send_due_reminders(
+    db: &DatabaseConnection,
+    now: DateTime,
+) -> Result<Vec<SentReminder>> {
+    let txn = db.begin().await?;
+    let since = match checkpoint(&txn).await? {
+        Some(since) if since >= now => return Ok(vec![]),
+        Some(since) => since,
+        None => {
+            save_checkpoint(&txn, now).await?;
+            txn.commit().await?;
+            return Ok(vec![]);
+        }
+    };
+
+    // Reminders go out before the deadline, by at most a week (or a day for
+    // reminders on the due date)
+    let horizon = now + Duration::minutes(MAX_MINUTES_BEFORE as i64) + Duration::days(1);
+    let tasks = Task::find()
+        .filter(task::Column::StatusCategory.ne(Category::Done.to_string()))
+        .filter(task::Column::DueAt.gt(since))
+        .filter(task::Column::DueAt.lte(horizon))
+        .all(&txn)
+        .await?;
+
+    // Tasks are visible to the members of their projects' workspaces
+    let task_workspaces: Vec<(Uuid, Uuid)> = TaskProject::find()
+        .select_only()
+        .column(TaskProjectColumn::TaskId)
+        .column(project::Column::WorkspaceId)
+        .inner_join(Project)
+        .filter(TaskProjectColumn::TaskId.is_in(tasks.iter().map(|t| t.id)))
+        .distinct()
+        .into_tuple()
+        .all(&txn)
+        .await?;
+    let workspace_ids: HashSet<Uuid> = task_workspaces.iter().map(|(_, w)| *w).collect();
+    let mut members: HashMap<Uuid, Vec<UserModel>> = HashMap::new();
+    for (member, user) in WorkspaceMember::find()
+        .find_also_related(User)
+        .filter(WorkspaceMemberColumn::WorkspaceId.is_in(workspace_ids))
+        .all(&txn)
+        .await?
+    {
+        if let Some(user) = user {
+            members.entry(member.workspace_id).or_default().push(user);
+        }
+    }
+
+    let tasks: HashMap<Uuid, TaskModel> = tasks.into_iter().map(|t| (t.id, t)).collect();
+    let mut seen = HashSet::new();
+    let mut sent = vec![];
+    for (task_id, workspace_id) in task_workspaces {
+        let task = &tasks[&task_id];
+        for user in members.get(&workspace_id).into_iter().flatten() {
+            // Users seeing the task through more than one workspace
+            if !seen.insert((user.id, task_id)) {
+                continue;
+            }
+            for offset in offsets(user) {
+                let Some(remind_at) = offset.remind_at(task) else {
+                    continue;
+                };
+                if remind_at <= since || remind_at > now {
+                    continue;
+                }
+                let notification = NotificationModel {
+                    id: Uuid::new_v4(),
+                    user_id: user.id,
+                    task_id,
+                    reminder: offset.key(),
+                    due_at: task.due_at.unwrap(),
+                    remind_at,
+                    created_at: Utc::now().naive_utc(),
+                    read_at: None,
+                };
+                let inserted =
+                    Notification::insert(NotificationActiveModel::from(notification.clone()))
+                        .on_conflict(
+                            OnConflict::columns([
+                                NotificationColumn::UserId,
+                                NotificationColumn::TaskId,
+                                NotificationColumn::Reminder,
+                                NotificationColumn::DueAt,
+                            ])
+                            .do_nothing()
+                            .to_owned(),
+                        )
+                        .exec_without_returning(&txn)
+                        .await?;
+                if inserted > 0 {
+                    sent.push((notification, workspace_id));
+                }
+            }
+        }
+    }
+
+    save_checkpoint(&txn, now).await?;
+    txn.commit().await?;
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod test {
+    use super::ReminderOffset;
+    use crate::models::deadline;
+    use crate::models::task::TaskModel;
+    use sea_orm::prelude::{Date, DateTime, Time};
+
+    #[test]
+    fn test_remind_at() {
+        let at = |s| Some(DateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap());
+        let date = Date::from_ymd_opt(2025, 10, 21);
+        let mut task = TaskModel {
+            id: Default::default(),
+            title: "A task".to_string(),
+            status: "todo".to_string(),
+            status_category: "not_started".to_string(),
+            description: None,
+            created_at: Default::default(),
+            due_date: date,
+            due_time: Time::from_hms_opt(15, 0, 0),
+            time_zone: Some("Europe/Berlin".to_string()),
+            due_at: None,
+            parent_id: None,
+            recurrence: None,
+            previous_occurrence_id: None,
+            version: 1,
+        };
+        task.due_at = deadline::due_at(task.due_date, task.due_time, task.time_zone.as_deref());
+
+        let offsets: Vec<ReminderOffset> = serde_json::from_str(
+            r#"[
+                { "kind": "before", "minutes": 90 },
+                { "kind": "on_day", "time": "09:00:00" },
+                { "kind": "on_day", "time": "16:00:00" }
+            ]"#,
+        )
+        .unwrap();
+        let remind_at: Vec<_> = offsets.iter().map(|o| o.remind_at(&task)).collect();
+        // 16:00 is after the deadline
+        assert_eq!(
+            remind_at,
+            vec![at("2025-10-21 11:30"), at("2025-10-21 07:00"), None]
+        );
+        assert_eq!(offsets[1].key(), "on_day:09:00");
+
+        task.due_at = None;
+        assert_eq!(offsets[0].remind_at(&task), None);
+    }
+}
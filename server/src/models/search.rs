@@ -0,0 +1,116 @@
+// Full text search over the titles and descriptions of tasks and projects
+use sea_orm::{DatabaseConnection, FromQueryResult, raw_sql};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::result::{Error, Result};
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, FromQueryResult)]
+pub struct SearchHit {
+    // "task" or "project"
+    pub entity_type: String,
+    pub id: Uuid,
+    pub title: String,
+    // Best matching fragment, with matches wrapped in <mark> tags
+    pub snippet: String,
+    // Higher is a better match
+    pub score: f64,
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+// Translate a user query into FTS5 syntax. Words must all match, in any
+// order; "quoted words" must match as a phrase; a trailing * matches prefixes.
+// Everything else is quoted, so user input can't break the query syntax.
+pub fn fts_query(q: &str) -> Result<String> {
+    let mut terms = vec![];
+    let mut rest = q.trim();
+    while !rest.is_empty() {
+        let (term, remainder) = if let Some(phrase) = rest.strip_prefix('"') {
+            // An unterminated phrase runs to the end of the query
+            let end = phrase.find('"').unwrap_or(phrase.len());
+            (&phrase[..end], phrase.get(end + 1..).unwrap_or(""))
+        } else {
+            let end = rest.find(|c: char| c.is_whitespace() || c == '"');
+            let end = end.unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        let (term, prefix) = match term.strip_suffix('*') {
+            Some(term) => (term, true),
+            None => (term, remainder.starts_with('*')),
+        };
+        let remainder = remainder.strip_prefix('*').unwrap_or(remainder);
+
+        if term.chars().any(char::is_alphanumeric) {
+            let prefix = if prefix { "*" } else { "" };
+            terms.push(format!("{}{prefix}", quote(term.trim_end_matches('*'))));
+        }
+        rest = remainder.trim_start();
+    }
+
+    if terms.is_empty() {
+        return Err(Error::validation("q", "Search query cannot be empty"));
+    }
+    Ok(terms.join(" "))
+}
+
+// Tasks and projects in the given workspaces matching the query, best first
+pub async fn search(
+    db: &DatabaseConnection,
+    workspace_ids: Vec<Uuid>,
+    q: &str,
+    limit: u32,
+) -> Result<Vec<SearchHit>> {
+    let fts = fts_query(q)?;
+    let hits = SearchHit::find_by_statement(raw_sql!(
+        Sqlite,
+        r#"SELECT
+             s.entity_type AS entity_type,
+             s.entity_id AS id,
+             s.title AS title,
+             snippet(search_index, -1, '<mark>', '</mark>', '…', 12) AS snippet,
+             -bm25(search_index, 0.0, 0.0, 4.0, 1.0) AS score
+           FROM search_index s
+           WHERE search_index MATCH {fts}
+             AND (
+               (s.entity_type = 'project' AND s.entity_id IN (
+                 SELECT p.id FROM project p WHERE p.workspace_id IN ({..workspace_ids})
+               ))
+               OR (s.entity_type = 'task' AND s.entity_id IN (
+                 SELECT tp.task_id FROM task_project tp
+                 JOIN project p ON p.id = tp.project_id
+                 WHERE p.workspace_id IN ({..workspace_ids})
+               ))
+             )
+           ORDER BY bm25(search_index, 0.0, 0.0, 4.0, 1.0)
+           LIMIT {limit}
+        "#
+    ))
+    .all(db)
+    .await?;
+
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::fts_query;
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!(fts_query("foo bar").unwrap(), r#""foo" "bar""#);
+        assert_eq!(fts_query("fo* \"foo bar\"").unwrap(), r#""fo"* "foo bar""#);
+        assert_eq!(
+            fts_query("\"foo bar\"* baz").unwrap(),
+            r#""foo bar"* "baz""#
+        );
+        assert_eq!(
+            fts_query("\"unterminated phrase").unwrap(),
+            r#""unterminated phrase""#
+        );
+        assert_eq!(fts_query("a\"b").unwrap(), r#""a" "b""#);
+        assert!(fts_query(" * \"\" ").is_err());
+    }
+}
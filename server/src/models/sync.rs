@@ -0,0 +1,624 @@
+// Offline sync: clients pull the changes to a workspace's projects, tasks and
+// memberships since a cursor, and push back the mutations they made meanwhile.
+//
+// Changes are journaled by triggers (see the sync_changes migration), so pulls
+// see every write however it was made. A pushed mutation conflicts when what
+// it changes was changed after the client's cursor by anyone but that client.
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use rocket::serde::json::Json;
+use sea_orm::sea_query::{Expr, SelectStatement};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DatabaseTransaction, EntityTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait,
+    TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+use super::access::{self, ProjectAction, Role, TaskAccess, TaskAction};
+use super::dependency;
+use super::project::{self, EditProjectPayload, Project, ProjectModel};
+use super::task::{
+    self, DeletedTask, EditTaskPayload, Task, TaskModel, TaskProject, TaskProjectColumn,
+    TaskProjectModel,
+};
+use super::workflow;
+use crate::result::{Error, ErrorBody, Result};
+
+pub use entity::sync_change::{Column as SyncChangeColumn, Entity as SyncChange};
+pub use entity::sync_mutation::{ActiveModel as SyncMutationActiveModel, Entity as SyncMutation};
+
+// Most journaled changes read by one pull, the rest are left for the next
+pub const PULL_LIMIT: u64 = 500;
+
+// How long journaled changes are kept. Clients with an older cursor are sent
+// a snapshot instead.
+pub const JOURNAL_RETENTION: TimeDelta = TimeDelta::days(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct MembershipKey {
+    pub project_id: Uuid,
+    pub task_id: Uuid,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Deleted {
+    pub projects: Vec<Uuid>,
+    pub tasks: Vec<Uuid>,
+    pub task_projects: Vec<MembershipKey>,
+}
+
+// Current state of everything that changed, and what no longer exists. Tasks
+// that left the workspace's projects count as deleted.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Pull {
+    // Pass back to pull the changes made after these
+    pub cursor: i32,
+    // Whether this is everything in the workspace, to replace what the client
+    // has, rather than changes. Also sent when the cursor has expired.
+    pub snapshot: bool,
+    // Whether there are more changes to pull already
+    pub more: bool,
+    pub projects: Vec<ProjectModel>,
+    pub tasks: Vec<TaskModel>,
+    pub task_projects: Vec<TaskProjectModel>,
+    pub deleted: Deleted,
+}
+
+// Ids of the tasks in the workspace's projects
+fn task_ids_in_workspace(workspace_id: &Uuid) -> SelectStatement {
+    TaskProject::find()
+        .select_only()
+        .column(TaskProjectColumn::TaskId)
+        .inner_join(Project)
+        .filter(project::Column::WorkspaceId.eq(*workspace_id))
+        .into_query()
+}
+
+// Everything in the workspace, or what changed after the cursor if given
+pub async fn pull(
+    db: &DatabaseConnection,
+    workspace_id: &Uuid,
+    cursor: Option<i32>,
+) -> Result<Pull> {
+    // Read the journal and the rows it points to as of the same moment
+    let txn = db.begin().await?;
+    let pull = match cursor {
+        Some(cursor) if !cursor_expired(&txn, cursor).await? => {
+            changes_since(&txn, workspace_id, cursor).await?
+        }
+        _ => snapshot(&txn, workspace_id).await?,
+    };
+    txn.commit().await?;
+    Ok(pull)
+}
+
+async fn snapshot<C: ConnectionTrait>(db: &C, workspace_id: &Uuid) -> Result<Pull> {
+    let cursor = SyncChange::find()
+        .order_by_desc(SyncChangeColumn::Id)
+        .one(db)
+        .await?
+        .map_or(0, |c| c.id);
+    let projects = Project::find()
+        .filter(project::Column::WorkspaceId.eq(*workspace_id))
+        .order_by_asc(project::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let tasks = Task::find()
+        .filter(task::Column::Id.in_subquery(task_ids_in_workspace(workspace_id)))
+        .order_by_asc(task::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let task_projects = TaskProject::find()
+        .inner_join(Project)
+        .filter(project::Column::WorkspaceId.eq(*workspace_id))
+        .order_by_asc(TaskProjectColumn::Position)
+        .all(db)
+        .await?;
+
+    Ok(Pull {
+        cursor,
+        snapshot: true,
+        more: false,
+        projects,
+        tasks,
+        task_projects,
+        deleted: Deleted::default(),
+    })
+}
+
+async fn changes_since<C: ConnectionTrait>(
+    db: &C,
+    workspace_id: &Uuid,
+    cursor: i32,
+) -> Result<Pull> {
+    let mut changes = SyncChange::find()
+        .filter(SyncChangeColumn::WorkspaceId.eq(*workspace_id))
+        .filter(SyncChangeColumn::Id.gt(cursor))
+        .order_by_asc(SyncChangeColumn::Id)
+        .limit(PULL_LIMIT + 1)
+        .all(db)
+        .await?;
+    let more = changes.len() as u64 > PULL_LIMIT;
+    changes.truncate(PULL_LIMIT as usize);
+    let next_cursor = changes.last().map_or(cursor, |c| c.id);
+
+    let mut project_ids = BTreeSet::new();
+    let mut task_ids = BTreeSet::new();
+    let mut memberships = BTreeSet::new();
+    for change in changes {
+        match (change.entity_type.as_str(), change.project_id) {
+            ("project", _) => {
+                project_ids.insert(change.entity_id);
+            }
+            ("task", _) => {
+                task_ids.insert(change.entity_id);
+            }
+            ("task_project", Some(project_id)) => {
+                // The task may have joined or left the workspace with it
+                task_ids.insert(change.entity_id);
+                memberships.insert(MembershipKey {
+                    project_id,
+                    task_id: change.entity_id,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let projects = Project::find()
+        .filter(project::Column::Id.is_in(project_ids.iter().copied()))
+        .filter(project::Column::WorkspaceId.eq(*workspace_id))
+        .all(db)
+        .await?;
+    let tasks = Task::find()
+        .filter(task::Column::Id.is_in(task_ids.iter().copied()))
+        .filter(task::Column::Id.in_subquery(task_ids_in_workspace(workspace_id)))
+        .all(db)
+        .await?;
+    let task_projects: Vec<TaskProjectModel> = TaskProject::find()
+        .filter(TaskProjectColumn::TaskId.is_in(memberships.iter().map(|m| m.task_id)))
+        .filter(TaskProjectColumn::ProjectId.is_in(memberships.iter().map(|m| m.project_id)))
+        .all(db)
+        .await?
+        .into_iter()
+        .filter(|tp| memberships.contains(&membership_key(tp)))
+        .collect();
+
+    for p in &projects {
+        project_ids.remove(&p.id);
+    }
+    for t in &tasks {
+        task_ids.remove(&t.id);
+    }
+    for tp in &task_projects {
+        memberships.remove(&membership_key(tp));
+    }
+    Ok(Pull {
+        cursor: next_cursor,
+        snapshot: false,
+        more,
+        projects,
+        tasks,
+        task_projects,
+        deleted: Deleted {
+            projects: project_ids.into_iter().collect(),
+            tasks: task_ids.into_iter().collect(),
+            task_projects: memberships.into_iter().collect(),
+        },
+    })
+}
+
+// Whether changes after the cursor have been pruned. The latest change is
+// never pruned, so the journal always shows how far it goes back.
+async fn cursor_expired<C: ConnectionTrait>(db: &C, cursor: i32) -> Result<bool> {
+    let oldest = SyncChange::find()
+        .order_by_asc(SyncChangeColumn::Id)
+        .one(db)
+        .await?;
+    Ok(oldest.is_some_and(|c| cursor < c.id - 1))
+}
+
+// Forget changes journaled before the given time
+pub async fn prune(db: &DatabaseConnection, before: NaiveDateTime) -> Result<()> {
+    let Some(latest) = SyncChange::find()
+        .order_by_desc(SyncChangeColumn::Id)
+        .one(db)
+        .await?
+    else {
+        return Ok(());
+    };
+    SyncChange::delete_many()
+        .filter(SyncChangeColumn::CreatedAt.lt(before))
+        .filter(SyncChangeColumn::Id.lt(latest.id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+fn membership_key(tp: &TaskProjectModel) -> MembershipKey {
+    MembershipKey {
+        project_id: tp.project_id,
+        task_id: tp.task_id,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PushPayload {
+    // Identifies the device pushing, so its own earlier pushes aren't
+    // conflicts for it
+    pub client_id: Uuid,
+    // The cursor of the client's last pull
+    pub cursor: i32,
+    pub mutations: Vec<Mutation>,
+}
+
+#[derive(Deserialize)]
+pub struct Mutation {
+    // Picked by the client, so mutations sent again aren't applied twice
+    pub id: Uuid,
+    // When the mutation was made on the client
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+// Ids of created projects and tasks are picked by the client
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    CreateProject {
+        project_id: Uuid,
+        title: String,
+    },
+    UpdateProject {
+        project_id: Uuid,
+        changes: EditProjectPayload,
+    },
+    DeleteProject {
+        project_id: Uuid,
+    },
+    // Starts in the initial status of the project unless given
+    CreateTask {
+        task_id: Uuid,
+        project_id: Uuid,
+        title: String,
+        status: Option<String>,
+    },
+    UpdateTask {
+        task_id: Uuid,
+        changes: EditTaskPayload,
+    },
+    DeleteTask {
+        task_id: Uuid,
+    },
+    AddToProject {
+        task_id: Uuid,
+        project_id: Uuid,
+    },
+    RemoveFromProject {
+        task_id: Uuid,
+        project_id: Uuid,
+    },
+}
+
+// What a mutation changes that others may have changed too. Created projects
+// and tasks are new, so can't conflict.
+enum Target {
+    Project(Uuid),
+    Task(Uuid),
+    Membership(MembershipKey),
+}
+
+impl Change {
+    fn target(&self) -> Option<Target> {
+        match self {
+            Change::CreateProject { .. } | Change::CreateTask { .. } => None,
+            Change::UpdateProject { project_id, .. } | Change::DeleteProject { project_id } => {
+                Some(Target::Project(*project_id))
+            }
+            Change::UpdateTask { task_id, .. } | Change::DeleteTask { task_id } => {
+                Some(Target::Task(*task_id))
+            }
+            Change::AddToProject {
+                task_id,
+                project_id,
+            }
+            | Change::RemoveFromProject {
+                task_id,
+                project_id,
+            } => Some(Target::Membership(MembershipKey {
+                project_id: *project_id,
+                task_id: *task_id,
+            })),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    // Including by an earlier push of the same mutation
+    Applied,
+    // Changed by someone else since the client's cursor, so left alone
+    Conflict,
+    // Not allowed or invalid, like any request making the same change
+    Rejected,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MutationResult {
+    pub id: Uuid,
+    pub outcome: Outcome,
+    pub error: Option<ErrorBody>,
+}
+
+// What an applied mutation did, for broadcasting
+pub enum Effect {
+    ProjectCreated(ProjectModel),
+    ProjectUpdated {
+        old: ProjectModel,
+        project: ProjectModel,
+    },
+    ProjectDeleted {
+        project: ProjectModel,
+        // Tasks deleted with it, and subtasks of theirs moved to the top level
+        task_ids: Vec<Uuid>,
+        detached_ids: Vec<Uuid>,
+    },
+    TaskCreated(TaskModel),
+    TaskUpdated {
+        access: TaskAccess,
+        task: Box<TaskModel>,
+        // Following a completed recurring task
+        next: Option<Box<TaskModel>>,
+    },
+    TaskDeleted {
+        access: TaskAccess,
+        project_ids: Vec<Uuid>,
+        subtasks: Vec<DeletedTask>,
+        // Tasks it was blocking
+        dependent_ids: Vec<Uuid>,
+    },
+    AddedToProject {
+        access: TaskAccess,
+        project_id: Uuid,
+    },
+    RemovedFromProject {
+        access: TaskAccess,
+        project_id: Uuid,
+    },
+}
+
+// Apply the mutations in order, in a single transaction. Each is applied fully
+// or not at all, and one not applied doesn't stop the rest.
+pub async fn push(
+    db: &DatabaseConnection,
+    user_id: &Uuid,
+    workspace_id: &Uuid,
+    payload: PushPayload,
+) -> Result<(Vec<MutationResult>, Vec<Effect>)> {
+    let mut results = vec![];
+    let mut effects = vec![];
+    let txn = db.begin().await?;
+    // Changes since an expired cursor can't be told apart, so every change to
+    // something existing conflicts
+    let expired = cursor_expired(&txn, payload.cursor).await?;
+    for mutation in payload.mutations {
+        let id = mutation.id;
+        let result = |outcome, error| MutationResult { id, outcome, error };
+        if SyncMutation::find_by_id(id).one(&txn).await?.is_some() {
+            results.push(result(Outcome::Applied, None));
+            continue;
+        }
+        if let Some(target) = mutation.change.target()
+            && (expired
+                || changed_since(
+                    &txn,
+                    workspace_id,
+                    &payload.client_id,
+                    payload.cursor,
+                    &target,
+                )
+                .await?)
+        {
+            let error = Error::conflict("Changed since the client's cursor");
+            results.push(result(Outcome::Conflict, Some(error.body())));
+            continue;
+        }
+
+        let savepoint = txn.begin().await?;
+        match apply(
+            &savepoint,
+            user_id,
+            workspace_id,
+            &payload.client_id,
+            mutation,
+        )
+        .await
+        {
+            Ok(effect) => {
+                savepoint.commit().await?;
+                results.push(result(Outcome::Applied, None));
+                effects.push(effect);
+            }
+            Err(Error::Internal(e)) => return Err(Error::Internal(e)),
+            Err(e) => {
+                savepoint.rollback().await?;
+                results.push(result(Outcome::Rejected, Some(e.body())));
+            }
+        }
+    }
+    txn.commit().await?;
+    Ok((results, effects))
+}
+
+async fn changed_since(
+    db: &DatabaseTransaction,
+    workspace_id: &Uuid,
+    client_id: &Uuid,
+    cursor: i32,
+    target: &Target,
+) -> Result<bool> {
+    let query = SyncChange::find()
+        .filter(SyncChangeColumn::WorkspaceId.eq(*workspace_id))
+        .filter(SyncChangeColumn::Id.gt(cursor))
+        .filter(
+            Condition::any()
+                .add(SyncChangeColumn::ClientId.is_null())
+                .add(SyncChangeColumn::ClientId.ne(*client_id)),
+        );
+    let query = match target {
+        Target::Project(id) => query
+            .filter(SyncChangeColumn::EntityType.eq("project"))
+            .filter(SyncChangeColumn::EntityId.eq(*id)),
+        Target::Task(id) => query
+            .filter(SyncChangeColumn::EntityType.eq("task"))
+            .filter(SyncChangeColumn::EntityId.eq(*id)),
+        Target::Membership(key) => query
+            .filter(SyncChangeColumn::EntityType.eq("task_project"))
+            .filter(SyncChangeColumn::EntityId.eq(key.task_id))
+            .filter(SyncChangeColumn::ProjectId.eq(key.project_id)),
+    };
+    Ok(query.one(db).await?.is_some())
+}
+
+fn require_in_workspace(workspace_id: &Uuid, actual: &Uuid, what: &str) -> Result<()> {
+    if workspace_id != actual {
+        return Err(Error::not_found(format!("{what} not found in workspace")));
+    }
+    Ok(())
+}
+
+// Apply a mutation with the same checks as the equivalent request, marking
+// the changes journaled as the client's own
+async fn apply(
+    db: &DatabaseTransaction,
+    user_id: &Uuid,
+    workspace_id: &Uuid,
+    client_id: &Uuid,
+    mutation: Mutation,
+) -> Result<Effect> {
+    let last_change = SyncChange::find()
+        .order_by_desc(SyncChangeColumn::Id)
+        .one(db)
+        .await?
+        .map_or(0, |c| c.id);
+    let at = mutation.at.naive_utc();
+
+    let effect = match mutation.change {
+        Change::CreateProject { project_id, title } => {
+            let project =
+                project::create_project_with_id(db, project_id, at, workspace_id, title).await?;
+            project::set_member_role(db, &project.id, user_id, Role::Owner).await?;
+            Effect::ProjectCreated(project)
+        }
+        Change::UpdateProject {
+            project_id,
+            changes,
+        } => {
+            let old =
+                access::require_project(db, user_id, &project_id, ProjectAction::Edit).await?;
+            require_in_workspace(workspace_id, &old.workspace_id, "Project")?;
+            let project =
+                project::edit_project(db, user_id, &project_id, Json(changes), None).await?;
+            Effect::ProjectUpdated { old, project }
+        }
+        Change::DeleteProject { project_id } => {
+            let project =
+                access::require_project(db, user_id, &project_id, ProjectAction::Delete).await?;
+            require_in_workspace(workspace_id, &project.workspace_id, "Project")?;
+            let (task_ids, detached_ids) =
+                project::delete_project(db, user_id, &project_id, None).await?;
+            Effect::ProjectDeleted {
+                project,
+                task_ids,
+                detached_ids,
+            }
+        }
+        Change::CreateTask {
+            task_id,
+            project_id,
+            title,
+            status,
+        } => {
+            let project =
+                access::require_project(db, user_id, &project_id, ProjectAction::Edit).await?;
+            require_in_workspace(workspace_id, &project.workspace_id, "Project")?;
+            let status = match status {
+                Some(status) => status,
+                None => workflow::initial_status(db, &project_id).await?,
+            };
+            let task =
+                task::create_task_with_id(db, task_id, at, title, status, &project_id).await?;
+            Effect::TaskCreated(task)
+        }
+        Change::UpdateTask { task_id, changes } => {
+            let access = access::require_task(db, user_id, &task_id, TaskAction::Edit).await?;
+            require_in_workspace(workspace_id, &access.workspace_id, "Task")?;
+            let (task, next) = task::edit_task(db, user_id, &task_id, Json(changes), None).await?;
+            Effect::TaskUpdated {
+                access,
+                task: Box::new(task),
+                next: next.map(Box::new),
+            }
+        }
+        Change::DeleteTask { task_id } => {
+            let access = access::require_task(db, user_id, &task_id, TaskAction::Delete).await?;
+            require_in_workspace(workspace_id, &access.workspace_id, "Task")?;
+            let dependent_ids = dependency::dependents(&task_id)
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|t| t.id)
+                .collect();
+            let project_ids = task::project_ids(db, &task_id).await?;
+            let subtasks = task::delete_task(db, user_id, &task_id, None).await?;
+            Effect::TaskDeleted {
+                access,
+                project_ids,
+                subtasks,
+                dependent_ids,
+            }
+        }
+        Change::AddToProject {
+            task_id,
+            project_id,
+        } => {
+            let project =
+                access::require_project(db, user_id, &project_id, ProjectAction::Edit).await?;
+            require_in_workspace(workspace_id, &project.workspace_id, "Project")?;
+            let access = access::require_task(db, user_id, &task_id, TaskAction::Edit).await?;
+            require_in_workspace(workspace_id, &access.workspace_id, "Task")?;
+            task::add_to_project(db, user_id, &task_id, &project_id).await?;
+            Effect::AddedToProject { access, project_id }
+        }
+        Change::RemoveFromProject {
+            task_id,
+            project_id,
+        } => {
+            let project =
+                access::require_project(db, user_id, &project_id, ProjectAction::Edit).await?;
+            require_in_workspace(workspace_id, &project.workspace_id, "Project")?;
+            let access = access::require_task(db, user_id, &task_id, TaskAction::View).await?;
+            task::remove_from_project(db, user_id, &task_id, &project_id).await?;
+            Effect::RemovedFromProject { access, project_id }
+        }
+    };
+
+    SyncChange::update_many()
+        .col_expr(SyncChangeColumn::ClientId, Expr::value(*client_id))
+        .filter(SyncChangeColumn::Id.gt(last_change))
+        .exec(db)
+        .await?;
+    let applied = SyncMutationActiveModel {
+        id: ActiveValue::Set(mutation.id),
+        client_id: ActiveValue::Set(*client_id),
+        workspace_id: ActiveValue::Set(*workspace_id),
+        made_at: ActiveValue::Set(at),
+        ..Default::default()
+    };
+    applied.insert(db).await?;
+    Ok(effect)
+}
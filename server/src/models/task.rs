@@ -1,55 +1,89 @@
-use anyhow::{Error, Result};
+use chrono::Utc;
 use rocket::serde::json::Json;
-use sea_orm::prelude::Date;
-use sea_orm::{ActiveModelTrait, ActiveValue, DatabaseConnection, EntityTrait};
-use serde::Deserialize;
-use std::collections::HashSet;
+use sea_orm::prelude::{Date, DateTime};
+use sea_orm::sea_query::SelectStatement;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    FromQueryResult, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait, Select,
+    TransactionSession, TransactionTrait, raw_sql,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use uuid::Uuid;
 
+use super::access::{self, TaskAction};
+use super::change_log::{self, Action, Change, LoggedEntity};
+use super::deadline::{self, Due};
+use super::dependency;
+use super::description;
+use super::listing::Page;
+use super::ordering;
+use super::recurrence::Recurrence;
+use super::user::User;
+use super::workflow::{self, Category};
+use crate::result::{Error, Result};
+
 pub use entity::task::{
     ActiveModel as TaskActiveModel, Column, Entity as Task, Model as TaskModel,
 };
 pub use entity::task_project::{
-    ActiveModel as TaskProjectActiveModel, Entity as TaskProject, Model as TaskProjectModel,
+    ActiveModel as TaskProjectActiveModel, Column as TaskProjectColumn, Entity as TaskProject,
+    Model as TaskProjectModel,
 };
 
-#[derive(Debug)]
+// Statuses of the default workflow every project starts out with
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Status {
     Todo,
     InProgress,
     Complete,
 }
 
-impl Status {
-    pub fn to_string(&self) -> String {
-        match self {
-            Status::Todo => "todo".to_string(),
-            Status::InProgress => "in_progress".to_string(),
-            Status::Complete => "complete".to_string(),
-        }
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Status::Todo => "todo",
+            Status::InProgress => "in_progress",
+            Status::Complete => "complete",
+        };
+        write!(f, "{s}")
     }
+}
 
-    pub fn parse(s: &str) -> Result<Self> {
-        let res = match s {
-            "todo" => Status::Todo,
-            "in_progress" => Status::InProgress,
-            "complete" => Status::Complete,
-            _ => return Err(Error::msg(format!("Invalid task status {s}"))),
-        };
+impl From<Status> for String {
+    fn from(status: Status) -> Self {
+        status.to_string()
+    }
+}
 
-        Ok(res)
+impl Status {
+    pub fn category(&self) -> Category {
+        match self {
+            Status::Todo => Category::NotStarted,
+            Status::InProgress => Category::Active,
+            Status::Complete => Category::Done,
+        }
     }
 }
 
-pub async fn create_task(
-    db: &DatabaseConnection,
+async fn insert_task<C: ConnectionTrait>(
+    db: &C,
+    id: Uuid,
+    created_at: DateTime,
     title: String,
-    status: Status,
+    status: String,
+    category: Category,
+    parent_id: Option<Uuid>,
 ) -> Result<TaskModel> {
     let task = TaskActiveModel {
-        id: ActiveValue::Set(Uuid::new_v4()),
+        id: ActiveValue::Set(id),
         title: ActiveValue::Set(title),
-        status: ActiveValue::Set(status.to_string()),
+        status: ActiveValue::Set(status),
+        status_category: ActiveValue::Set(category.to_string()),
+        parent_id: ActiveValue::Set(parent_id),
+        created_at: ActiveValue::Set(created_at),
         ..Default::default()
     };
     let task = task.insert(db).await?;
@@ -57,21 +91,284 @@ pub async fn create_task(
     Ok(task)
 }
 
+// Create a task outside of any project, which only tests need
+#[cfg(test)]
+pub async fn create_task(
+    db: &DatabaseConnection,
+    title: String,
+    status: Status,
+) -> Result<TaskModel> {
+    let (id, created_at) = (Uuid::new_v4(), Utc::now().naive_utc());
+    let (category, status) = (status.category(), status.to_string());
+    insert_task(db, id, created_at, title, status, category, None).await
+}
+
+// Ids of the tasks in the project
+pub fn ids_in_project(project_id: &Uuid) -> SelectStatement {
+    TaskProject::find()
+        .select_only()
+        .column(TaskProjectColumn::TaskId)
+        .filter(TaskProjectColumn::ProjectId.eq(*project_id))
+        .into_query()
+}
+
+pub async fn project_ids<C: ConnectionTrait>(db: &C, task_id: &Uuid) -> Result<Vec<Uuid>> {
+    let ids = TaskProject::find()
+        .filter(TaskProjectColumn::TaskId.eq(*task_id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|tp| tp.project_id)
+        .collect();
+    Ok(ids)
+}
+
+// Project ids of each of the tasks
+pub async fn projects_by_task<C: ConnectionTrait>(
+    db: &C,
+    task_ids: impl IntoIterator<Item = Uuid>,
+) -> Result<HashMap<Uuid, Vec<Uuid>>> {
+    let mut projects: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for tp in TaskProject::find()
+        .filter(TaskProjectColumn::TaskId.is_in(task_ids))
+        .all(db)
+        .await?
+    {
+        projects.entry(tp.task_id).or_default().push(tp.project_id);
+    }
+    Ok(projects)
+}
+
+// Status category counts of a task's direct subtasks
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SubtaskStats {
+    pub complete: u32,
+    pub in_progress: u32,
+    pub todo: u32,
+    pub total: u32,
+}
+
+#[derive(FromQueryResult)]
+struct ParentStats {
+    id: Uuid,
+    complete: u32,
+    in_progress: u32,
+    todo: u32,
+    total: u32,
+}
+
+async fn subtask_stats(
+    db: &DatabaseConnection,
+    ids: Vec<Uuid>,
+) -> Result<HashMap<Uuid, SubtaskStats>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let stats = ParentStats::find_by_statement(raw_sql!(
+        Sqlite,
+        r#"SELECT
+             t.parent_id AS id,
+             COUNT(CASE WHEN t.status_category = 'not_started' THEN 1 ELSE NULL END) AS todo,
+             COUNT(CASE WHEN t.status_category = 'active' THEN 1 ELSE NULL END) AS in_progress,
+             COUNT(CASE WHEN t.status_category = 'done' THEN 1 ELSE NULL END) AS complete,
+             COUNT(*) AS total
+           FROM task t
+           WHERE t.parent_id IN ({..ids})
+           GROUP BY t.parent_id
+        "#
+    ))
+    .all(db)
+    .await?;
+
+    let stats = stats
+        .into_iter()
+        .map(|s| {
+            let stats = SubtaskStats {
+                complete: s.complete,
+                in_progress: s.in_progress,
+                todo: s.todo,
+                total: s.total,
+            };
+            (s.id, stats)
+        })
+        .collect();
+    Ok(stats)
+}
+
+// Task as returned by the API, along with state derived from related tasks
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TaskResponse {
+    #[serde(flatten)]
+    pub task: TaskModel,
+    // Whether any of the task's blockers is still open
+    pub blocked: bool,
+    pub subtasks: SubtaskStats,
+    pub due: Option<Due>,
+}
+
+pub async fn responses(
+    db: &DatabaseConnection,
+    tasks: Vec<TaskModel>,
+) -> Result<Vec<TaskResponse>> {
+    let ids: Vec<Uuid> = tasks.iter().map(|t| t.id).collect();
+    let blocked = dependency::blocked_task_ids(db, ids.clone()).await?;
+    let mut subtasks = subtask_stats(db, ids).await?;
+    let responses = tasks
+        .into_iter()
+        .map(|task| TaskResponse {
+            blocked: blocked.contains(&task.id),
+            subtasks: subtasks.remove(&task.id).unwrap_or_default(),
+            due: deadline::due(&task),
+            task,
+        })
+        .collect();
+    Ok(responses)
+}
+
+pub async fn response(db: &DatabaseConnection, task: TaskModel) -> Result<TaskResponse> {
+    let mut responses = responses(db, vec![task]).await?;
+    Ok(responses.remove(0))
+}
+
+// Fields of the task's response that differ between two versions of it
+pub fn changed_fields(old: &TaskModel, new: &TaskModel) -> Result<Vec<String>> {
+    let mut fields: Vec<String> = change_log::field_changes(old, new)?
+        .into_iter()
+        .filter_map(|c| c.field)
+        .collect();
+    if deadline::due(old) != deadline::due(new) {
+        fields.push("due".to_string());
+    }
+    Ok(fields)
+}
+
+pub async fn response_page(
+    db: &DatabaseConnection,
+    page: Page<TaskModel>,
+) -> Result<Page<TaskResponse>> {
+    Ok(Page {
+        items: responses(db, page.items).await?,
+        next_cursor: page.next_cursor,
+    })
+}
+
 #[derive(Deserialize)]
 pub struct EditTaskPayload {
     title: Option<String>,
     description: Option<String>,
     status: Option<String>,
     due_date: Option<String>,
+    // Local time of day on the due date, like 15:00
+    due_time: Option<String>,
+    // Defaults to the time zone of whoever first sets the due date
+    time_zone: Option<String>,
+    recurrence: Option<Recurrence>,
 }
 
-pub async fn edit_task(
-    db: &DatabaseConnection,
+fn parse_due_date(s: &str) -> Result<Date> {
+    Date::parse_from_str(s, "%Y-%m-%d").map_err(|_| {
+        Error::validation(
+            "due_date",
+            format!("Invalid due date {s}, expected YYYY-MM-DD"),
+        )
+    })
+}
+
+async fn find_task<C: ConnectionTrait>(db: &C, id: &Uuid) -> Result<TaskModel> {
+    Task::find_by_id(*id)
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Task with id {id:?} not found!")))
+}
+
+// Fail if the task isn't at the version given, if any
+fn check_version(task: &TaskModel, version: Option<i32>) -> Result<()> {
+    match version {
+        Some(version) if version != task.version => Err(Error::precondition_failed(task)),
+        _ => Ok(()),
+    }
+}
+
+// Apply the changes to a task, recording them in the change log. Only applied
+// to the given version of the task, if any.
+async fn update_task<C: TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    mut task: TaskActiveModel,
+    version: Option<i32>,
+) -> Result<TaskModel> {
+    let txn = db.begin().await?;
+    let id = task.id.clone().unwrap();
+    let old = find_task(&txn, &id).await?;
+    check_version(&old, version)?;
+    task.version = ActiveValue::Set(old.version + 1);
+    let mut task = task.update(&txn).await?;
+    if task.description != old.description {
+        description::replace(&txn, &old, task.description.as_deref()).await?;
+    }
+    if task.due_time.is_some() && task.due_date.is_none() {
+        return Err(Error::validation(
+            "due_time",
+            "Tasks need a due date to have a due time",
+        ));
+    }
+    let due_at = deadline::due_at(task.due_date, task.due_time, task.time_zone.as_deref());
+    if due_at != task.due_at {
+        let mut with_due_at: TaskActiveModel = task.into();
+        with_due_at.due_at = ActiveValue::Set(due_at);
+        task = with_due_at.update(&txn).await?;
+    }
+    let mut changes = change_log::field_changes(&old, &task)?;
+    // Follow from other fields, which are recorded already
+    changes.retain(|c| !matches!(c.field.as_deref(), Some("status_category" | "due_at")));
+    change_log::record(&txn, actor_id, LoggedEntity::Task, &id, changes).await?;
+    txn.commit().await?;
+
+    Ok(task)
+}
+
+// Edit the task, if it's at the given version. Completing a recurring task
+// creates its next occurrence, which is returned along with the edited task.
+pub async fn edit_task<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
     id: &Uuid,
     payload: Json<EditTaskPayload>,
-) -> Result<TaskModel> {
+    version: Option<i32>,
+) -> Result<(TaskModel, Option<TaskModel>)> {
+    // Checked against the same version of the task as the changes are applied to
+    let txn = db.begin().await?;
+    let current = find_task(&txn, id).await?;
+    check_version(&current, version)?;
+    let (status, status_category) = match payload.status.clone() {
+        // Must be in the workflows of the task's projects, and a move they allow
+        Some(status) => {
+            let project_ids = project_ids(&txn, id).await?;
+            let category =
+                workflow::check_status(&txn, &project_ids, Some(&current.status), &status).await?;
+            if category == Category::Done {
+                dependency::check_can_complete(&txn, id).await?;
+            }
+            (
+                ActiveValue::Set(status),
+                ActiveValue::Set(category.to_string()),
+            )
+        }
+        None => (ActiveValue::NotSet, ActiveValue::NotSet),
+    };
+    let time_zone = match &payload.time_zone {
+        Some(time_zone) => {
+            let time_zone = deadline::parse_time_zone(time_zone)?;
+            ActiveValue::Set(Some(time_zone.name().to_string()))
+        }
+        None if payload.due_date.is_some() && current.time_zone.is_none() => {
+            let actor = User::find_by_id(*actor_id).one(&txn).await?;
+            ActiveValue::Set(actor.and_then(|a| a.time_zone))
+        }
+        None => ActiveValue::NotSet,
+    };
     let task = TaskActiveModel {
-        id: ActiveValue::Set(id.clone()),
+        id: ActiveValue::Set(*id),
         title: match payload.title.clone() {
             Some(title) => ActiveValue::Set(title),
             None => ActiveValue::NotSet,
@@ -80,31 +377,116 @@ pub async fn edit_task(
             Some(description) => ActiveValue::Set(Some(description)),
             None => ActiveValue::NotSet,
         },
-        status: match payload.status.clone() {
-            // Parse and re-stringify to validate
-            Some(status) => ActiveValue::Set(Status::parse(&status)?.to_string()),
+        status,
+        status_category,
+        due_date: match payload.due_date.clone() {
+            Some(due_date) => ActiveValue::Set(Some(parse_due_date(&due_date)?)),
             None => ActiveValue::NotSet,
         },
-        due_date: match payload.due_date.clone() {
-            Some(due_date) => ActiveValue::Set(Some(Date::parse_from_str(&due_date, "%Y-%m-%d")?)),
+        due_time: match &payload.due_time {
+            Some(due_time) => ActiveValue::Set(Some(deadline::parse_due_time(due_time)?)),
+            None => ActiveValue::NotSet,
+        },
+        time_zone,
+        recurrence: match &payload.recurrence {
+            Some(recurrence) => {
+                recurrence.validate()?;
+                ActiveValue::Set(Some(recurrence.to_json()?))
+            }
             None => ActiveValue::NotSet,
         },
         ..Default::default()
     };
-    let task = task.update(db).await?;
 
-    Ok(task)
+    let task = update_task(&txn, actor_id, task, version).await?;
+    let completed = task.status_category == Category::Done.to_string()
+        && current.status_category != Category::Done.to_string();
+    let next = if completed {
+        create_next_occurrence(&txn, &task).await?
+    } else {
+        None
+    };
+    txn.commit().await?;
+
+    Ok((task, next))
+}
+
+// Create the occurrence following a completed recurring task, due at the next
+// date of its rule and in all the same projects. Each occurrence is only
+// followed once, even if it's reopened and completed again.
+async fn create_next_occurrence<C: ConnectionTrait>(
+    db: &C,
+    task: &TaskModel,
+) -> Result<Option<TaskModel>> {
+    let Some(recurrence) = &task.recurrence else {
+        return Ok(None);
+    };
+    let followed = Task::find()
+        .filter(Column::PreviousOccurrenceId.eq(task.id))
+        .one(db)
+        .await?
+        .is_some();
+    if followed {
+        return Ok(None);
+    }
+
+    let recurrence = Recurrence::from_json(recurrence)?;
+    let today = Utc::now().date_naive();
+    let project_ids = project_ids(db, &task.id).await?;
+    let status = match project_ids.first() {
+        Some(project_id) => workflow::initial_status(db, project_id).await?,
+        None => Status::Todo.to_string(),
+    };
+    let category = workflow::check_status(db, &project_ids, None, &status).await?;
+
+    let due_date = recurrence.next_due(task.due_date, today);
+    let due_at = deadline::due_at(Some(due_date), task.due_time, task.time_zone.as_deref());
+    let next = TaskActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        title: ActiveValue::Set(task.title.clone()),
+        description: ActiveValue::Set(task.description.clone()),
+        status: ActiveValue::Set(status),
+        status_category: ActiveValue::Set(category.to_string()),
+        due_date: ActiveValue::Set(Some(due_date)),
+        due_time: ActiveValue::Set(task.due_time),
+        time_zone: ActiveValue::Set(task.time_zone.clone()),
+        due_at: ActiveValue::Set(due_at),
+        parent_id: ActiveValue::Set(task.parent_id),
+        recurrence: ActiveValue::Set(task.recurrence.clone()),
+        previous_occurrence_id: ActiveValue::Set(Some(task.id)),
+        created_at: ActiveValue::Set(Utc::now().naive_utc()),
+        version: ActiveValue::Set(1),
+    };
+    let next = next.insert(db).await?;
+    for project_id in project_ids {
+        insert_task_project(db, &next.id, &project_id).await?;
+    }
+
+    Ok(Some(next))
+}
+
+// Due dates of the recurring task's next occurrences
+pub fn preview_occurrences(task: &TaskModel, count: u32) -> Result<Vec<Date>> {
+    let recurrence = task
+        .recurrence
+        .as_ref()
+        .ok_or_else(|| Error::validation("recurrence", "Task doesn't recur"))?;
+    Recurrence::from_json(recurrence)?.preview(task.due_date, Utc::now().date_naive(), count)
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum ClearableField {
+    // Along with the due time
     DueDate,
+    DueTime,
+    TimeZone,
     Description,
+    Recurrence,
 }
 
 impl ClearableField {
     pub fn from_field_strs(field_strs: Vec<&str>) -> Result<HashSet<Self>> {
-        let mut results = HashSet::with_capacity(2);
+        let mut results = HashSet::with_capacity(5);
         for s in field_strs {
             match Self::from(s) {
                 Ok(v) => results.insert(v),
@@ -118,8 +500,16 @@ impl ClearableField {
     pub fn from(s: &str) -> Result<Self> {
         let res = match s {
             "due_date" => ClearableField::DueDate,
+            "due_time" => ClearableField::DueTime,
+            "time_zone" => ClearableField::TimeZone,
             "description" => ClearableField::Description,
-            _ => return Err(Error::msg(format!("Field not clearable on task: {s}"))),
+            "recurrence" => ClearableField::Recurrence,
+            _ => {
+                return Err(Error::validation(
+                    "fields",
+                    format!("Field not clearable on task: {s}"),
+                ));
+            }
         };
 
         Ok(res)
@@ -128,8 +518,10 @@ impl ClearableField {
 
 pub async fn clear_fields(
     db: &DatabaseConnection,
+    actor_id: &Uuid,
     id: &Uuid,
     fields: HashSet<ClearableField>,
+    version: Option<i32>,
 ) -> Result<TaskModel> {
     let description = if fields.contains(&ClearableField::Description) {
         ActiveValue::Set(None)
@@ -141,28 +533,179 @@ pub async fn clear_fields(
     } else {
         ActiveValue::NotSet
     };
+    let due_time =
+        if fields.contains(&ClearableField::DueDate) || fields.contains(&ClearableField::DueTime) {
+            ActiveValue::Set(None)
+        } else {
+            ActiveValue::NotSet
+        };
+    let time_zone = if fields.contains(&ClearableField::TimeZone) {
+        ActiveValue::Set(None)
+    } else {
+        ActiveValue::NotSet
+    };
+    let recurrence = if fields.contains(&ClearableField::Recurrence) {
+        ActiveValue::Set(None)
+    } else {
+        ActiveValue::NotSet
+    };
 
     let task = TaskActiveModel {
-        id: ActiveValue::Set(id.clone()),
+        id: ActiveValue::Set(*id),
         title: ActiveValue::NotSet,
         status: ActiveValue::NotSet,
         description,
         due_date,
+        due_time,
+        time_zone,
+        recurrence,
         ..Default::default()
     };
 
-    let task = task.update(db).await?;
+    update_task(db, actor_id, task, version).await
+}
+
+// A subtask deleted along with its parent, and the projects it was in
+pub struct DeletedTask {
+    pub id: Uuid,
+    pub project_ids: Vec<Uuid>,
+}
+
+// Delete the task along with all its subtasks, if it's at the given version,
+// returning the subtasks deleted. The actor needs to be allowed to delete
+// each subtask, as they may be in other projects than the task.
+pub async fn delete_task<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    id: &Uuid,
+    version: Option<i32>,
+) -> Result<Vec<DeletedTask>> {
+    let txn = db.begin().await?;
+    check_version(&find_task(&txn, id).await?, version)?;
+    let subtask_ids = descendant_ids(&txn, id).await?;
+    let mut subtasks = vec![];
+    for id in &subtask_ids {
+        access::require_task(&txn, actor_id, id, TaskAction::Delete).await?;
+        let project_ids = project_ids(&txn, id).await?;
+        subtasks.push(DeletedTask {
+            id: *id,
+            project_ids,
+        });
+    }
+    for id in std::iter::once(id).chain(subtask_ids.iter()) {
+        let task = find_task(&txn, id).await?;
+        let changes = vec![change_log::deletion(&task)?];
+        change_log::record(&txn, actor_id, LoggedEntity::Task, id, changes).await?;
+        Task::delete_by_id(*id).exec(&txn).await?;
+    }
+    // Later occurrences of deleted tasks no longer have a previous one
+    let ids: Vec<Uuid> = std::iter::once(*id).chain(subtask_ids).collect();
+    Task::update_many()
+        .set(TaskActiveModel {
+            previous_occurrence_id: ActiveValue::Set(None),
+            ..Default::default()
+        })
+        .filter(Column::PreviousOccurrenceId.is_in(ids))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    Ok(subtasks)
+}
+
+// Subtasks of the task, oldest first
+pub fn subtasks(id: &Uuid) -> Select<Task> {
+    Task::find().filter(Column::ParentId.eq(*id))
+}
+
+// Subtasks of the task, their subtasks and so on
+async fn descendant_ids<C: ConnectionTrait>(db: &C, id: &Uuid) -> Result<Vec<Uuid>> {
+    let mut ids = vec![];
+    let mut frontier = vec![*id];
+    while !frontier.is_empty() {
+        frontier = Task::find()
+            .filter(Column::ParentId.is_in(frontier))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        ids.extend(frontier.iter());
+    }
+    Ok(ids)
+}
+
+// Create a task under another, in all the same projects. It starts in the
+// initial status of the first of them.
+pub async fn create_subtask(
+    db: &DatabaseConnection,
+    parent_id: &Uuid,
+    title: String,
+) -> Result<TaskModel> {
+    let project_ids = project_ids(db, parent_id).await?;
+    let status = match project_ids.first() {
+        Some(project_id) => workflow::initial_status(db, project_id).await?,
+        None => Status::Todo.to_string(),
+    };
+    let category = workflow::check_status(db, &project_ids, None, &status).await?;
+
+    let txn = db.begin().await?;
+    let (id, created_at) = (Uuid::new_v4(), Utc::now().naive_utc());
+    let parent_id = Some(*parent_id);
+    let task = insert_task(&txn, id, created_at, title, status, category, parent_id).await?;
+    for project_id in project_ids {
+        insert_task_project(&txn, &task.id, &project_id).await?;
+    }
+    txn.commit().await?;
+
     Ok(task)
 }
 
-pub async fn add_to_project(
+// Move the task under a new parent, or to the top level
+pub async fn set_parent(
     db: &DatabaseConnection,
+    actor_id: &Uuid,
+    id: &Uuid,
+    parent_id: Option<Uuid>,
+    version: Option<i32>,
+) -> Result<TaskModel> {
+    let txn = db.begin().await?;
+    check_version(&find_task(&txn, id).await?, version)?;
+    if let Some(parent_id) = parent_id
+        && (parent_id == *id || descendant_ids(&txn, id).await?.contains(&parent_id))
+    {
+        return Err(Error::validation(
+            "parent_id",
+            "Task can't be moved under itself or its subtasks",
+        ));
+    }
+
+    let task = TaskActiveModel {
+        id: ActiveValue::Set(*id),
+        parent_id: ActiveValue::Set(parent_id),
+        ..Default::default()
+    };
+    let task = update_task(&txn, actor_id, task, version).await?;
+    txn.commit().await?;
+    Ok(task)
+}
+
+async fn insert_task_project<C: ConnectionTrait>(
+    db: &C,
     task_id: &Uuid,
     project_id: &Uuid,
 ) -> Result<TaskProjectModel> {
+    // New tasks go at the end of the project
+    let last = TaskProject::find()
+        .filter(TaskProjectColumn::ProjectId.eq(*project_id))
+        .order_by_desc(TaskProjectColumn::Position)
+        .one(db)
+        .await?;
+    let position = ordering::key_between(last.as_ref().map(|tp| tp.position.as_str()), None)?;
+
     let tp = TaskProjectActiveModel {
-        project_id: ActiveValue::Set(project_id.clone()),
-        task_id: ActiveValue::Set(task_id.clone()),
+        project_id: ActiveValue::Set(*project_id),
+        task_id: ActiveValue::Set(*task_id),
+        position: ActiveValue::Set(position),
         ..Default::default()
     };
 
@@ -170,27 +713,280 @@ pub async fn add_to_project(
     Ok(tp)
 }
 
-pub async fn remove_from_project(
-    db: &DatabaseConnection,
+// Record the change of membership on both the task and the project
+async fn record_membership<C: ConnectionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    action: Action,
     task_id: &Uuid,
     project_id: &Uuid,
 ) -> Result<()> {
-    let tp = TaskProjectActiveModel {
-        project_id: ActiveValue::Set(project_id.clone()),
-        task_id: ActiveValue::Set(task_id.clone()),
-        ..Default::default()
-    };
-    let _ = TaskProject::delete(tp).exec(db).await?;
+    let change = change_log::membership(action, "project_id", project_id);
+    change_log::record(db, actor_id, LoggedEntity::Task, task_id, vec![change]).await?;
+    let change = change_log::membership(action, "task_id", task_id);
+    change_log::record(
+        db,
+        actor_id,
+        LoggedEntity::Project,
+        project_id,
+        vec![change],
+    )
+    .await
+}
+
+// Add the task to another project, whose workflow must have the task's status
+pub async fn add_to_project<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    task_id: &Uuid,
+    project_id: &Uuid,
+) -> Result<TaskProjectModel> {
+    let task = find_task(db, task_id).await?;
+    workflow::check_status(db, &[*project_id], None, &task.status).await?;
+
+    let txn = db.begin().await?;
+    let tp = insert_task_project(&txn, task_id, project_id).await?;
+    record_membership(&txn, actor_id, Action::Add, task_id, project_id).await?;
+    txn.commit().await?;
+    Ok(tp)
+}
+
+pub async fn remove_from_project<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    task_id: &Uuid,
+    project_id: &Uuid,
+) -> Result<()> {
+    let txn = db.begin().await?;
+    let res = TaskProject::delete_by_id((*project_id, *task_id))
+        .exec(&txn)
+        .await?;
+    if res.rows_affected > 0 {
+        // Tasks outside every project could no longer be reached
+        let projects = TaskProject::find()
+            .filter(TaskProjectColumn::TaskId.eq(*task_id))
+            .count(&txn)
+            .await?;
+        if projects == 0 {
+            return Err(Error::validation(
+                "task_id",
+                "Tasks must stay in at least one project; delete the task instead",
+            ));
+        }
+        record_membership(&txn, actor_id, Action::Remove, task_id, project_id).await?;
+    }
+    txn.commit().await?;
     Ok(())
 }
 
+// Delete the tasks that are in no project but the given one, with their
+// subtasks, returning the ids of every task deleted and of the subtasks moved
+// to the top level because they stay in another project
+pub async fn delete_only_in_project<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    project_id: &Uuid,
+) -> Result<(Vec<Uuid>, Vec<Uuid>)> {
+    let txn = db.begin().await?;
+    let task_ids: Vec<Uuid> = TaskProject::find()
+        .filter(TaskProjectColumn::ProjectId.eq(*project_id))
+        .all(&txn)
+        .await?
+        .into_iter()
+        .map(|tp| tp.task_id)
+        .collect();
+    let (mut deleted, mut detached) = (vec![], vec![]);
+    for id in task_ids {
+        let projects = TaskProject::find()
+            .filter(TaskProjectColumn::TaskId.eq(id))
+            .count(&txn)
+            .await?;
+        // Skip subtasks already deleted, and tasks staying in another project
+        if deleted.contains(&id) || projects > 1 {
+            continue;
+        }
+        detached.extend(detach_staying(&txn, actor_id, &id, project_id).await?);
+        let subtasks = delete_task(&txn, actor_id, &id, None).await?;
+        deleted.push(id);
+        deleted.extend(subtasks.into_iter().map(|t| t.id));
+    }
+    txn.commit().await?;
+    Ok((deleted, detached))
+}
+
+// Move the task's subtasks that are in a project other than the given one to
+// the top level, along with their own subtasks, returning their ids
+async fn detach_staying<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    actor_id: &Uuid,
+    id: &Uuid,
+    project_id: &Uuid,
+) -> Result<Vec<Uuid>> {
+    let mut detached = vec![];
+    let mut frontier = vec![*id];
+    while !frontier.is_empty() {
+        let subtasks = Task::find()
+            .filter(Column::ParentId.is_in(frontier))
+            .all(db)
+            .await?;
+        frontier = vec![];
+        for subtask in subtasks {
+            let elsewhere = TaskProject::find()
+                .filter(TaskProjectColumn::TaskId.eq(subtask.id))
+                .filter(TaskProjectColumn::ProjectId.ne(*project_id))
+                .count(db)
+                .await?;
+            if elsewhere > 0 {
+                let task = TaskActiveModel {
+                    id: ActiveValue::Set(subtask.id),
+                    parent_id: ActiveValue::Set(None),
+                    ..Default::default()
+                };
+                update_task(db, actor_id, task, None).await?;
+                detached.push(subtask.id);
+            } else {
+                frontier.push(subtask.id);
+            }
+        }
+    }
+    Ok(detached)
+}
+
+async fn find_task_project<C: ConnectionTrait>(
+    db: &C,
+    project_id: &Uuid,
+    task_id: &Uuid,
+    field: &str,
+) -> Result<TaskProjectModel> {
+    TaskProject::find_by_id((*project_id, *task_id))
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::validation(field, format!("Task {task_id} is not in the project")))
+}
+
+// Closest position to the given one within the project, in the given direction,
+// ignoring the task being moved
+async fn neighbour_position<C: ConnectionTrait>(
+    db: &C,
+    project_id: &Uuid,
+    moving_id: &Uuid,
+    position: &str,
+    after: bool,
+) -> Result<Option<String>> {
+    let query = TaskProject::find()
+        .filter(TaskProjectColumn::ProjectId.eq(*project_id))
+        .filter(TaskProjectColumn::TaskId.ne(*moving_id));
+    let query = if after {
+        query
+            .filter(TaskProjectColumn::Position.gt(position))
+            .order_by_asc(TaskProjectColumn::Position)
+    } else {
+        query
+            .filter(TaskProjectColumn::Position.lt(position))
+            .order_by_desc(TaskProjectColumn::Position)
+    };
+    Ok(query.one(db).await?.map(|tp| tp.position))
+}
+
+// Move a task within a project's ordering, to sit after the `after` task and/or
+// before the `before` one. Only the moved task gets a new position.
+pub async fn move_in_project(
+    db: &DatabaseConnection,
+    actor_id: &Uuid,
+    project_id: &Uuid,
+    task_id: &Uuid,
+    before: Option<Uuid>,
+    after: Option<Uuid>,
+) -> Result<TaskProjectModel> {
+    if before == Some(*task_id) || after == Some(*task_id) {
+        return Err(Error::validation(
+            "before",
+            "Task can't be moved relative to itself",
+        ));
+    }
+
+    let txn = db.begin().await?;
+    let moving = TaskProject::find_by_id((*project_id, *task_id))
+        .one(&txn)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Task {task_id} is not in the project")))?;
+    let (lower, upper) = match (after, before) {
+        (None, None) => {
+            return Err(Error::validation(
+                "before",
+                "Either before or after is required",
+            ));
+        }
+        (Some(after), Some(before)) => {
+            let lower = find_task_project(&txn, project_id, &after, "after").await?;
+            let upper = find_task_project(&txn, project_id, &before, "before").await?;
+            if lower.position >= upper.position {
+                return Err(Error::validation(
+                    "before",
+                    format!("Task {before} comes before task {after}"),
+                ));
+            }
+            (Some(lower.position), Some(upper.position))
+        }
+        (Some(after), None) => {
+            let lower = find_task_project(&txn, project_id, &after, "after").await?;
+            let upper =
+                neighbour_position(&txn, project_id, task_id, &lower.position, true).await?;
+            (Some(lower.position), upper)
+        }
+        (None, Some(before)) => {
+            let upper = find_task_project(&txn, project_id, &before, "before").await?;
+            let lower =
+                neighbour_position(&txn, project_id, task_id, &upper.position, false).await?;
+            (lower, Some(upper.position))
+        }
+    };
+    let position = ordering::key_between(lower.as_deref(), upper.as_deref())?;
+
+    // Positions only mean something within their project
+    let logged =
+        |tp: &TaskProjectModel| json!({ "project_id": tp.project_id, "position": tp.position });
+    let old_value = logged(&moving);
+    let mut moving: TaskProjectActiveModel = moving.into();
+    moving.position = ActiveValue::Set(position);
+    let moved = moving.update(&txn).await?;
+    let change = Change {
+        action: Action::Update,
+        field: Some("position".to_string()),
+        old_value: Some(old_value),
+        new_value: Some(logged(&moved)),
+    };
+    change_log::record(&txn, actor_id, LoggedEntity::Task, task_id, vec![change]).await?;
+    txn.commit().await?;
+    Ok(moved)
+}
+
 pub async fn create_task_in_project(
     db: &DatabaseConnection,
     title: String,
-    status: Status,
+    status: impl Into<String>,
+    project_id: &Uuid,
+) -> Result<TaskModel> {
+    // Finer than the column default, so creation order is kept in listings
+    let created_at = Utc::now().naive_utc();
+    create_task_with_id(db, Uuid::new_v4(), created_at, title, status, project_id).await
+}
+
+// Create a task whose id and creation time were picked by the client, like one
+// made while offline
+pub async fn create_task_with_id<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    id: Uuid,
+    created_at: DateTime,
+    title: String,
+    status: impl Into<String>,
     project_id: &Uuid,
 ) -> Result<TaskModel> {
-    let task = create_task(db, title, status).await?;
-    add_to_project(db, &task.id, project_id).await?;
+    let status = status.into();
+    let category = workflow::check_status(db, &[*project_id], None, &status).await?;
+    let txn = db.begin().await?;
+    let task = insert_task(&txn, id, created_at, title, status, category, None).await?;
+    insert_task_project(&txn, &task.id, project_id).await?;
+    txn.commit().await?;
     Ok(task)
 }
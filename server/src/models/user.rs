@@ -0,0 +1,150 @@
+use argon2::Argon2;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::deadline;
+use super::workspace;
+use crate::result::{Error, Result};
+
+pub use entity::auth_token::{
+    ActiveModel as AuthTokenActiveModel, Column as AuthTokenColumn, Entity as AuthToken,
+};
+pub use entity::user::{
+    ActiveModel as UserActiveModel, Column, Entity as User, Model as UserModel,
+};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const TOKEN_LIFETIME_DAYS: i64 = 30;
+
+pub async fn create_user(
+    db: &DatabaseConnection,
+    username: String,
+    password: &str,
+) -> Result<UserModel> {
+    let username = username.trim().to_string();
+    if username.is_empty() {
+        return Err(Error::validation("username", "Username cannot be empty"));
+    }
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err(Error::validation(
+            "password",
+            format!("Password must be at least {MIN_PASSWORD_LENGTH} characters"),
+        ));
+    }
+
+    let existing = User::find()
+        .filter(Column::Username.eq(&username))
+        .one(db)
+        .await?;
+    if existing.is_some() {
+        return Err(Error::conflict(format!("Username {username} is taken")));
+    }
+
+    // Every user starts out with a workspace of their own
+    let txn = db.begin().await?;
+    let user = UserActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        username: ActiveValue::Set(username.clone()),
+        password_hash: ActiveValue::Set(hash_password(password)?),
+        ..Default::default()
+    };
+    let user = user.insert(&txn).await?;
+    workspace::create_workspace(&txn, format!("{username}'s workspace"), &user.id).await?;
+    txn.commit().await?;
+
+    Ok(user)
+}
+
+// Set or clear the time zone the user's deadlines default to
+pub async fn set_time_zone(
+    db: &DatabaseConnection,
+    user_id: &Uuid,
+    time_zone: Option<String>,
+) -> Result<UserModel> {
+    let time_zone = time_zone
+        .map(|tz| deadline::parse_time_zone(&tz).map(|tz| tz.name().to_string()))
+        .transpose()?;
+    let user = UserActiveModel {
+        id: ActiveValue::Set(*user_id),
+        time_zone: ActiveValue::Set(time_zone),
+        ..Default::default()
+    };
+    Ok(user.update(db).await?)
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| Error::Internal(anyhow::Error::msg(e.to_string())))?;
+    Ok(hash.to_string())
+}
+
+// Look up user by username and check their password.
+// Deliberately vague about which of the two was wrong.
+pub async fn authenticate(
+    db: &DatabaseConnection,
+    username: &str,
+    password: &str,
+) -> Result<UserModel> {
+    let invalid = || Error::Unauthorized("Invalid username or password".to_string());
+    let user = User::find()
+        .filter(Column::Username.eq(username.trim()))
+        .one(db)
+        .await?
+        .ok_or_else(invalid)?;
+
+    let hash = PasswordHash::new(&user.password_hash).map_err(|_| invalid())?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .map_err(|_| invalid())?;
+
+    Ok(user)
+}
+
+// Tokens are only stored hashed, so a leaked database can't be used to log in
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Issue a new bearer token for the user. Returns the plain token, which is
+// never persisted.
+pub async fn create_token(db: &DatabaseConnection, user_id: &Uuid) -> Result<String> {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+
+    let record = AuthTokenActiveModel {
+        token_hash: ActiveValue::Set(hash_token(&token)),
+        user_id: ActiveValue::Set(*user_id),
+        expires_at: ActiveValue::Set(
+            (Utc::now() + Duration::days(TOKEN_LIFETIME_DAYS)).naive_utc(),
+        ),
+        ..Default::default()
+    };
+    record.insert(db).await?;
+
+    Ok(token)
+}
+
+pub async fn user_for_token(db: &DatabaseConnection, token: &str) -> Result<Option<UserModel>> {
+    let found = AuthToken::find_by_id(hash_token(token))
+        .filter(AuthTokenColumn::ExpiresAt.gt(Utc::now().naive_utc()))
+        .find_also_related(User)
+        .one(db)
+        .await?;
+
+    Ok(found.and_then(|(_, user)| user))
+}
+
+pub async fn revoke_token(db: &DatabaseConnection, token: &str) -> Result<()> {
+    AuthToken::delete_by_id(hash_token(token)).exec(db).await?;
+    Ok(())
+}
@@ -0,0 +1,257 @@
+// Named task filters saved for everyone in a workspace, like "overdue across
+// all projects". A view is evaluated against the workspace's tasks each time
+// it's listed, so its tasks are always current.
+use sea_orm::prelude::{Date, DateTime, Expr};
+use sea_orm::sea_query::{ExprTrait, Query};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, DatabaseConnection, EntityTrait, Order,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Select,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::listing::{self, Sort, TaskFilter, TaskListParams};
+use super::project::{self, Project};
+use super::task::{self, Task, TaskProject, TaskProjectColumn};
+use super::workflow::Category;
+use crate::result::{Error, Result};
+
+pub use entity::saved_view::{
+    ActiveModel as SavedViewActiveModel, Column, Entity as SavedView, Model as SavedViewModel,
+};
+
+// What a view matches and how its tasks are ordered.
+// Unset fields don't restrict the view.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ViewDefinition {
+    // Any of these status keys
+    #[serde(default)]
+    pub statuses: Vec<String>,
+    // Any of these status categories
+    #[serde(default)]
+    pub categories: Vec<Category>,
+    pub due_before: Option<Date>,
+    pub due_after: Option<Date>,
+    pub overdue: Option<bool>,
+    pub has_description: Option<bool>,
+    pub created_after: Option<DateTime>,
+    // Tasks in any of these projects, or in any project of the workspace if empty
+    #[serde(default)]
+    pub project_ids: Vec<Uuid>,
+    // Text the title or description contains
+    pub query: Option<String>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+}
+
+impl ViewDefinition {
+    fn filter(&self) -> Result<TaskFilter> {
+        Ok(TaskFilter {
+            statuses: self.statuses.clone(),
+            categories: self.categories.clone(),
+            due_before: self.due_before,
+            due_after: self.due_after,
+            overdue: self.overdue,
+            has_description: self.has_description,
+            created_after: self.created_after,
+        })
+    }
+
+    fn sort(&self) -> Result<(Sort, Order)> {
+        let sort = match &self.sort {
+            Some(sort) => Sort::parse(sort, None)?,
+            None => Sort::CreatedAt,
+        };
+        let order = match &self.order {
+            Some(order) => listing::parse_order(order)?,
+            None => Order::Asc,
+        };
+        Ok((sort, order))
+    }
+
+    // Tasks of the workspace matching the definition
+    fn condition(&self, workspace_id: &Uuid) -> Result<Condition> {
+        let mut projects = Query::select()
+            .column(TaskProjectColumn::TaskId)
+            .from(TaskProject)
+            .inner_join(
+                Project,
+                Expr::col(project::Column::Id.as_column_ref())
+                    .equals(TaskProjectColumn::ProjectId.as_column_ref()),
+            )
+            .and_where(project::Column::WorkspaceId.eq(*workspace_id))
+            .to_owned();
+        if !self.project_ids.is_empty() {
+            projects.and_where(TaskProjectColumn::ProjectId.is_in(self.project_ids.clone()));
+        }
+
+        let mut cond = Condition::all()
+            .add(task::Column::Id.in_subquery(projects))
+            .add(self.filter()?.condition());
+        if let Some(query) = &self.query {
+            cond = cond.add(
+                Condition::any()
+                    .add(task::Column::Title.contains(query))
+                    .add(task::Column::Description.contains(query)),
+            );
+        }
+        Ok(cond)
+    }
+
+    async fn validate(&self, db: &DatabaseConnection, workspace_id: &Uuid) -> Result<()> {
+        self.filter()?;
+        self.sort()?;
+        let projects = Project::find()
+            .filter(project::Column::Id.is_in(self.project_ids.clone()))
+            .filter(project::Column::WorkspaceId.eq(*workspace_id))
+            .count(db)
+            .await?;
+        if projects != self.project_ids.iter().collect::<HashSet<_>>().len() as u64 {
+            return Err(Error::validation(
+                "project_ids",
+                "Views can only include projects of their workspace",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn definition(view: &SavedViewModel) -> Result<ViewDefinition> {
+    Ok(serde_json::from_value(view.definition.clone())?)
+}
+
+fn validate_name(name: String) -> Result<String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(Error::validation("name", "View name cannot be empty"));
+    }
+    Ok(name)
+}
+
+// Views of the given workspaces, by name
+pub async fn views_for_workspaces(
+    db: &DatabaseConnection,
+    workspace_ids: Vec<Uuid>,
+) -> Result<Vec<SavedViewModel>> {
+    let views = SavedView::find()
+        .filter(Column::WorkspaceId.is_in(workspace_ids))
+        .order_by_asc(Column::Name)
+        .all(db)
+        .await?;
+    Ok(views)
+}
+
+// Find a view, if it belongs to one of the given workspaces
+pub async fn find_view(
+    db: &DatabaseConnection,
+    workspace_ids: Vec<Uuid>,
+    id: &Uuid,
+) -> Result<SavedViewModel> {
+    SavedView::find_by_id(*id)
+        .filter(Column::WorkspaceId.is_in(workspace_ids))
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("View with id {id:?} not found!")))
+}
+
+pub async fn create_view(
+    db: &DatabaseConnection,
+    workspace_id: &Uuid,
+    creator_id: &Uuid,
+    name: String,
+    definition: ViewDefinition,
+) -> Result<SavedViewModel> {
+    definition.validate(db, workspace_id).await?;
+    let view = SavedViewActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        workspace_id: ActiveValue::Set(*workspace_id),
+        creator_id: ActiveValue::Set(Some(*creator_id)),
+        name: ActiveValue::Set(validate_name(name)?),
+        definition: ActiveValue::Set(serde_json::to_value(definition)?),
+        ..Default::default()
+    };
+    let view = view.insert(db).await?;
+
+    Ok(view)
+}
+
+pub async fn edit_view(
+    db: &DatabaseConnection,
+    view: SavedViewModel,
+    name: Option<String>,
+    definition: Option<ViewDefinition>,
+) -> Result<SavedViewModel> {
+    let name = match name {
+        Some(name) => ActiveValue::Set(validate_name(name)?),
+        None => ActiveValue::NotSet,
+    };
+    let definition = match definition {
+        Some(definition) => {
+            definition.validate(db, &view.workspace_id).await?;
+            ActiveValue::Set(serde_json::to_value(definition)?)
+        }
+        None => ActiveValue::NotSet,
+    };
+    let view = SavedViewActiveModel {
+        id: ActiveValue::Set(view.id),
+        name,
+        definition,
+        ..Default::default()
+    };
+    let view = view.update(db).await?;
+
+    Ok(view)
+}
+
+pub async fn delete_view(db: &DatabaseConnection, id: &Uuid) -> Result<()> {
+    SavedView::delete_by_id(*id).exec(db).await?;
+    Ok(())
+}
+
+// Tasks currently in the view, to page through with the view's sort
+pub fn tasks(
+    view: &SavedViewModel,
+    cursor: Option<String>,
+    limit: u64,
+) -> Result<(Select<Task>, TaskListParams)> {
+    let definition = definition(view)?;
+    let (sort, order) = definition.sort()?;
+    let query = Task::find().filter(definition.condition(&view.workspace_id)?);
+    let params = TaskListParams {
+        filter: TaskFilter::default(),
+        sort,
+        order,
+        cursor,
+        limit,
+    };
+    Ok((query, params))
+}
+
+// Ids of all tasks in the view
+pub async fn task_ids(db: &DatabaseConnection, view: &SavedViewModel) -> Result<HashSet<Uuid>> {
+    let condition = definition(view)?.condition(&view.workspace_id)?;
+    let ids = Task::find()
+        .select_only()
+        .column(task::Column::Id)
+        .filter(condition)
+        .into_tuple::<Uuid>()
+        .all(db)
+        .await?;
+    Ok(ids.into_iter().collect())
+}
+
+// Whether the task is in the view
+pub async fn contains(
+    db: &DatabaseConnection,
+    view: &SavedViewModel,
+    task_id: &Uuid,
+) -> Result<bool> {
+    let condition = definition(view)?.condition(&view.workspace_id)?;
+    let count = Task::find()
+        .filter(task::Column::Id.eq(*task_id))
+        .filter(condition)
+        .count(db)
+        .await?;
+    Ok(count > 0)
+}
@@ -0,0 +1,326 @@
+// Outgoing webhooks.
+//
+// When an event happens, a delivery of it is recorded for every webhook that
+// wants it, and deliveries are then sent in the background. Failed deliveries
+// are retried with exponential backoff until they succeed or run out of
+// attempts, and each attempt is kept for the delivery log. As deliveries are
+// stored before they're sent, they survive restarts.
+//
+// Payloads are signed with the webhook's secret. The signature header holds
+// `sha256=` followed by the hex HMAC-SHA256 of the request body.
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use sea_orm::prelude::{DateTime, Expr, Json};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+use uuid::Uuid;
+
+use crate::result::{Error, Result};
+
+pub use entity::webhook::{
+    ActiveModel as WebhookActiveModel, Column, Entity as Webhook, Model as WebhookModel,
+};
+pub use entity::webhook_attempt::{
+    ActiveModel as WebhookAttemptActiveModel, Column as WebhookAttemptColumn,
+    Entity as WebhookAttempt, Model as WebhookAttemptModel,
+};
+pub use entity::webhook_delivery::{
+    ActiveModel as WebhookDeliveryActiveModel, Column as WebhookDeliveryColumn,
+    Entity as WebhookDelivery, Model as WebhookDeliveryModel,
+};
+
+pub const SIGNATURE_HEADER: &str = "X-Tasked-Signature";
+pub const EVENT_HEADER: &str = "X-Tasked-Event";
+pub const DELIVERY_HEADER: &str = "X-Tasked-Delivery";
+
+const MAX_ATTEMPTS: i32 = 8;
+// Doubled after every failed attempt, so the last retry is about half an hour
+// after the one before, and an hour after the first attempt
+const FIRST_RETRY_SECONDS: i64 = 30;
+const REQUEST_TIMEOUT_SECONDS: i64 = 10;
+// Deliveries sent per run, oldest first
+const BATCH_SIZE: u64 = 100;
+const LOG_SIZE: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeliveryState {
+    Pending,
+    Succeeded,
+    // Given up on after running out of attempts
+    Failed,
+}
+
+impl fmt::Display for DeliveryState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DeliveryState::Pending => "pending",
+            DeliveryState::Succeeded => "succeeded",
+            DeliveryState::Failed => "failed",
+        };
+        write!(f, "{s}")
+    }
+}
+
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::seconds(REQUEST_TIMEOUT_SECONDS).to_std().unwrap())
+        .build()
+        .expect("HTTP client")
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("Any key size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn validate_url(url: &str) -> Result<String> {
+    let invalid = || Error::validation("url", format!("Invalid webhook URL {url}"));
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|_| invalid())?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host().is_none() {
+        return Err(invalid());
+    }
+    Ok(parsed.to_string())
+}
+
+pub async fn find_webhook(db: &DatabaseConnection, id: &Uuid) -> Result<WebhookModel> {
+    Webhook::find_by_id(*id)
+        .one(db)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Webhook with id {id:?} not found!")))
+}
+
+// Webhooks of the workspace, including those limited to one of its projects
+pub async fn webhooks_for_workspace(
+    db: &DatabaseConnection,
+    workspace_id: &Uuid,
+) -> Result<Vec<WebhookModel>> {
+    let webhooks = Webhook::find()
+        .filter(Column::WorkspaceId.eq(*workspace_id))
+        .order_by_asc(Column::CreatedAt)
+        .all(db)
+        .await?;
+    Ok(webhooks)
+}
+
+pub struct CreateWebhook {
+    pub url: String,
+    pub project_id: Option<Uuid>,
+    // Names of the entity types and kinds of update to send, empty for all
+    pub entity_types: Json,
+    pub kinds: Json,
+}
+
+// The webhook returned includes its newly generated secret
+pub async fn create_webhook(
+    db: &DatabaseConnection,
+    workspace_id: &Uuid,
+    creator_id: &Uuid,
+    webhook: CreateWebhook,
+) -> Result<WebhookModel> {
+    let webhook = WebhookActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        workspace_id: ActiveValue::Set(*workspace_id),
+        project_id: ActiveValue::Set(webhook.project_id),
+        creator_id: ActiveValue::Set(Some(*creator_id)),
+        url: ActiveValue::Set(validate_url(&webhook.url)?),
+        secret: ActiveValue::Set(Uuid::new_v4().simple().to_string()),
+        entity_types: ActiveValue::Set(webhook.entity_types),
+        kinds: ActiveValue::Set(webhook.kinds),
+        created_at: ActiveValue::Set(Utc::now().naive_utc()),
+    };
+    Ok(webhook.insert(db).await?)
+}
+
+pub async fn delete_webhook(db: &DatabaseConnection, id: &Uuid) -> Result<()> {
+    Webhook::delete_by_id(*id).exec(db).await?;
+    Ok(())
+}
+
+// Queue an event for delivery to each of the webhooks
+pub async fn enqueue(
+    db: &DatabaseConnection,
+    webhooks: &[WebhookModel],
+    event: &str,
+    payload: &Json,
+) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    let deliveries = webhooks.iter().map(|webhook| WebhookDeliveryActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        webhook_id: ActiveValue::Set(webhook.id),
+        event: ActiveValue::Set(event.to_string()),
+        payload: ActiveValue::Set(payload.clone()),
+        state: ActiveValue::Set(DeliveryState::Pending.to_string()),
+        attempt_count: ActiveValue::Set(0),
+        next_attempt_at: ActiveValue::Set(Some(now)),
+        created_at: ActiveValue::Set(now),
+    });
+    WebhookDelivery::insert_many(deliveries)
+        .exec_without_returning(db)
+        .await?;
+    Ok(())
+}
+
+// Send deliveries that are due by `now`: new ones and retries
+pub async fn deliver_due(
+    db: &DatabaseConnection,
+    client: &reqwest::Client,
+    now: DateTime,
+) -> Result<()> {
+    let due = WebhookDelivery::find()
+        .find_also_related(Webhook)
+        .filter(WebhookDeliveryColumn::State.eq(DeliveryState::Pending.to_string()))
+        .filter(WebhookDeliveryColumn::NextAttemptAt.lte(now))
+        .order_by_asc(WebhookDeliveryColumn::NextAttemptAt)
+        .limit(BATCH_SIZE)
+        .all(db)
+        .await?;
+    for (delivery, webhook) in due {
+        if let Some(webhook) = webhook {
+            attempt(db, client, delivery, &webhook, now).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn attempt(
+    db: &DatabaseConnection,
+    client: &reqwest::Client,
+    delivery: WebhookDeliveryModel,
+    webhook: &WebhookModel,
+    now: DateTime,
+) -> Result<()> {
+    // Hold off other runs until this attempt has had time to finish. Skip the
+    // delivery if another run got to it first.
+    let claimed = WebhookDelivery::update_many()
+        .col_expr(
+            WebhookDeliveryColumn::NextAttemptAt,
+            Expr::value(now + Duration::seconds(2 * REQUEST_TIMEOUT_SECONDS)),
+        )
+        .filter(WebhookDeliveryColumn::Id.eq(delivery.id))
+        .filter(WebhookDeliveryColumn::NextAttemptAt.eq(delivery.next_attempt_at))
+        .exec(db)
+        .await?;
+    if claimed.rows_affected == 0 {
+        return Ok(());
+    }
+
+    let body = serde_json::to_vec(&delivery.payload)?;
+    let started = Instant::now();
+    let response = client
+        .post(&webhook.url)
+        .header("Content-Type", "application/json")
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, &body))
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .body(body)
+        .send()
+        .await;
+    let (status_code, error) = match response {
+        Ok(response) if response.status().is_success() => (Some(response.status()), None),
+        Ok(response) => (
+            Some(response.status()),
+            Some(format!("Unexpected response {}", response.status())),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    let attempt = WebhookAttemptActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        delivery_id: ActiveValue::Set(delivery.id),
+        attempted_at: ActiveValue::Set(now),
+        status_code: ActiveValue::Set(status_code.map(|s| s.as_u16() as i32)),
+        error: ActiveValue::Set(error.clone()),
+        duration_ms: ActiveValue::Set(started.elapsed().as_millis() as i32),
+    };
+    attempt.insert(db).await?;
+
+    let attempts = delivery.attempt_count + 1;
+    let (state, next_attempt_at) = match error {
+        None => (DeliveryState::Succeeded, None),
+        Some(_) if attempts >= MAX_ATTEMPTS => (DeliveryState::Failed, None),
+        Some(_) => (DeliveryState::Pending, Some(now + retry_delay(attempts))),
+    };
+    let delivery = WebhookDeliveryActiveModel {
+        id: ActiveValue::Set(delivery.id),
+        state: ActiveValue::Set(state.to_string()),
+        attempt_count: ActiveValue::Set(attempts),
+        next_attempt_at: ActiveValue::Set(next_attempt_at),
+        ..Default::default()
+    };
+    delivery.update(db).await?;
+    Ok(())
+}
+
+// Wait before retrying a delivery after it failed `attempts` times
+fn retry_delay(attempts: i32) -> Duration {
+    Duration::seconds(FIRST_RETRY_SECONDS << (attempts - 1).clamp(0, 16))
+}
+
+// A delivery along with its attempts, oldest first
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeliveryLog {
+    #[serde(flatten)]
+    pub delivery: WebhookDeliveryModel,
+    pub attempts: Vec<WebhookAttemptModel>,
+}
+
+// The webhook's latest deliveries, newest first
+pub async fn delivery_log(db: &DatabaseConnection, webhook_id: &Uuid) -> Result<Vec<DeliveryLog>> {
+    let deliveries = WebhookDelivery::find()
+        .filter(WebhookDeliveryColumn::WebhookId.eq(*webhook_id))
+        .order_by_desc(WebhookDeliveryColumn::CreatedAt)
+        .limit(LOG_SIZE)
+        .all(db)
+        .await?;
+    let attempts = WebhookAttempt::find()
+        .filter(WebhookAttemptColumn::DeliveryId.is_in(deliveries.iter().map(|d| d.id)))
+        .order_by_asc(WebhookAttemptColumn::AttemptedAt)
+        .all(db)
+        .await?;
+    let mut by_delivery: HashMap<Uuid, Vec<WebhookAttemptModel>> = HashMap::new();
+    for attempt in attempts {
+        by_delivery
+            .entry(attempt.delivery_id)
+            .or_default()
+            .push(attempt);
+    }
+    let log = deliveries
+        .into_iter()
+        .map(|delivery| DeliveryLog {
+            attempts: by_delivery.remove(&delivery.id).unwrap_or_default(),
+            delivery,
+        })
+        .collect();
+    Ok(log)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{retry_delay, sign};
+    use chrono::Duration;
+
+    #[test]
+    fn test_sign() {
+        // Known HMAC-SHA256 test vector (RFC 4231, test case 2)
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let delays: Vec<_> = (1..=7).map(retry_delay).collect();
+        assert_eq!(delays[0], Duration::seconds(30));
+        assert_eq!(delays[1], Duration::seconds(60));
+        assert_eq!(delays[6], Duration::seconds(30 * 64));
+    }
+}
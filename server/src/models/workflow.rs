@@ -0,0 +1,425 @@
+// Per-project workflows: the statuses a project's tasks move through and,
+// optionally, which moves between them are allowed.
+//
+// Every status belongs to a category, and that's what the rest of the app
+// reasons about: a task is complete when its status is in the done category,
+// whatever the project calls it. Tasks store their status's key along with a
+// copy of its category. A task shared between projects needs a status that's
+// part of each of their workflows.
+use sea_orm::sea_query::{Expr, ExprTrait};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use uuid::Uuid;
+
+use super::task::{self, Status, Task, TaskActiveModel};
+use crate::result::{Error, Result};
+
+pub use entity::workflow_status::{
+    ActiveModel as WorkflowStatusActiveModel, Column, Entity as WorkflowStatus,
+    Model as WorkflowStatusModel,
+};
+pub use entity::workflow_transition::{
+    ActiveModel as WorkflowTransitionActiveModel, Column as WorkflowTransitionColumn,
+    Entity as WorkflowTransition, Model as WorkflowTransitionModel,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    NotStarted,
+    Active,
+    Done,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Category::NotStarted => "not_started",
+            Category::Active => "active",
+            Category::Done => "done",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Category {
+    pub fn parse(s: &str) -> Result<Self> {
+        let res = match s {
+            "not_started" => Category::NotStarted,
+            "active" => Category::Active,
+            "done" => Category::Done,
+            _ => {
+                return Err(Error::validation(
+                    "category",
+                    format!("Invalid status category {s}"),
+                ));
+            }
+        };
+
+        Ok(res)
+    }
+}
+
+// Statuses every project starts out with
+const DEFAULT_STATUSES: [(Status, &str, &str); 3] = [
+    (Status::Todo, "Todo", "#9ca3af"),
+    (Status::InProgress, "In Progress", "#3b82f6"),
+    (Status::Complete, "Complete", "#22c55e"),
+];
+
+pub async fn create_default_workflow<C: ConnectionTrait>(db: &C, project_id: &Uuid) -> Result<()> {
+    let statuses = DEFAULT_STATUSES
+        .into_iter()
+        .enumerate()
+        .map(|(i, (status, name, color))| WorkflowStatusActiveModel {
+            id: ActiveValue::Set(Uuid::new_v4()),
+            project_id: ActiveValue::Set(*project_id),
+            key: ActiveValue::Set(status.to_string()),
+            name: ActiveValue::Set(name.to_string()),
+            position: ActiveValue::Set(i as i32),
+            category: ActiveValue::Set(status.category().to_string()),
+            color: ActiveValue::Set(color.to_string()),
+        });
+    WorkflowStatus::insert_many(statuses).exec(db).await?;
+    Ok(())
+}
+
+// Statuses of the project, in order
+pub async fn statuses<C: ConnectionTrait>(
+    db: &C,
+    project_id: &Uuid,
+) -> Result<Vec<WorkflowStatusModel>> {
+    let statuses = WorkflowStatus::find()
+        .filter(Column::ProjectId.eq(*project_id))
+        .order_by_asc(Column::Position)
+        .order_by_asc(Column::Key)
+        .all(db)
+        .await?;
+    Ok(statuses)
+}
+
+// Allowed moves between statuses. Any move is allowed if there are none.
+pub async fn transitions<C: ConnectionTrait>(
+    db: &C,
+    project_id: &Uuid,
+) -> Result<Vec<WorkflowTransitionModel>> {
+    let transitions = WorkflowTransition::find()
+        .filter(WorkflowTransitionColumn::ProjectId.eq(*project_id))
+        .order_by_asc(WorkflowTransitionColumn::FromStatus)
+        .order_by_asc(WorkflowTransitionColumn::ToStatus)
+        .all(db)
+        .await?;
+    Ok(transitions)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Workflow {
+    pub statuses: Vec<WorkflowStatusModel>,
+    pub transitions: Vec<WorkflowTransitionModel>,
+}
+
+pub async fn workflow(db: &DatabaseConnection, project_id: &Uuid) -> Result<Workflow> {
+    Ok(Workflow {
+        statuses: statuses(db, project_id).await?,
+        transitions: transitions(db, project_id).await?,
+    })
+}
+
+async fn find_status<C: ConnectionTrait>(
+    db: &C,
+    project_id: &Uuid,
+    key: &str,
+) -> Result<Option<WorkflowStatusModel>> {
+    let status = WorkflowStatus::find()
+        .filter(Column::ProjectId.eq(*project_id))
+        .filter(Column::Key.eq(key))
+        .one(db)
+        .await?;
+    Ok(status)
+}
+
+// Status new tasks of the project start in: the first not started one, or
+// failing that the first of all
+pub async fn initial_status<C: ConnectionTrait>(db: &C, project_id: &Uuid) -> Result<String> {
+    let statuses = statuses(db, project_id).await?;
+    statuses
+        .iter()
+        .find(|s| s.category == Category::NotStarted.to_string())
+        .or(statuses.first())
+        .map(|s| s.key.clone())
+        .ok_or_else(|| Error::validation("status", "Project has no statuses"))
+}
+
+// Check a task of the given projects can move from one status to another,
+// returning the new status's category.
+// Tasks outside of any project use the default statuses.
+pub async fn check_status<C: ConnectionTrait>(
+    db: &C,
+    project_ids: &[Uuid],
+    from: Option<&str>,
+    to: &str,
+) -> Result<Category> {
+    if project_ids.is_empty() {
+        return DEFAULT_STATUSES
+            .iter()
+            .find(|(status, _, _)| status.to_string() == to)
+            .map(|(status, _, _)| status.category())
+            .ok_or_else(|| Error::validation("status", format!("Invalid task status {to}")));
+    }
+
+    let mut category = None;
+    for project_id in project_ids {
+        let status = find_status(db, project_id, to).await?.ok_or_else(|| {
+            Error::validation(
+                "status",
+                format!("Status {to} isn't part of the project's workflow"),
+            )
+        })?;
+        if let Some(from) = from
+            && from != to
+        {
+            let transitions = transitions(db, project_id).await?;
+            let allowed = transitions.is_empty()
+                || transitions
+                    .iter()
+                    .any(|t| t.from_status == from && t.to_status == to);
+            if !allowed {
+                return Err(Error::validation(
+                    "status",
+                    format!("Tasks can't move from {from} to {to} in the project's workflow"),
+                ));
+            }
+        }
+        // Categories could differ between projects, the first one wins
+        category.get_or_insert(Category::parse(&status.category)?);
+    }
+    Ok(category.unwrap())
+}
+
+fn validate_name(name: String) -> Result<String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(Error::validation("name", "Status name cannot be empty"));
+    }
+    Ok(name)
+}
+
+// Like #1a2b3c
+fn validate_color(color: String) -> Result<String> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err(Error::validation(
+            "color",
+            format!("Invalid color {color}, expected #rrggbb"),
+        ));
+    }
+    Ok(color.to_lowercase())
+}
+
+#[derive(Deserialize)]
+pub struct CreateStatusPayload {
+    // Lowercase letters, digits and underscores
+    key: String,
+    name: String,
+    category: String,
+    color: String,
+}
+
+// Add a status at the end of the project's workflow
+pub async fn create_status(
+    db: &DatabaseConnection,
+    project_id: &Uuid,
+    payload: CreateStatusPayload,
+) -> Result<WorkflowStatusModel> {
+    let valid_key = !payload.key.is_empty()
+        && payload
+            .key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid_key {
+        return Err(Error::validation(
+            "key",
+            "Status keys can only have lowercase letters, digits and underscores",
+        ));
+    }
+    if find_status(db, project_id, &payload.key).await?.is_some() {
+        return Err(Error::conflict(format!(
+            "Project already has a status {}",
+            payload.key
+        )));
+    }
+
+    let last = statuses(db, project_id).await?.pop();
+    let status = WorkflowStatusActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        project_id: ActiveValue::Set(*project_id),
+        key: ActiveValue::Set(payload.key),
+        name: ActiveValue::Set(validate_name(payload.name)?),
+        position: ActiveValue::Set(last.map_or(0, |s| s.position + 1)),
+        category: ActiveValue::Set(Category::parse(&payload.category)?.to_string()),
+        color: ActiveValue::Set(validate_color(payload.color)?),
+    };
+    let status = status.insert(db).await?;
+
+    Ok(status)
+}
+
+#[derive(Deserialize)]
+pub struct EditStatusPayload {
+    name: Option<String>,
+    category: Option<String>,
+    color: Option<String>,
+    position: Option<i32>,
+}
+
+// Change how a status is shown or categorized. Tasks of the project in the
+// status take on a new category.
+pub async fn edit_status(
+    db: &DatabaseConnection,
+    project_id: &Uuid,
+    key: &str,
+    payload: EditStatusPayload,
+) -> Result<(WorkflowStatusModel, Vec<Uuid>)> {
+    let existing = find_status(db, project_id, key)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Status {key} not found!")))?;
+    let category = payload.category.map(|c| Category::parse(&c)).transpose()?;
+
+    let txn = db.begin().await?;
+    let mut status: WorkflowStatusActiveModel = existing.into();
+    if let Some(name) = payload.name {
+        status.name = ActiveValue::Set(validate_name(name)?);
+    }
+    if let Some(color) = payload.color {
+        status.color = ActiveValue::Set(validate_color(color)?);
+    }
+    if let Some(position) = payload.position {
+        status.position = ActiveValue::Set(position);
+    }
+    // Tasks in the status take its new category
+    let mut task_ids = vec![];
+    if let Some(category) = category {
+        status.category = ActiveValue::Set(category.to_string());
+        task_ids = Task::find()
+            .filter(task::Column::Status.eq(key))
+            .filter(task::Column::Id.in_subquery(task::ids_in_project(project_id)))
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        Task::update_many()
+            .set(TaskActiveModel {
+                status_category: ActiveValue::Set(category.to_string()),
+                ..Default::default()
+            })
+            .col_expr(
+                task::Column::Version,
+                Expr::col(task::Column::Version).add(1),
+            )
+            .filter(task::Column::Id.is_in(task_ids.clone()))
+            .exec(&txn)
+            .await?;
+    }
+    let status = status.update(&txn).await?;
+    txn.commit().await?;
+
+    Ok((status, task_ids))
+}
+
+// Remove a status no task of the project is in, along with moves to or from it
+pub async fn delete_status(db: &DatabaseConnection, project_id: &Uuid, key: &str) -> Result<()> {
+    let status = find_status(db, project_id, key)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("Status {key} not found!")))?;
+    let in_use = Task::find()
+        .filter(task::Column::Status.eq(key))
+        .filter(task::Column::Id.in_subquery(task::ids_in_project(project_id)))
+        .count(db)
+        .await?;
+    if in_use > 0 {
+        return Err(Error::conflict(format!(
+            "{in_use} task(s) of the project are still in status {key}"
+        )));
+    }
+    if statuses(db, project_id).await?.len() == 1 {
+        return Err(Error::validation(
+            "key",
+            "Projects need at least one status",
+        ));
+    }
+
+    let txn = db.begin().await?;
+    WorkflowTransition::delete_many()
+        .filter(WorkflowTransitionColumn::ProjectId.eq(*project_id))
+        .filter(
+            Condition::any()
+                .add(WorkflowTransitionColumn::FromStatus.eq(key))
+                .add(WorkflowTransitionColumn::ToStatus.eq(key)),
+        )
+        .exec(&txn)
+        .await?;
+    WorkflowStatus::delete_by_id(status.id).exec(&txn).await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct TransitionPayload {
+    from: String,
+    to: String,
+}
+
+// Replace the allowed moves between the project's statuses.
+// An empty list allows any move.
+pub async fn set_transitions(
+    db: &DatabaseConnection,
+    project_id: &Uuid,
+    transitions: Vec<TransitionPayload>,
+) -> Result<Vec<WorkflowTransitionModel>> {
+    let keys: Vec<String> = statuses(db, project_id)
+        .await?
+        .into_iter()
+        .map(|s| s.key)
+        .collect();
+    for t in &transitions {
+        for key in [&t.from, &t.to] {
+            if !keys.contains(key) {
+                return Err(Error::validation(
+                    "transitions",
+                    format!("Status {key} isn't part of the project's workflow"),
+                ));
+            }
+        }
+    }
+
+    let txn = db.begin().await?;
+    WorkflowTransition::delete_many()
+        .filter(WorkflowTransitionColumn::ProjectId.eq(*project_id))
+        .exec(&txn)
+        .await?;
+    let mut seen = HashSet::new();
+    let models: Vec<WorkflowTransitionActiveModel> = transitions
+        .into_iter()
+        .filter(|t| seen.insert((t.from.clone(), t.to.clone())))
+        .map(|t| WorkflowTransitionActiveModel {
+            project_id: ActiveValue::Set(*project_id),
+            from_status: ActiveValue::Set(t.from),
+            to_status: ActiveValue::Set(t.to),
+        })
+        .collect();
+    if !models.is_empty() {
+        WorkflowTransition::insert_many(models).exec(&txn).await?;
+    }
+    let transitions = self::transitions(&txn, project_id).await?;
+    txn.commit().await?;
+
+    Ok(transitions)
+}
@@ -0,0 +1,133 @@
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionSession, TransactionTrait,
+};
+use uuid::Uuid;
+
+use crate::result::{Error, Result};
+
+pub use entity::workspace::{
+    ActiveModel as WorkspaceActiveModel, Column, Entity as Workspace, Model as WorkspaceModel,
+};
+pub use entity::workspace_member::{
+    ActiveModel as WorkspaceMemberActiveModel, Column as WorkspaceMemberColumn,
+    Entity as WorkspaceMember,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorkspaceRole {
+    // Created the workspace, and may remove other members
+    Owner,
+    Member,
+}
+
+impl WorkspaceRole {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WorkspaceRole::Owner => "owner",
+            WorkspaceRole::Member => "member",
+        }
+    }
+}
+
+// Create a workspace with the given user as its first member
+pub async fn create_workspace<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    name: String,
+    user_id: &Uuid,
+) -> Result<WorkspaceModel> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(Error::validation("name", "Workspace name cannot be empty"));
+    }
+
+    let txn = db.begin().await?;
+    let workspace = WorkspaceActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        name: ActiveValue::Set(name),
+        ..Default::default()
+    };
+    let workspace = workspace.insert(&txn).await?;
+    add_member(&txn, &workspace.id, user_id, WorkspaceRole::Owner).await?;
+    txn.commit().await?;
+
+    Ok(workspace)
+}
+
+pub async fn add_member<C: ConnectionTrait>(
+    db: &C,
+    workspace_id: &Uuid,
+    user_id: &Uuid,
+    role: WorkspaceRole,
+) -> Result<()> {
+    let member = WorkspaceMemberActiveModel {
+        workspace_id: ActiveValue::Set(*workspace_id),
+        user_id: ActiveValue::Set(*user_id),
+        role: ActiveValue::Set(role.as_str().to_string()),
+        ..Default::default()
+    };
+    member.insert(db).await?;
+    Ok(())
+}
+
+// Remove the user from the workspace. Members may only remove themselves and
+// owners anyone, as long as the workspace keeps an owner.
+pub async fn remove_member(
+    db: &DatabaseConnection,
+    actor_id: &Uuid,
+    workspace_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<()> {
+    let txn = db.begin().await?;
+    let find = |user_id: Uuid| WorkspaceMember::find_by_id((*workspace_id, user_id)).one(&txn);
+    let not_member = || Error::not_found("User is not a member of this workspace");
+    let actor = find(*actor_id).await?.ok_or_else(not_member)?;
+    if actor_id != user_id && actor.role != WorkspaceRole::Owner.as_str() {
+        return Err(Error::forbidden("Only owners can remove other members"));
+    }
+    let member = find(*user_id).await?.ok_or_else(not_member)?;
+    if member.role == WorkspaceRole::Owner.as_str() {
+        let owners = WorkspaceMember::find()
+            .filter(WorkspaceMemberColumn::WorkspaceId.eq(*workspace_id))
+            .filter(WorkspaceMemberColumn::Role.eq(WorkspaceRole::Owner.as_str()))
+            .count(&txn)
+            .await?;
+        if owners <= 1 {
+            return Err(Error::conflict(
+                "Workspace needs another owner before its last one leaves",
+            ));
+        }
+    }
+    WorkspaceMember::delete_by_id((*workspace_id, *user_id))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+// Ids of all workspaces the user belongs to, oldest membership first
+pub async fn workspace_ids_for_user<C: ConnectionTrait>(
+    db: &C,
+    user_id: &Uuid,
+) -> Result<Vec<Uuid>> {
+    let ids = WorkspaceMember::find()
+        .select_only()
+        .column(WorkspaceMemberColumn::WorkspaceId)
+        .filter(WorkspaceMemberColumn::UserId.eq(*user_id))
+        .order_by_asc(WorkspaceMemberColumn::CreatedAt)
+        .into_tuple()
+        .all(db)
+        .await?;
+    Ok(ids)
+}
+
+pub async fn is_member<C: ConnectionTrait>(
+    db: &C,
+    workspace_id: &Uuid,
+    user_id: &Uuid,
+) -> Result<bool> {
+    let member = WorkspaceMember::find_by_id((*workspace_id, *user_id))
+        .one(db)
+        .await?;
+    Ok(member.is_some())
+}
@@ -1,26 +1,195 @@
-// "Vendored" from: https://docs.rs/rocket_anyhow/latest/src/rocket_anyhow/lib.rs.html#1-54
-use rocket::Request;
+use rocket::http::{Header, Status};
 use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::{Catcher, Request};
+use sea_orm::{DbErr, SqlErr};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Debug)]
-pub struct Error(pub anyhow::Error);
+pub enum Error {
+    NotFound(String),
+    Validation {
+        message: String,
+        field: Option<String>,
+    },
+    Conflict(String),
+    Unauthorized(String),
+    Forbidden(String),
+    // The entity changed since the version the client had, which is the
+    // current one
+    PreconditionFailed {
+        message: String,
+        current: Value,
+    },
+    Internal(anyhow::Error),
+}
 pub type Result<T = ()> = std::result::Result<T, Error>;
 
+// Stable JSON body for every error response, so clients can branch on `code`
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    pub field: Option<String>,
+    // The entity as it is now, when a precondition failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<Value>,
+}
+
+impl Error {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Error::NotFound(message.into())
+    }
+
+    pub fn validation(field: &str, message: impl Into<String>) -> Self {
+        Error::Validation {
+            message: message.into(),
+            field: Some(field.to_string()),
+        }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Error::Conflict(message.into())
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Error::Forbidden(message.into())
+    }
+
+    pub fn precondition_failed<T: Serialize>(current: &T) -> Self {
+        match serde_json::to_value(current) {
+            Ok(current) => Error::PreconditionFailed {
+                message: "Changed since the version given in If-Match".to_string(),
+                current,
+            },
+            Err(e) => Error::Internal(e.into()),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            Error::NotFound(_) => Status::NotFound,
+            Error::Validation { .. } => Status::UnprocessableEntity,
+            Error::Conflict(_) => Status::Conflict,
+            Error::Unauthorized(_) => Status::Unauthorized,
+            Error::Forbidden(_) => Status::Forbidden,
+            Error::PreconditionFailed { .. } => Status::PreconditionFailed,
+            Error::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::Validation { .. } => "validation",
+            Error::Conflict(_) => "conflict",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::PreconditionFailed { .. } => "precondition_failed",
+            Error::Internal(_) => "internal",
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let (message, field) = match self {
+            Error::NotFound(message)
+            | Error::Conflict(message)
+            | Error::Unauthorized(message)
+            | Error::Forbidden(message)
+            | Error::PreconditionFailed { message, .. } => (message.clone(), None),
+            Error::Validation { message, field } => (message.clone(), field.clone()),
+            // Don't leak internals to clients, details are logged instead
+            Error::Internal(_) => ("Internal server error".to_string(), None),
+        };
+
+        let current = match self {
+            Error::PreconditionFailed { current, .. } => Some(current.clone()),
+            _ => None,
+        };
+        ErrorBody {
+            code: self.code().to_string(),
+            message,
+            field,
+            current,
+        }
+    }
+}
+
+// Anything convertible to anyhow is an internal error, unless it is a database
+// error with a more specific meaning.
 impl<E> From<E> for Error
 where
     E: Into<anyhow::Error>,
 {
     fn from(error: E) -> Self {
-        Error(error.into())
+        let error = error.into();
+        if let Some(db_err) = error.downcast_ref::<DbErr>() {
+            match db_err {
+                DbErr::RecordNotFound(message) => return Error::NotFound(message.clone()),
+                DbErr::RecordNotUpdated => return Error::not_found("Record not found"),
+                _ => {}
+            }
+            match db_err.sql_err() {
+                Some(SqlErr::UniqueConstraintViolation(_)) => {
+                    return Error::conflict("Record already exists");
+                }
+                Some(SqlErr::ForeignKeyConstraintViolation(_)) => {
+                    return Error::not_found("Referenced record not found");
+                }
+                _ => {}
+            }
+        }
+        Error::Internal(error)
     }
 }
 
-impl<'r> Responder<'r, 'r> for Error {
-    fn respond_to(self, request: &Request<'_>) -> response::Result<'static> {
-        response::Debug(self.0).respond_to(request)
+impl<'r> Responder<'r, 'static> for Error {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        if let Error::Internal(e) = &self {
+            eprintln!("Internal error handling {request}: {e:?}");
+        }
+        let status = self.status();
+        let mut response = Json(self.body()).respond_to(request)?;
+        response.set_status(status);
+        if let Error::PreconditionFailed { current, .. } = &self
+            && let Some(version) = current.get("version").and_then(Value::as_i64)
+            && let Ok(version) = i32::try_from(version)
+        {
+            response.set_header(etag(version));
+        }
+        Ok(response)
     }
 }
 
-pub fn error_response(msg: String) -> Error {
-    Error(anyhow::Error::msg(msg))
+// Entities are tagged with their version, which clients send back in If-Match
+pub fn etag(version: i32) -> Header<'static> {
+    Header::new("ETag", format!("\"{version}\""))
+}
+
+// Render errors raised by rocket itself (unmatched routes, malformed bodies,
+// failed guards) with the same body as handler errors.
+#[catch(default)]
+fn default_catcher(status: Status, _request: &Request) -> (Status, Json<ErrorBody>) {
+    let code = match status.code {
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        409 => "conflict",
+        412 => "precondition_failed",
+        400 | 422 => "validation",
+        _ if status.code >= 500 => "internal",
+        _ => "error",
+    };
+    let body = ErrorBody {
+        code: code.to_string(),
+        message: status.reason_lossy().to_string(),
+        field: None,
+        current: None,
+    };
+    (status, Json(body))
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
 }
@@ -1,10 +1,91 @@
 use super::initialize_rocket;
+use crate::api::subscription::{FeedWriter, UpdateEvent};
+use crate::models::user::{self, User, UserActiveModel, UserModel};
+use crate::models::workspace;
 use migration::{Migrator, MigratorTrait};
-use rocket::local::asynchronous::Client;
-use sea_orm::{Database, DatabaseConnection};
-use tokio::sync::broadcast;
+use rocket::http::{Header, Method, uri::Origin};
+use rocket::local::asynchronous::{Client, LocalRequest};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Database, DatabaseConnection, EntityTrait,
+    QueryFilter,
+};
+use std::fmt::Display;
+use tokio::sync::broadcast::Receiver;
+use uuid::Uuid;
 
-pub async fn init_server(db: Option<DatabaseConnection>) -> anyhow::Result<Client> {
+// Local client which sends requests authenticated as a test user
+pub struct TestClient {
+    client: Client,
+    pub user: UserModel,
+    pub token: String,
+    pub workspace_id: Uuid,
+}
+
+impl TestClient {
+    pub fn req<'c, 'u: 'c, U>(&'c self, method: Method, uri: U) -> LocalRequest<'c>
+    where
+        U: TryInto<Origin<'u>> + Display,
+    {
+        self.client.req(method, uri).header(Header::new(
+            "Authorization",
+            format!("Bearer {}", self.token),
+        ))
+    }
+
+    pub fn get<'c, 'u: 'c, U>(&'c self, uri: U) -> LocalRequest<'c>
+    where
+        U: TryInto<Origin<'u>> + Display,
+    {
+        self.req(Method::Get, uri)
+    }
+
+    pub fn post<'c, 'u: 'c, U>(&'c self, uri: U) -> LocalRequest<'c>
+    where
+        U: TryInto<Origin<'u>> + Display,
+    {
+        self.req(Method::Post, uri)
+    }
+
+    pub fn patch<'c, 'u: 'c, U>(&'c self, uri: U) -> LocalRequest<'c>
+    where
+        U: TryInto<Origin<'u>> + Display,
+    {
+        self.req(Method::Patch, uri)
+    }
+
+    pub fn put<'c, 'u: 'c, U>(&'c self, uri: U) -> LocalRequest<'c>
+    where
+        U: TryInto<Origin<'u>> + Display,
+    {
+        self.req(Method::Put, uri)
+    }
+
+    pub fn delete<'c, 'u: 'c, U>(&'c self, uri: U) -> LocalRequest<'c>
+    where
+        U: TryInto<Origin<'u>> + Display,
+    {
+        self.req(Method::Delete, uri)
+    }
+
+    // Client without any credentials
+    pub fn unauthenticated(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn feed(&self) -> &FeedWriter {
+        self.client
+            .rocket()
+            .state::<FeedWriter>()
+            .expect("update feed")
+    }
+
+    // Updates broadcast from now on
+    pub fn updates(&self) -> Receiver<UpdateEvent> {
+        self.feed().subscribe()
+    }
+}
+
+pub async fn init_server(db: Option<DatabaseConnection>) -> anyhow::Result<TestClient> {
     let conn = match db {
         Some(db) => db,
         None => {
@@ -13,13 +94,24 @@ pub async fn init_server(db: Option<DatabaseConnection>) -> anyhow::Result<Clien
         }
     };
 
-    let update_feed = broadcast::channel(1).0;
+    let user = test_user(&conn).await?;
+    let workspace_id = workspace_id(&conn).await?;
+    let token = user::create_token(&conn, &user.id)
+        .await
+        .map_err(|e| anyhow::Error::msg(format!("{e:?}")))?;
+
+    let update_feed = FeedWriter::load(&conn, 16).await?;
     let rocket = initialize_rocket(conn, update_feed).await.unwrap();
     let client = Client::tracked(rocket)
         .await
         .expect("valid rocket instance");
 
-    Ok(client)
+    Ok(TestClient {
+        client,
+        user,
+        token,
+        workspace_id,
+    })
 }
 
 pub async fn db_conn() -> anyhow::Result<DatabaseConnection> {
@@ -27,3 +119,38 @@ pub async fn db_conn() -> anyhow::Result<DatabaseConnection> {
     Migrator::refresh(&conn).await?;
     Ok(conn)
 }
+
+const TEST_USERNAME: &str = "test-user";
+
+// The user test requests are made as, created on first use.
+// Skips password hashing (slow in debug builds), so this user can't log in.
+pub async fn test_user(db: &DatabaseConnection) -> anyhow::Result<UserModel> {
+    let existing = User::find()
+        .filter(user::Column::Username.eq(TEST_USERNAME))
+        .one(db)
+        .await?;
+    if let Some(user) = existing {
+        return Ok(user);
+    }
+
+    let user = UserActiveModel {
+        id: ActiveValue::Set(Uuid::new_v4()),
+        username: ActiveValue::Set(TEST_USERNAME.to_string()),
+        password_hash: ActiveValue::Set(String::new()),
+        ..Default::default()
+    };
+    let user = user.insert(db).await?;
+    workspace::create_workspace(db, "Test workspace".to_string(), &user.id)
+        .await
+        .map_err(|e| anyhow::Error::msg(format!("{e:?}")))?;
+    Ok(user)
+}
+
+// The test user's workspace, which test data should be created in
+pub async fn workspace_id(db: &DatabaseConnection) -> anyhow::Result<Uuid> {
+    let user = test_user(db).await?;
+    let ids = workspace::workspace_ids_for_user(db, &user.id)
+        .await
+        .map_err(|e| anyhow::Error::msg(format!("{e:?}")))?;
+    Ok(ids[0])
+}